@@ -0,0 +1,176 @@
+//! Requests forwarded by an Application Load Balancer target group. The body of a `POST` is the
+//! validation event; the answer is wrapped in the response shape the load balancer expects.
+
+use std::collections::HashMap;
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::encoding::Encoding;
+use crate::error::ServiceError;
+use crate::http::{self, Answer, EncodedBody, EventHeaders};
+use crate::ValidationEvent;
+
+#[derive(Deserialize)]
+pub struct AlbEvent {
+    /// Only there to tell ALB events apart from the others.
+    #[serde(rename = "requestContext")]
+    _request_context: AlbRequestContext,
+    #[serde(rename = "httpMethod")]
+    http_method: String,
+    /// Set unless the target group has multi-value headers enabled, holding the last value of repeated headers.
+    #[serde(default)]
+    headers: Option<HashMap<String, String>>,
+    /// Set instead of `headers` when the target group has multi-value headers enabled.
+    #[serde(rename = "multiValueHeaders", default)]
+    multi_value_headers: Option<HashMap<String, Vec<String>>>,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(rename = "isBase64Encoded", default)]
+    is_base64_encoded: bool
+}
+
+#[derive(Deserialize)]
+struct AlbRequestContext {
+    #[serde(rename = "elb")]
+    _elb: serde_json::Value
+}
+
+#[derive(Serialize, Debug)]
+pub struct AlbResponse {
+    #[serde(rename = "statusCode")]
+    status_code: u16,
+    /// e.g. `200 OK`; the load balancer answers 502 without it.
+    #[serde(rename = "statusDescription")]
+    status_description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    headers: Option<HashMap<String, String>>,
+    /// Used instead of `headers` when the request came with multi-value headers, the load
+    /// balancer ignoring the other field.
+    #[serde(rename = "multiValueHeaders", skip_serializing_if = "Option::is_none")]
+    multi_value_headers: Option<HashMap<String, Vec<String>>>,
+    body: String,
+    #[serde(rename = "isBase64Encoded")]
+    is_base64_encoded: bool
+}
+
+/// Answers the request with `validate` run on the event in its body, `validate` answering
+/// with the response serialized as JSON and the errors that decide its status. With
+/// `strict_input`, fields the event does not know are rejected.
+pub fn run<F>(event: &AlbEvent, strict_input: bool, validate: F) -> AlbResponse
+    where F: FnOnce(ValidationEvent) -> Result<Answer, ServiceError>
+{
+    let outcome = http::validation_event(&event.http_method, event.body.as_deref(), event.is_base64_encoded, EventHeaders {
+        accept_language: header(event, "accept-language"),
+        api_key: header(event, "x-api-key"),
+        content_type: header(event, "content-type"),
+        correlation_id: header(event, "x-correlation-id")
+    }, strict_input).map(|mut validation_event| {
+        // the load balancer appends the address it was called from to whatever the client sent
+        validation_event.source_ip = header(event, "x-forwarded-for")
+            .and_then(|forwarded_for| forwarded_for.rsplit(',').next())
+            .map(|source_ip| source_ip.trim().to_string());
+        validation_event
+    }).and_then(validate);
+    let (status, body) = http::answer(&event.http_method, outcome);
+    let encoding = Encoding::negotiate(header(event, "accept"), Encoding::of_request(header(event, "content-type")));
+    response(event, status, http::encoded_body(body, encoding))
+}
+
+/// Value of the header `name`, the last one when repeated.
+fn header<'a>(event: &'a AlbEvent, name: &str) -> Option<&'a str> {
+    match (event.headers.as_ref(), event.multi_value_headers.as_ref()) {
+        (Some(headers), _) => headers.get(name).map(String::as_str),
+        (None, Some(headers)) => headers.get(name).and_then(|values| values.last()).map(String::as_str),
+        (None, None) => None,
+    }
+}
+
+fn response(event: &AlbEvent, status: u16, body: EncodedBody) -> AlbResponse {
+    let content_type = (String::from("content-type"), String::from(body.content_type));
+    let (headers, multi_value_headers) = if event.multi_value_headers.is_some() && event.headers.is_none() {
+        (None, Some(vec![(content_type.0, vec![content_type.1])].into_iter().collect()))
+    } else {
+        (Some(vec![content_type].into_iter().collect()), None)
+    };
+    AlbResponse {
+        status_code: status,
+        status_description: format!("{} {}", status, http::reason(status)),
+        headers,
+        multi_value_headers,
+        body: body.body,
+        is_base64_encoded: body.is_base64_encoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alb_event(method: &str, body: &str, multi_value: bool) -> AlbEvent {
+        let mut event = serde_json::json!({
+            "requestContext": {"elb": {"targetGroupArn": "arn:aws:elasticloadbalancing:eu-central-1:123456789012:targetgroup/validator/abc"}},
+            "httpMethod": method,
+            "path": "/validate",
+            "body": base64::encode(body),
+            "isBase64Encoded": true
+        });
+        if multi_value {
+            event["multiValueHeaders"] = serde_json::json!({"content-type": ["application/json"]});
+        } else {
+            event["headers"] = serde_json::json!({"content-type": "application/json"});
+        }
+        serde_json::from_value(event).unwrap()
+    }
+
+    #[test]
+    fn validates_the_event_in_the_body() {
+        let response = run(&alb_event("POST", r#"{"serialNumber": "AB1234"}"#, false), false, |event| Ok(event.serial_number.into()));
+        assert_eq!((200, "200 OK", "AB1234"), (response.status_code, response.status_description.as_str(), response.body.as_str()));
+        assert_eq!(Some("application/json"), response.headers.as_ref().and_then(|headers| headers.get("content-type")).map(String::as_str));
+        assert_eq!(None, response.multi_value_headers);
+    }
+
+    #[test]
+    fn answers_multi_value_requests_with_multi_value_headers() {
+        let response = run(&alb_event("POST", "{}", true), false, |_| Ok(String::new().into()));
+        assert_eq!(None, response.headers);
+        assert_eq!(Some(&vec![String::from("application/json")]), response.multi_value_headers.as_ref().and_then(|headers| headers.get("content-type")));
+    }
+
+    #[test]
+    fn maps_failures_to_status_codes() {
+        assert_eq!(405, run(&alb_event("GET", "", false), false, |_| Ok(String::new().into())).status_code);
+        assert_eq!(400, run(&alb_event("POST", "[", false), false, |_| Ok(String::new().into())).status_code);
+        let missing_serial = run(&alb_event("POST", "{}", false), false, |_| Ok(String::new().into()));
+        assert_eq!((400, true), (missing_serial.status_code, missing_serial.body.contains(r#""field":"serialNumber""#)));
+        let throttled = run(&alb_event("POST", r#"{"serialNumber": "AB1234"}"#, false), false, |_| Err(ServiceError::StoreThrottled(String::from("slow down"))));
+        assert_eq!((503, "503 Service Unavailable"), (throttled.status_code, throttled.status_description.as_str()));
+        assert_eq!(true, throttled.body.contains("StoreThrottled"));
+        let limited = run(&alb_event("POST", r#"{"serialNumber": "AB1234"}"#, false), false, |_| Err(ServiceError::RateLimited { retry_after_seconds: 2 }));
+        assert_eq!((429, "429 Too Many Requests"), (limited.status_code, limited.status_description.as_str()));
+        let misspelled = r#"{"serailNumber": "AB1234"}"#;
+        let strict = run(&alb_event("POST", misspelled, false), true, |_| Ok(String::new().into()));
+        assert_eq!((400, true), (strict.status_code, strict.body.contains("unknown_field: serailNumber")));
+    }
+
+    #[test]
+    fn answers_in_the_accepted_encoding() {
+        let mut event = alb_event("POST", "", false);
+        event.body = Some(base64::encode(&rmp_serde::to_vec_named(&serde_json::json!({"serialNumber": "AB1234"})).unwrap()));
+        let headers = event.headers.as_mut().unwrap();
+        headers.insert(String::from("content-type"), String::from("application/msgpack"));
+        headers.insert(String::from("accept"), String::from("application/cbor"));
+        let response = run(&event, false, |event| Ok(serde_json::json!({"serialNumber": event.serial_number}).to_string().into()));
+        assert_eq!((200, true), (response.status_code, response.is_base64_encoded));
+        assert_eq!(Some("application/cbor"), response.headers.as_ref().and_then(|headers| headers.get("content-type")).map(String::as_str));
+        let body: serde_json::Value = ciborium::de::from_reader(&base64::decode(&response.body).unwrap()[..]).unwrap();
+        assert_eq!(serde_json::json!({"serialNumber": "AB1234"}), body);
+    }
+
+    #[test]
+    fn takes_the_source_address_the_load_balancer_appended() {
+        let mut event = alb_event("POST", r#"{"serialNumber": "AB1234"}"#, false);
+        event.headers.as_mut().unwrap().insert(String::from("x-forwarded-for"), String::from("198.51.100.1, 203.0.113.7"));
+        assert_eq!("203.0.113.7", run(&event, false, |event| Ok(event.source_ip.unwrap_or_default().into())).body);
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+use rusoto_dynamodb::{DynamoDb, UpdateItemInput};
+use serde_json::json;
+
+use crate::aws;
+use crate::context::RequestContext;
+use crate::hashing::KeyHasher;
+use crate::store::{client_in, send, string_value, number_value, DEFAULT_REGION};
+
+/// Alerting on serials that keep failing with `already_exists`.
+#[derive(Clone, Debug)]
+pub struct DuplicateAlertSettings {
+    /// Table keyed by `counter_key` holding the attempt counters.
+    pub table_name: String,
+    /// SNS topic notified when a serial crosses the threshold.
+    pub topic_arn: String,
+    /// Duplicate attempts tolerated within a window before alerting.
+    pub threshold: u64,
+    pub window_seconds: u64
+}
+
+/// Counter of a serial's duplicate attempts within the window containing `now`, the serial hashed
+/// with `hasher`.
+pub fn counter_key(hasher: &KeyHasher, serial_number: &str, window_seconds: u64, now: u64) -> String {
+    let window_start = now - now % window_seconds.max(1);
+    format!("duplicate#{}#{}", hasher.hash(serial_number), window_start)
+}
+
+/// True for the single attempt that takes the count past the threshold, so each window alerts once.
+pub fn crosses_threshold(count: u64, threshold: u64) -> bool {
+    count == threshold + 1
+}
+
+/// Counts a duplicate attempt and notifies the topic when the threshold is crossed, on a
+/// background thread, each call abandoned after `timeout`. `hasher`, whose key may have to be
+/// read, is called on that thread too; attempts are not counted while it returns `None`. Best
+/// effort: failures are logged with the request's context, whose tenant the alert names.
+pub fn record_duplicate_async<H>(context: &RequestContext, settings: &DuplicateAlertSettings, serial_number: &str, now: u64, hasher: H, timeout: Duration)
+    where H: FnOnce() -> Option<KeyHasher> + Send + 'static
+{
+    let settings = settings.clone();
+    let serial_number = serial_number.to_string();
+    let context = context.clone();
+    thread::spawn(move || {
+        let hasher = match hasher() {
+            Some(hasher) => hasher,
+            None => return context.log("duplicate attempt not counted: the hash key could not be read"),
+        };
+        let count = match increment(&settings, &counter_key(&hasher, &serial_number, settings.window_seconds, now), now, timeout) {
+            Ok(count) => count,
+            Err(error) => return context.log(&format!("failed to count duplicate attempt: {}", error)),
+        };
+        if !crosses_threshold(count, settings.threshold) {
+            return;
+        }
+        let message = json!({
+            "alert": "repeated_duplicate_serial",
+            "serialNumber": serial_number,
+            "tenantId": context.tenant_id,
+            "attempts": count,
+            "windowSeconds": settings.window_seconds
+        });
+        let region = aws::arn_region(&settings.topic_arn).unwrap_or_default();
+        let params = [
+            ("TopicArn", settings.topic_arn.as_str()),
+            ("Subject", "Repeated duplicate serial number"),
+            ("Message", &message.to_string())
+        ];
+        if let Err(error) = aws::call_query("sns", "Publish", "2010-03-31", &region, &params, Some(timeout)) {
+            context.log(&format!("failed to publish duplicate serial alert: {}", error));
+        }
+    });
+}
+
+/// Atomically adds one to the counter under `counter_key` and returns the new count.
+fn increment(settings: &DuplicateAlertSettings, counter_key: &str, now: u64, timeout: Duration) -> Result<u64, String> {
+    let mut key = HashMap::new();
+    key.insert(String::from("counter_key"), string_value(counter_key));
+    let mut values = HashMap::new();
+    values.insert(String::from(":one"), number_value(1));
+    // keep the counter a little past its window so late attempts still find it
+    values.insert(String::from(":expires_at"), number_value(now + 2 * settings.window_seconds));
+
+    let update = UpdateItemInput {
+        table_name: settings.table_name.clone(),
+        key,
+        update_expression: Some(String::from("ADD attempts :one SET expires_at = if_not_exists(expires_at, :expires_at)")),
+        expression_attribute_values: Some(values),
+        return_values: Some(String::from("UPDATED_NEW")),
+        ..Default::default()
+    };
+    let output = send(client_in(&DEFAULT_REGION).update_item(update), Some(timeout)).map_err(|error| format!("{:?}", error))?;
+    output.attributes
+        .and_then(|attributes| attributes.get("attempts").and_then(|attempts| attempts.n.clone()))
+        .and_then(|attempts| attempts.parse().ok())
+        .ok_or_else(|| String::from("attempts missing from the update response"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::secrets::Secret;
+
+    #[test]
+    fn counters_are_per_window() {
+        let hasher = KeyHasher::Sha256;
+        assert_eq!(counter_key(&hasher, "serial1", 3600, 7200), counter_key(&hasher, "serial1", 3600, 10799));
+        assert_ne!(counter_key(&hasher, "serial1", 3600, 7200), counter_key(&hasher, "serial1", 3600, 10800));
+        assert_ne!(counter_key(&hasher, "serial1", 3600, 7200), counter_key(&hasher, "serial2", 3600, 7200));
+    }
+
+    #[test]
+    fn counters_are_keyed_by_the_hash_of_the_serial() {
+        let key = Secret { value: String::from("pepper"), version_id: String::from("v1") };
+        let hasher = KeyHasher::HmacSha256(Arc::new(key));
+        let counter = counter_key(&hasher, "serial1", 3600, 7200);
+        assert_eq!(format!("duplicate#{}#7200", hasher.hash("serial1")), counter);
+        assert_eq!(false, counter.contains("serial1"));
+        assert_ne!(counter_key(&KeyHasher::Sha256, "serial1", 3600, 7200), counter);
+    }
+
+    #[test]
+    fn alerts_once_when_the_threshold_is_exceeded() {
+        assert_eq!(false, crosses_threshold(5, 5));
+        assert_eq!(true, crosses_threshold(6, 5));
+        assert_eq!(false, crosses_threshold(7, 5));
+    }
+}
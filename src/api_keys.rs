@@ -0,0 +1,142 @@
+//! Per-caller API keys, for deployments reachable through a Function URL or load balancer. Only
+//! SHA-256 digests of the keys are stored, in Secrets Manager or SSM Parameter Store, as a JSON
+//! object naming the caller of each: `{"<hex digest>": "label-printing"}`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rusoto_core::Region;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::aws::{self, AwsError};
+use crate::context::log_shared;
+use crate::error::ServiceError;
+
+/// Where the digests are kept.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeySource {
+    /// Id or ARN of a Secrets Manager secret.
+    Secret(String),
+    /// Name of an SSM parameter, usually a `SecureString`.
+    Parameter(String)
+}
+
+#[derive(Clone, Debug)]
+pub struct ApiKeySettings {
+    pub source: KeySource,
+    /// How long the digests are used before they are read again, picking up revoked keys.
+    pub refresh_interval: Duration
+}
+
+/// The digests of the valid keys and the callers they belong to.
+pub struct ApiKeys {
+    callers: HashMap<String, String>
+}
+
+impl ApiKeys {
+    pub fn parse(json: &str) -> Result<ApiKeys, String> {
+        let callers: HashMap<String, String> = serde_json::from_str(json).map_err(|error| error.to_string())?;
+        Ok(ApiKeys { callers: callers.into_iter().map(|(digest, caller)| (digest.to_lowercase(), caller)).collect() })
+    }
+
+    /// Caller the key belongs to, `None` for unknown keys.
+    pub fn caller(&self, key: &str) -> Option<&str> {
+        self.callers.get(&digest(key)).map(String::as_str)
+    }
+}
+
+/// Hex encoded SHA-256 digest of a key, as stored.
+pub fn digest(key: &str) -> String {
+    Sha256::digest(key.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Reads the digests from their source.
+pub fn load(source: &KeySource, timeout: Option<Duration>) -> Result<ApiKeys, AwsError> {
+    let value = match *source {
+        KeySource::Secret(ref secret_id) => {
+            let response = aws::call_json("secretsmanager", "secretsmanager.GetSecretValue", "1.1", &Region::default(), &json!({"SecretId": secret_id}), timeout)?;
+            response["SecretString"].as_str().map(String::from)
+        },
+        KeySource::Parameter(ref name) => {
+            let response = aws::call_json("ssm", "AmazonSSM.GetParameter", "1.1", &Region::default(), &json!({"Name": name, "WithDecryption": true}), timeout)?;
+            response["Parameter"]["Value"].as_str().map(String::from)
+        },
+    };
+    let value = value.ok_or_else(|| AwsError::MalformedResponse(format!("{:?} holds no string", source)))?;
+    ApiKeys::parse(&value).map_err(|error| AwsError::MalformedResponse(format!("{:?} is not an object of key digests: {}", source, error)))
+}
+
+/// The digests loaded by this container, shared by every invocation it serves.
+pub struct KeyCache {
+    keys: Mutex<Option<(Arc<ApiKeys>, Instant)>>
+}
+
+impl KeyCache {
+    pub fn new() -> KeyCache {
+        KeyCache { keys: Mutex::new(None) }
+    }
+
+    /// The digests, loaded again once they are `refresh_interval` old. A failed reload keeps the
+    /// digests loaded before until the next attempt; `None` while none could be loaded yet.
+    pub fn get<F>(&self, refresh_interval: Duration, now: Instant, load: F) -> Option<Arc<ApiKeys>>
+        where F: FnOnce() -> Result<ApiKeys, AwsError>
+    {
+        let mut keys = self.keys.lock().unwrap();
+        if let Some((ref cached, loaded_at)) = *keys {
+            if now.duration_since(loaded_at) < refresh_interval {
+                return Some(cached.clone());
+            }
+        }
+        match load() {
+            Ok(loaded) => *keys = Some((Arc::new(loaded), now)),
+            Err(error) => {
+                log_shared(&format!("API keys could not be loaded: {}", error));
+                if let Some((_, ref mut loaded_at)) = *keys {
+                    *loaded_at = now;
+                }
+            },
+        }
+        keys.as_ref().map(|(cached, _)| cached.clone())
+    }
+}
+
+/// The caller presenting `key`. Requests are refused while no digests could be loaded.
+pub fn authorize(keys: Option<&ApiKeys>, key: Option<&str>) -> Result<String, ServiceError> {
+    let keys = keys.ok_or_else(|| ServiceError::Unauthorized(String::from("API keys are unavailable")))?;
+    let key = key.filter(|key| !key.is_empty())
+        .ok_or_else(|| ServiceError::Unauthorized(String::from("an API key is required")))?;
+    keys.caller(key).map(String::from).ok_or_else(|| ServiceError::Unauthorized(String::from("unknown API key")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> ApiKeys {
+        ApiKeys::parse(&format!(r#"{{"{}": "label-printing"}}"#, digest("secret-key").to_uppercase())).ok().unwrap()
+    }
+
+    #[test]
+    fn authorizes_known_keys_only() {
+        assert_eq!(String::from("label-printing"), authorize(Some(&keys()), Some("secret-key")).ok().unwrap());
+        assert_eq!(true, authorize(Some(&keys()), Some("other-key")).is_err());
+        assert_eq!(true, authorize(Some(&keys()), None).is_err());
+        assert_eq!(true, authorize(None, Some("secret-key")).is_err());
+        assert_eq!(true, ApiKeys::parse("[]").is_err());
+    }
+
+    #[test]
+    fn keeps_the_loaded_keys_when_a_reload_fails() {
+        let cache = KeyCache::new();
+        let refresh = Duration::from_secs(60);
+        let start = Instant::now();
+        assert_eq!(true, cache.get(refresh, start, || Err(AwsError::HttpDispatch(String::from("down")))).is_none());
+        assert_eq!(true, cache.get(refresh, start, || Ok(keys())).is_some());
+        let cached = cache.get(refresh, start + Duration::from_secs(30), || panic!("reloaded before the interval"));
+        assert_eq!(Some("label-printing"), cached.as_ref().and_then(|keys| keys.caller("secret-key")));
+        let stale = cache.get(refresh, start + refresh, || Err(AwsError::HttpDispatch(String::from("down"))));
+        assert_eq!(true, stale.is_some());
+    }
+}
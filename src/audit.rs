@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+use rusoto_dynamodb::{DynamoDb, PutItemInput, AttributeValue};
+use crate::context::RequestContext;
+use crate::hashing::KeyHasher;
+use crate::store::{client_in, send, string_value, number_value, DEFAULT_REGION};
+
+/// One validation attempt or release as recorded in the audit table.
+#[derive(Debug, PartialEq)]
+pub struct AuditEntry {
+    pub request_id: String,
+    pub tenant_id: Option<String>,
+    pub serial_number: String,
+    /// `valid`, `invalid` or `error`, or `released` or `not_found` for a release.
+    pub outcome: &'static str,
+    /// Validation error codes, or the errorType when no answer could be given.
+    pub error_codes: Vec<String>,
+    pub bypass_token_id: Option<String>,
+    pub correlation_id: Option<String>,
+    /// Why the serial was released.
+    pub reason: Option<String>,
+    pub timestamp: u64
+}
+
+impl AuditEntry {
+    /// DynamoDB item for the entry, expiring `ttl_seconds` after it was recorded. The serial is
+    /// hashed with `hasher`, the item naming the version of the key of keyed hashes.
+    pub fn to_item(&self, ttl_seconds: u64, hasher: &KeyHasher) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+        item.insert(String::from("request_id"), string_value(&self.request_id));
+        item.insert(String::from("serial_hash"), string_value(&hasher.hash(&self.serial_number)));
+        if let Some(key_version) = hasher.key_version() {
+            item.insert(String::from("salt_version"), string_value(key_version));
+        }
+        item.insert(String::from("outcome"), string_value(self.outcome));
+        item.insert(String::from("error_codes"), AttributeValue {
+            l: Some(self.error_codes.iter().map(|code| string_value(code)).collect()),
+            ..Default::default()
+        });
+        item.insert(String::from("timestamp"), number_value(self.timestamp));
+        item.insert(String::from("expires_at"), number_value(self.timestamp + ttl_seconds));
+        if let Some(ref tenant_id) = self.tenant_id {
+            item.insert(String::from("tenant_id"), string_value(tenant_id));
+        }
+        if let Some(ref token_id) = self.bypass_token_id {
+            item.insert(String::from("bypass_token_id"), string_value(token_id));
+        }
+        if let Some(ref correlation_id) = self.correlation_id {
+            item.insert(String::from("correlation_id"), string_value(correlation_id));
+        }
+        if let Some(ref reason) = self.reason {
+            item.insert(String::from("reason"), string_value(reason));
+        }
+        item
+    }
+}
+
+/// Writes the entry on a background thread so the response is not held up by the audit table.
+/// `hasher`, whose key may have to be read, is called on that thread too; entries are not written
+/// while it returns `None`. Best effort: failures are logged with the request's context, and a
+/// write still in flight when the container is frozen resumes with the next invocation, to be
+/// abandoned after `timeout`.
+pub fn record_async<H>(context: &RequestContext, table_name: &str, ttl_seconds: u64, entry: AuditEntry, hasher: H, timeout: Duration)
+    where H: FnOnce() -> Option<KeyHasher> + Send + 'static
+{
+    let table_name = table_name.to_string();
+    let context = context.clone();
+    thread::spawn(move || {
+        let hasher = match hasher() {
+            Some(hasher) => hasher,
+            None => return context.log("audit entry not written: the hash key could not be read"),
+        };
+        let input = PutItemInput {
+            table_name,
+            item: entry.to_item(ttl_seconds, &hasher),
+            ..Default::default()
+        };
+        if let Err(error) = send(client_in(&DEFAULT_REGION).put_item(input), Some(timeout)) {
+            context.log(&format!("failed to write audit entry: {:?}", error));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::secrets::Secret;
+
+    fn test_entry() -> AuditEntry {
+        AuditEntry {
+            request_id: String::from("req-1"),
+            tenant_id: None,
+            serial_number: String::from("serial1"),
+            outcome: "invalid",
+            error_codes: vec![String::from("already_exists")],
+            bypass_token_id: None,
+            correlation_id: None,
+            reason: None,
+            timestamp: 1_000
+        }
+    }
+
+    #[test]
+    fn item_holds_the_hash_instead_of_the_serial() {
+        let item = test_entry().to_item(60, &KeyHasher::Sha256);
+        assert_eq!(Some(String::from("e1941afddc9c25b33e4d11f9d7d9223cde63a2fd63fb886218a03e22a7955054")), item["serial_hash"].s);
+        assert_eq!(false, item.values().any(|value| value.s == Some(String::from("serial1"))));
+    }
+
+    #[test]
+    fn keyed_items_name_the_key_version() {
+        let key = Secret { value: String::from("pepper"), version_id: String::from("v1") };
+        let hasher = KeyHasher::HmacSha256(Arc::new(key));
+        let item = test_entry().to_item(60, &hasher);
+        assert_eq!(Some(hasher.hash("serial1")), item["serial_hash"].s);
+        assert_eq!(Some(String::from("v1")), item["salt_version"].s);
+        assert_ne!(Some(KeyHasher::Sha256.hash("serial1")), item["serial_hash"].s);
+        assert_eq!(None, test_entry().to_item(60, &KeyHasher::Sha256).get("salt_version"));
+    }
+
+    #[test]
+    fn item_expires_after_the_ttl() {
+        let item = test_entry().to_item(60, &KeyHasher::Sha256);
+        assert_eq!(Some(String::from("1060")), item["expires_at"].n);
+        assert_eq!(Some(String::from("1000")), item["timestamp"].n);
+    }
+}
@@ -0,0 +1,295 @@
+//! Signed calls to AWS services that have no rusoto crate in this build.
+
+use std::fmt;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+use futures::{Future, Stream};
+use futures::stream::Wait;
+use rusoto_core::{ByteStream, Client, Region, CredentialsError, HttpDispatchError};
+use rusoto_core::request::{HttpResponse, BufferedHttpResponse};
+use rusoto_core::signature::SignedRequest;
+use url::form_urlencoded;
+
+#[derive(Debug)]
+pub enum AwsError {
+    Credentials(String),
+    HttpDispatch(String),
+    /// The call was abandoned once its timeout elapsed.
+    Timeout,
+    /// The service answered with an error status.
+    Service { status: u16, code: String, message: String },
+    /// The service answered, but not with what was expected.
+    MalformedResponse(String)
+}
+
+impl From<CredentialsError> for AwsError {
+    fn from(error: CredentialsError) -> AwsError {
+        AwsError::Credentials(error.to_string())
+    }
+}
+
+impl From<HttpDispatchError> for AwsError {
+    fn from(error: HttpDispatchError) -> AwsError {
+        AwsError::HttpDispatch(error.to_string())
+    }
+}
+
+impl fmt::Display for AwsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AwsError::Credentials(ref message) => write!(f, "credentials: {}", message),
+            AwsError::HttpDispatch(ref message) => write!(f, "dispatch: {}", message),
+            AwsError::Timeout => write!(f, "timed out"),
+            AwsError::Service { status, ref code, ref message } => write!(f, "{} ({}): {}", code, status, message),
+            AwsError::MalformedResponse(ref message) => write!(f, "malformed response: {}", message),
+        }
+    }
+}
+
+fn buffer(response: HttpResponse) -> Box<dyn Future<Item=BufferedHttpResponse, Error=AwsError> + Send> {
+    Box::new(response.buffer().from_err())
+}
+
+fn unbuffered(response: HttpResponse) -> Box<dyn Future<Item=HttpResponse, Error=AwsError> + Send> {
+    Box::new(futures::future::ok(response))
+}
+
+fn dispatch(request: SignedRequest, timeout: Option<Duration>) -> Result<BufferedHttpResponse, AwsError> {
+    dispatch_with(&Client::shared(), request, timeout)
+}
+
+/// Like `dispatch`, signing with the credentials of `client`.
+fn dispatch_with(client: &Client, request: SignedRequest, timeout: Option<Duration>) -> Result<BufferedHttpResponse, AwsError> {
+    let mut future = client.sign_and_dispatch(request, buffer);
+    if let Some(timeout) = timeout {
+        future.set_timeout(timeout);
+    }
+    let started = Instant::now();
+    future.sync().map_err(|error| match error {
+        AwsError::HttpDispatch(_) if elapsed(started, timeout) => AwsError::Timeout,
+        error => error,
+    })
+}
+
+/// Whether a call started at `started` has used up `timeout`, telling a call rusoto abandoned
+/// from one that failed to dispatch, which its errors do not.
+pub fn elapsed(started: Instant, timeout: Option<Duration>) -> bool {
+    timeout.is_some_and(|timeout| started.elapsed() >= timeout)
+}
+
+/// Calls an operation of a JSON protocol service, e.g. `call_json("events", "AWSEvents.PutEvents", "1.1", ...)`.
+pub fn call_json(service: &str, target: &str, json_version: &str, region: &Region, payload: &serde_json::Value, timeout: Option<Duration>) -> Result<serde_json::Value, AwsError> {
+    let (status, body) = call_json_unchecked(&Client::shared(), service, target, json_version, region, payload, timeout)?;
+    if (200..300).contains(&status) {
+        return Ok(body);
+    }
+    Err(json_error(status, &body))
+}
+
+/// Error described by the body of a JSON protocol error response.
+pub fn json_error(status: u16, body: &serde_json::Value) -> AwsError {
+    let code = body.get("__type").and_then(|value| value.as_str()).unwrap_or("Unknown");
+    let message = body.get("message").or_else(|| body.get("Message")).and_then(|value| value.as_str()).unwrap_or("");
+    AwsError::Service {
+        status,
+        code: code.rsplit('#').next().unwrap_or(code).to_string(),
+        message: message.to_string()
+    }
+}
+
+/// Like `call_json`, but hands error responses back as they are, for callers that need more of
+/// them than the code and message. `client` signs the call, see `Client::shared` for the
+/// function's own credentials.
+pub fn call_json_unchecked(client: &Client, service: &str, target: &str, json_version: &str, region: &Region, payload: &serde_json::Value, timeout: Option<Duration>) -> Result<(u16, serde_json::Value), AwsError> {
+    let mut request = SignedRequest::new("POST", service, region, "/");
+    request.set_content_type(format!("application/x-amz-json-{}", json_version));
+    request.add_header("x-amz-target", target);
+    request.set_payload(Some(payload.to_string().into_bytes()));
+
+    let response = dispatch_with(client, request, timeout)?;
+    let body: serde_json::Value = serde_json::from_slice(&response.body).unwrap_or(serde_json::Value::Null);
+    Ok((response.status.as_u16(), body))
+}
+
+/// Sends `request` to a REST JSON protocol service, returning the response whatever its body;
+/// error statuses are turned into `AwsError::Service`.
+pub fn call_rest(mut request: SignedRequest, payload: Option<&serde_json::Value>, timeout: Option<Duration>) -> Result<BufferedHttpResponse, AwsError> {
+    if let Some(payload) = payload {
+        request.set_content_type(String::from("application/json"));
+        request.set_payload(Some(payload.to_string().into_bytes()));
+    }
+
+    let response = dispatch(request, timeout)?;
+    if response.status.is_success() {
+        return Ok(response);
+    }
+    let body: serde_json::Value = serde_json::from_slice(&response.body).unwrap_or(serde_json::Value::Null);
+    Err(json_error(response.status.as_u16(), &body))
+}
+
+/// Calls an action of a query protocol service, returning the raw XML response.
+pub fn call_query(service: &str, action: &str, version: &str, region: &Region, params: &[(&str, &str)], timeout: Option<Duration>) -> Result<String, AwsError> {
+    let mut form = form_urlencoded::Serializer::new(String::new());
+    form.append_pair("Action", action);
+    form.append_pair("Version", version);
+    for &(key, value) in params {
+        form.append_pair(key, value);
+    }
+
+    let mut request = SignedRequest::new("POST", service, region, "/");
+    request.set_content_type(String::from("application/x-www-form-urlencoded"));
+    request.set_payload(Some(form.finish().into_bytes()));
+
+    let response = dispatch(request, timeout)?;
+    let body = String::from_utf8_lossy(&response.body).into_owned();
+    if response.status.is_success() {
+        return Ok(body);
+    }
+    Err(AwsError::Service {
+        status: response.status.as_u16(),
+        code: xml_element(&body, "Code").unwrap_or("Unknown").to_string(),
+        message: xml_element(&body, "Message").unwrap_or("").to_string()
+    })
+}
+
+/// An S3 object with the ETag that conditional writes replacing it have to match.
+pub struct S3Object {
+    pub body: Vec<u8>,
+    pub etag: Option<String>
+}
+
+/// Path-style request for an S3 object, sent to the regional endpoint.
+fn s3_request(method: &str, region: &Region, bucket: &str, key: &str) -> SignedRequest {
+    let mut request = SignedRequest::new(method, "s3", region, &format!("/{}/{}", bucket, key));
+    request.set_hostname(Some(format!("s3.{}.amazonaws.com", region.name())));
+    request
+}
+
+fn s3_error(response: &BufferedHttpResponse) -> AwsError {
+    let body = String::from_utf8_lossy(&response.body);
+    AwsError::Service {
+        status: response.status.as_u16(),
+        code: xml_element(&body, "Code").unwrap_or("Unknown").to_string(),
+        message: xml_element(&body, "Message").unwrap_or("").to_string()
+    }
+}
+
+/// Reads an object, `None` when it does not exist.
+pub fn get_object(region: &Region, bucket: &str, key: &str, timeout: Option<Duration>) -> Result<Option<S3Object>, AwsError> {
+    let response = dispatch(s3_request("GET", region, bucket, key), timeout)?;
+    match response.status.as_u16() {
+        404 => Ok(None),
+        _ if response.status.is_success() => Ok(Some(S3Object {
+            etag: response.headers.get("etag").map(String::from),
+            body: response.body
+        })),
+        _ => Err(s3_error(&response)),
+    }
+}
+
+/// Writes an object if its current ETag is `etag`, or if it does not exist yet when `etag` is
+/// `None`. Returns `false` when the condition failed because another writer got there first.
+pub fn put_object(region: &Region, bucket: &str, key: &str, body: Vec<u8>, etag: Option<&str>, timeout: Option<Duration>) -> Result<bool, AwsError> {
+    let mut request = s3_request("PUT", region, bucket, key);
+    request.set_content_type(String::from("application/octet-stream"));
+    match etag {
+        Some(etag) => request.add_header("if-match", etag),
+        None => request.add_header("if-none-match", "*"),
+    }
+    request.set_payload(Some(body));
+
+    let response = dispatch(request, timeout)?;
+    match response.status.as_u16() {
+        // 409 when a concurrent conditional write is still in flight
+        412 | 409 => Ok(false),
+        _ if response.status.is_success() => Ok(true),
+        _ => Err(s3_error(&response)),
+    }
+}
+
+/// Writes an object, replacing whatever was stored under `key`.
+pub fn write_object(region: &Region, bucket: &str, key: &str, body: Vec<u8>, content_type: &str, timeout: Option<Duration>) -> Result<(), AwsError> {
+    let mut request = s3_request("PUT", region, bucket, key);
+    request.set_content_type(String::from(content_type));
+    request.set_payload(Some(body));
+
+    let response = dispatch(request, timeout)?;
+    if response.status.is_success() {
+        Ok(())
+    } else {
+        Err(s3_error(&response))
+    }
+}
+
+/// Body of an S3 object, read chunk by chunk as it arrives rather than buffered whole.
+pub struct ObjectReader {
+    chunks: Wait<ByteStream>,
+    chunk: Vec<u8>,
+    position: usize
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.chunks.next() {
+                Some(chunk) => {
+                    self.chunk = chunk?;
+                    self.position = 0;
+                },
+                None => return Ok(0),
+            }
+        }
+        let length = buf.len().min(self.chunk.len() - self.position);
+        buf[..length].copy_from_slice(&self.chunk[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+/// Opens an object for reading. `timeout` bounds the wait for the response headers only.
+pub fn stream_object(region: &Region, bucket: &str, key: &str, timeout: Option<Duration>) -> Result<ObjectReader, AwsError> {
+    let mut future = Client::shared().sign_and_dispatch(s3_request("GET", region, bucket, key), unbuffered);
+    if let Some(timeout) = timeout {
+        future.set_timeout(timeout);
+    }
+    let response = future.sync()?;
+    if !response.status.is_success() {
+        let response = response.buffer().wait()?;
+        return Err(s3_error(&response));
+    }
+    Ok(ObjectReader { chunks: response.body.wait(), chunk: Vec::new(), position: 0 })
+}
+
+/// Text of the first `<name>` element of a query protocol response.
+pub fn xml_element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&close)? + start;
+    Some(&xml[start..end])
+}
+
+/// Region named in an ARN such as `arn:aws:sns:eu-central-1:123456789012:alerts`.
+pub fn arn_region(arn: &str) -> Option<Region> {
+    arn.split(':').nth(3).and_then(|region| region.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_xml_elements() {
+        let xml = "<ErrorResponse><Error><Code>Throttling</Code><Message>Rate exceeded</Message></Error></ErrorResponse>";
+        assert_eq!(Some("Throttling"), xml_element(xml, "Code"));
+        assert_eq!(Some("Rate exceeded"), xml_element(xml, "Message"));
+        assert_eq!(None, xml_element(xml, "RequestId"));
+    }
+
+    #[test]
+    fn reads_the_region_of_an_arn() {
+        assert_eq!(Some(Region::ApSoutheast1), arn_region("arn:aws:sns:ap-southeast-1:123456789012:alerts"));
+        assert_eq!(None, arn_region("alerts"));
+    }
+}
@@ -0,0 +1,96 @@
+//! Benchmarks of the validation path, built with `--features bench` and run with
+//! `cargo run --release --features bench -- --bench [filter]`. The store is a `MemoryStore`, so
+//! the numbers are those of the function's own code, without DynamoDB round trips.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput};
+
+use crate::config::Config;
+use crate::context::RequestContext;
+use crate::rules::{RuleSettings, ValidationStrategy, ValidatorRegistry};
+use crate::store::MemoryStore;
+use crate::{validate_serial, ResponseVersion, ValidationResult, VersionedResult};
+
+/// Serials registered in the store validated against.
+const REGISTERED: usize = 10_000;
+
+/// Batch sizes of the `batch` group.
+const BATCH_SIZES: [usize; 3] = [1, 10, 100];
+
+lazy_static! {
+    // the validations are not timed out, so the deadline goes unread
+    static ref CONTEXT: RequestContext = RequestContext::new(String::from("bench"), Instant::now());
+}
+
+pub fn run() {
+    let mut criterion = Criterion::default().configure_from_args();
+    validation(&mut criterion);
+    rules(&mut criterion);
+    serialization(&mut criterion);
+    batches(&mut criterion);
+    criterion.final_summary();
+}
+
+fn store() -> MemoryStore {
+    MemoryStore::new((0..REGISTERED).map(|n| format!("AB{:06}", n)).collect())
+}
+
+fn validate(serial_number: &str, store: &MemoryStore, config: &Config) -> ValidationResult {
+    validate_serial(serial_number, &CONTEXT, None, store, config, ValidationStrategy::CollectAll, None).ok().unwrap()
+}
+
+fn validation(criterion: &mut Criterion) {
+    let (store, config) = (store(), Config::default());
+    let mut group = criterion.benchmark_group("validate_serial");
+    group.bench_function("unique", |bench| bench.iter(|| validate(black_box("CD123456"), &store, &config)));
+    group.bench_function("already_exists", |bench| bench.iter(|| validate(black_box("AB000042"), &store, &config)));
+    group.bench_function("invalid_format", |bench| bench.iter(|| validate(black_box("AB-12"), &store, &config)));
+    group.finish();
+}
+
+fn rules(criterion: &mut Criterion) {
+    let pattern = ValidatorRegistry::from_names(&["pattern"], &RuleSettings {
+        pattern: Some(String::from("[A-Z]{2}[0-9]{6}")),
+        ..RuleSettings::default()
+    }).unwrap();
+    let template = ValidatorRegistry::from_names(&["template"], &RuleSettings {
+        templates: vec![String::from("AAA-####-XX"), String::from("AA######")],
+        ..RuleSettings::default()
+    }).unwrap();
+    let mut group = criterion.benchmark_group("rules");
+    group.bench_function("pattern", |bench| bench.iter(|| pattern.accepts(black_box("AB123456"))));
+    group.bench_function("template", |bench| bench.iter(|| template.accepts(black_box("AB123456"))));
+    group.bench_function("defaults", |bench| bench.iter(|| ValidatorRegistry::default().accepts(black_box("AB123456"))));
+    group.finish();
+}
+
+fn serialization(criterion: &mut Criterion) {
+    let (store, config) = (store(), Config::default());
+    let mut group = criterion.benchmark_group("serialize");
+    for &(name, version) in &[("legacy", ResponseVersion::Legacy), ("current", ResponseVersion::Current)] {
+        group.bench_function(name, |bench| bench.iter_batched(
+            || validate("AB000042", &store, &config),
+            |result| serde_json::to_string(&VersionedResult::new(result, version)).unwrap(),
+            BatchSize::SmallInput
+        ));
+    }
+    group.finish();
+}
+
+fn batches(criterion: &mut Criterion) {
+    let (store, config) = (store(), Config::default());
+    // every other serial registered
+    let serials: Vec<String> = (0..BATCH_SIZES[BATCH_SIZES.len() - 1]).map(|n| format!("{}{:06}", if n % 2 == 0 { "AB" } else { "CD" }, n)).collect();
+    let mut group = criterion.benchmark_group("batch");
+    for &size in &BATCH_SIZES {
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &serials[..size], |bench, serials| bench.iter(|| {
+            serials.iter()
+                .map(|serial_number| serde_json::to_string(&VersionedResult::new(validate(serial_number, &store, &config), ResponseVersion::Current)).unwrap())
+                .collect::<Vec<String>>()
+        }));
+    }
+    group.finish();
+}
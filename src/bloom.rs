@@ -0,0 +1,265 @@
+//! Bloom filter of registered serials, kept as a snapshot in S3 by the stream consumer.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use rusoto_core::Region;
+use sha2::{Digest, Sha256};
+
+use crate::aws::{self, AwsError};
+use crate::context::log_shared;
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"SBF1";
+const SNAPSHOT_HEADER_LENGTH: usize = 16;
+
+/// Where the snapshots live and how large new filters are made.
+#[derive(Clone, Debug)]
+pub struct BloomSettings {
+    pub bucket: String,
+    /// Prepended to the table name to form the snapshot's object key.
+    pub prefix: String,
+    /// Serials a new filter is sized for; beyond that false positives become more frequent.
+    pub expected_items: u64,
+    pub false_positive_rate: f64,
+    /// How often the validator reads the snapshot again.
+    pub refresh_interval: Duration
+}
+
+impl Default for BloomSettings {
+    fn default() -> BloomSettings {
+        BloomSettings {
+            bucket: String::new(),
+            prefix: String::from("bloom/"),
+            expected_items: 1_000_000,
+            false_positive_rate: 0.01,
+            refresh_interval: Duration::from_secs(300)
+        }
+    }
+}
+
+impl BloomSettings {
+    pub fn object_key(&self, table_name: &str) -> String {
+        format!("{}{}.bloom", self.prefix, table_name)
+    }
+}
+
+/// Answers "definitely not registered" or "maybe registered". Inserts take `&self` so a shared
+/// filter can learn about the serials its own container registers.
+#[derive(Debug)]
+pub struct BloomFilter {
+    words: Vec<AtomicU64>,
+    hash_count: u32
+}
+
+impl BloomFilter {
+    pub fn with_capacity(expected_items: u64, false_positive_rate: f64) -> BloomFilter {
+        let items = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-items * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let hash_count = ((bits as f64 / items) * ln2).round().max(1.0) as u32;
+        BloomFilter {
+            words: (0..bits.div_ceil(64)).map(|_| AtomicU64::new(0)).collect(),
+            hash_count
+        }
+    }
+
+    fn bit_count(&self) -> u64 {
+        self.words.len() as u64 * 64
+    }
+
+    /// Bit positions of `key`, derived from one SHA-256 by double hashing.
+    fn positions(&self, key: &str) -> impl Iterator<Item = u64> {
+        let digest = Sha256::digest(key.as_bytes());
+        let mut h1 = [0u8; 8];
+        let mut h2 = [0u8; 8];
+        h1.copy_from_slice(&digest[..8]);
+        h2.copy_from_slice(&digest[8..16]);
+        let h1 = u64::from_be_bytes(h1);
+        let h2 = u64::from_be_bytes(h2) | 1;
+        let bit_count = self.bit_count();
+        (0..u64::from(self.hash_count)).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bit_count)
+    }
+
+    pub fn insert(&self, key: &str) {
+        for position in self.positions(key) {
+            self.words[(position / 64) as usize].fetch_or(1 << (position % 64), Ordering::Relaxed);
+        }
+    }
+
+    /// `false` only for keys that were never inserted.
+    pub fn might_contain(&self, key: &str) -> bool {
+        self.positions(key).all(|position| self.words[(position / 64) as usize].load(Ordering::Relaxed) & (1 << (position % 64)) != 0)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SNAPSHOT_HEADER_LENGTH + self.words.len() * 8);
+        bytes.extend_from_slice(SNAPSHOT_MAGIC);
+        bytes.extend_from_slice(&self.hash_count.to_be_bytes());
+        bytes.extend_from_slice(&(self.words.len() as u64).to_be_bytes());
+        for word in &self.words {
+            bytes.extend_from_slice(&word.load(Ordering::Relaxed).to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<BloomFilter> {
+        if bytes.len() < SNAPSHOT_HEADER_LENGTH || &bytes[..4] != SNAPSHOT_MAGIC {
+            return None;
+        }
+        let mut hash_count = [0u8; 4];
+        hash_count.copy_from_slice(&bytes[4..8]);
+        let mut word_count = [0u8; 8];
+        word_count.copy_from_slice(&bytes[8..16]);
+        let word_count = u64::from_be_bytes(word_count) as usize;
+        let body = &bytes[SNAPSHOT_HEADER_LENGTH..];
+        if word_count == 0 || body.len() != word_count * 8 {
+            return None;
+        }
+        let words = body.chunks(8)
+            .map(|chunk| {
+                let mut word = [0u8; 8];
+                word.copy_from_slice(chunk);
+                AtomicU64::new(u64::from_be_bytes(word))
+            })
+            .collect();
+        Some(BloomFilter { words, hash_count: u32::from_be_bytes(hash_count).max(1) })
+    }
+}
+
+/// A snapshot read from S3, with the ETag its replacement has to match.
+pub struct Snapshot {
+    pub filter: BloomFilter,
+    pub etag: Option<String>
+}
+
+/// Reads the snapshot of `table_name`, `None` when there is none yet.
+pub fn load(settings: &BloomSettings, table_name: &str, timeout: Option<Duration>) -> Result<Option<Snapshot>, AwsError> {
+    let object = match aws::get_object(&Region::default(), &settings.bucket, &settings.object_key(table_name), timeout)? {
+        Some(object) => object,
+        None => return Ok(None),
+    };
+    let filter = BloomFilter::from_bytes(&object.body).ok_or_else(|| {
+        AwsError::MalformedResponse(format!("s3://{}/{} is not a bloom filter snapshot", settings.bucket, settings.object_key(table_name)))
+    })?;
+    Ok(Some(Snapshot { filter, etag: object.etag }))
+}
+
+/// Writes the snapshot unless another writer replaced the one read as `etag` (or created one
+/// when `etag` is `None`) in the meantime. Returns `false` when it lost that race.
+pub fn save(settings: &BloomSettings, table_name: &str, filter: &BloomFilter, etag: Option<&str>, timeout: Option<Duration>) -> Result<bool, AwsError> {
+    aws::put_object(&Region::default(), &settings.bucket, &settings.object_key(table_name), filter.to_bytes(), etag, timeout)
+}
+
+struct CachedFilter {
+    filter: Option<Arc<BloomFilter>>,
+    loaded_at: Instant
+}
+
+/// Snapshots loaded by this container, one per table, shared by every invocation it serves.
+pub struct FilterCache {
+    tables: Mutex<HashMap<String, CachedFilter>>
+}
+
+impl FilterCache {
+    pub fn new() -> FilterCache {
+        FilterCache { tables: Mutex::new(HashMap::new()) }
+    }
+
+    /// The table's filter, loaded again once it is `refresh_interval` old. `None` while there is no
+    /// snapshot or the last load failed: lookups then go to the store rather than trust a stale filter.
+    pub fn get<F>(&self, table_name: &str, refresh_interval: Duration, now: Instant, load: F) -> Option<Arc<BloomFilter>>
+        where F: FnOnce() -> Result<Option<BloomFilter>, AwsError>
+    {
+        let mut tables = self.tables.lock().unwrap();
+        if let Some(cached) = tables.get(table_name) {
+            if now.duration_since(cached.loaded_at) < refresh_interval {
+                return cached.filter.clone();
+            }
+        }
+        let filter = match load() {
+            Ok(Some(filter)) => Some(Arc::new(filter)),
+            Ok(None) => {
+                log_shared(&format!("no bloom filter snapshot of `{}` yet, lookups go to DynamoDB", table_name));
+                None
+            },
+            Err(error) => {
+                log_shared(&format!("bloom filter snapshot of `{}` could not be loaded, lookups go to DynamoDB: {}", table_name, error));
+                None
+            },
+        };
+        tables.insert(table_name.to_string(), CachedFilter { filter: filter.clone(), loaded_at: now });
+        filter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn never_misses_an_inserted_key() {
+        let filter = BloomFilter::with_capacity(1_000, 0.01);
+        let keys: Vec<String> = (0..1_000).map(|i| format!("SERIAL{}", i)).collect();
+        keys.iter().for_each(|key| filter.insert(key));
+        assert_eq!(true, keys.iter().all(|key| filter.might_contain(key)));
+    }
+
+    #[test]
+    fn rejects_most_unknown_keys() {
+        let filter = BloomFilter::with_capacity(1_000, 0.01);
+        (0..1_000).for_each(|i| filter.insert(&format!("SERIAL{}", i)));
+        let false_positives = (0..10_000).filter(|i| filter.might_contain(&format!("OTHER{}", i))).count();
+        assert_eq!(true, false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn snapshots_round_trip() {
+        let filter = BloomFilter::with_capacity(100, 0.01);
+        filter.insert("AB1234");
+        let restored = BloomFilter::from_bytes(&filter.to_bytes()).unwrap();
+        assert_eq!(true, restored.might_contain("AB1234"));
+        assert_eq!(filter.to_bytes(), restored.to_bytes());
+    }
+
+    fn snapshot_with(key: &str) -> Result<Option<BloomFilter>, AwsError> {
+        let filter = BloomFilter::with_capacity(100, 0.01);
+        filter.insert(key);
+        Ok(Some(filter))
+    }
+
+    #[test]
+    fn reloads_snapshots_after_the_refresh_interval() {
+        let cache = FilterCache::new();
+        let interval = Duration::from_secs(300);
+        let now = Instant::now();
+        let first = cache.get("assets", interval, now, || snapshot_with("AB1234")).unwrap();
+        assert_eq!(true, first.might_contain("AB1234"));
+
+        let cached = cache.get("assets", interval, now + Duration::from_secs(299), || panic!("loaded before the interval")).unwrap();
+        assert_eq!(true, Arc::ptr_eq(&first, &cached));
+
+        let refreshed = cache.get("assets", interval, now + interval, || snapshot_with("CD5678")).unwrap();
+        assert_eq!(true, refreshed.might_contain("CD5678"));
+    }
+
+    #[test]
+    fn drops_the_filter_when_a_refresh_fails() {
+        let cache = FilterCache::new();
+        let interval = Duration::from_secs(300);
+        let now = Instant::now();
+        cache.get("assets", interval, now, || snapshot_with("AB1234")).unwrap();
+        let failed = cache.get("assets", interval, now + interval, || Err(AwsError::HttpDispatch(String::from("timed out"))));
+        assert_eq!(true, failed.is_none());
+        assert_eq!(true, cache.get("assets", interval, now + interval, || panic!("retried before the interval")).is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_snapshots() {
+        assert_eq!(true, BloomFilter::from_bytes(b"garbage").is_none());
+        let mut bytes = BloomFilter::with_capacity(100, 0.01).to_bytes();
+        bytes.pop();
+        assert_eq!(true, BloomFilter::from_bytes(&bytes).is_none());
+    }
+}
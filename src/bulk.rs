@@ -0,0 +1,308 @@
+//! Bulk validation of serial manifests uploaded to S3. Each object named in an S3 event is read
+//! line by line, checked in chunks and answered with a results CSV and a JSON report.
+
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader};
+use std::time::{Duration, Instant};
+
+use rusoto_core::Region;
+use serde_derive::{Serialize, Deserialize};
+use url::percent_encoding::percent_decode;
+
+use crate::aws;
+use crate::context::RequestContext;
+use crate::error::ServiceError;
+use crate::results::{self, ResultsSettings, StoredResult};
+use crate::store::{normalize_serial, SerialStore};
+use crate::rules::ValidatorRegistry;
+use crate::ValidationError;
+
+/// Longest a single S3 request or store lookup may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time kept back from the invocation to upload the results of a job stopped by the deadline.
+const REPORT_UPLOAD_TIME: Duration = Duration::from_secs(20);
+
+/// Where results go and how many serials are looked up together.
+#[derive(Clone, Debug)]
+pub struct BulkSettings {
+    /// Bucket receiving the results, the manifest's own bucket when unset.
+    pub output_bucket: Option<String>,
+    /// Prepended to the manifest key to form the keys of the results. Manifests under it are
+    /// ignored, so results written to the watched bucket do not start another job.
+    pub output_prefix: String,
+    /// Serials validated per chunk; progress is logged after each one.
+    pub chunk_size: usize
+}
+
+impl Default for BulkSettings {
+    fn default() -> BulkSettings {
+        BulkSettings { output_bucket: None, output_prefix: String::from("bulk-results/"), chunk_size: 1000 }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct S3Event {
+    #[serde(rename = "Records")]
+    records: Vec<S3Record>
+}
+
+#[derive(Deserialize)]
+struct S3Record {
+    #[serde(rename = "awsRegion")]
+    aws_region: String,
+    s3: S3Entity
+}
+
+#[derive(Deserialize)]
+struct S3Entity {
+    bucket: S3Bucket,
+    object: S3ObjectKey
+}
+
+#[derive(Deserialize)]
+struct S3Bucket {
+    name: String
+}
+
+#[derive(Deserialize)]
+struct S3ObjectKey {
+    /// URL encoded, with spaces as `+`.
+    key: String
+}
+
+#[derive(Serialize)]
+pub struct BulkReport {
+    jobs: Vec<ManifestReport>
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct ManifestReport {
+    source: String,
+    /// Location of the results CSV with a `serialNumber,isValid,errors` row per serial.
+    results: String,
+    #[serde(rename = "linesRead")]
+    lines_read: u64,
+    valid: u64,
+    invalid: u64,
+    /// `false` when the invocation ran out of time; the results then cover the serials read so far.
+    complete: bool
+}
+
+/// Validates every manifest named in `event` against `store`, uploading the results of each and
+/// recording the state of each job in the results table, when there is one.
+pub fn run(event: &S3Event, context: &RequestContext, store: &dyn SerialStore, validators: &ValidatorRegistry, settings: &BulkSettings, results: Option<&ResultsSettings>) -> Result<BulkReport, ServiceError> {
+    let mut jobs = Vec::new();
+    for record in &event.records {
+        let key = object_key(&record.s3.object.key);
+        let output_bucket = settings.output_bucket.as_ref().unwrap_or(&record.s3.bucket.name);
+        if *output_bucket == record.s3.bucket.name && key.starts_with(&settings.output_prefix) {
+            continue;
+        }
+        let token = results::bulk_token(&record.s3.bucket.name, &key);
+        record_result(context, results, &StoredResult::pending(&token));
+        match run_manifest(record, &key, context, store, validators, settings) {
+            Ok(report) => {
+                record_result(context, results, &StoredResult::complete(&token, serde_json::to_value(&report).unwrap_or_default()));
+                jobs.push(report);
+            },
+            Err(error) => {
+                record_result(context, results, &StoredResult::failed(&token, &error.to_json()));
+                return Err(error);
+            },
+        }
+    }
+    Ok(BulkReport { jobs })
+}
+
+/// Best effort: a job whose state could not be recorded still runs, the failure is logged.
+fn record_result(context: &RequestContext, settings: Option<&ResultsSettings>, result: &StoredResult) {
+    if let Some(settings) = settings {
+        if let Err(error) = results::store(settings, result, crate::unix_now(), Some(REQUEST_TIMEOUT)) {
+            context.log(&format!("state of {} could not be recorded: {}", result.token, error));
+        }
+    }
+}
+
+fn run_manifest(record: &S3Record, key: &str, context: &RequestContext, store: &dyn SerialStore, validators: &ValidatorRegistry, settings: &BulkSettings) -> Result<ManifestReport, ServiceError> {
+    let region: Region = record.aws_region.parse().unwrap_or_default();
+    let bucket = &record.s3.bucket.name;
+    let output_bucket = settings.output_bucket.as_ref().unwrap_or(bucket);
+    let source = format!("s3://{}/{}", bucket, key);
+    let s3_error = |error: aws::AwsError| ServiceError::StoreUnavailable(format!("{}: {}", source, error));
+    let input = aws::stream_object(&region, bucket, key, Some(REQUEST_TIMEOUT)).map_err(s3_error)?;
+
+    let results_key = format!("{}{}.results.csv", settings.output_prefix, key);
+    let mut results = String::new();
+    let reading = RequestContext { deadline: context.deadline - REPORT_UPLOAD_TIME, ..context.clone() };
+    let mut report = validate_manifest(BufReader::new(input), &mut results, &reading, store, validators, settings, &source)?;
+    report.results = format!("s3://{}/{}", output_bucket, results_key);
+
+    aws::write_object(&region, output_bucket, &results_key, results.into_bytes(), "text/csv", Some(REQUEST_TIMEOUT)).map_err(s3_error)?;
+    let report_json = serde_json::to_vec(&report).unwrap_or_default();
+    let report_key = format!("{}{}.report.json", settings.output_prefix, key);
+    aws::write_object(&region, output_bucket, &report_key, report_json, "application/json", Some(REQUEST_TIMEOUT)).map_err(s3_error)?;
+    context.log(&format!("bulk validation of {} finished: {} valid, {} invalid, complete: {}", source, report.valid, report.invalid, report.complete));
+    Ok(report)
+}
+
+/// Object key of an S3 event record, which arrives URL encoded.
+fn object_key(encoded: &str) -> String {
+    percent_decode(encoded.replace('+', " ").as_bytes()).decode_utf8_lossy().into_owned()
+}
+
+/// Serial on a manifest line: the first column, trimmed and unquoted.
+pub fn manifest_serial(line: &str) -> &str {
+    line.split(',').next().unwrap_or("").trim().trim_matches('"').trim()
+}
+
+pub fn is_header(serial_number: &str) -> bool {
+    matches!(serial_number.to_lowercase().as_str(), "serial" | "serialnumber" | "serial_number")
+}
+
+/// Reads the manifest, appending a results row per serial to `results`. Stops early, reporting an
+/// incomplete job, once the deadline of `context` passes.
+fn validate_manifest<R: BufRead>(input: R, results: &mut String, context: &RequestContext, store: &dyn SerialStore, validators: &ValidatorRegistry, settings: &BulkSettings, source: &str) -> Result<ManifestReport, ServiceError> {
+    let mut report = ManifestReport { source: source.to_string(), complete: true, ..Default::default() };
+    results.push_str("serialNumber,isValid,errors\n");
+    let mut chunk = Vec::with_capacity(settings.chunk_size);
+    let mut seen = SeenSerials::default();
+    for line in input.split(b'\n') {
+        let line = line.map_err(|error| ServiceError::StoreUnavailable(format!("{}: {}", source, error)))?;
+        report.lines_read += 1;
+        let line = String::from_utf8_lossy(&line);
+        let serial_number = manifest_serial(&line);
+        if serial_number.is_empty() || (report.lines_read == 1 && is_header(serial_number)) {
+            continue;
+        }
+        chunk.push(serial_number.to_string());
+        if chunk.len() >= settings.chunk_size && !validate_chunk(&mut chunk, &mut seen, results, &mut report, context, store, validators)? {
+            return Ok(report);
+        }
+    }
+    validate_chunk(&mut chunk, &mut seen, results, &mut report, context, store, validators)?;
+    Ok(report)
+}
+
+/// Validates and drains `chunk`, returning `false` when the deadline passed before it could start.
+fn validate_chunk(chunk: &mut Vec<String>, seen: &mut SeenSerials, results: &mut String, report: &mut ManifestReport, context: &RequestContext, store: &dyn SerialStore, validators: &ValidatorRegistry) -> Result<bool, ServiceError> {
+    if chunk.is_empty() {
+        return Ok(true);
+    }
+    let timeout = match context.deadline.checked_duration_since(Instant::now()) {
+        Some(remaining) => remaining.min(REQUEST_TIMEOUT),
+        None => {
+            report.complete = false;
+            return Ok(false);
+        },
+    };
+
+    let rule_errors: Vec<Option<ValidationError>> = chunk.iter()
+        .map(|serial_number| if seen.repeats(serial_number) {
+            Some(ValidationError::DuplicateInRequest)
+        } else {
+            validators.blocking().find_map(|validator| validator.validate(serial_number))
+        })
+        .collect();
+    // repeated serials and those breaking a rule cannot be registered, so only the others are looked up
+    let lookups: Vec<&str> = chunk.iter().zip(&rule_errors)
+        .filter(|&(_, error)| error.is_none())
+        .map(|(serial_number, _)| serial_number.as_str())
+        .collect();
+    let mut found = store.contains_many(&lookups, Some(timeout)).map_err(ServiceError::from)?.into_iter();
+    for (serial_number, rule_error) in chunk.drain(..).zip(rule_errors) {
+        let error = if rule_error.is_some() {
+            rule_error
+        } else if found.next().unwrap_or(false) {
+            Some(ValidationError::AlreadyExists)
+        } else {
+            None
+        };
+        match error {
+            Some(_) => report.invalid += 1,
+            None => report.valid += 1,
+        }
+        results.push_str(&format!("{},{},{}\n", csv_field(&serial_number), error.is_none(), error.map(|error| error.value()).unwrap_or_default()));
+    }
+    context.log(&format!("bulk validation of {}: {} lines read, {} valid, {} invalid", report.source, report.lines_read, report.valid, report.invalid));
+    Ok(true)
+}
+
+/// Serials met so far in a manifest or import, normalized like the stored index, so that repeats
+/// are reported as `duplicate_in_request` instead of being looked up again.
+#[derive(Default)]
+pub struct SeenSerials(HashSet<String>);
+
+impl SeenSerials {
+    /// Whether `serial_number` was met before, remembering it otherwise.
+    pub fn repeats(&mut self, serial_number: &str) -> bool {
+        !self.0.insert(normalize_serial(serial_number))
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    fn settings(chunk_size: usize) -> BulkSettings {
+        BulkSettings { chunk_size, ..Default::default() }
+    }
+
+    fn context(deadline: Instant) -> RequestContext {
+        RequestContext::new(String::from("req-1"), deadline)
+    }
+
+    #[test]
+    fn validates_every_serial_of_a_manifest() {
+        let store = MemoryStore::new(vec![String::from("serial1")]);
+        let manifest = "serial_number,model\nAB1234,x1\nserial1,x1\n\n\"i2@4\",x2\nCD5678\n";
+        let mut results = String::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let report = validate_manifest(manifest.as_bytes(), &mut results, &context(deadline), &store, &ValidatorRegistry::default(), &settings(2), "s3://in/m.csv").ok().unwrap();
+        assert_eq!("serialNumber,isValid,errors\nAB1234,true,\nserial1,false,already_exists\ni2@4,false,invalid_format\nCD5678,true,\n", results);
+        assert_eq!(6, report.lines_read);
+        assert_eq!(2, report.valid);
+        assert_eq!(2, report.invalid);
+        assert_eq!(true, report.complete);
+    }
+
+    #[test]
+    fn reports_repeated_serials_once_looked_up() {
+        let store = MemoryStore::new(Vec::new());
+        let mut results = String::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let report = validate_manifest("AB1234\nCD5678\nab1234\nAB1234\n".as_bytes(), &mut results, &context(deadline), &store, &ValidatorRegistry::default(), &settings(2), "s3://in/m.csv").ok().unwrap();
+        assert_eq!("serialNumber,isValid,errors\nAB1234,true,\nCD5678,true,\nab1234,false,duplicate_in_request\nAB1234,false,duplicate_in_request\n", results);
+        assert_eq!((2, 2), (report.valid, report.invalid));
+    }
+
+    #[test]
+    fn stops_at_the_deadline() {
+        let store = MemoryStore::new(Vec::new());
+        let mut results = String::new();
+        let report = validate_manifest("AB1234\nCD5678\n".as_bytes(), &mut results, &context(Instant::now()), &store, &ValidatorRegistry::default(), &settings(1), "s3://in/m.csv").ok().unwrap();
+        assert_eq!(false, report.complete);
+        assert_eq!(0, report.valid);
+    }
+
+    #[test]
+    fn decodes_object_keys() {
+        assert_eq!("manifests/batch 7.csv", object_key("manifests/batch+7.csv"));
+        assert_eq!("manifests/a+b.csv", object_key("manifests/a%2Bb.csv"));
+    }
+
+    #[test]
+    fn reads_the_first_column() {
+        assert_eq!("AB1234", manifest_serial(" \"AB1234\" ,x1\r"));
+        assert_eq!(true, is_header(manifest_serial("serialNumber")));
+    }
+}
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use rusoto_core::Region;
+use serde_json::json;
+
+use crate::aws;
+use crate::error::ServiceError;
+
+/// Longest the callback to Step Functions may take.
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `SendTaskSuccess` or `SendTaskFailure` call reporting the outcome of a `.waitForTaskToken` task.
+pub fn task_callback<T: serde::Serialize>(task_token: &str, outcome: &Result<T, ServiceError>) -> (&'static str, serde_json::Value) {
+    match *outcome {
+        Ok(ref output) => ("AWSStepFunctions.SendTaskSuccess", json!({
+            "taskToken": task_token,
+            "output": serde_json::to_string(output).unwrap_or_default()
+        })),
+        Err(ref error) => ("AWSStepFunctions.SendTaskFailure", json!({
+            "taskToken": task_token,
+            "error": error.error_type(),
+            "cause": error.to_json()
+        })),
+    }
+}
+
+/// Reports the outcome to the waiting state machine.
+pub fn send_task_result<T: serde::Serialize>(task_token: &str, outcome: &Result<T, ServiceError>) -> Result<(), ServiceError> {
+    let (target, payload) = task_callback(task_token, outcome);
+    aws::call_json("states", target, "1.0", &Region::default(), &payload, Some(CALLBACK_TIMEOUT))
+        .map(|_| ())
+        .map_err(|error| ServiceError::CallbackFailed(error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_a_result_as_task_success() {
+        let (target, payload) = task_callback("token", &Ok(json!({"isValid": true})));
+        assert_eq!("AWSStepFunctions.SendTaskSuccess", target);
+        assert_eq!("token", payload["taskToken"]);
+        assert_eq!(r#"{"isValid":true}"#, payload["output"]);
+    }
+
+    #[test]
+    fn reports_an_error_as_task_failure() {
+        let outcome: Result<serde_json::Value, ServiceError> = Err(ServiceError::StoreThrottled(String::from("slow down")));
+        let (target, payload) = task_callback("token", &outcome);
+        assert_eq!("AWSStepFunctions.SendTaskFailure", target);
+        assert_eq!("StoreThrottled", payload["error"]);
+        assert_eq!(ServiceError::StoreThrottled(String::from("slow down")).to_json(), payload["cause"]);
+    }
+}
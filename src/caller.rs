@@ -0,0 +1,216 @@
+//! Who is calling, as established by API Gateway or the Function URL before the function runs, and
+//! the rule overrides `CALLER_RULE_OVERRIDES` and permissions `CALLER_PERMISSIONS` grant the
+//! caller's groups.
+
+use std::collections::HashMap;
+
+use serde_derive::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::rules::Severity;
+
+/// How the caller was authenticated.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CallerSource {
+    /// Claims of a JWT authorizer, e.g. a Cognito user pool.
+    Jwt,
+    /// SigV4 signed request to an `AWS_IAM` protected endpoint.
+    Iam,
+    /// Key checked against `API_KEYS_SECRET_ID` or `API_KEYS_PARAMETER`.
+    ApiKey
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CallerContext {
+    pub source: CallerSource,
+    /// `sub` claim, IAM user or role ARN, or the name an API key was handed out under.
+    pub principal: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// `cognito:groups` claim; IAM and API key callers belong to no group.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>
+}
+
+impl CallerContext {
+    /// Caller holding an API key handed out under `name`.
+    pub fn api_key(name: String) -> CallerContext {
+        CallerContext { source: CallerSource::ApiKey, principal: name, username: None, groups: Vec::new() }
+    }
+}
+
+/// `requestContext.authorizer` of a payload format 2.0 event.
+#[derive(Deserialize, Default)]
+pub struct Authorizer {
+    #[serde(default)]
+    jwt: Option<JwtAuthorizer>,
+    #[serde(default)]
+    iam: Option<IamAuthorizer>
+}
+
+#[derive(Deserialize)]
+struct JwtAuthorizer {
+    #[serde(default)]
+    claims: HashMap<String, Value>
+}
+
+#[derive(Deserialize)]
+struct IamAuthorizer {
+    #[serde(rename = "userArn", default)]
+    user_arn: Option<String>,
+    #[serde(rename = "callerId", default)]
+    caller_id: Option<String>
+}
+
+impl Authorizer {
+    /// The caller the authorizer let through, `None` when it established none.
+    pub fn caller(&self) -> Option<CallerContext> {
+        if let Some(ref jwt) = self.jwt {
+            let claim = |name: &str| jwt.claims.get(name).and_then(Value::as_str).map(String::from);
+            return Some(CallerContext {
+                source: CallerSource::Jwt,
+                principal: claim("sub")?,
+                username: claim("cognito:username").or_else(|| claim("username")),
+                groups: jwt.claims.get("cognito:groups").map(groups).unwrap_or_default()
+            });
+        }
+        let iam = self.iam.as_ref()?;
+        Some(CallerContext {
+            source: CallerSource::Iam,
+            principal: iam.user_arn.clone().or_else(|| iam.caller_id.clone())?,
+            username: None,
+            groups: Vec::new()
+        })
+    }
+}
+
+/// Groups of the `cognito:groups` claim, which HTTP APIs hand over flattened to `[a b]` rather
+/// than as a list.
+fn groups(claim: &Value) -> Vec<String> {
+    match *claim {
+        Value::Array(ref groups) => groups.iter().filter_map(Value::as_str).map(String::from).collect(),
+        Value::String(ref groups) => groups.trim_matches(|c| c == '[' || c == ']')
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|group| !group.is_empty())
+            .map(String::from)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Severity a group gets for a rule, `None` turning the rule off.
+pub type RuleOverride = (String, Option<Severity>);
+
+/// `CALLER_RULE_OVERRIDES` by group.
+#[derive(Clone, Default)]
+pub struct CallerPolicies {
+    overrides: Vec<(String, RuleOverride)>
+}
+
+impl CallerPolicies {
+    /// Overrides listed as `<group>:<rule>=<error|warning|off>`, e.g. `internal-tools:deprecated_prefix=off`.
+    pub fn parse<S: AsRef<str>>(entries: &[S]) -> Result<CallerPolicies, String> {
+        let overrides = entries.iter().map(|entry| {
+            let entry = entry.as_ref();
+            let malformed = || format!("expected <group>:<rule>=<error|warning|off>, got `{}`", entry);
+            let (group, rule) = entry.split_once(':').ok_or_else(malformed)?;
+            let (rule, level) = rule.split_once('=').ok_or_else(malformed)?;
+            let severity = match level.trim() {
+                "off" => None,
+                level => Some(Severity::parse(level).ok_or_else(malformed)?),
+            };
+            Ok((group.trim().to_string(), (rule.trim().to_string(), severity)))
+        }).collect::<Result<_, String>>()?;
+        Ok(CallerPolicies { overrides })
+    }
+
+    /// Overrides of the caller's groups, in the order they were listed, so later ones win.
+    pub fn overrides_for(&self, caller: &CallerContext) -> Vec<RuleOverride> {
+        self.overrides.iter()
+            .filter(|(group, _)| caller.groups.contains(group))
+            .map(|(_, rule_override)| rule_override.clone())
+            .collect()
+    }
+}
+
+/// Permission to see the owner, registration time and status of the item holding a serial that
+/// already exists.
+pub const INCLUDE_CONFLICT: &str = "includeConflict";
+
+/// Permission to load serials into the table with the `import` action.
+pub const IMPORT: &str = "import";
+
+const PERMISSIONS: [&str; 2] = [INCLUDE_CONFLICT, IMPORT];
+
+/// `CALLER_PERMISSIONS` by group.
+#[derive(Clone, Default)]
+pub struct CallerPermissions {
+    grants: Vec<(String, String)>
+}
+
+impl CallerPermissions {
+    /// Permissions listed as `<group>:<permission>`, e.g. `support:includeConflict`.
+    pub fn parse<S: AsRef<str>>(entries: &[S]) -> Result<CallerPermissions, String> {
+        let grants = entries.iter().map(|entry| {
+            let entry = entry.as_ref();
+            let (group, permission) = entry.split_once(':')
+                .filter(|(_, permission)| PERMISSIONS.contains(&permission.trim()))
+                .ok_or_else(|| format!("expected <group>:<{}>, got `{}`", PERMISSIONS.join("|"), entry))?;
+            Ok((group.trim().to_string(), permission.trim().to_string()))
+        }).collect::<Result<_, String>>()?;
+        Ok(CallerPermissions { grants })
+    }
+
+    /// Whether one of the caller's groups was granted `permission`.
+    pub fn allows(&self, caller: &CallerContext, permission: &str) -> bool {
+        self.grants.iter().any(|(group, granted)| granted == permission && caller.groups.contains(group))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn authorizer(value: Value) -> Authorizer {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn reads_cognito_claims() {
+        let caller = authorizer(json!({"jwt": {"claims": {"sub": "u-1", "cognito:username": "ana", "cognito:groups": "[internal-tools qa]"}, "scopes": null}})).caller().unwrap();
+        assert_eq!((CallerSource::Jwt, "u-1", Some("ana")), (caller.source, caller.principal.as_str(), caller.username.as_deref()));
+        assert_eq!(vec!["internal-tools", "qa"], caller.groups);
+        let caller = authorizer(json!({"jwt": {"claims": {"sub": "u-2", "cognito:groups": ["qa"]}}})).caller().unwrap();
+        assert_eq!(vec!["qa"], caller.groups);
+    }
+
+    #[test]
+    fn reads_iam_callers() {
+        let caller = authorizer(json!({"iam": {"userArn": "arn:aws:iam::123456789012:user/ci", "callerId": "AIDA"}})).caller().unwrap();
+        assert_eq!((CallerSource::Iam, "arn:aws:iam::123456789012:user/ci"), (caller.source, caller.principal.as_str()));
+        assert_eq!(None, Authorizer::default().caller());
+    }
+
+    #[test]
+    fn applies_the_overrides_of_the_callers_groups() {
+        let policies = CallerPolicies::parse(&["internal-tools:deprecated_prefix=off", "qa:checksum=warning", "internal-tools:blocklist=warning"]).ok().unwrap();
+        let caller = CallerContext { source: CallerSource::Jwt, principal: String::from("u-1"), username: None, groups: vec![String::from("internal-tools")] };
+        assert_eq!(vec![(String::from("deprecated_prefix"), None), (String::from("blocklist"), Some(Severity::Warning))], policies.overrides_for(&caller));
+        assert_eq!(true, CallerPolicies::parse(&["deprecated_prefix=off"]).is_err());
+        assert_eq!(true, CallerPolicies::parse(&["qa:checksum=maybe"]).is_err());
+    }
+
+    #[test]
+    fn grants_permissions_to_groups() {
+        let permissions = CallerPermissions::parse(&["support : includeConflict"]).ok().unwrap();
+        let caller = |group: &str| CallerContext { source: CallerSource::Jwt, principal: String::from("u-1"), username: None, groups: vec![String::from(group)] };
+        assert_eq!(true, permissions.allows(&caller("support"), INCLUDE_CONFLICT));
+        assert_eq!(false, permissions.allows(&caller("qa"), INCLUDE_CONFLICT));
+        assert_eq!(false, permissions.allows(&caller("support"), IMPORT));
+        assert_eq!(false, permissions.allows(&CallerContext::api_key(String::from("support")), INCLUDE_CONFLICT));
+        assert_eq!(true, CallerPermissions::parse(&["support:deleteEverything"]).is_err());
+        assert_eq!(true, CallerPermissions::parse(&["includeConflict"]).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+//! Command line use of the binary outside Lambda: `validate <serial> [--tenant <id>]` runs the
+//! full validation, DynamoDB lookup included, and prints the JSON result.
+
+use std::time::{Duration, Instant};
+
+use crate::context::RequestContext;
+use crate::{handle, Event, Response, ValidationEvent};
+
+const USAGE: &str = "usage: aws_validate_serial validate <serial> [--tenant <id>]";
+
+/// Time a validation gets, standing in for the function timeout.
+const VALIDATION_BUDGET: Duration = Duration::from_secs(30);
+
+/// Environment variable set by Lambda for the runtime API; the binary is a CLI when it is absent.
+pub const RUNTIME_API_VARIABLE: &str = "AWS_LAMBDA_RUNTIME_API";
+
+/// Exit code when the serial is valid.
+const EXIT_VALID: i32 = 0;
+/// Exit code when the serial was rejected.
+const EXIT_INVALID: i32 = 1;
+/// Exit code when no answer could be given or the arguments are wrong.
+const EXIT_ERROR: i32 = 2;
+
+/// Event described by the arguments following the program name.
+fn parse_args(args: &[String]) -> Result<ValidationEvent, String> {
+    let mut args = args.iter();
+    if args.next().map(String::as_str) != Some("validate") {
+        return Err(String::from(USAGE));
+    }
+    let serial_number = args.next().ok_or_else(|| String::from(USAGE))?;
+    let mut event = serde_json::json!({ "serialNumber": serial_number });
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--tenant", Some(tenant_id)) => event["tenantId"] = serde_json::json!(tenant_id),
+            _ => return Err(String::from(USAGE)),
+        }
+    }
+    serde_json::from_value(event).map_err(|error| error.to_string())
+}
+
+/// Runs the command, printing the result to stdout and failures to stderr. Returns the exit code.
+pub fn run(args: &[String]) -> i32 {
+    let event = match parse_args(args) {
+        Ok(event) => event,
+        Err(usage) => {
+            eprintln!("{}", usage);
+            return EXIT_ERROR;
+        },
+    };
+    let context = RequestContext::new(format!("cli-{:016x}", rand::random::<u64>()), Instant::now() + VALIDATION_BUDGET);
+    match handle(Event::Validation(Box::new(event)), &context) {
+        Ok(response) => {
+            println!("{}", serde_json::to_string_pretty(&response).unwrap_or_default());
+            match response {
+                Response::Validation(ref result) if !result.is_valid() => EXIT_INVALID,
+                _ => EXIT_VALID,
+            }
+        },
+        Err(error) => {
+            eprintln!("{}", error.to_json());
+            EXIT_ERROR
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| String::from(*arg)).collect()
+    }
+
+    #[test]
+    fn parses_the_validate_command() {
+        let event = parse_args(&args(&["validate", "AB1234", "--tenant", "acme"])).ok().unwrap();
+        assert_eq!("AB1234", event.serial_number);
+        assert_eq!(Some(String::from("acme")), event.tenant_id);
+        assert_eq!(None, parse_args(&args(&["validate", "AB1234"])).ok().unwrap().tenant_id);
+    }
+
+    #[test]
+    fn rejects_other_commands() {
+        assert_eq!(true, parse_args(&args(&["validate"])).is_err());
+        assert_eq!(true, parse_args(&args(&["register", "AB1234"])).is_err());
+        assert_eq!(true, parse_args(&args(&["validate", "AB1234", "--tenant"])).is_err());
+    }
+}
@@ -0,0 +1,518 @@
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+use rusoto_core::Region;
+
+use crate::alerts::DuplicateAlertSettings;
+use crate::api_keys::{ApiKeySettings, KeySource};
+use crate::bloom::BloomSettings;
+use crate::bulk::BulkSettings;
+use crate::caller::{CallerPermissions, CallerPolicies};
+use crate::context::log_shared;
+use crate::function_url::CorsSettings;
+use crate::generate::GeneratorSettings;
+use crate::hashing::HashingMode;
+use crate::messages::MessageSettings;
+use crate::metrics::MetricsSettings;
+use crate::rate_limit::RateLimitSettings;
+use crate::result_cache::ResultCacheSettings;
+use crate::results::ResultsSettings;
+use crate::rule_config::{RuleConfigSettings, RuleConfigSource};
+use crate::similarity::SimilaritySettings;
+use crate::rules::{self, Charset, InputGuard, RuleSettings, ValidationStrategy, ValidatorRegistry};
+use crate::store::{AssumeRoleSettings, CapacityBudget, CircuitBreakerSettings, DeletedPolicy, DynamoDbSettings, FailoverSettings, RangeSettings, ReplicaRoutingSettings, SequenceSettings};
+#[cfg(feature = "fault-injection")]
+use crate::store::FaultInjectionSettings;
+use crate::tenant::{self, TenantSettings};
+use crate::ResponseVersion;
+
+/// Runtime switches read from the Lambda function's environment variables.
+#[derive(Clone, Default)]
+pub struct Config {
+    /// `DEGRADE_ON_STORE_ERROR`: answer from the format checks alone when the store is unreachable.
+    pub degrade_on_store_error: bool,
+    /// `CIRCUIT_BREAKER_FAILURE_THRESHOLD`, `CIRCUIT_BREAKER_OPEN_MS` and `CIRCUIT_BREAKER_HALF_OPEN_PROBES`.
+    pub circuit_breaker: CircuitBreakerSettings,
+    /// `FAULT_INJECTION`: failures injected into the table store, none unless set.
+    #[cfg(feature = "fault-injection")]
+    pub fault_injection: Option<FaultInjectionSettings>,
+    /// `BYPASS_TOKEN_SECRET`: HMAC key for bypass tokens. Bypass tokens are rejected when unset.
+    pub bypass_token_secret: Option<String>,
+    /// `ADMIN_API_KEY`: key required by admin actions such as `issueBypassToken`.
+    pub admin_api_key: Option<String>,
+    /// `API_KEYS_SECRET_ID` or `API_KEYS_PARAMETER`, and `API_KEYS_REFRESH_SECONDS`: digests of the
+    /// keys callers have to present. Requests need no key when unset.
+    pub api_keys: Option<ApiKeySettings>,
+    /// `CALLER_RULE_OVERRIDES`: rule severities changed for callers in the listed groups.
+    pub caller_policies: CallerPolicies,
+    /// `CALLER_PERMISSIONS`: permissions granted to callers in the listed groups.
+    pub caller_permissions: CallerPermissions,
+    /// Whether the caller of the request may see who holds a serial that already exists, see
+    /// `caller::INCLUDE_CONFLICT`. Set per request, never from the environment.
+    pub include_conflict: bool,
+    /// `RATE_LIMIT_TABLE`, `RATE_LIMIT_BURST` and `RATE_LIMIT_PER_SECOND`: token buckets per caller
+    /// or address of HTTP requests, off unless a table is set.
+    pub rate_limit: Option<RateLimitSettings>,
+    /// `RESULTS_TABLE` and `RESULTS_TTL_SECONDS`: where `getResult` finds results, off unless set.
+    pub results: Option<ResultsSettings>,
+    /// `TABLE_NAME`, `PARTITION_KEY`, `SORT_KEY`, `PARTITION_VALUE`, `INDEX_NAME`, `INDEX_KEY`, `LOOKUP_CONCURRENCY`
+    /// the `SIMILARITY_*` index, the `CAPACITY_*_UNITS` budget and the `TABLE_ROLE_*` of a table in another account.
+    pub dynamodb: DynamoDbSettings,
+    /// `REPLICA_REGIONS`: Global Table replica regions to route reads between, fastest first.
+    pub replica_regions: Vec<Region>,
+    /// `REPLICA_PROBE_INTERVAL_MS` and `REPLICA_PROBE_TIMEOUT_MS`.
+    pub replica_routing: ReplicaRoutingSettings,
+    /// `PRIMARY_REGION` and `SECONDARY_REGION`: Global Table regions to fail over between, taking
+    /// precedence over `REPLICA_REGIONS`.
+    pub failover_regions: Option<(Region, Region)>,
+    /// `FAILOVER_FAILURE_THRESHOLD`, `FAILOVER_PROBE_INTERVAL_MS` and `FAILOVER_PROBE_TIMEOUT_MS`.
+    pub failover: FailoverSettings,
+    /// `TENANTS`: allowlist of tenants and where their serials live, see `tenant::parse_tenants`.
+    pub tenants: HashMap<String, TenantSettings>,
+    /// `AUDIT_TABLE`: table receiving an audit item per validation. Auditing is off when unset.
+    pub audit_table: Option<String>,
+    /// `AUDIT_TTL_DAYS`: how long audit items are kept, 90 days by default.
+    pub audit_ttl_seconds: u64,
+    /// `KEY_HASHING` and `HASH_KEY_SECRET_ID` (formerly `AUDIT_SALT_SECRET_ID`): how serials and
+    /// caller ids are hashed into the keys of the audit and rate limit tables, HMAC-SHA256 keyed with
+    /// the secret when one is set and SHA-256 otherwise.
+    pub key_hashing: HashingMode,
+    /// `SECRETS_REFRESH_SECONDS`: how long a container uses a secret before reading it again, 300
+    /// seconds by default.
+    pub secrets_refresh_interval: Duration,
+    /// `RESERVATION_TTL_SECONDS`: how long the `reserve` action holds a serial, 15 minutes by default.
+    pub reservation_ttl_seconds: u64,
+    /// `PUBLISH_EVENTS`: publish a `serial.validation.completed` event after each validation.
+    pub publish_events: bool,
+    /// `EVENT_BUS_NAME`: EventBridge bus receiving the events, `default` unless set.
+    pub event_bus_name: String,
+    /// `EVENT_SOURCE`: source of the published events, `serial-validation` unless set.
+    pub event_source: String,
+    /// `DUPLICATE_ALERT_TABLE`, `DUPLICATE_ALERT_TOPIC_ARN`, `DUPLICATE_ALERT_THRESHOLD` and
+    /// `DUPLICATE_ALERT_WINDOW_SECONDS`. Alerting is off unless both the table and topic are set.
+    pub duplicate_alerts: Option<DuplicateAlertSettings>,
+    /// `GENERATE_PREFIX`, `GENERATE_BODY_LENGTH`, `GENERATE_MAX_ATTEMPTS` and `IDEMPOTENCY_TTL_SECONDS` for the `generate` action.
+    pub generator: GeneratorSettings,
+    /// `SEQUENCE_TABLE` and `SEQUENCE_BLOCK_SIZE`: counters `generate` takes the body of serials
+    /// from instead of drawing it at random, off unless a table is set.
+    pub sequence: Option<SequenceSettings>,
+    /// `RANGES_TABLE` and `RANGES_REFRESH_SECONDS`: ranges reserved for a tenant or product line,
+    /// rejected with `range_conflict` for anyone else. Off unless a table is set.
+    pub ranges: Option<RangeSettings>,
+    /// `BLOOM_BUCKET` and friends: bloom filter snapshots of the tables, off unless a bucket is set.
+    pub bloom: Option<BloomSettings>,
+    /// `RESULT_CACHE_BUCKET` and friends: lookup results kept by the container, dropped through
+    /// the invalidation logs the stream consumer writes. Off unless a bucket is set.
+    pub result_cache: Option<ResultCacheSettings>,
+    /// `METRICS_NAMESPACE`, `METRICS_TENANTS` and `METRICS_MAX_RULE_SET_VERSIONS`:
+    /// stage timings written as EMF log lines, off unless a namespace is set.
+    pub metrics: Option<MetricsSettings>,
+    /// `BULK_OUTPUT_BUCKET`, `BULK_OUTPUT_PREFIX` and `BULK_CHUNK_SIZE` for manifests validated from S3 events.
+    pub bulk: BulkSettings,
+    /// `KINESIS_CONCURRENCY`: records of a Kinesis batch validated at once, 8 by default.
+    pub kinesis_concurrency: usize,
+    /// `VALIDATION_RULES`, `RULE_SEVERITIES`, `BLOCKLIST`, `SERIAL_PATTERN`, `SERIAL_TEMPLATES`,
+    /// `DEPRECATED_PREFIXES` and `ALLOWED_CHARSET`: the format rules, `length` and `alphanumeric` by default.
+    pub validators: ValidatorRegistry,
+    /// `RULES_APPCONFIG` or `RULES_PARAMETER`, and `RULES_POLL_SECONDS`: where a rule configuration
+    /// replacing the settings above is polled from.
+    pub rule_config: Option<RuleConfigSettings>,
+    /// Version of the rule configuration `validators` were built from, once one has been loaded.
+    pub rule_config_version: Option<String>,
+    /// `SHADOW_RULES_APPCONFIG` or `SHADOW_RULES_PARAMETER`: a candidate rule configuration, polled
+    /// like the one above, that validations are also judged by without it changing the response.
+    pub shadow_rule_config: Option<RuleConfigSettings>,
+    /// Rules built from the candidate configuration once it has been loaded, with its version.
+    pub shadow_validators: Option<(ValidatorRegistry, String)>,
+    /// `MAX_SERIAL_LENGTH`: longest serial, after trimming, let through to the rules and the store.
+    pub input_guard: InputGuard,
+    /// `VALIDATION_STRATEGY`: `collect_all` (the default) or `fail_fast`, unless the event names one.
+    pub validation_strategy: ValidationStrategy,
+    /// `RULE_UNIQUENESS=off`: validations leave the store alone and judge the format only.
+    pub skip_uniqueness: bool,
+    /// `DEFAULT_RESPONSE_VERSION`: shape of validation results for requests without `responseVersion`,
+    /// the current one unless set to `1`.
+    pub response_version: ResponseVersion,
+    /// `SIMILAR_SERIALS_LIMIT` and `SIMILAR_SERIALS_MAX_DISTANCE`: near-miss suggestions for serials
+    /// that already exist, read from the `SIMILARITY_INDEX_NAME` index.
+    pub similar_serials: SimilaritySettings,
+    /// `CORS_ALLOWED_ORIGINS`, `CORS_ALLOWED_METHODS`, `CORS_ALLOWED_HEADERS` and `CORS_MAX_AGE_SECONDS`
+    /// for Function URL requests. No CORS headers are sent unless origins are allowed.
+    pub cors: CorsSettings,
+    /// `WARMUP_MARKER`: field marking the payloads of scheduled warmers, `warmer` unless set.
+    pub warmup_marker: String,
+    /// `WARMUP_PRELOAD`: initialize the client and caches when warmed rather than on the next request.
+    pub warmup_preload: bool,
+    /// `STRICT_INPUT`: reject validation events holding fields they do not know with `unknown_field`
+    /// rather than ignoring them.
+    pub strict_input: bool,
+    /// `DEFAULT_LOCALE`, `ERROR_MESSAGES_BUCKET`, `ERROR_MESSAGES_KEY` and `ERROR_MESSAGES`: the
+    /// catalog of messages for error codes and where it is overridden.
+    pub messages: MessageSettings
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        let defaults = CircuitBreakerSettings::default();
+        let table_defaults = DynamoDbSettings::default();
+        let routing_defaults = ReplicaRoutingSettings::default();
+        let failover_defaults = FailoverSettings::default();
+        let generator_defaults = GeneratorSettings::default();
+        let bloom_defaults = BloomSettings::default();
+        let result_cache_defaults = ResultCacheSettings::default();
+        let metrics_defaults = MetricsSettings::default();
+        let bulk_defaults = BulkSettings::default();
+        let cors_defaults = CorsSettings::default();
+        let message_defaults = MessageSettings::default();
+        Config {
+            degrade_on_store_error: env_flag("DEGRADE_ON_STORE_ERROR"),
+            circuit_breaker: CircuitBreakerSettings {
+                failure_threshold: env_number("CIRCUIT_BREAKER_FAILURE_THRESHOLD", defaults.failure_threshold),
+                open_duration: Duration::from_millis(env_number("CIRCUIT_BREAKER_OPEN_MS", defaults.open_duration.as_millis() as u64)),
+                half_open_probes: env_number("CIRCUIT_BREAKER_HALF_OPEN_PROBES", defaults.half_open_probes)
+            },
+            #[cfg(feature = "fault-injection")]
+            fault_injection: env_string("FAULT_INJECTION").and_then(|entries| {
+                FaultInjectionSettings::parse(&parse_list(&entries)).map_err(|error| log_shared(&format!("ignoring malformed FAULT_INJECTION: {}", error))).ok()
+            }),
+            bypass_token_secret: env_string("BYPASS_TOKEN_SECRET"),
+            admin_api_key: env_string("ADMIN_API_KEY"),
+            api_keys: env_string("API_KEYS_SECRET_ID").map(KeySource::Secret)
+                .or_else(|| env_string("API_KEYS_PARAMETER").map(KeySource::Parameter))
+                .map(|source| ApiKeySettings { source, refresh_interval: Duration::from_secs(env_number("API_KEYS_REFRESH_SECONDS", 300)) }),
+            caller_policies: CallerPolicies::parse(&env_list("CALLER_RULE_OVERRIDES")).unwrap_or_else(|error| {
+                log_shared(&format!("ignoring malformed CALLER_RULE_OVERRIDES: {}", error));
+                CallerPolicies::default()
+            }),
+            caller_permissions: CallerPermissions::parse(&env_list("CALLER_PERMISSIONS")).unwrap_or_else(|error| {
+                log_shared(&format!("ignoring malformed CALLER_PERMISSIONS: {}", error));
+                CallerPermissions::default()
+            }),
+            include_conflict: false,
+            rate_limit: env_string("RATE_LIMIT_TABLE").map(|table_name| RateLimitSettings {
+                table_name,
+                burst: env_number("RATE_LIMIT_BURST", 20.0_f64).max(1.0),
+                per_second: env_number("RATE_LIMIT_PER_SECOND", 5.0_f64).max(0.001)
+            }),
+            results: env_string("RESULTS_TABLE").map(|table_name| ResultsSettings {
+                table_name,
+                ttl_seconds: env_number("RESULTS_TTL_SECONDS", 86_400)
+            }),
+            dynamodb: DynamoDbSettings {
+                table_name: env_string("TABLE_NAME").unwrap_or(table_defaults.table_name),
+                partition_key: env_string("PARTITION_KEY").unwrap_or(table_defaults.partition_key),
+                sort_key: env_string("SORT_KEY"),
+                partition_value: env_string("PARTITION_VALUE"),
+                index_name: env_string("INDEX_NAME"),
+                index_key: env_string("INDEX_KEY").unwrap_or(table_defaults.index_key),
+                key_prefix: table_defaults.key_prefix,
+                lookup_concurrency: env_number("LOOKUP_CONCURRENCY", table_defaults.lookup_concurrency).max(1),
+                similarity_index_name: env_string("SIMILARITY_INDEX_NAME"),
+                similarity_key: env_string("SIMILARITY_KEY").unwrap_or(table_defaults.similarity_key),
+                similarity_prefix_length: env_number("SIMILARITY_PREFIX_LENGTH", table_defaults.similarity_prefix_length).max(1),
+                owners_table_name: env_string("OWNERS_TABLE").unwrap_or(table_defaults.owners_table_name),
+                deleted_policy: env_deleted_policy(table_defaults.deleted_policy),
+                registered_index_name: env_string("REGISTERED_INDEX_NAME"),
+                registered_day_key: env_string("REGISTERED_DAY_KEY").unwrap_or(table_defaults.registered_day_key),
+                capacity_budget: CapacityBudget {
+                    read_units: env_string("CAPACITY_READ_UNITS").and_then(|value| value.trim().parse().ok()),
+                    write_units: env_string("CAPACITY_WRITE_UNITS").and_then(|value| value.trim().parse().ok())
+                },
+                assume_role: env_assume_role()
+            },
+            replica_regions: env_list("REPLICA_REGIONS").iter().filter_map(|region| region.parse().ok()).collect(),
+            replica_routing: ReplicaRoutingSettings {
+                probe_interval: Duration::from_millis(env_number("REPLICA_PROBE_INTERVAL_MS", routing_defaults.probe_interval.as_millis() as u64)),
+                probe_timeout: Duration::from_millis(env_number("REPLICA_PROBE_TIMEOUT_MS", routing_defaults.probe_timeout.as_millis() as u64))
+            },
+            failover_regions: env_failover_regions(),
+            failover: FailoverSettings {
+                failure_threshold: env_number("FAILOVER_FAILURE_THRESHOLD", failover_defaults.failure_threshold).max(1),
+                probe_interval: Duration::from_millis(env_number("FAILOVER_PROBE_INTERVAL_MS", failover_defaults.probe_interval.as_millis() as u64)),
+                probe_timeout: Duration::from_millis(env_number("FAILOVER_PROBE_TIMEOUT_MS", failover_defaults.probe_timeout.as_millis() as u64))
+            },
+            tenants: env_string("TENANTS").map(|value| tenant::parse_tenants(&value).unwrap_or_else(|error| {
+                log_shared(&format!("ignoring malformed TENANTS: {}", error));
+                HashMap::new()
+            })).unwrap_or_default(),
+            audit_table: env_string("AUDIT_TABLE"),
+            audit_ttl_seconds: env_number("AUDIT_TTL_DAYS", 90) * 24 * 60 * 60,
+            key_hashing: env_key_hashing(),
+            secrets_refresh_interval: Duration::from_secs(env_number("SECRETS_REFRESH_SECONDS", 300)),
+            reservation_ttl_seconds: env_number("RESERVATION_TTL_SECONDS", 15 * 60),
+            publish_events: env_flag("PUBLISH_EVENTS"),
+            event_bus_name: env_string("EVENT_BUS_NAME").unwrap_or_else(|| String::from("default")),
+            event_source: env_string("EVENT_SOURCE").unwrap_or_else(|| String::from("serial-validation")),
+            duplicate_alerts: match (env_string("DUPLICATE_ALERT_TABLE"), env_string("DUPLICATE_ALERT_TOPIC_ARN")) {
+                (Some(table_name), Some(topic_arn)) => Some(DuplicateAlertSettings {
+                    table_name,
+                    topic_arn,
+                    threshold: env_number("DUPLICATE_ALERT_THRESHOLD", 5),
+                    window_seconds: env_number("DUPLICATE_ALERT_WINDOW_SECONDS", 3600)
+                }),
+                _ => None
+            },
+            generator: GeneratorSettings {
+                prefix: env_string("GENERATE_PREFIX").unwrap_or_default(),
+                body_length: env_number("GENERATE_BODY_LENGTH", generator_defaults.body_length),
+                max_attempts: env_number("GENERATE_MAX_ATTEMPTS", generator_defaults.max_attempts),
+                idempotency_ttl_seconds: env_number("IDEMPOTENCY_TTL_SECONDS", generator_defaults.idempotency_ttl_seconds)
+            },
+            sequence: env_string("SEQUENCE_TABLE").map(|table_name| SequenceSettings {
+                table_name,
+                block_size: env_number("SEQUENCE_BLOCK_SIZE", 1_u64).max(1)
+            }),
+            ranges: env_string("RANGES_TABLE").map(|table_name| RangeSettings {
+                table_name,
+                refresh_interval: Duration::from_secs(env_number("RANGES_REFRESH_SECONDS", 300))
+            }),
+            bloom: env_string("BLOOM_BUCKET").map(|bucket| BloomSettings {
+                bucket,
+                prefix: env_string("BLOOM_PREFIX").unwrap_or(bloom_defaults.prefix),
+                expected_items: env_number("BLOOM_EXPECTED_ITEMS", bloom_defaults.expected_items),
+                false_positive_rate: env_number("BLOOM_FALSE_POSITIVE_RATE", bloom_defaults.false_positive_rate),
+                refresh_interval: Duration::from_secs(env_number("BLOOM_REFRESH_SECONDS", bloom_defaults.refresh_interval.as_secs()))
+            }),
+            result_cache: env_string("RESULT_CACHE_BUCKET").map(|bucket| ResultCacheSettings {
+                bucket,
+                prefix: env_string("RESULT_CACHE_PREFIX").unwrap_or(result_cache_defaults.prefix),
+                ttl: Duration::from_secs(env_number("RESULT_CACHE_TTL_SECONDS", result_cache_defaults.ttl.as_secs())),
+                poll_interval: Duration::from_secs(env_number("RESULT_CACHE_POLL_SECONDS", result_cache_defaults.poll_interval.as_secs())),
+                max_entries: env_number("RESULT_CACHE_MAX_ENTRIES", result_cache_defaults.max_entries),
+                log_length: env_number("RESULT_CACHE_LOG_LENGTH", result_cache_defaults.log_length).max(1)
+            }),
+            metrics: env_string("METRICS_NAMESPACE").map(|namespace| MetricsSettings {
+                namespace,
+                tenants: env_list("METRICS_TENANTS"),
+                max_rule_set_versions: env_number("METRICS_MAX_RULE_SET_VERSIONS", metrics_defaults.max_rule_set_versions)
+            }),
+            bulk: BulkSettings {
+                output_bucket: env_string("BULK_OUTPUT_BUCKET"),
+                output_prefix: env_string("BULK_OUTPUT_PREFIX").unwrap_or(bulk_defaults.output_prefix),
+                chunk_size: env_number("BULK_CHUNK_SIZE", bulk_defaults.chunk_size).max(1)
+            },
+            kinesis_concurrency: env_number("KINESIS_CONCURRENCY", 8),
+            validators: build_env_validators(),
+            rule_config: env_rule_config_source("RULES").map(|source| RuleConfigSettings {
+                source,
+                poll_interval: Duration::from_secs(env_number("RULES_POLL_SECONDS", 60))
+            }),
+            rule_config_version: None,
+            shadow_rule_config: env_rule_config_source("SHADOW_RULES").map(|source| RuleConfigSettings {
+                source,
+                poll_interval: Duration::from_secs(env_number("RULES_POLL_SECONDS", 60))
+            }),
+            shadow_validators: None,
+            input_guard: InputGuard { max_length: env_number("MAX_SERIAL_LENGTH", rules::DEFAULT_MAX_SERIAL_LENGTH).max(1) },
+            validation_strategy: env_string("VALIDATION_STRATEGY").map(|name| ValidationStrategy::parse(&name).unwrap_or_else(|| {
+                log_shared(&format!("ignoring unknown VALIDATION_STRATEGY `{}`", name));
+                ValidationStrategy::default()
+            })).unwrap_or_default(),
+            skip_uniqueness: env_toggle("RULE_UNIQUENESS") == Some(false),
+            response_version: env_string("DEFAULT_RESPONSE_VERSION").map(|value| {
+                value.trim().parse().ok().and_then(ResponseVersion::parse).unwrap_or_else(|| {
+                    log_shared(&format!("ignoring unknown DEFAULT_RESPONSE_VERSION `{}`", value));
+                    ResponseVersion::default()
+                })
+            }).unwrap_or_default(),
+            similar_serials: SimilaritySettings {
+                limit: env_number("SIMILAR_SERIALS_LIMIT", 3),
+                max_distance: env_number("SIMILAR_SERIALS_MAX_DISTANCE", 2)
+            },
+            cors: CorsSettings {
+                allowed_origins: env_list("CORS_ALLOWED_ORIGINS"),
+                allowed_methods: env_string("CORS_ALLOWED_METHODS").map(|value| parse_list(&value)).unwrap_or(cors_defaults.allowed_methods),
+                allowed_headers: env_string("CORS_ALLOWED_HEADERS").map(|value| parse_list(&value)).unwrap_or(cors_defaults.allowed_headers),
+                max_age_seconds: env_number("CORS_MAX_AGE_SECONDS", cors_defaults.max_age_seconds)
+            },
+            warmup_marker: env_string("WARMUP_MARKER").unwrap_or_else(|| String::from("warmer")),
+            warmup_preload: env_flag("WARMUP_PRELOAD"),
+            strict_input: env_flag("STRICT_INPUT"),
+            messages: MessageSettings {
+                default_locale: env_string("DEFAULT_LOCALE").unwrap_or(message_defaults.default_locale),
+                bucket: env_string("ERROR_MESSAGES_BUCKET"),
+                key: env_string("ERROR_MESSAGES_KEY").unwrap_or(message_defaults.key),
+                overrides: env_string("ERROR_MESSAGES")
+            }
+        }
+    }
+}
+
+/// The rules named in `VALIDATION_RULES`, falling back to the default rules when it is malformed.
+fn build_env_validators() -> ValidatorRegistry {
+    let (names, settings) = env_rules();
+    ValidatorRegistry::from_names(&names, &settings).unwrap_or_else(|error| {
+        log_shared(&format!("ignoring malformed VALIDATION_RULES: {}", error));
+        ValidatorRegistry::from_names(&rules::DEFAULT_RULES, &RuleSettings::default()).unwrap_or_default()
+    })
+}
+
+/// `VALIDATION_RULES` and the settings of the rules, which a rule configuration builds on.
+pub fn env_rules() -> (Vec<String>, RuleSettings) {
+    let names = env_list("VALIDATION_RULES");
+    let names = if names.is_empty() { rules::DEFAULT_RULES.iter().map(|name| name.to_string()).collect() } else { names };
+    let severities = rules::parse_severities(&env_list("RULE_SEVERITIES")).unwrap_or_else(|error| {
+        log_shared(&format!("ignoring malformed RULE_SEVERITIES: {}", error));
+        HashMap::new()
+    });
+    let charset = env_string("ALLOWED_CHARSET").map(|value| Charset::parse(&value).unwrap_or_else(|error| {
+        log_shared(&format!("ignoring malformed ALLOWED_CHARSET: {}", error));
+        Charset::default()
+    })).unwrap_or_default();
+    let settings = RuleSettings {
+        blocklist: env_list("BLOCKLIST"),
+        pattern: env_string("SERIAL_PATTERN"),
+        deprecated_prefixes: env_list("DEPRECATED_PREFIXES"),
+        charset,
+        templates: env_list("SERIAL_TEMPLATES"),
+        severities,
+        min_length: env_string("SERIAL_MIN_LENGTH").and_then(|value| value.trim().parse().ok()),
+        max_length: env_string("SERIAL_MAX_LENGTH").and_then(|value| value.trim().parse().ok()),
+        toggles: rules::ALL_RULES.iter()
+            .filter_map(|name| env_toggle(&format!("RULE_{}", name.to_uppercase())).map(|enabled| (name.to_string(), enabled)))
+            .collect()
+    };
+    (names, settings)
+}
+
+/// An `on` or `off` variable, `None` when it is unset or malformed.
+fn env_toggle(name: &str) -> Option<bool> {
+    let value = env_string(name)?;
+    parse_toggle(&value).or_else(|| {
+        log_shared(&format!("ignoring malformed {}: {}", name, value));
+        None
+    })
+}
+
+/// `KEY_HASHING`, defaulting to `hmac_sha256` when a key secret is set. An unusable mode falls back
+/// to SHA-256.
+fn env_key_hashing() -> HashingMode {
+    let secret_id = env_string("HASH_KEY_SECRET_ID").or_else(|| env_string("AUDIT_SALT_SECRET_ID"));
+    let name = env_string("KEY_HASHING").unwrap_or_else(|| String::from(if secret_id.is_some() { "hmac_sha256" } else { "sha256" }));
+    HashingMode::parse(&name, secret_id).unwrap_or_else(|error| {
+        log_shared(&format!("ignoring malformed KEY_HASHING: {}", error));
+        HashingMode::Sha256
+    })
+}
+
+/// `<prefix>_APPCONFIG`, or `<prefix>_PARAMETER` when it is unset.
+fn env_rule_config_source(prefix: &str) -> Option<RuleConfigSource> {
+    match env_string(&format!("{}_APPCONFIG", prefix)) {
+        Some(value) => RuleConfigSource::app_config(&value).map_err(|error| log_shared(&format!("ignoring malformed {}_APPCONFIG: {}", prefix, error))).ok(),
+        None => env_string(&format!("{}_PARAMETER", prefix)).map(RuleConfigSource::Parameter),
+    }
+}
+
+/// `DELETED_POLICY`, blocking serials for `DELETED_BLOCKED_DAYS` under `blocked_for_days`.
+fn env_deleted_policy(default: DeletedPolicy) -> DeletedPolicy {
+    match env_string("DELETED_POLICY") {
+        Some(name) => DeletedPolicy::parse(&name, env_number("DELETED_BLOCKED_DAYS", 30)).unwrap_or_else(|| {
+            log_shared(&format!("ignoring unknown DELETED_POLICY `{}`", name));
+            default
+        }),
+        None => default,
+    }
+}
+
+/// `TABLE_ROLE_ARN`, with `TABLE_ROLE_EXTERNAL_ID`, `TABLE_ROLE_SESSION_SECONDS` and `TABLE_ROLE_REFRESH_SECONDS`.
+fn env_assume_role() -> Option<AssumeRoleSettings> {
+    let defaults = AssumeRoleSettings::default();
+    env_string("TABLE_ROLE_ARN").map(|role_arn| {
+        // assumed with the session of the function's own role, which STS treats as role chaining and
+        // holds to sessions of 15 minutes to 1 hour
+        let session_seconds = env_number("TABLE_ROLE_SESSION_SECONDS", defaults.session_duration.as_secs()).clamp(900, 3_600);
+        AssumeRoleSettings {
+            role_arn,
+            external_id: env_string("TABLE_ROLE_EXTERNAL_ID"),
+            session_duration: Duration::from_secs(session_seconds),
+            refresh_margin: Duration::from_secs(env_number("TABLE_ROLE_REFRESH_SECONDS", defaults.refresh_margin.as_secs()).min(session_seconds / 2))
+        }
+    })
+}
+
+/// `PRIMARY_REGION` and `SECONDARY_REGION`, which are only used together.
+fn env_failover_regions() -> Option<(Region, Region)> {
+    let region = |name: &str| env_string(name).and_then(|region| region.parse::<Region>()
+        .map_err(|error| log_shared(&format!("ignoring malformed {}: {}", name, error)))
+        .ok());
+    match (region("PRIMARY_REGION"), region("SECONDARY_REGION")) {
+        (Some(primary), Some(secondary)) => Some((primary, secondary)),
+        (None, None) => None,
+        _ => {
+            log_shared("ignoring PRIMARY_REGION and SECONDARY_REGION, failover needs both");
+            None
+        },
+    }
+}
+
+fn env_flag(name: &str) -> bool {
+    match env::var(name) {
+        Ok(value) => parse_flag(&value),
+        Err(_) => false
+    }
+}
+
+fn env_string(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Reads a comma separated variable, skipping empty entries.
+fn env_list(name: &str) -> Vec<String> {
+    env_string(name).map(|value| parse_list(&value)).unwrap_or_default()
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect()
+}
+
+/// Reads a numeric variable, falling back to `default` when it is unset or malformed.
+fn env_number<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|value| value.trim().parse().ok()).unwrap_or(default)
+}
+
+fn parse_flag(value: &str) -> bool {
+    matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes" | "on")
+}
+
+/// Unlike `parse_flag`, tells switching off apart from a typo.
+fn parse_toggle(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_truthy_flags() {
+        assert_eq!(true, parse_flag("1"));
+        assert_eq!(true, parse_flag("true"));
+        assert_eq!(true, parse_flag(" TRUE "));
+    }
+
+    #[test]
+    fn parses_lists() {
+        assert_eq!(vec![String::from("eu-central-1"), String::from("ap-southeast-1")], parse_list("eu-central-1, ap-southeast-1,"));
+        assert_eq!(true, parse_list(" ").is_empty());
+    }
+
+    #[test]
+    fn parses_toggles() {
+        assert_eq!(Some(false), parse_toggle(" OFF "));
+        assert_eq!(Some(true), parse_toggle("on"));
+        assert_eq!(None, parse_toggle("of"));
+    }
+
+    #[test]
+    fn parses_other_values_as_disabled() {
+        assert_eq!(false, parse_flag("0"));
+        assert_eq!(false, parse_flag(""));
+        assert_eq!(false, parse_flag("disabled"));
+    }
+}
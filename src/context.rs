@@ -0,0 +1,117 @@
+//! What a request is handled with besides its event: who asked, for which tenant, in which
+//! language, by when the answer is due and the stores of its tables. Passed explicitly from the
+//! handler down to the log lines, audit items and events of the request.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use serde_json::{json, Map, Value};
+
+use crate::caller::CallerContext;
+use crate::config::Config;
+use crate::metrics::InvocationMetrics;
+use crate::store::{DynamoDbSettings, SerialStore};
+
+/// Builds the store of the table described by the settings, see `table_store`.
+pub type TableStores = fn(DynamoDbSettings, &Config) -> Box<dyn SerialStore>;
+
+#[derive(Clone, Debug)]
+pub struct RequestContext {
+    /// Lambda request id, or the event id of a stream record.
+    pub request_id: String,
+    /// When the request has to answer by, see `invocation_deadline`.
+    pub deadline: Instant,
+    pub tenant_id: Option<String>,
+    pub caller: Option<CallerContext>,
+    pub locale: Option<String>,
+    /// `correlationId` the caller sent, for stitching traces across services.
+    pub correlation_id: Option<String>,
+    /// Stage timings of the invocation, shared by every context narrowed from it.
+    pub metrics: Arc<InvocationMetrics>,
+    /// Stores of the tables the request reads and writes, DynamoDB unless a test stands in for it.
+    pub tables: TableStores
+}
+
+impl RequestContext {
+    /// Context of an invocation, before its event said more.
+    pub fn new(request_id: String, deadline: Instant) -> RequestContext {
+        RequestContext { request_id, deadline, tenant_id: None, caller: None, locale: None, correlation_id: None, metrics: Arc::default(), tables: crate::table_store }
+    }
+
+    /// This context reaching its tables through `tables`.
+    pub fn with_tables(mut self, tables: TableStores) -> RequestContext {
+        self.tables = tables;
+        self
+    }
+
+    /// This context narrowed to a request of `tenant_id` made by `caller`, keeping its correlation id.
+    pub fn for_request(&self, tenant_id: Option<&str>, caller: Option<CallerContext>, locale: Option<&str>) -> RequestContext {
+        RequestContext {
+            request_id: self.request_id.clone(),
+            deadline: self.deadline,
+            tenant_id: tenant_id.map(String::from),
+            caller,
+            locale: locale.map(String::from),
+            correlation_id: self.correlation_id.clone(),
+            metrics: self.metrics.clone(),
+            tables: self.tables
+        }
+    }
+
+    /// This context carrying `correlation_id`, when the request sent one.
+    pub fn correlated(mut self, correlation_id: Option<&str>) -> RequestContext {
+        if let Some(correlation_id) = correlation_id {
+            self.correlation_id = Some(correlation_id.to_string());
+        }
+        self
+    }
+
+    /// The context as carried by log lines and events; unknown fields are left out.
+    pub fn fields(&self) -> Value {
+        let mut fields = Map::new();
+        fields.insert(String::from("requestId"), Value::from(self.request_id.as_str()));
+        if let Some(ref tenant_id) = self.tenant_id {
+            fields.insert(String::from("tenantId"), Value::from(tenant_id.as_str()));
+        }
+        if let Some(ref caller) = self.caller {
+            fields.insert(String::from("caller"), Value::from(caller.principal.as_str()));
+        }
+        if let Some(ref locale) = self.locale {
+            fields.insert(String::from("locale"), Value::from(locale.as_str()));
+        }
+        if let Some(ref correlation_id) = self.correlation_id {
+            fields.insert(String::from("correlationId"), Value::from(correlation_id.as_str()));
+        }
+        Value::Object(fields)
+    }
+
+    /// Writes `message` to the function log as a JSON line carrying the context.
+    pub fn log(&self, message: &str) {
+        let mut line = self.fields();
+        line["message"] = json!(message);
+        eprintln!("{}", line);
+    }
+}
+
+/// Writes `message` about what the container shares between requests, such as its caches,
+/// routers and configuration, to the function log as a JSON line like `RequestContext::log`
+/// writes, naming no request.
+pub fn log_shared(message: &str) {
+    eprintln!("{}", json!({ "message": message }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carries_what_is_known_of_the_request() {
+        let invocation = RequestContext::new(String::from("req-1"), Instant::now());
+        assert_eq!(json!({"requestId": "req-1"}), invocation.fields());
+        let request = invocation.for_request(Some("acme"), Some(CallerContext::api_key(String::from("ci"))), Some("de"));
+        assert_eq!(json!({"requestId": "req-1", "tenantId": "acme", "caller": "ci", "locale": "de"}), request.fields());
+        assert_eq!(invocation.deadline, request.deadline);
+        let correlated = request.correlated(Some("trace-9")).for_request(None, None, None);
+        assert_eq!(json!({"requestId": "req-1", "correlationId": "trace-9"}), correlated.fields());
+    }
+}
@@ -0,0 +1,39 @@
+//! The check character closing generated serials, which the `checksum` rule verifies.
+
+/// Characters of the random part of a serial. Every one of them passes the alphanumeric rule.
+pub const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// Luhn mod 36 check character over `value`, catching single character typos and most swaps.
+pub fn check_character(value: &str) -> char {
+    let base = ALPHABET.len() as u32;
+    let sum: u32 = value.to_uppercase().chars().rev()
+        .filter_map(|c| ALPHABET.iter().position(|&a| a as char == c))
+        .enumerate()
+        .map(|(index, code)| {
+            let factor = if index % 2 == 0 { 2 } else { 1 };
+            let addend = code as u32 * factor;
+            addend / base + addend % base
+        })
+        .sum();
+    ALPHABET[((base - sum % base) % base) as usize] as char
+}
+
+/// Whether the last character of `serial_number` is the check character of the rest.
+pub fn has_valid_check_character(serial_number: &str) -> bool {
+    match serial_number.char_indices().last() {
+        Some((index, last)) => check_character(&serial_number[..index]) == last.to_ascii_uppercase(),
+        None => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_character_detects_a_typo() {
+        let check = check_character("AB12345");
+        assert_eq!(false, has_valid_check_character(&format!("AB12346{}", check)));
+        assert_eq!(true, has_valid_check_character(&format!("AB12345{}", check)));
+    }
+}
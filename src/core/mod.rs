@@ -0,0 +1,60 @@
+//! The validation rules without anything AWS or Lambda around them, for services applying the
+//! same rules elsewhere. Built on its own as the `serial_validation` library when the default
+//! `lambda` feature is off; the function composes it with the stores and the runtime.
+
+pub mod checksum;
+pub mod ranges;
+pub mod rules;
+pub mod template;
+
+#[derive(Debug, PartialEq)]
+pub enum ValidationError {
+    InvalidFormat,
+    TooLong,
+    InvalidChecksum,
+    Blocklisted,
+    AlreadyExists,
+    Timeout,
+    InvalidBypassToken,
+    DeprecatedPrefix,
+    /// The serial appeared earlier in the same bulk request.
+    DuplicateInRequest,
+    /// Nothing but whitespace or control characters was given.
+    MissingSerial,
+    /// The serial falls inside a range reserved by another tenant or product line.
+    RangeConflict
+}
+
+impl ValidationError {
+    /// Every error, in the order they are documented.
+    pub const ALL: [ValidationError; 11] = [
+        ValidationError::InvalidFormat,
+        ValidationError::TooLong,
+        ValidationError::InvalidChecksum,
+        ValidationError::Blocklisted,
+        ValidationError::AlreadyExists,
+        ValidationError::Timeout,
+        ValidationError::InvalidBypassToken,
+        ValidationError::DeprecatedPrefix,
+        ValidationError::DuplicateInRequest,
+        ValidationError::MissingSerial,
+        ValidationError::RangeConflict
+    ];
+
+    /// Code the error is reported under in `errors`.
+    pub fn value(&self) -> String {
+        match *self {
+            ValidationError::InvalidFormat => String::from("invalid_format"),
+            ValidationError::TooLong => String::from("too_long"),
+            ValidationError::InvalidChecksum => String::from("invalid_checksum"),
+            ValidationError::Blocklisted => String::from("blocklisted"),
+            ValidationError::AlreadyExists => String::from("already_exists"),
+            ValidationError::Timeout => String::from("timeout"),
+            ValidationError::InvalidBypassToken => String::from("invalid_bypass_token"),
+            ValidationError::DeprecatedPrefix => String::from("deprecated_prefix"),
+            ValidationError::DuplicateInRequest => String::from("duplicate_in_request"),
+            ValidationError::MissingSerial => String::from("missing_serial"),
+            ValidationError::RangeConflict => String::from("range_conflict"),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Ranges of serials reserved ahead of production, e.g. `XK100000` to `XK199999` for one product
+//! line of one tenant. Serials inside a range belong to its owner; anyone else validating them is
+//! told so with `range_conflict`.
+
+use std::sync::Arc;
+
+use super::rules::Validator;
+use super::ValidationError;
+
+pub const RULE_RANGE: &str = "range";
+
+/// Who a range is reserved for.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RangeOwner {
+    pub tenant_id: Option<String>,
+    /// `None` reserves the range for every product line of the tenant.
+    pub product_line: Option<String>
+}
+
+impl RangeOwner {
+    /// Whether a request by `caller` may use serials reserved for this owner. A caller naming no
+    /// product line is only held to the tenant.
+    pub fn admits(&self, caller: &RangeOwner) -> bool {
+        self.tenant_id == caller.tenant_id && match (&self.product_line, &caller.product_line) {
+            (Some(reserved), Some(requested)) => reserved.eq_ignore_ascii_case(requested),
+            _ => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReservedRange {
+    /// First and last serial of the range, both inclusive, upper-cased and of the same length.
+    pub first: String,
+    pub last: String,
+    pub owner: RangeOwner
+}
+
+impl ReservedRange {
+    /// A range from `first` to `last`, `None` unless both are as long and `first` comes first.
+    pub fn new(first: &str, last: &str, owner: RangeOwner) -> Option<ReservedRange> {
+        let (first, last) = (first.trim().to_uppercase(), last.trim().to_uppercase());
+        if first.is_empty() || first.chars().count() != last.chars().count() || first > last {
+            return None;
+        }
+        Some(ReservedRange { first, last, owner })
+    }
+
+    /// Whether `serial_number` lies in the range. Serials are compared character by character,
+    /// ignoring case, and only with ranges of serials as long as they are.
+    pub fn contains(&self, serial_number: &str) -> bool {
+        let serial_number = serial_number.to_uppercase();
+        serial_number.chars().count() == self.first.chars().count()
+            && self.first <= serial_number && serial_number <= self.last
+    }
+}
+
+/// Rejects serials inside a range reserved for anyone but the caller. Built per request, since
+/// the outcome depends on who is asking.
+pub struct RangeRule {
+    ranges: Arc<Vec<ReservedRange>>,
+    caller: RangeOwner
+}
+
+impl RangeRule {
+    pub fn new(ranges: Arc<Vec<ReservedRange>>, caller: RangeOwner) -> RangeRule {
+        RangeRule { ranges, caller }
+    }
+}
+
+impl Validator for RangeRule {
+    fn name(&self) -> &str {
+        RULE_RANGE
+    }
+
+    fn validate(&self, serial_number: &str) -> Option<ValidationError> {
+        let conflicting = self.ranges.iter()
+            .any(|range| range.contains(serial_number) && !range.owner.admits(&self.caller));
+        if conflicting { Some(ValidationError::RangeConflict) } else { None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owner(tenant_id: &str, product_line: Option<&str>) -> RangeOwner {
+        RangeOwner { tenant_id: Some(tenant_id.to_string()), product_line: product_line.map(String::from) }
+    }
+
+    #[test]
+    fn rejects_serials_reserved_for_someone_else() {
+        let ranges = Arc::new(vec![
+            ReservedRange::new("XK100000", "xk199999", owner("acme", Some("XK"))).unwrap(),
+            ReservedRange::new("ZZ000", "ZZ999", owner("globex", None)).unwrap()
+        ]);
+        let rule = |caller: RangeOwner| RangeRule::new(ranges.clone(), caller);
+
+        assert_eq!(Some(ValidationError::RangeConflict), rule(owner("globex", None)).validate("xk150000"));
+        assert_eq!(Some(ValidationError::RangeConflict), rule(owner("acme", Some("AB"))).validate("XK150000"));
+        assert_eq!(Some(ValidationError::RangeConflict), rule(RangeOwner::default()).validate("ZZ500"));
+        assert_eq!(None, rule(owner("acme", Some("xk"))).validate("XK199999"));
+        assert_eq!(None, rule(owner("acme", None)).validate("XK100000"));
+        assert_eq!(None, rule(owner("globex", Some("AB"))).validate("ZZ500"));
+        // outside the ranges, or of another length
+        assert_eq!(None, rule(owner("globex", None)).validate("XK200000"));
+        assert_eq!(None, rule(owner("globex", None)).validate("XK1500000"));
+
+        assert_eq!(None, ReservedRange::new("XK2", "XK1", RangeOwner::default()));
+        assert_eq!(None, ReservedRange::new("XK1", "XK10", RangeOwner::default()));
+    }
+}
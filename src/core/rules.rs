@@ -0,0 +1,714 @@
+//! Format rules applied to a serial before its uniqueness is checked. Each rule is a `Validator`;
+//! the registry holds the ones enabled by `VALIDATION_RULES`, in the order they are listed, with
+//! the severity each is reported with.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use regex::Regex;
+use sha2::{Digest, Sha256};
+
+use super::checksum;
+use super::template::{self, SegmentMismatch, Template};
+use super::ValidationError;
+
+pub const RULE_LENGTH: &str = "length";
+pub const RULE_ALPHANUMERIC: &str = "alphanumeric";
+pub const RULE_CHECKSUM: &str = "checksum";
+pub const RULE_BLOCKLIST: &str = "blocklist";
+pub const RULE_PATTERN: &str = "pattern";
+pub const RULE_DEPRECATED_PREFIX: &str = "deprecated_prefix";
+pub const RULE_TEMPLATE: &str = "template";
+
+/// Rules enabled when `VALIDATION_RULES` is unset.
+pub const DEFAULT_RULES: [&str; 2] = [RULE_LENGTH, RULE_ALPHANUMERIC];
+
+/// How the uniqueness check, which runs after the rules, is listed among them. `RULE_UNIQUENESS`
+/// switches it off.
+pub const UNIQUENESS_CHECK: &str = "uniqueness";
+
+/// Every rule a registry can be built with, each of which `RULE_<NAME>` switches on or off.
+pub const ALL_RULES: [&str; 7] = [RULE_LENGTH, RULE_ALPHANUMERIC, RULE_CHECKSUM, RULE_BLOCKLIST, RULE_PATTERN, RULE_DEPRECATED_PREFIX, RULE_TEMPLATE];
+
+/// Shortest serial accepted by the `length` rule unless `SERIAL_MIN_LENGTH` sets another.
+const MIN_SERIAL_LENGTH: usize = 6;
+
+/// Longest serial, after trimming, the rules run on when `MAX_SERIAL_LENGTH` is unset.
+pub const DEFAULT_MAX_SERIAL_LENGTH: usize = 128;
+
+/// What every serial goes through before any rule runs, and before it ends up in a key.
+#[derive(Clone, Copy, Debug)]
+pub struct InputGuard {
+    /// Characters allowed after trimming.
+    pub max_length: usize
+}
+
+impl Default for InputGuard {
+    fn default() -> InputGuard {
+        InputGuard { max_length: DEFAULT_MAX_SERIAL_LENGTH }
+    }
+}
+
+impl InputGuard {
+    /// `serial_number` without surrounding whitespace, or the error rejecting it: `missing_serial`
+    /// when nothing but whitespace or control characters is left, `too_long` past `max_length`
+    /// characters, `invalid_format` when it holds control characters.
+    pub fn sanitize<'a>(&self, serial_number: &'a str) -> Result<&'a str, ValidationError> {
+        let serial_number = serial_number.trim();
+        if serial_number.chars().all(char::is_control) {
+            return Err(ValidationError::MissingSerial);
+        }
+        // stops counting at the limit, however long the serial
+        if serial_number.chars().nth(self.max_length).is_some() {
+            return Err(ValidationError::TooLong);
+        }
+        if serial_number.chars().any(char::is_control) {
+            return Err(ValidationError::InvalidFormat);
+        }
+        Ok(serial_number)
+    }
+}
+
+pub trait Validator: Send + Sync {
+    /// Name the rule is enabled and lifted by bypass tokens under.
+    fn name(&self) -> &str;
+
+    /// The error `serial_number` violates the rule with, `None` when it passes.
+    fn validate(&self, serial_number: &str) -> Option<ValidationError>;
+
+    /// Severity unless `RULE_SEVERITIES` sets another.
+    fn default_severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    /// Which segment of a template `serial_number` failed, for rules checking templates.
+    fn segment_mismatch(&self, _serial_number: &str) -> Option<SegmentMismatch> {
+        None
+    }
+}
+
+/// Whether a failing rule invalidates the serial or is only reported.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    /// Reported in `errors`, making the serial invalid.
+    Error,
+    /// Reported in `warnings`, leaving `isValid` alone.
+    Warning
+}
+
+impl Severity {
+    pub fn parse(name: &str) -> Option<Severity> {
+        match name.trim() {
+            "error" => Some(Severity::Error),
+            "warning" => Some(Severity::Warning),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// Severities listed as `<rule>=<severity>`, e.g. `checksum=warning`.
+pub fn parse_severities<S: AsRef<str>>(entries: &[S]) -> Result<HashMap<String, Severity>, String> {
+    entries.iter().map(|entry| {
+        let entry = entry.as_ref();
+        let (name, severity) = entry.split_once('=').ok_or_else(|| format!("expected <rule>=<severity>, got `{}`", entry))?;
+        let severity = Severity::parse(severity).ok_or_else(|| format!("unknown severity `{}`, expected `error` or `warning`", severity.trim()))?;
+        Ok((name.trim().to_string(), severity))
+    }).collect()
+}
+
+/// Whether `serial_number` is long enough for the `length` rule at its default bounds.
+pub fn validate_serial_length(serial_number: &str) -> bool {
+    serial_number.chars().count() >= MIN_SERIAL_LENGTH
+}
+
+pub fn validate_serial_alphanumeric(serial_number: &str) -> bool {
+    serial_number.chars().all(char::is_alphanumeric)
+}
+
+struct LengthRule {
+    min_length: usize,
+    max_length: Option<usize>
+}
+
+impl Validator for LengthRule {
+    fn name(&self) -> &str {
+        RULE_LENGTH
+    }
+
+    fn validate(&self, serial_number: &str) -> Option<ValidationError> {
+        let length = serial_number.chars().count();
+        if length < self.min_length {
+            Some(ValidationError::InvalidFormat)
+        } else if self.max_length.is_some_and(|max_length| length > max_length) {
+            Some(ValidationError::TooLong)
+        } else {
+            None
+        }
+    }
+}
+
+/// Characters the `alphanumeric` rule accepts.
+#[derive(Clone, Debug, Default)]
+pub enum Charset {
+    /// `A`-`Z`, `a`-`z` and `0`-`9`, for label printers that handle nothing else.
+    AsciiAlphanumeric,
+    /// Letters and digits of any script.
+    #[default]
+    UnicodeAlphanumeric,
+    /// A regular expression character class such as `[A-HJ-NP-Z0-9]`, given as written and
+    /// compiled to match whole serials of its characters.
+    Class(String, Regex)
+}
+
+impl Charset {
+    /// `ascii_alphanumeric`, `unicode_alphanumeric` or a character class in brackets.
+    pub fn parse(value: &str) -> Result<Charset, String> {
+        match value.trim() {
+            "ascii_alphanumeric" => Ok(Charset::AsciiAlphanumeric),
+            "unicode_alphanumeric" => Ok(Charset::UnicodeAlphanumeric),
+            class if class.starts_with('[') && class.ends_with(']') => Regex::new(&format!("^{}*$", class))
+                .map(|pattern| Charset::Class(class.to_string(), pattern))
+                .map_err(|error| error.to_string()),
+            other => Err(format!("unknown charset `{}`, expected `ascii_alphanumeric`, `unicode_alphanumeric` or a character class like `[A-Z0-9]`", other)),
+        }
+    }
+
+    pub fn allows(&self, serial_number: &str) -> bool {
+        match *self {
+            Charset::AsciiAlphanumeric => serial_number.chars().all(|c| c.is_ascii_alphanumeric()),
+            Charset::UnicodeAlphanumeric => validate_serial_alphanumeric(serial_number),
+            Charset::Class(_, ref pattern) => pattern.is_match(serial_number),
+        }
+    }
+
+    fn name(&self) -> &str {
+        match *self {
+            Charset::AsciiAlphanumeric => "ascii_alphanumeric",
+            Charset::UnicodeAlphanumeric => "unicode_alphanumeric",
+            Charset::Class(ref class, _) => class,
+        }
+    }
+}
+
+struct AlphanumericRule {
+    charset: Charset
+}
+
+impl Validator for AlphanumericRule {
+    fn name(&self) -> &str {
+        RULE_ALPHANUMERIC
+    }
+
+    fn validate(&self, serial_number: &str) -> Option<ValidationError> {
+        if self.charset.allows(serial_number) { None } else { Some(ValidationError::InvalidFormat) }
+    }
+}
+
+/// Requires serials to match one of the `SERIAL_TEMPLATES`, see `template`.
+struct TemplateRule {
+    templates: Vec<Template>
+}
+
+impl Validator for TemplateRule {
+    fn name(&self) -> &str {
+        RULE_TEMPLATE
+    }
+
+    fn validate(&self, serial_number: &str) -> Option<ValidationError> {
+        self.segment_mismatch(serial_number).map(|_| ValidationError::InvalidFormat)
+    }
+
+    fn segment_mismatch(&self, serial_number: &str) -> Option<SegmentMismatch> {
+        template::closest_mismatch(&self.templates, serial_number)
+    }
+}
+
+/// Requires the Luhn mod 36 check character the `generate` action appends.
+struct ChecksumRule;
+
+impl Validator for ChecksumRule {
+    fn name(&self) -> &str {
+        RULE_CHECKSUM
+    }
+
+    fn validate(&self, serial_number: &str) -> Option<ValidationError> {
+        if checksum::has_valid_check_character(serial_number) { None } else { Some(ValidationError::InvalidChecksum) }
+    }
+}
+
+/// Rejects serials known to be bogus, e.g. placeholders printed on sample labels.
+struct BlocklistRule {
+    /// Upper-cased, so matching ignores case.
+    serial_numbers: Vec<String>
+}
+
+impl Validator for BlocklistRule {
+    fn name(&self) -> &str {
+        RULE_BLOCKLIST
+    }
+
+    fn validate(&self, serial_number: &str) -> Option<ValidationError> {
+        let serial_number = serial_number.to_uppercase();
+        if self.serial_numbers.contains(&serial_number) { Some(ValidationError::Blocklisted) } else { None }
+    }
+}
+
+/// Requires the whole serial to match a regular expression.
+struct PatternRule {
+    pattern: Regex
+}
+
+impl Validator for PatternRule {
+    fn name(&self) -> &str {
+        RULE_PATTERN
+    }
+
+    fn validate(&self, serial_number: &str) -> Option<ValidationError> {
+        if self.pattern.is_match(serial_number) { None } else { Some(ValidationError::InvalidFormat) }
+    }
+}
+
+/// Flags serials starting with a prefix that is being phased out. Only a warning unless configured otherwise.
+struct DeprecatedPrefixRule {
+    prefixes: Vec<String>
+}
+
+impl Validator for DeprecatedPrefixRule {
+    fn name(&self) -> &str {
+        RULE_DEPRECATED_PREFIX
+    }
+
+    fn validate(&self, serial_number: &str) -> Option<ValidationError> {
+        let serial_number = serial_number.to_uppercase();
+        if self.prefixes.iter().any(|prefix| serial_number.starts_with(prefix.as_str())) { Some(ValidationError::DeprecatedPrefix) } else { None }
+    }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Warning
+    }
+}
+
+/// Whether the uniqueness lookup still runs for serials that already failed a format rule.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ValidationStrategy {
+    /// Report every failure, looking the serial up even when a rule failed.
+    #[default]
+    CollectAll,
+    /// Skip the lookup once a rule failed, saving the round trip to DynamoDB.
+    FailFast
+}
+
+impl ValidationStrategy {
+    /// Strategy named `collect_all` or `fail_fast`.
+    pub fn parse(name: &str) -> Option<ValidationStrategy> {
+        match name.trim() {
+            "collect_all" => Some(ValidationStrategy::CollectAll),
+            "fail_fast" => Some(ValidationStrategy::FailFast),
+            _ => None,
+        }
+    }
+}
+
+/// What the configurable rules need besides their names.
+#[derive(Default)]
+pub struct RuleSettings {
+    /// `BLOCKLIST`: serials rejected by the `blocklist` rule.
+    pub blocklist: Vec<String>,
+    /// `SERIAL_PATTERN`: regular expression of the `pattern` rule, anchored at both ends.
+    pub pattern: Option<String>,
+    /// `DEPRECATED_PREFIXES`: prefixes flagged by the `deprecated_prefix` rule.
+    pub deprecated_prefixes: Vec<String>,
+    /// `ALLOWED_CHARSET`: characters of the `alphanumeric` rule.
+    pub charset: Charset,
+    /// `SERIAL_TEMPLATES`: formats of the `template` rule, such as `AAA-####-XX`.
+    pub templates: Vec<String>,
+    /// `RULE_SEVERITIES`: severities overriding the rules' defaults.
+    pub severities: HashMap<String, Severity>,
+    /// `SERIAL_MIN_LENGTH`: shortest serial the `length` rule accepts, 6 unless set.
+    pub min_length: Option<usize>,
+    /// `SERIAL_MAX_LENGTH`: longest serial the `length` rule accepts, any unless set.
+    pub max_length: Option<usize>,
+    /// `RULE_<NAME>`: rules switched on or off whatever the list of rules names.
+    pub toggles: HashMap<String, bool>
+}
+
+impl RuleSettings {
+    /// `names` without the rules switched off, followed by the rules switched on that it lacks.
+    fn toggled<'a, S: AsRef<str>>(&'a self, names: &'a [S]) -> Vec<&'a str> {
+        let mut enabled: Vec<&str> = names.iter().map(|name| name.as_ref()).filter(|name| self.toggles.get(*name) != Some(&false)).collect();
+        for &name in ALL_RULES.iter() {
+            if self.toggles.get(name) == Some(&true) && !enabled.contains(&name) {
+                enabled.push(name);
+            }
+        }
+        enabled
+    }
+}
+
+#[derive(Clone)]
+struct Rule {
+    // shared by the copies of a registry, so that copying one does not rebuild its rules
+    validator: Arc<dyn Validator>,
+    severity: Severity
+}
+
+/// The enabled rules, applied in order.
+#[derive(Clone)]
+pub struct ValidatorRegistry {
+    rules: Vec<Rule>,
+    /// Rule names and the settings they were built with, identifying the rule set.
+    fingerprint: String
+}
+
+impl Default for ValidatorRegistry {
+    fn default() -> ValidatorRegistry {
+        ValidatorRegistry::from_names(&DEFAULT_RULES, &RuleSettings::default()).unwrap_or_else(|_| ValidatorRegistry::empty())
+    }
+}
+
+impl ValidatorRegistry {
+    /// Builds the rules named in `names`, failing on unknown names or settings a rule lacks.
+    pub fn from_names<S: AsRef<str>>(names: &[S], settings: &RuleSettings) -> Result<ValidatorRegistry, String> {
+        let names = settings.toggled(names);
+        let mut registry = ValidatorRegistry::empty();
+        for &name in names.iter() {
+            let validator: Box<dyn Validator> = match name {
+                RULE_LENGTH => Box::new(LengthRule { min_length: settings.min_length.unwrap_or(MIN_SERIAL_LENGTH), max_length: settings.max_length }),
+                RULE_ALPHANUMERIC => Box::new(AlphanumericRule { charset: settings.charset.clone() }),
+                RULE_CHECKSUM => Box::new(ChecksumRule),
+                RULE_BLOCKLIST => Box::new(BlocklistRule {
+                    serial_numbers: settings.blocklist.iter().map(|serial_number| serial_number.to_uppercase()).collect()
+                }),
+                RULE_PATTERN => {
+                    let pattern = settings.pattern.as_ref().ok_or_else(|| String::from("the pattern rule needs SERIAL_PATTERN"))?;
+                    let pattern = Regex::new(&format!("^(?:{})$", pattern)).map_err(|error| error.to_string())?;
+                    Box::new(PatternRule { pattern })
+                },
+                RULE_DEPRECATED_PREFIX => {
+                    if settings.deprecated_prefixes.is_empty() {
+                        return Err(String::from("the deprecated_prefix rule needs DEPRECATED_PREFIXES"));
+                    }
+                    Box::new(DeprecatedPrefixRule { prefixes: settings.deprecated_prefixes.iter().map(|prefix| prefix.to_uppercase()).collect() })
+                },
+                RULE_TEMPLATE => {
+                    if settings.templates.is_empty() {
+                        return Err(String::from("the template rule needs SERIAL_TEMPLATES"));
+                    }
+                    Box::new(TemplateRule { templates: settings.templates.iter().map(|source| Template::parse(source)).collect::<Result<_, _>>()? })
+                },
+                unknown => return Err(format!("unknown rule `{}`", unknown)),
+            };
+            let severity = settings.severities.get(validator.name()).cloned().unwrap_or_else(|| validator.default_severity());
+            registry.register(validator, severity);
+        }
+        // a rule switched off keeps its severity for when it is switched back on
+        let unused_severity = settings.severities.keys()
+            .find(|name| settings.toggles.get(*name) != Some(&false) && !names.contains(&name.as_str()));
+        if let Some(name) = unused_severity {
+            return Err(format!("severity given for `{}`, which is not enabled", name));
+        }
+        if names.contains(&RULE_BLOCKLIST) {
+            registry.fingerprint.push_str(&format!("blocklist={}\n", settings.blocklist.join(",").to_uppercase()));
+        }
+        if let Some(pattern) = settings.pattern.as_ref().filter(|_| names.contains(&RULE_PATTERN)) {
+            registry.fingerprint.push_str(&format!("pattern={}\n", pattern));
+        }
+        if names.contains(&RULE_TEMPLATE) {
+            registry.fingerprint.push_str(&format!("templates={}\n", settings.templates.join(",")));
+        }
+        // the default charset leaves the digest of existing deployments alone
+        if !matches!(settings.charset, Charset::UnicodeAlphanumeric) && names.contains(&RULE_ALPHANUMERIC) {
+            registry.fingerprint.push_str(&format!("charset={}\n", settings.charset.name()));
+        }
+        // as with the charset, the default bounds leave existing digests alone
+        if (settings.min_length.is_some() || settings.max_length.is_some()) && names.contains(&RULE_LENGTH) {
+            let max_length = settings.max_length.map(|max_length| max_length.to_string()).unwrap_or_default();
+            registry.fingerprint.push_str(&format!("length={}..{}\n", settings.min_length.unwrap_or(MIN_SERIAL_LENGTH), max_length));
+        }
+        if names.contains(&RULE_DEPRECATED_PREFIX) {
+            registry.fingerprint.push_str(&format!("deprecated_prefixes={}\n", settings.deprecated_prefixes.join(",").to_uppercase()));
+        }
+        Ok(registry)
+    }
+
+    fn empty() -> ValidatorRegistry {
+        ValidatorRegistry { rules: Vec::new(), fingerprint: String::new() }
+    }
+
+    /// Adds a rule, applied after the ones already registered.
+    pub fn register(&mut self, validator: Box<dyn Validator>, severity: Severity) {
+        self.fingerprint.push_str(&format!("{}={}\n", validator.name(), severity.name()));
+        self.rules.push(Rule { validator: Arc::from(validator), severity });
+    }
+
+    /// Changes the severity of enabled rules for one request, `None` leaving the rule out. Overrides
+    /// of rules that are not enabled are skipped.
+    pub fn override_rules(&mut self, overrides: &[(String, Option<Severity>)]) {
+        for (name, severity) in overrides {
+            let position = match self.rules.iter().position(|rule| rule.validator.name() == name) {
+                Some(position) => position,
+                None => continue,
+            };
+            match *severity {
+                Some(severity) => self.rules[position].severity = severity,
+                None => { self.rules.remove(position); },
+            }
+            self.fingerprint.push_str(&format!("override {}={}\n", name, severity.map_or("off", Severity::name)));
+        }
+    }
+
+    /// Short digest of the rules and their settings, changing whenever the rule set does.
+    pub fn version(&self) -> String {
+        Sha256::digest(self.fingerprint.as_bytes()).iter().take(6).map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Names of the rules, in the order they are applied.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.rules.iter().map(|rule| rule.validator.name())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&dyn Validator, Severity)> {
+        self.rules.iter().map(|rule| (rule.validator.as_ref(), rule.severity))
+    }
+
+    /// The rules a serial has to pass to be valid.
+    pub fn blocking(&self) -> impl Iterator<Item = &dyn Validator> {
+        self.iter().filter(|(_, severity)| *severity == Severity::Error).map(|(validator, _)| validator)
+    }
+
+    /// Whether `serial_number` passes every rule of `error` severity.
+    pub fn accepts(&self, serial_number: &str) -> bool {
+        self.blocking().all(|validator| validator.validate(serial_number).is_none())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn guards_the_input_before_the_rules() {
+        let guard = InputGuard { max_length: 8 };
+        assert_eq!(Ok("AB1234"), guard.sanitize(" AB1234\t"));
+        assert_eq!(Ok("AB123456"), guard.sanitize("AB123456"));
+        assert_eq!(Err(ValidationError::TooLong), guard.sanitize("AB1234567"));
+        assert_eq!(Err(ValidationError::InvalidFormat), guard.sanitize("AB\u{0}1234"));
+        assert_eq!(Err(ValidationError::MissingSerial), guard.sanitize(""));
+        assert_eq!(Err(ValidationError::MissingSerial), guard.sanitize(" \t\n"));
+        assert_eq!(Err(ValidationError::MissingSerial), guard.sanitize(" \u{0} "));
+    }
+
+    #[test]
+    fn applies_rule_toggles_over_the_configured_rules() {
+        let mut toggles = HashMap::new();
+        toggles.insert(String::from("alphanumeric"), false);
+        toggles.insert(String::from("checksum"), true);
+        toggles.insert(String::from("length"), true);
+        let severities = parse_severities(&["alphanumeric=warning"]).ok().unwrap();
+        let settings = RuleSettings { toggles, severities, ..Default::default() };
+        let registry = ValidatorRegistry::from_names(&DEFAULT_RULES, &settings).ok().unwrap();
+        assert_eq!(vec!["length", "checksum"], registry.names().collect::<Vec<_>>());
+        assert_ne!(ValidatorRegistry::default().version(), registry.version());
+    }
+
+    #[test]
+    fn builds_the_rules_in_the_configured_order() {
+        let settings = RuleSettings { blocklist: vec![String::from("abc123")], pattern: Some(String::from("[A-Z]{2}[0-9]+")), ..Default::default() };
+        let registry = ValidatorRegistry::from_names(&["pattern", "blocklist", "length"], &settings).ok().unwrap();
+        assert_eq!(vec!["pattern", "blocklist", "length"], registry.names().collect::<Vec<_>>());
+        assert_eq!(true, registry.accepts("AB1234"));
+        assert_eq!(false, registry.accepts("AB1234X"));
+        assert_eq!(false, registry.accepts("AB12"));
+    }
+
+    #[test]
+    fn restricts_the_alphanumeric_rule_to_the_charset() {
+        let unicode = ValidatorRegistry::from_names(&["alphanumeric"], &RuleSettings::default()).ok().unwrap();
+        assert_eq!(true, unicode.accepts("ЖЯ1234"));
+        let ascii = RuleSettings { charset: Charset::parse("ascii_alphanumeric").ok().unwrap(), ..Default::default() };
+        let ascii = ValidatorRegistry::from_names(&["alphanumeric"], &ascii).ok().unwrap();
+        assert_eq!((true, false), (ascii.accepts("Ab1234"), ascii.accepts("ЖЯ1234")));
+        assert_eq!(true, ascii.version() != unicode.version());
+        let class = RuleSettings { charset: Charset::parse("[A-HJ-NP-Z0-9]").ok().unwrap(), ..Default::default() };
+        let class = ValidatorRegistry::from_names(&["alphanumeric"], &class).ok().unwrap();
+        assert_eq!((true, false, false), (class.accepts("AB1234"), class.accepts("AI1234"), class.accepts("ab1234")));
+        assert_eq!(true, Charset::parse("latin").is_err());
+        assert_eq!(true, Charset::parse("[A-").is_err());
+    }
+
+    #[test]
+    fn template_rule_explains_mismatches() {
+        let settings = RuleSettings { templates: vec![String::from("AAA-####-XX")], ..Default::default() };
+        let registry = ValidatorRegistry::from_names(&["template"], &settings).ok().unwrap();
+        assert_eq!(true, registry.accepts("ABC-1234-Z9"));
+        let (validator, _) = registry.iter().next().unwrap();
+        assert_eq!(Some(ValidationError::InvalidFormat), validator.validate("ABC-12X4-Z9"));
+        assert_eq!(Some(3), validator.segment_mismatch("ABC-12X4-Z9").map(|mismatch| mismatch.segment));
+        assert_eq!(true, ValidatorRegistry::from_names(&["template"], &RuleSettings::default()).is_err());
+    }
+
+    #[test]
+    fn blocklist_ignores_case() {
+        let settings = RuleSettings { blocklist: vec![String::from("abc123")], ..Default::default() };
+        let registry = ValidatorRegistry::from_names(&["blocklist"], &settings).ok().unwrap();
+        assert_eq!(false, registry.accepts("ABC123"));
+        assert_eq!(true, registry.accepts("ABC124"));
+    }
+
+    #[test]
+    fn checksum_rule_accepts_generated_serials() {
+        let registry = ValidatorRegistry::from_names(&["checksum"], &RuleSettings::default()).ok().unwrap();
+        let check = checksum::check_character("AB12345");
+        assert_eq!(true, registry.accepts(&format!("AB12345{}", check)));
+        assert_eq!(false, registry.accepts(&format!("AB12346{}", check)));
+    }
+
+    #[test]
+    fn version_follows_the_rules_and_their_settings() {
+        let settings = RuleSettings { blocklist: vec![String::from("abc123")], ..Default::default() };
+        let blocklist = ValidatorRegistry::from_names(&["blocklist"], &settings).ok().unwrap();
+        let other_blocklist = ValidatorRegistry::from_names(&["blocklist"], &RuleSettings::default()).ok().unwrap();
+        let defaults = ValidatorRegistry::from_names(&DEFAULT_RULES, &settings).ok().unwrap();
+        assert_eq!(12, blocklist.version().len());
+        assert_ne!(blocklist.version(), other_blocklist.version());
+        assert_eq!(ValidatorRegistry::default().version(), defaults.version());
+    }
+
+    #[test]
+    fn warnings_do_not_block() {
+        let severities = parse_severities(&["blocklist=warning"]).ok().unwrap();
+        let settings = RuleSettings { blocklist: vec![String::from("ab1234")], deprecated_prefixes: vec![String::from("zz")], severities, ..Default::default() };
+        let registry = ValidatorRegistry::from_names(&["length", "blocklist", "deprecated_prefix"], &settings).ok().unwrap();
+        let severities: Vec<Severity> = registry.iter().map(|(_, severity)| severity).collect();
+        assert_eq!(vec![Severity::Error, Severity::Warning, Severity::Warning], severities);
+        assert_eq!(true, registry.accepts("AB1234"));
+        assert_eq!(true, registry.accepts("ZZ1234"));
+        assert_eq!(false, registry.accepts("ZZ12"));
+    }
+
+    #[test]
+    fn length_rule_follows_its_bounds() {
+        let settings = RuleSettings { min_length: Some(4), max_length: Some(8), ..Default::default() };
+        let bounded = ValidatorRegistry::from_names(&["length"], &settings).ok().unwrap();
+        let (length, _) = bounded.iter().next().unwrap();
+        assert_eq!((None, None), (length.validate("AB12"), length.validate("AB123456")));
+        assert_eq!((Some(ValidationError::InvalidFormat), Some(ValidationError::TooLong)), (length.validate("AB1"), length.validate("AB1234567")));
+        assert_ne!(ValidatorRegistry::default().version(), ValidatorRegistry::from_names(&DEFAULT_RULES, &settings).ok().unwrap().version());
+    }
+
+    #[test]
+    fn overrides_apply_to_enabled_rules_only() {
+        let settings = RuleSettings { blocklist: vec![String::from("ab1234")], deprecated_prefixes: vec![String::from("zz")], ..Default::default() };
+        let mut registry = ValidatorRegistry::from_names(&["length", "blocklist", "deprecated_prefix"], &settings).ok().unwrap();
+        let version = registry.version();
+        registry.override_rules(&[(String::from("deprecated_prefix"), None), (String::from("blocklist"), Some(Severity::Warning)), (String::from("checksum"), None)]);
+        assert_eq!(vec!["length", "blocklist"], registry.names().collect::<Vec<_>>());
+        assert_eq!(true, registry.accepts("AB1234"));
+        assert_ne!(version, registry.version());
+    }
+
+    #[test]
+    fn rejects_malformed_severities() {
+        assert_eq!(true, parse_severities(&["checksum"]).is_err());
+        assert_eq!(true, parse_severities(&["checksum=info"]).is_err());
+        let settings = RuleSettings { severities: parse_severities(&["checksum=warning"]).ok().unwrap(), ..Default::default() };
+        assert_eq!(true, ValidatorRegistry::from_names(&["length"], &settings).is_err());
+    }
+
+    #[test]
+    fn parses_strategies() {
+        assert_eq!(Some(ValidationStrategy::FailFast), ValidationStrategy::parse("fail_fast"));
+        assert_eq!(Some(ValidationStrategy::CollectAll), ValidationStrategy::parse(" collect_all"));
+        assert_eq!(None, ValidationStrategy::parse("failFast"));
+    }
+
+    #[test]
+    fn rejects_unknown_or_unconfigured_rules() {
+        assert_eq!(true, ValidatorRegistry::from_names(&["uniqueness"], &RuleSettings::default()).is_err());
+        assert_eq!(true, ValidatorRegistry::from_names(&["pattern"], &RuleSettings::default()).is_err());
+        assert_eq!(true, ValidatorRegistry::from_names(&["deprecated_prefix"], &RuleSettings::default()).is_err());
+    }
+
+    /// Characters that look like nothing or sit next to the surrogate range, mixed with ordinary
+    /// serial characters, whitespace and control characters.
+    fn serial_char() -> impl Strategy<Value = char> {
+        prop_oneof![
+            4 => prop::char::range('0', '9'),
+            4 => prop::char::range('A', 'Z'),
+            1 => prop::char::range('a', 'z'),
+            1 => prop::sample::select(vec!['\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}', '\u{FEFF}', '\u{00AD}']),
+            1 => prop::sample::select(vec!['\u{D7FF}', '\u{E000}', '\u{FFFD}', '\u{FFFF}', '\u{10000}', '\u{10FFFF}']),
+            1 => prop::sample::select(vec!['\u{0301}', 'é', 'ß', 'Ж', '٣', '一', '-', ' ', '\t', '\u{3000}', '\u{0}', '\u{7F}', '\u{85}']),
+            1 => any::<char>(),
+        ]
+    }
+
+    fn serial(max_length: usize) -> impl Strategy<Value = String> {
+        prop::collection::vec(serial_char(), 0..max_length).prop_map(|chars| chars.into_iter().collect())
+    }
+
+    fn every_rule() -> ValidatorRegistry {
+        let settings = RuleSettings {
+            blocklist: vec![String::from("AB1234")],
+            pattern: Some(String::from("[A-Z]{2}[0-9]{4,}")),
+            deprecated_prefixes: vec![String::from("OLD")],
+            templates: vec![String::from("AAA-####-XX"), String::from(r"AA\-######")],
+            max_length: Some(64),
+            ..RuleSettings::default()
+        };
+        ValidatorRegistry::from_names(&["length", "alphanumeric", "checksum", "blocklist", "pattern", "deprecated_prefix", "template"], &settings).ok().unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn sanitizing_is_idempotent(serial_number in serial(300), max_length in 1usize..200) {
+            let guard = InputGuard { max_length };
+            if let Ok(sanitized) = guard.sanitize(&serial_number) {
+                prop_assert_eq!(Ok(sanitized), guard.sanitize(sanitized));
+                prop_assert_eq!(sanitized.trim(), sanitized);
+                prop_assert!(sanitized.chars().count() <= max_length);
+                prop_assert!(!sanitized.chars().any(char::is_control));
+            }
+        }
+
+        #[test]
+        fn length_rule_counts_characters(serial_number in serial(80), min_length in 0usize..40, extra in prop::option::of(0usize..40)) {
+            let rule = LengthRule { min_length, max_length: extra.map(|extra| min_length + extra) };
+            let length = serial_number.chars().count();
+            let expected = if length < min_length {
+                Some(ValidationError::InvalidFormat)
+            } else if extra.is_some_and(|extra| length > min_length + extra) {
+                Some(ValidationError::TooLong)
+            } else {
+                None
+            };
+            prop_assert_eq!(expected, rule.validate(&serial_number));
+        }
+
+        #[test]
+        fn zero_width_characters_are_never_alphanumeric(prefix in "[A-Z0-9]{3,10}", suffix in "[A-Z0-9]{3,10}", invisible in prop::sample::select(vec!['\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}', '\u{FEFF}', '\u{00AD}'])) {
+            let serial_number = format!("{}{}{}", prefix, invisible, suffix);
+            prop_assert_eq!(false, ValidatorRegistry::default().accepts(&serial_number));
+        }
+
+        #[test]
+        fn rules_handle_any_input(serial_number in prop_oneof![serial(40), serial(1000), any::<String>()]) {
+            let registry = every_rule();
+            for (validator, _) in registry.iter() {
+                validator.validate(&serial_number);
+                validator.segment_mismatch(&serial_number);
+            }
+            // the default rules are the length and character checks, nothing more
+            let expected = serial_number.chars().count() >= MIN_SERIAL_LENGTH && validate_serial_alphanumeric(&serial_number);
+            prop_assert_eq!(expected, ValidatorRegistry::default().accepts(&serial_number));
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Serial formats written as templates, e.g. `AAA-####-XX`: `A` stands for a letter, `#` for a
+//! digit, `X` for either, and every other character for itself. `\` makes the next character
+//! literal. A template is split into segments, runs of the same placeholder or of literals, so that
+//! a serial failing it can be told which segment went wrong.
+
+use serde_derive::{Serialize, Deserialize};
+use schemars::JsonSchema;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Slot {
+    Letter,
+    Digit,
+    Alphanumeric,
+    Literal(char)
+}
+
+impl Slot {
+    fn accepts(self, c: char) -> bool {
+        match self {
+            Slot::Letter => c.is_ascii_alphabetic(),
+            Slot::Digit => c.is_ascii_digit(),
+            Slot::Alphanumeric => c.is_ascii_alphanumeric(),
+            Slot::Literal(literal) => c == literal,
+        }
+    }
+
+    fn is_literal(self) -> bool {
+        matches!(self, Slot::Literal(_))
+    }
+}
+
+/// Where a serial departs from a template.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SegmentMismatch {
+    pub template: String,
+    /// 1-based position of the segment in the template, separators counting as segments; one past
+    /// the last segment when the serial is longer than the template.
+    pub segment: usize,
+    /// The segment as written in the template, empty past the last one.
+    pub expected: String,
+    /// The characters of the serial in place of the segment, fewer when the serial ends early.
+    pub found: String
+}
+
+#[derive(Clone, Debug)]
+pub struct Template {
+    source: String,
+    /// Each segment as written and its slots.
+    segments: Vec<(String, Vec<Slot>)>
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let mut segments: Vec<(String, Vec<Slot>)> = Vec::new();
+        let mut chars = source.trim().chars();
+        while let Some(c) = chars.next() {
+            let (written, slot) = match c {
+                'A' => (String::from("A"), Slot::Letter),
+                '#' => (String::from("#"), Slot::Digit),
+                'X' => (String::from("X"), Slot::Alphanumeric),
+                '\\' => match chars.next() {
+                    Some(literal) => (format!("\\{}", literal), Slot::Literal(literal)),
+                    None => return Err(format!("template `{}` ends with an escape", source)),
+                },
+                literal => (literal.to_string(), Slot::Literal(literal)),
+            };
+            match segments.last_mut() {
+                Some(&mut (ref mut segment, ref mut slots)) if same_segment(slots[0], slot) => {
+                    segment.push_str(&written);
+                    slots.push(slot);
+                },
+                _ => segments.push((written, vec![slot])),
+            }
+        }
+        if segments.is_empty() {
+            return Err(String::from("templates must not be empty"));
+        }
+        Ok(Template { source: source.trim().to_string(), segments })
+    }
+
+    /// The first segment `serial_number` fails, `None` when it matches the template.
+    pub fn mismatch(&self, serial_number: &str) -> Option<SegmentMismatch> {
+        let mut rest = serial_number.chars();
+        for (position, (written, slots)) in self.segments.iter().enumerate() {
+            let found: String = rest.by_ref().take(slots.len()).collect();
+            if found.chars().count() < slots.len() || !found.chars().zip(slots.iter()).all(|(c, slot)| slot.accepts(c)) {
+                return Some(self.segment_mismatch(position + 1, written, found));
+            }
+        }
+        let extra: String = rest.collect();
+        if extra.is_empty() { None } else { Some(self.segment_mismatch(self.segments.len() + 1, "", extra)) }
+    }
+
+    fn segment_mismatch(&self, segment: usize, expected: &str, found: String) -> SegmentMismatch {
+        SegmentMismatch { template: self.source.clone(), segment, expected: expected.to_string(), found }
+    }
+}
+
+/// Literals share a segment with each other, placeholders only with their own kind.
+fn same_segment(segment: Slot, slot: Slot) -> bool {
+    if segment.is_literal() { slot.is_literal() } else { segment == slot }
+}
+
+/// The mismatch of the template `serial_number` gets furthest into, the first one on a tie.
+/// `None` when it matches one of the templates.
+pub fn closest_mismatch(templates: &[Template], serial_number: &str) -> Option<SegmentMismatch> {
+    let mut closest: Option<SegmentMismatch> = None;
+    for template in templates {
+        let mismatch = template.mismatch(serial_number)?;
+        if closest.as_ref().is_none_or(|closest| mismatch.segment > closest.segment) {
+            closest = Some(mismatch);
+        }
+    }
+    closest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(source: &str) -> Template {
+        Template::parse(source).ok().unwrap()
+    }
+
+    #[test]
+    fn splits_templates_into_segments() {
+        let written: Vec<String> = template("AAA-####-XX").segments.into_iter().map(|(written, _)| written).collect();
+        assert_eq!(vec!["AAA", "-", "####", "-", "XX"], written);
+        assert_eq!(3, template("A\\A#").segments.len());
+        assert_eq!(true, Template::parse(" ").is_err());
+        assert_eq!(true, Template::parse("AA\\").is_err());
+    }
+
+    #[test]
+    fn names_the_segment_that_mismatched() {
+        let template = template("AAA-####-XX");
+        assert_eq!(None, template.mismatch("ABC-1234-Z9"));
+        let mismatch = template.mismatch("ABC-12A4-Z9").unwrap();
+        assert_eq!((3, "####", "12A4"), (mismatch.segment, mismatch.expected.as_str(), mismatch.found.as_str()));
+        assert_eq!(2, template.mismatch("ABC_1234-Z9").unwrap().segment);
+        assert_eq!((5, String::from("Z")), template.mismatch("ABC-1234-Z").map(|mismatch| (mismatch.segment, mismatch.found)).unwrap());
+        assert_eq!((6, String::from("0")), template.mismatch("ABC-1234-Z90").map(|mismatch| (mismatch.segment, mismatch.found)).unwrap());
+    }
+
+    #[test]
+    fn reports_the_closest_of_several_templates() {
+        let templates = vec![template("AA####"), template("AAA-####")];
+        assert_eq!(None, closest_mismatch(&templates, "AB1234"));
+        assert_eq!(None, closest_mismatch(&templates, "ABC-1234"));
+        let mismatch = closest_mismatch(&templates, "ABC-12").unwrap();
+        assert_eq!(("AAA-####", 3), (mismatch.template.as_str(), mismatch.segment));
+    }
+}
@@ -0,0 +1,114 @@
+//! The `describe` action: what the function accepts and answers, as JSON schemas generated from
+//! the event and response types, with the rules applied to the caller and every error code. SDKs
+//! and contract tests are generated from it rather than from the README.
+
+use schemars::{schema_for, JsonSchema};
+use schemars::schema::RootSchema;
+use serde_derive::Serialize;
+
+use crate::config::Config;
+use crate::error::{ServiceError, ERROR_TYPES};
+use crate::health::HealthReport;
+use crate::import::ImportReport;
+use crate::listing::{ListedSerials, LookedUpSerial};
+use crate::report::RegistrationReport;
+use crate::results::StoredResult;
+use crate::rules::UNIQUENESS_CHECK;
+use crate::self_test::SelfTestReport;
+use crate::{BypassTokenIssued, ConfirmedSerial, CurrentValidationResult, GeneratedSerial, RegisteredSerial, ReleasedSerial, ReservedSerial, TransferredSerial, UpdatedSerial, ValidationError, ValidationEvent};
+
+#[derive(Serialize)]
+pub struct Description {
+    version: &'static str,
+    /// Every action but `describe` with the schema of its response; all of them take the event in
+    /// `request`.
+    actions: Vec<DescribedAction>,
+    request: RootSchema,
+    /// Body of the function error every action fails with.
+    error: RootSchema,
+    /// Rules applied to the caller's serials, in order, with `uniqueness` last unless it is
+    /// switched off.
+    rules: Vec<DescribedRule>,
+    /// Every code `errors` and `warnings` of a validation result may hold.
+    #[serde(rename = "errorCodes")]
+    error_codes: Vec<String>,
+    /// Every `errorType` of `error`.
+    #[serde(rename = "errorTypes")]
+    error_types: Vec<&'static str>
+}
+
+#[derive(Serialize)]
+struct DescribedAction {
+    name: &'static str,
+    response: RootSchema
+}
+
+#[derive(Serialize)]
+struct DescribedRule {
+    name: String,
+    /// `error` or `warning`.
+    severity: &'static str
+}
+
+fn action<T: JsonSchema>(name: &'static str) -> DescribedAction {
+    DescribedAction { name, response: schema_for!(T) }
+}
+
+/// The description of the deployment as `config` sets it up for the caller.
+pub fn describe(config: &Config) -> Description {
+    let actions = vec![
+        action::<CurrentValidationResult>("validate"),
+        action::<CurrentValidationResult>("resolvePending"),
+        action::<StoredResult>("getResult"),
+        action::<GeneratedSerial>("generate"),
+        action::<ReservedSerial>("reserve"),
+        action::<RegisteredSerial>("register"),
+        action::<UpdatedSerial>("update"),
+        action::<TransferredSerial>("transfer"),
+        action::<ConfirmedSerial>("confirm"),
+        action::<ReleasedSerial>("release"),
+        action::<ImportReport>("import"),
+        action::<ListedSerials>("list"),
+        action::<LookedUpSerial>("lookup"),
+        action::<RegistrationReport>("report"),
+        action::<BypassTokenIssued>("issueBypassToken"),
+        action::<SelfTestReport>("selfTest"),
+        action::<HealthReport>("healthcheck")
+    ];
+    let mut rules: Vec<DescribedRule> = config.validators.iter()
+        .map(|(validator, severity)| DescribedRule { name: validator.name().to_string(), severity: severity.name() })
+        .collect();
+    if !config.skip_uniqueness {
+        rules.push(DescribedRule { name: UNIQUENESS_CHECK.to_string(), severity: "error" });
+    }
+    Description {
+        version: env!("CARGO_PKG_VERSION"),
+        actions,
+        request: schema_for!(ValidationEvent),
+        error: ServiceError::contract_schema(),
+        rules,
+        error_codes: ValidationError::ALL.iter().map(ValidationError::value).collect(),
+        error_types: ERROR_TYPES.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_requests_responses_rules_and_errors() {
+        let description = serde_json::to_value(describe(&Config::default())).unwrap();
+        let request = &description["request"]["properties"];
+        assert_eq!(true, request.get("serialNumber").is_some());
+        assert_eq!(true, request.get("caller").is_none());
+        let validate = &description["actions"][0];
+        assert_eq!("validate", validate["name"]);
+        assert_eq!(true, validate["response"]["properties"].get("schemaVersion").is_some());
+        assert_eq!(true, validate["response"]["properties"].get("isValid").is_some());
+        let rules: Vec<&str> = description["rules"].as_array().unwrap().iter().map(|rule| rule["name"].as_str().unwrap()).collect();
+        assert_eq!(vec!["length", "alphanumeric", "uniqueness"], rules);
+        assert_eq!(true, description["errorCodes"].as_array().unwrap().contains(&serde_json::json!("missing_serial")));
+        assert_eq!(true, description["error"]["properties"].get("errorType").is_some());
+    }
+}
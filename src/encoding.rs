@@ -0,0 +1,94 @@
+//! Encodings of the bodies the HTTP front ends accept and answer with. Internal callers sending
+//! many requests can use MessagePack or CBOR instead of JSON, picked by `Content-Type` for the
+//! request and `Accept` for the answer. Both go through the same serde types as JSON: bodies are
+//! decoded to a JSON value, checked and deserialized as usual.
+
+use serde_json::Value;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+    Cbor
+}
+
+impl Encoding {
+    /// Encoding of a media type, parameters and case ignored. `None` for media types other than
+    /// the supported ones.
+    fn of_media_type(media_type: &str) -> Option<Encoding> {
+        let media_type = media_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+        match media_type.as_str() {
+            "application/json" => Some(Encoding::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => Some(Encoding::MessagePack),
+            "application/cbor" => Some(Encoding::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Encoding of a request body with the `Content-Type` header `content_type`. Bodies of other
+    /// or no content types are read as JSON, as they were before other encodings were supported.
+    pub fn of_request(content_type: Option<&str>) -> Encoding {
+        content_type.and_then(Encoding::of_media_type).unwrap_or(Encoding::Json)
+    }
+
+    /// Encoding of the answer: the first supported one listed in the `Accept` header `accept`,
+    /// otherwise the encoding of the request.
+    pub fn negotiate(accept: Option<&str>, request: Encoding) -> Encoding {
+        accept.and_then(|accept| accept.split(',').find_map(Encoding::of_media_type)).unwrap_or(request)
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::MessagePack => "application/msgpack",
+            Encoding::Cbor => "application/cbor",
+        }
+    }
+
+    pub fn decode(self, body: &[u8]) -> Result<Value, String> {
+        match self {
+            Encoding::Json => serde_json::from_slice(body).map_err(|error| error.to_string()),
+            Encoding::MessagePack => rmp_serde::from_slice(body).map_err(|error| error.to_string()),
+            Encoding::Cbor => ciborium::de::from_reader(body).map_err(|error| error.to_string()),
+        }
+    }
+
+    /// `json`, a JSON answer, in this encoding.
+    pub fn encode(self, json: &str) -> Result<Vec<u8>, String> {
+        let value = || serde_json::from_str::<Value>(json).map_err(|error| error.to_string());
+        match self {
+            Encoding::Json => Ok(json.as_bytes().to_vec()),
+            Encoding::MessagePack => rmp_serde::to_vec_named(&value()?).map_err(|error| error.to_string()),
+            Encoding::Cbor => {
+                let mut body = Vec::new();
+                ciborium::ser::into_writer(&value()?, &mut body).map_err(|error| error.to_string())?;
+                Ok(body)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn negotiates_from_the_headers() {
+        assert_eq!(Encoding::MessagePack, Encoding::of_request(Some("Application/MsgPack; charset=binary")));
+        assert_eq!(Encoding::Json, Encoding::of_request(Some("text/plain")));
+        assert_eq!(Encoding::Json, Encoding::of_request(None));
+        assert_eq!(Encoding::Cbor, Encoding::negotiate(Some("text/html, application/cbor;q=0.9, application/json"), Encoding::Json));
+        assert_eq!(Encoding::MessagePack, Encoding::negotiate(Some("*/*"), Encoding::MessagePack));
+    }
+
+    #[test]
+    fn round_trips_answers() {
+        let answer = json!({"isValid": false, "errors": ["already_exists"], "meta": {"timings": {"formatChecksMicros": 12}}});
+        for &encoding in [Encoding::Json, Encoding::MessagePack, Encoding::Cbor].iter() {
+            let body = encoding.encode(&answer.to_string()).ok().unwrap();
+            assert_eq!(Ok(answer.clone()), encoding.decode(&body));
+        }
+        assert_eq!(true, Encoding::Cbor.decode(&[0xff]).is_err());
+    }
+}
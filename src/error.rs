@@ -0,0 +1,241 @@
+use schemars::{schema_for, JsonSchema};
+use schemars::schema::RootSchema;
+use serde_derive::Serialize;
+
+use crate::store::StoreError;
+
+/// Failures that prevent a validation answer and are returned to the caller as a Lambda
+/// function error. The error message is a JSON document following the contract below so that
+/// Step Functions Retry/Catch policies and SDK wrappers can branch on it without parsing text:
+///
+/// ```json
+/// {"errorType": "StoreThrottled", "errorMessage": "...", "retryable": true, "throttle": true}
+/// ```
+#[derive(Debug, PartialEq)]
+pub enum ServiceError {
+    /// The store could not be reached or failed server side.
+    StoreUnavailable(String),
+    /// The store did not answer within the time the call was given; reported as a
+    /// `StoreUnavailable` whose message starts with `timeout`.
+    StoreTimeout,
+    /// The store rejected the request because of exceeded throughput.
+    StoreThrottled(String),
+    /// The store rejected the request because of its configuration (missing table, credentials, ...).
+    StoreMisconfigured(String),
+    /// The function's role is not allowed to call the store.
+    StoreAccessDenied(String),
+    /// The store was not called because its circuit breaker is open after repeated failures.
+    StoreCircuitOpen,
+    /// The event is missing parameters or asks for something unsupported.
+    InvalidRequest(String),
+    /// A field of the event is missing, of the wrong type or too long; see `input::check_event`.
+    BadRequest { field: String, reason: String },
+    /// The event holds a field it does not know, rejected with `STRICT_INPUT` only; reported as a
+    /// `BadRequest` naming the field.
+    UnknownField(String),
+    /// The caller is not allowed to perform the requested action.
+    Unauthorized(String),
+    /// The caller used up its rate limit, see `rate_limit::check`.
+    RateLimited { retry_after_seconds: u64 },
+    /// The outcome could not be reported back to the waiting Step Functions task.
+    CallbackFailed(String),
+    /// Every generated serial collided with a registered one, after the given number of attempts.
+    GenerationExhausted(u32),
+    /// The function panicked while handling the request; the panic is in the log.
+    Internal(String)
+}
+
+/// Every `errorType` of the error contract.
+pub const ERROR_TYPES: [&str; 12] = ["StoreUnavailable", "StoreThrottled", "StoreMisconfigured", "StoreAccessDenied", "StoreCircuitOpen", "InvalidRequest", "BadRequest", "Unauthorized", "RateLimited", "CallbackFailed", "GenerationExhausted", "InternalError"];
+
+#[derive(Serialize, JsonSchema)]
+struct ErrorContract<'a> {
+    #[serde(rename = "errorType")]
+    error_type: &'a str,
+    #[serde(rename = "errorMessage")]
+    error_message: &'a str,
+    retryable: bool,
+    throttle: bool,
+    /// The offending field of a `BadRequest`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<&'a str>
+}
+
+impl ServiceError {
+    pub fn error_type(&self) -> &'static str {
+        match *self {
+            ServiceError::StoreUnavailable(_) | ServiceError::StoreTimeout => "StoreUnavailable",
+            ServiceError::StoreThrottled(_) => "StoreThrottled",
+            ServiceError::StoreMisconfigured(_) => "StoreMisconfigured",
+            ServiceError::StoreAccessDenied(_) => "StoreAccessDenied",
+            ServiceError::StoreCircuitOpen => "StoreCircuitOpen",
+            ServiceError::InvalidRequest(_) => "InvalidRequest",
+            ServiceError::BadRequest { .. } | ServiceError::UnknownField(_) => "BadRequest",
+            ServiceError::Unauthorized(_) => "Unauthorized",
+            ServiceError::RateLimited { .. } => "RateLimited",
+            ServiceError::CallbackFailed(_) => "CallbackFailed",
+            ServiceError::GenerationExhausted(_) => "GenerationExhausted",
+            ServiceError::Internal(_) => "InternalError",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match *self {
+            ServiceError::StoreUnavailable(ref message)
+            | ServiceError::StoreThrottled(ref message)
+            | ServiceError::InvalidRequest(ref message)
+            | ServiceError::Unauthorized(ref message)
+            | ServiceError::CallbackFailed(ref message) => message.clone(),
+            ServiceError::BadRequest { ref field, ref reason } => format!("{} {}", field, reason),
+            ServiceError::StoreMisconfigured(ref message) => format!("store_misconfigured: {}", message),
+            ServiceError::StoreAccessDenied(ref message) => format!("store_access_denied: {}", message),
+            ServiceError::UnknownField(ref field) => format!("unknown_field: {} is not a field of the event", field),
+            ServiceError::StoreTimeout => String::from("timeout: store request timed out"),
+            ServiceError::StoreCircuitOpen => String::from("store circuit breaker is open"),
+            ServiceError::RateLimited { retry_after_seconds } => format!("rate_limited: retry after {} seconds", retry_after_seconds),
+            ServiceError::GenerationExhausted(attempts) => format!("no unused serial found in {} attempts", attempts),
+            ServiceError::Internal(ref request_id) => format!("internal_error: request {} failed unexpectedly", request_id),
+        }
+    }
+
+    pub fn retryable(&self) -> bool {
+        match *self {
+            ServiceError::StoreUnavailable(_)
+            | ServiceError::StoreTimeout
+            | ServiceError::StoreThrottled(_)
+            | ServiceError::StoreCircuitOpen
+            | ServiceError::RateLimited { .. }
+            | ServiceError::CallbackFailed(_)
+            | ServiceError::GenerationExhausted(_) => true,
+            ServiceError::StoreMisconfigured(_)
+            | ServiceError::StoreAccessDenied(_)
+            | ServiceError::InvalidRequest(_)
+            | ServiceError::BadRequest { .. }
+            | ServiceError::UnknownField(_)
+            | ServiceError::Unauthorized(_)
+            | ServiceError::Internal(_) => false,
+        }
+    }
+
+    pub fn throttle(&self) -> bool {
+        matches!(*self, ServiceError::StoreThrottled(_) | ServiceError::RateLimited { .. })
+    }
+
+    /// JSON schema of the body `to_json` writes.
+    pub fn contract_schema() -> RootSchema {
+        schema_for!(ErrorContract)
+    }
+
+    pub fn to_json(&self) -> String {
+        let contract = ErrorContract {
+            error_type: self.error_type(),
+            error_message: &self.message(),
+            retryable: self.retryable(),
+            throttle: self.throttle(),
+            field: match *self {
+                ServiceError::BadRequest { ref field, .. } | ServiceError::UnknownField(ref field) => Some(field),
+                _ => None,
+            }
+        };
+        serde_json::to_string(&contract).expect("error contract is always serializable")
+    }
+}
+
+/// Error contract for a store call that could not complete.
+impl From<StoreError> for ServiceError {
+    fn from(error: StoreError) -> ServiceError {
+        match error {
+            StoreError::Timeout => ServiceError::StoreTimeout,
+            StoreError::Unavailable(error) => ServiceError::StoreUnavailable(error),
+            StoreError::Throttled(error) => ServiceError::StoreThrottled(error),
+            StoreError::Misconfigured(error) => ServiceError::StoreMisconfigured(error),
+            StoreError::AccessDenied(error) => ServiceError::StoreAccessDenied(error),
+            StoreError::CircuitOpen => ServiceError::StoreCircuitOpen,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throttled_store_is_retryable_throttle() {
+        let error = ServiceError::StoreThrottled(String::from("slow down"));
+        assert_eq!(
+            r#"{"errorType":"StoreThrottled","errorMessage":"slow down","retryable":true,"throttle":true}"#,
+            error.to_json()
+        );
+    }
+
+    #[test]
+    fn bad_requests_name_the_field() {
+        let error = ServiceError::BadRequest { field: String::from("serialNumber"), reason: String::from("is required") };
+        assert_eq!(
+            r#"{"errorType":"BadRequest","errorMessage":"serialNumber is required","retryable":false,"throttle":false,"field":"serialNumber"}"#,
+            error.to_json()
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_bad_requests() {
+        let error = ServiceError::UnknownField(String::from("serailNumber"));
+        assert_eq!(
+            r#"{"errorType":"BadRequest","errorMessage":"unknown_field: serailNumber is not a field of the event","retryable":false,"throttle":false,"field":"serailNumber"}"#,
+            error.to_json()
+        );
+    }
+
+    #[test]
+    fn lists_every_error_type() {
+        let errors = vec![
+            ServiceError::StoreUnavailable(String::new()),
+            ServiceError::StoreThrottled(String::new()),
+            ServiceError::StoreMisconfigured(String::new()),
+            ServiceError::StoreAccessDenied(String::new()),
+            ServiceError::StoreCircuitOpen,
+            ServiceError::InvalidRequest(String::new()),
+            ServiceError::BadRequest { field: String::new(), reason: String::new() },
+            ServiceError::Unauthorized(String::new()),
+            ServiceError::RateLimited { retry_after_seconds: 1 },
+            ServiceError::CallbackFailed(String::new()),
+            ServiceError::GenerationExhausted(1),
+            ServiceError::Internal(String::new())
+        ];
+        let error_types: Vec<&str> = errors.iter().map(ServiceError::error_type).collect();
+        assert_eq!(ERROR_TYPES.to_vec(), error_types);
+    }
+
+    #[test]
+    fn unavailable_store_is_retryable() {
+        let error = ServiceError::StoreUnavailable(String::from("connection refused"));
+        assert_eq!(true, error.retryable());
+        assert_eq!(false, error.throttle());
+    }
+
+    #[test]
+    fn store_timeouts_are_retryable_with_the_timeout_code() {
+        let error = ServiceError::from(StoreError::Timeout);
+        assert_eq!(
+            r#"{"errorType":"StoreUnavailable","errorMessage":"timeout: store request timed out","retryable":true,"throttle":false}"#,
+            error.to_json()
+        );
+    }
+
+    #[test]
+    fn misconfigured_store_is_not_retryable() {
+        let error = ServiceError::StoreMisconfigured(String::from("table not found"));
+        assert_eq!(false, error.retryable());
+        assert_eq!(false, error.throttle());
+    }
+
+    #[test]
+    fn store_configuration_errors_are_told_apart_by_code() {
+        assert_eq!("store_misconfigured: table not found", ServiceError::from(StoreError::Misconfigured(String::from("table not found"))).message());
+        let error = ServiceError::from(StoreError::AccessDenied(String::from("not authorized to perform dynamodb:GetItem")));
+        assert_eq!(
+            r#"{"errorType":"StoreAccessDenied","errorMessage":"store_access_denied: not authorized to perform dynamodb:GetItem","retryable":false,"throttle":false}"#,
+            error.to_json()
+        );
+    }
+}
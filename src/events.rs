@@ -0,0 +1,52 @@
+use std::thread;
+use std::time::Duration;
+
+use rusoto_core::Region;
+use serde_json::json;
+
+use crate::aws;
+use crate::context::RequestContext;
+
+pub const VALIDATION_COMPLETED: &str = "serial.validation.completed";
+
+/// PutEvents request body for a single event.
+pub fn put_events_payload(bus_name: &str, source: &str, detail_type: &str, detail: &serde_json::Value) -> serde_json::Value {
+    json!({
+        "Entries": [{
+            "EventBusName": bus_name,
+            "Source": source,
+            "DetailType": detail_type,
+            "Detail": detail.to_string()
+        }]
+    })
+}
+
+/// Publishes the event to EventBridge on a background thread, abandoning the call after `timeout`.
+/// Best effort: failures are logged with the request's context.
+pub fn publish_async(context: &RequestContext, bus_name: &str, source: &str, detail_type: &'static str, detail: serde_json::Value, timeout: Duration) {
+    let payload = put_events_payload(bus_name, source, detail_type, &detail);
+    let context = context.clone();
+    thread::spawn(move || {
+        match aws::call_json("events", "AWSEvents.PutEvents", "1.1", &Region::default(), &payload, Some(timeout)) {
+            Ok(ref response) if response["FailedEntryCount"].as_u64().unwrap_or(0) > 0 => {
+                context.log(&format!("EventBridge rejected {} event: {}", detail_type, response["Entries"]));
+            },
+            Ok(_) => {},
+            Err(error) => context.log(&format!("failed to publish {} event: {}", detail_type, error)),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_carries_the_detail_as_a_string() {
+        let payload = put_events_payload("serials", "serial-validation", VALIDATION_COMPLETED, &json!({"isValid": false}));
+        let entry = &payload["Entries"][0];
+        assert_eq!("serials", entry["EventBusName"]);
+        assert_eq!(VALIDATION_COMPLETED, entry["DetailType"]);
+        assert_eq!(r#"{"isValid":false}"#, entry["Detail"]);
+    }
+}
@@ -0,0 +1,213 @@
+//! Requests to the function's Function URL. The body of a `POST` is the validation event; CORS
+//! headers let browser applications on the allowed origins call the URL directly.
+
+use std::collections::HashMap;
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::caller::Authorizer;
+use crate::encoding::Encoding;
+use crate::error::ServiceError;
+use crate::http::{self, Answer, EventHeaders};
+use crate::ValidationEvent;
+
+/// Cross-origin access granted to browsers.
+#[derive(Clone, Debug)]
+pub struct CorsSettings {
+    /// Origins allowed to call the URL, `*` allowing every origin. No CORS headers are sent when empty.
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// How long browsers may cache a preflight answer.
+    pub max_age_seconds: u64
+}
+
+impl Default for CorsSettings {
+    fn default() -> CorsSettings {
+        CorsSettings {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec![String::from("POST"), String::from("OPTIONS")],
+            allowed_headers: vec![String::from("content-type")],
+            max_age_seconds: 600
+        }
+    }
+}
+
+/// Payload format 2.0 event, as sent for Function URL invocations.
+#[derive(Deserialize)]
+pub struct FunctionUrlEvent {
+    #[serde(rename = "requestContext")]
+    request_context: RequestContext,
+    /// Header names are lower-cased, repeated headers joined with commas.
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(rename = "isBase64Encoded", default)]
+    is_base64_encoded: bool
+}
+
+#[derive(Deserialize)]
+struct RequestContext {
+    http: HttpDescription,
+    /// Set when an HTTP API with a JWT or IAM authorizer forwards the request.
+    #[serde(default)]
+    authorizer: Option<Authorizer>
+}
+
+#[derive(Deserialize)]
+struct HttpDescription {
+    method: String,
+    #[serde(rename = "sourceIp", default)]
+    source_ip: Option<String>
+}
+
+#[derive(Serialize, Debug)]
+pub struct FunctionUrlResponse {
+    #[serde(rename = "statusCode")]
+    status_code: u16,
+    headers: HashMap<String, String>,
+    body: String,
+    #[serde(rename = "isBase64Encoded")]
+    is_base64_encoded: bool
+}
+
+/// Answers preflight requests from `cors`, and other requests with `validate` run on the event in
+/// their body, `validate` answering with the response serialized as JSON and the errors that
+/// decide its status. With `strict_input`, fields the event does not know are rejected.
+pub fn run<F>(event: &FunctionUrlEvent, cors: &CorsSettings, strict_input: bool, validate: F) -> FunctionUrlResponse
+    where F: FnOnce(ValidationEvent) -> Result<Answer, ServiceError>
+{
+    let method = event.request_context.http.method.as_str();
+    let mut headers = cors_headers(event.headers.get("origin").map(String::as_str), cors);
+    if method == "OPTIONS" {
+        if headers.contains_key("access-control-allow-origin") {
+            headers.insert(String::from("access-control-allow-methods"), cors.allowed_methods.join(", "));
+            headers.insert(String::from("access-control-allow-headers"), cors.allowed_headers.join(", "));
+            headers.insert(String::from("access-control-max-age"), cors.max_age_seconds.to_string());
+        }
+        return FunctionUrlResponse { status_code: 204, headers, body: String::new(), is_base64_encoded: false };
+    }
+
+    let outcome = http::validation_event(method, event.body.as_deref(), event.is_base64_encoded, EventHeaders {
+        accept_language: event.headers.get("accept-language").map(String::as_str),
+        api_key: event.headers.get("x-api-key").map(String::as_str),
+        content_type: event.headers.get("content-type").map(String::as_str),
+        correlation_id: event.headers.get("x-correlation-id").map(String::as_str)
+    }, strict_input).map(|mut validation_event| {
+        validation_event.caller = event.request_context.authorizer.as_ref().and_then(Authorizer::caller);
+        validation_event.source_ip = event.request_context.http.source_ip.clone();
+        validation_event
+    }).and_then(validate);
+    let (status_code, body) = http::answer(method, outcome);
+    let encoding = Encoding::negotiate(event.headers.get("accept").map(String::as_str), Encoding::of_request(event.headers.get("content-type").map(String::as_str)));
+    let body = http::encoded_body(body, encoding);
+    headers.insert(String::from("content-type"), String::from(body.content_type));
+    FunctionUrlResponse { status_code, headers, body: body.body, is_base64_encoded: body.is_base64_encoded }
+}
+
+/// `access-control-allow-origin` for requests from an allowed `origin`, nothing for the others.
+fn cors_headers(origin: Option<&str>, cors: &CorsSettings) -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    if cors.allowed_origins.iter().any(|allowed| allowed == "*") {
+        headers.insert(String::from("access-control-allow-origin"), String::from("*"));
+    } else if let Some(origin) = origin.filter(|origin| cors.allowed_origins.iter().any(|allowed| allowed == origin)) {
+        headers.insert(String::from("access-control-allow-origin"), origin.to_string());
+        // the answer depends on the origin, so caches must not hand it to other origins
+        headers.insert(String::from("vary"), String::from("origin"));
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url_event(method: &str, origin: &str, body: &str) -> FunctionUrlEvent {
+        serde_json::from_value(serde_json::json!({
+            "version": "2.0",
+            "rawPath": "/",
+            "headers": {"origin": origin, "content-type": "application/json"},
+            "requestContext": {"http": {"method": method, "path": "/"}},
+            "body": body,
+            "isBase64Encoded": false
+        })).unwrap()
+    }
+
+    fn cors() -> CorsSettings {
+        CorsSettings { allowed_origins: vec![String::from("https://app.example.com")], ..Default::default() }
+    }
+
+    #[test]
+    fn answers_preflight_requests_of_allowed_origins() {
+        let response = run(&url_event("OPTIONS", "https://app.example.com", ""), &cors(), false, |_| Ok(String::new().into()));
+        assert_eq!(204, response.status_code);
+        assert_eq!("https://app.example.com", response.headers["access-control-allow-origin"]);
+        assert_eq!("POST, OPTIONS", response.headers["access-control-allow-methods"]);
+
+        let response = run(&url_event("OPTIONS", "https://evil.example.com", ""), &cors(), false, |_| Ok(String::new().into()));
+        assert_eq!(None, response.headers.get("access-control-allow-origin"));
+        assert_eq!(None, response.headers.get("access-control-allow-methods"));
+    }
+
+    #[test]
+    fn validates_the_event_in_the_body() {
+        let response = run(&url_event("POST", "https://app.example.com", r#"{"serialNumber": "AB1234"}"#), &cors(), false, |event| Ok(event.serial_number.into()));
+        assert_eq!((200, "AB1234"), (response.status_code, response.body.as_str()));
+        assert_eq!("application/json", response.headers["content-type"]);
+        assert_eq!("origin", response.headers["vary"]);
+    }
+
+    #[test]
+    fn takes_the_locale_from_accept_language() {
+        let mut event = url_event("POST", "https://app.example.com", r#"{"serialNumber": "AB1234"}"#);
+        event.headers.insert(String::from("accept-language"), String::from("de-AT, en;q=0.5"));
+        assert_eq!("de-AT, en;q=0.5", run(&event, &cors(), false, |event| Ok(event.locale.unwrap_or_default().into())).body);
+        event.body = Some(String::from(r#"{"serialNumber": "AB1234", "locale": "fr"}"#));
+        assert_eq!("fr", run(&event, &cors(), false, |event| Ok(event.locale.unwrap_or_default().into())).body);
+        event.body = Some(String::from(r#"{"serial_number": "AB1234", "locale": "fr"}"#));
+        assert_eq!("AB1234", run(&event, &cors(), false, |event| Ok(event.serial_number.into())).body);
+    }
+
+    #[test]
+    fn answers_binary_requests_in_their_encoding() {
+        let mut event = url_event("POST", "https://app.example.com", "");
+        event.body = Some(base64::encode(&rmp_serde::to_vec_named(&serde_json::json!({"serialNumber": "AB1234"})).unwrap()));
+        event.is_base64_encoded = true;
+        event.headers.insert(String::from("content-type"), String::from("application/x-msgpack"));
+        let response = run(&event, &cors(), false, |_| Err(ServiceError::InvalidRequest(String::from("no"))));
+        assert_eq!((400, true, "application/msgpack"), (response.status_code, response.is_base64_encoded, response.headers["content-type"].as_str()));
+        let body: serde_json::Value = rmp_serde::from_slice(&base64::decode(&response.body).unwrap()).unwrap();
+        assert_eq!("InvalidRequest", body["errorType"]);
+    }
+
+    #[test]
+    fn takes_the_api_key_from_its_header() {
+        let mut event = url_event("POST", "https://app.example.com", r#"{"serialNumber": "AB1234"}"#);
+        event.headers.insert(String::from("x-api-key"), String::from("secret-key"));
+        assert_eq!("secret-key", run(&event, &cors(), false, |event| Ok(event.api_key.unwrap_or_default().into())).body);
+        event.headers.insert(String::from("x-correlation-id"), String::from("trace-9"));
+        assert_eq!("trace-9", run(&event, &cors(), false, |event| Ok(event.correlation_id.unwrap_or_default().into())).body);
+    }
+
+    #[test]
+    fn takes_the_caller_from_the_authorizer() {
+        let mut event: FunctionUrlEvent = serde_json::from_value(serde_json::json!({
+            "headers": {},
+            "requestContext": {"http": {"method": "POST"}, "authorizer": {"jwt": {"claims": {"sub": "u-1", "cognito:groups": "[internal-tools]"}}}},
+            "body": r#"{"serialNumber": "AB1234", "caller": {"principal": "spoofed"}}"#
+        })).unwrap();
+        let principal = |event: ValidationEvent| Ok(event.caller.map(|caller| caller.principal).unwrap_or_default().into());
+        assert_eq!("u-1", run(&event, &cors(), false, principal).body);
+        event.request_context.authorizer = None;
+        assert_eq!("", run(&event, &cors(), false, principal).body);
+    }
+
+    #[test]
+    fn allows_every_origin_with_a_wildcard() {
+        let cors = CorsSettings { allowed_origins: vec![String::from("*")], ..Default::default() };
+        let response = run(&url_event("GET", "https://other.example.com", ""), &cors, false, |_| Ok(String::new().into()));
+        assert_eq!(405, response.status_code);
+        assert_eq!("*", response.headers["access-control-allow-origin"]);
+    }
+}
@@ -0,0 +1,210 @@
+use std::time::Duration;
+
+use rand::{FromEntropy, Rng, SeedableRng};
+use rand::rngs::StdRng;
+use sha2::{Digest, Sha256};
+
+use serial_validation::core::checksum::{check_character, ALPHABET};
+use crate::store::{IdempotencyKey, Registration, SequenceBlocks, SequenceCounter, SerialStore, StoreError};
+
+/// Shape of generated serials: `<prefix><body><check character>`, the body being random or, from
+/// a sequence, the next number of the prefix's counter.
+#[derive(Clone, Debug)]
+pub struct GeneratorSettings {
+    pub prefix: String,
+    /// Length of the random body, and digits sequence numbers are padded to.
+    pub body_length: usize,
+    /// Attempts before giving up when generated serials keep colliding with registered ones.
+    pub max_attempts: u32,
+    /// How long a repeated idempotency key returns the serial generated for it.
+    pub idempotency_ttl_seconds: u64
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> GeneratorSettings {
+        GeneratorSettings {
+            prefix: String::new(),
+            body_length: 8,
+            max_attempts: 5,
+            idempotency_ttl_seconds: 24 * 60 * 60
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum GenerateError {
+    /// Every attempt produced a serial that was already registered.
+    Exhausted(u32),
+    Store(StoreError)
+}
+
+pub fn new_serial<R: Rng>(settings: &GeneratorSettings, rng: &mut R) -> String {
+    let body: String = (0..settings.body_length)
+        .map(|_| ALPHABET[rng.gen_range(0, ALPHABET.len())] as char)
+        .collect();
+    let serial_number = format!("{}{}", settings.prefix, body);
+    let check = check_character(&serial_number);
+    format!("{}{}", serial_number, check)
+}
+
+/// `number` of a sequence as a serial, e.g. `AB00000042` and its check character.
+pub fn sequence_serial(settings: &GeneratorSettings, number: u64) -> String {
+    let serial_number = format!("{}{:0width$}", settings.prefix, number, width = settings.body_length);
+    let check = check_character(&serial_number);
+    format!("{}{}", serial_number, check)
+}
+
+/// Random numbers for one `generate` request. A retried request with the same idempotency key
+/// draws the same serials, so it finds the serial it registered the first time.
+pub fn request_rng(tenant_id: Option<&str>, idempotency_key: Option<&IdempotencyKey>) -> StdRng {
+    match idempotency_key {
+        Some(idempotency_key) => {
+            let digest = Sha256::digest(format!("{}\n{}", tenant_id.unwrap_or_default(), idempotency_key.key).as_bytes());
+            let mut seed = [0u8; 32];
+            seed.copy_from_slice(&digest);
+            StdRng::from_seed(seed)
+        },
+        None => StdRng::from_entropy()
+    }
+}
+
+/// Generates serials until one registers, returning it with the number of attempts it took.
+/// A serial registered earlier under the same idempotency key counts as registered by this call.
+/// A `dry_run` stops at the first serial that would register, writing nothing.
+pub fn generate<R: Rng>(store: &dyn SerialStore, settings: &GeneratorSettings, rng: &mut R, idempotency_key: Option<&IdempotencyKey>, dry_run: bool, timeout: Option<Duration>) -> Result<(String, u32), GenerateError> {
+    register_first(store, settings, |_| Ok(new_serial(settings, rng)), idempotency_key, dry_run, timeout)
+}
+
+/// Generates serials from the next numbers of the counter `counter_name` until one registers,
+/// skipping numbers whose serial was registered some other way. Numbers are taken through
+/// `blocks`; a `dry_run` takes none, checking the serials of the numbers coming next instead.
+pub fn generate_sequenced(store: &dyn SerialStore, counter: &dyn SequenceCounter, blocks: &SequenceBlocks, settings: &GeneratorSettings, counter_name: &str, dry_run: bool, timeout: Option<Duration>) -> Result<(String, u32), GenerateError> {
+    let next_number = if dry_run { Some(blocks.peek(counter, counter_name, timeout).map_err(GenerateError::Store)?) } else { None };
+    register_first(store, settings, |attempt| {
+        let number = match next_number {
+            Some(number) => number + u64::from(attempt) - 1,
+            None => blocks.next(counter, counter_name, timeout).map_err(GenerateError::Store)?,
+        };
+        Ok(sequence_serial(settings, number))
+    }, None, dry_run, timeout)
+}
+
+/// Registers the serials `candidate` comes up with for each attempt until one registers.
+fn register_first<F>(store: &dyn SerialStore, settings: &GeneratorSettings, mut candidate: F, idempotency_key: Option<&IdempotencyKey>, dry_run: bool, timeout: Option<Duration>) -> Result<(String, u32), GenerateError>
+    where F: FnMut(u32) -> Result<String, GenerateError>
+{
+    for attempt in 1..=settings.max_attempts {
+        let serial_number = candidate(attempt)?;
+        let registration = if dry_run {
+            store.would_register(&serial_number, idempotency_key, timeout)
+        } else {
+            store.register(&serial_number, idempotency_key, timeout)
+        };
+        match registration.map_err(GenerateError::Store)? {
+            Registration::Registered | Registration::Replayed => return Ok((serial_number, attempt)),
+            Registration::AlreadyRegistered => {},
+        }
+    }
+    Err(GenerateError::Exhausted(settings.max_attempts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::thread;
+
+    use serial_validation::core::checksum::has_valid_check_character;
+    use crate::store::{MemoryCounter, MemoryStore};
+
+    #[test]
+    fn generated_serials_carry_the_prefix_and_a_valid_check_character() {
+        let settings = GeneratorSettings { prefix: String::from("AB"), ..Default::default() };
+        let serial_number = new_serial(&settings, &mut StdRng::seed_from_u64(7));
+        assert_eq!(true, serial_number.starts_with("AB"));
+        assert_eq!(11, serial_number.len());
+        assert_eq!(true, has_valid_check_character(&serial_number));
+    }
+
+    #[test]
+    fn retries_on_collision() {
+        let settings = GeneratorSettings::default();
+        let taken = new_serial(&settings, &mut StdRng::seed_from_u64(7));
+        let store = MemoryStore::new(vec![taken.clone()]);
+        let (serial_number, attempts) = generate(&store, &settings, &mut StdRng::seed_from_u64(7), None, false, None).ok().unwrap();
+        assert_ne!(taken, serial_number);
+        assert_eq!(2, attempts);
+    }
+
+    #[test]
+    fn repeated_idempotency_keys_return_the_original_serial() {
+        let settings = GeneratorSettings::default();
+        let idempotency_key = IdempotencyKey { key: String::from("retry-1"), expires_at: u64::MAX };
+        let store = MemoryStore::new(Vec::new());
+        let first = generate(&store, &settings, &mut request_rng(None, Some(&idempotency_key)), Some(&idempotency_key), false, None).ok().unwrap();
+        let retried = generate(&store, &settings, &mut request_rng(None, Some(&idempotency_key)), Some(&idempotency_key), false, None).ok().unwrap();
+        assert_eq!(first, retried);
+
+        let other_key = IdempotencyKey { key: String::from("retry-2"), ..idempotency_key };
+        let other = generate(&store, &settings, &mut request_rng(None, Some(&other_key)), Some(&other_key), false, None).ok().unwrap();
+        assert_ne!(first.0, other.0);
+    }
+
+    #[test]
+    fn dry_runs_leave_the_store_alone() {
+        let settings = GeneratorSettings::default();
+        let store = MemoryStore::new(Vec::new());
+        let (serial_number, _) = generate(&store, &settings, &mut StdRng::seed_from_u64(7), None, true, None).ok().unwrap();
+        assert_eq!(false, store.contains(&serial_number, None).ok().unwrap());
+        assert_eq!((serial_number, 1), generate(&store, &settings, &mut StdRng::seed_from_u64(7), None, false, None).ok().unwrap());
+    }
+
+    #[test]
+    fn gives_up_after_the_configured_attempts() {
+        let settings = GeneratorSettings { max_attempts: 1, ..Default::default() };
+        let taken = new_serial(&settings, &mut StdRng::seed_from_u64(7));
+        let store = MemoryStore::new(vec![taken]);
+        match generate(&store, &settings, &mut StdRng::seed_from_u64(7), None, false, None) {
+            Err(GenerateError::Exhausted(1)) => {},
+            other => panic!("expected exhaustion, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn sequenced_serials_never_collide_across_parallel_invocations() {
+        let settings = GeneratorSettings { prefix: String::from("AB"), body_length: 6, ..Default::default() };
+        let taken = sequence_serial(&settings, 3);
+        assert_eq!("AB000003", &taken[..taken.len() - 1]);
+        let store = MemoryStore::new(vec![taken.clone()]);
+        let counter = MemoryCounter::default();
+        // each thread stands for a container with blocks of its own
+        let generated: Vec<Vec<String>> = thread::scope(|scope| {
+            let invocations: Vec<_> = (0..8).map(|_| scope.spawn(|| {
+                let blocks = SequenceBlocks::new(5);
+                (0..25).map(|_| generate_sequenced(&store, &counter, &blocks, &settings, "AB", false, None).ok().unwrap().0).collect::<Vec<String>>()
+            })).collect();
+            invocations.into_iter().map(|invocation| invocation.join().unwrap()).collect()
+        });
+        for serials in &generated {
+            let mut increasing = serials.clone();
+            increasing.sort();
+            assert_eq!(&increasing, serials);
+        }
+        let distinct: HashSet<&String> = generated.iter().flatten().collect();
+        assert_eq!(200, distinct.len());
+        assert_eq!(false, distinct.contains(&taken));
+        assert_eq!(true, distinct.iter().all(|serial_number| store.contains(serial_number, None).ok().unwrap()));
+    }
+
+    #[test]
+    fn sequenced_dry_runs_take_no_number() {
+        let settings = GeneratorSettings { prefix: String::from("AB"), ..Default::default() };
+        let store = MemoryStore::new(vec![sequence_serial(&settings, 1)]);
+        let counter = MemoryCounter::default();
+        let blocks = SequenceBlocks::new(1);
+        let (serial_number, attempts) = generate_sequenced(&store, &counter, &blocks, &settings, "AB", true, None).ok().unwrap();
+        assert_eq!((sequence_serial(&settings, 2), 2), (serial_number.clone(), attempts));
+        assert_eq!(0, counter.current("AB", None).ok().unwrap());
+        assert_eq!((serial_number, 2), generate_sequenced(&store, &counter, &blocks, &settings, "AB", false, None).ok().unwrap());
+    }
+}
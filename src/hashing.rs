@@ -0,0 +1,91 @@
+//! Hashing of the serials and caller ids written as keys of the auxiliary tables (audit and rate
+//! limits), so that none of them holds them in clear. The `assets` table keeps its raw keys, which
+//! lookups need.
+
+use std::sync::Arc;
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::secrets::Secret;
+
+/// How keys are hashed, as set by `KEY_HASHING`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum HashingMode {
+    /// Plain SHA-256, which anyone can recompute for a guessed serial.
+    #[default]
+    Sha256,
+    /// HMAC-SHA256 keyed with the string of a Secrets Manager secret.
+    HmacSha256 { secret_id: String }
+}
+
+impl HashingMode {
+    /// `sha256` or `hmac_sha256`, the latter needing the secret holding the key.
+    pub fn parse(name: &str, secret_id: Option<String>) -> Result<HashingMode, String> {
+        match (name.trim(), secret_id) {
+            ("sha256", _) => Ok(HashingMode::Sha256),
+            ("hmac_sha256", Some(secret_id)) => Ok(HashingMode::HmacSha256 { secret_id }),
+            ("hmac_sha256", None) => Err(String::from("hmac_sha256 needs HASH_KEY_SECRET_ID")),
+            (other, _) => Err(format!("unknown hashing mode `{}`, expected `sha256` or `hmac_sha256`", other)),
+        }
+    }
+}
+
+/// Hashes keys with the key of the mode in use, once it has been read.
+#[derive(Clone, Debug)]
+pub enum KeyHasher {
+    Sha256,
+    HmacSha256(Arc<Secret>)
+}
+
+impl KeyHasher {
+    /// Hex encoded hash of `value`.
+    pub fn hash(&self, value: &str) -> String {
+        match *self {
+            KeyHasher::Sha256 => hex(&Sha256::digest(value.as_bytes())),
+            KeyHasher::HmacSha256(ref key) => {
+                let mut mac = Hmac::<Sha256>::new(key.value.as_bytes()).expect("HMAC accepts keys of any length");
+                mac.input(value.as_bytes());
+                hex(&mac.result().code())
+            },
+        }
+    }
+
+    /// Version of the secret keying the hashes, which changes with every rotation of the key.
+    pub fn key_version(&self) -> Option<&str> {
+        match *self {
+            KeyHasher::Sha256 => None,
+            KeyHasher::HmacSha256(ref key) => Some(&key.version_id),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hmac(key: &str) -> KeyHasher {
+        KeyHasher::HmacSha256(Arc::new(Secret { value: String::from(key), version_id: String::from("v1") }))
+    }
+
+    #[test]
+    fn matches_the_reference_vectors() {
+        // FIPS 180-2 and RFC 4231 test case 2
+        assert_eq!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad", KeyHasher::Sha256.hash("abc"));
+        assert_eq!("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843", hmac("Jefe").hash("what do ya want for nothing?"));
+        assert_eq!(Some("v1"), hmac("Jefe").key_version());
+        assert_eq!(None, KeyHasher::Sha256.key_version());
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!(Ok(HashingMode::HmacSha256 { secret_id: String::from("hash-key") }), HashingMode::parse("hmac_sha256", Some(String::from("hash-key"))));
+        assert_eq!(Ok(HashingMode::Sha256), HashingMode::parse(" sha256 ", Some(String::from("hash-key"))));
+        assert_eq!(true, HashingMode::parse("hmac_sha256", None).is_err());
+        assert_eq!(true, HashingMode::parse("md5", None).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+//! The `healthcheck` action: describes the configured table in every region it is read from, so
+//! canaries notice a missing table or lacking IAM permissions before real traffic does.
+
+use std::time::Duration;
+
+use rusoto_core::Region;
+use serde_derive::Serialize;
+use schemars::JsonSchema;
+
+use crate::rules::ValidatorRegistry;
+use crate::store::{DynamoDbSettings, DynamoDbStore, DEFAULT_REGION};
+
+/// Table states in which lookups are served.
+const SERVING_STATUSES: [&str; 2] = ["ACTIVE", "UPDATING"];
+
+#[derive(Serialize, JsonSchema)]
+pub struct HealthReport {
+    pub healthy: bool,
+    /// Version of the deployed crate.
+    pub version: &'static str,
+    /// Format rules applied, in order.
+    pub rules: Vec<String>,
+    pub tables: Vec<TableHealth>
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct TableHealth {
+    pub table: String,
+    pub region: String,
+    /// `DescribeTable` status, absent when the table could not be described.
+    #[serde(rename = "tableStatus", skip_serializing_if = "Option::is_none")]
+    pub table_status: Option<String>,
+    /// Why the table could not be described.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>
+}
+
+impl TableHealth {
+    fn serving(&self) -> bool {
+        self.table_status.as_deref().is_some_and(|status| SERVING_STATUSES.contains(&status))
+    }
+}
+
+/// Describes the table of `settings` in each of `regions`, or in the default region when none are configured.
+pub fn run(settings: &DynamoDbSettings, regions: &[Region], validators: &ValidatorRegistry, timeout: Duration) -> HealthReport {
+    let regions = if regions.is_empty() { vec![DEFAULT_REGION] } else { regions.to_vec() };
+    let tables = regions.into_iter().map(|region| {
+        let table = TableHealth { table: settings.table_name.clone(), region: region.name().to_string(), table_status: None, detail: None };
+        match DynamoDbStore::in_region(settings.clone(), region).table_status(Some(timeout)) {
+            Ok(table_status) => TableHealth { table_status: Some(table_status), ..table },
+            Err(error) => TableHealth { detail: Some(format!("{:?}", error)), ..table },
+        }
+    }).collect();
+    report(validators, tables)
+}
+
+fn report(validators: &ValidatorRegistry, tables: Vec<TableHealth>) -> HealthReport {
+    HealthReport {
+        healthy: tables.iter().all(TableHealth::serving),
+        version: env!("CARGO_PKG_VERSION"),
+        rules: validators.iter().map(|(validator, _)| validator.name().to_string()).collect(),
+        tables
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(region: &str, table_status: Option<&str>) -> TableHealth {
+        TableHealth { table: String::from("assets"), region: String::from(region), table_status: table_status.map(String::from), detail: None }
+    }
+
+    #[test]
+    fn healthy_only_while_every_replica_serves() {
+        let validators = ValidatorRegistry::default();
+        assert_eq!(true, report(&validators, vec![table("eu-central-1", Some("ACTIVE")), table("eu-west-1", Some("UPDATING"))]).healthy);
+        assert_eq!(false, report(&validators, vec![table("eu-central-1", Some("ACTIVE")), table("eu-west-1", None)]).healthy);
+        assert_eq!(false, report(&validators, vec![table("eu-central-1", Some("DELETING"))]).healthy);
+    }
+
+    #[test]
+    fn reports_the_rules_and_version() {
+        let report = report(&ValidatorRegistry::default(), vec![table("eu-central-1", Some("ACTIVE"))]);
+        assert_eq!(vec!["length", "alphanumeric"], report.rules);
+        assert_eq!(env!("CARGO_PKG_VERSION"), report.version);
+    }
+}
@@ -0,0 +1,168 @@
+//! What the HTTP front ends share: the ALB and Function URL events and the local server.
+
+use crate::context::log_shared;
+use crate::encoding::Encoding;
+use crate::error::ServiceError;
+use crate::input;
+use crate::ValidationEvent;
+
+/// Headers of a request standing in for fields of the validation event it carries.
+#[derive(Default)]
+pub struct EventHeaders<'a> {
+    /// `Accept-Language`, for the `locale`.
+    pub accept_language: Option<&'a str>,
+    /// `x-api-key`, for the `apiKey`.
+    pub api_key: Option<&'a str>,
+    /// `Content-Type`, for the encoding of the body.
+    pub content_type: Option<&'a str>,
+    /// `x-correlation-id`, for the `correlationId`.
+    pub correlation_id: Option<&'a str>
+}
+
+/// Validation event in the body of a `POST` request, in the encoding of its `Content-Type`, its
+/// `locale`, `apiKey` and `correlationId` taken from the request's headers unless the event sets them.
+/// With `strict_input`, fields the event does not know are rejected.
+pub fn validation_event(method: &str, body: Option<&str>, is_base64_encoded: bool, headers: EventHeaders, strict_input: bool) -> Result<ValidationEvent, ServiceError> {
+    if method != "POST" {
+        return Err(ServiceError::InvalidRequest(format!("method {} is not allowed, use POST", method)));
+    }
+    let body = body.unwrap_or_default();
+    let body = if is_base64_encoded {
+        base64::decode(body).map_err(|error| ServiceError::InvalidRequest(format!("malformed body: {}", error)))?
+    } else {
+        body.as_bytes().to_vec()
+    };
+    let mut event = Encoding::of_request(headers.content_type).decode(&body).map_err(|error| ServiceError::InvalidRequest(format!("malformed event: {}", error)))?;
+    input::normalize_field_names(&mut event)?;
+    if strict_input {
+        input::check_known_fields(&event)?;
+    }
+    input::check_event(&event)?;
+    let mut event: ValidationEvent = serde_json::from_value(event).map_err(|error| ServiceError::InvalidRequest(format!("malformed event: {}", error)))?;
+    if event.locale.is_none() {
+        event.locale = headers.accept_language.map(String::from);
+    }
+    if event.api_key.is_none() {
+        event.api_key = headers.api_key.map(String::from);
+    }
+    if event.correlation_id.is_none() {
+        event.correlation_id = headers.correlation_id.map(String::from);
+    }
+    Ok(event)
+}
+
+/// A request validated, with the body answering it and the codes of the errors it found.
+pub struct Answer {
+    pub body: String,
+    /// Empty for valid serials and for answers other than validation results.
+    pub errors: Vec<String>
+}
+
+impl From<String> for Answer {
+    fn from(body: String) -> Answer {
+        Answer { body, errors: Vec::new() }
+    }
+}
+
+/// Body of a response, as the HTTP front ends hand it back to Lambda.
+pub struct EncodedBody {
+    pub body: String,
+    /// Set for the binary encodings, whose body is base64 encoded.
+    pub is_base64_encoded: bool,
+    pub content_type: &'static str
+}
+
+/// `body`, a JSON answer, in `encoding`. Answers that cannot be encoded are sent as JSON.
+pub fn encoded_body(body: String, encoding: Encoding) -> EncodedBody {
+    if encoding != Encoding::Json {
+        match encoding.encode(&body) {
+            Ok(encoded) => return EncodedBody { body: base64::encode(&encoded), is_base64_encoded: true, content_type: encoding.content_type() },
+            Err(error) => log_shared(&format!("answering with JSON, the answer could not be encoded: {}", error)),
+        }
+    }
+    EncodedBody { body, is_base64_encoded: false, content_type: Encoding::Json.content_type() }
+}
+
+/// Status and body answering a `method` request that `validate` produced `outcome` for.
+pub fn answer(method: &str, outcome: Result<Answer, ServiceError>) -> (u16, String) {
+    match outcome {
+        Ok(answer) => (ErrorMapper::rejection_status(&answer.errors), answer.body),
+        Err(error) if method != "POST" => (405, error.to_json()),
+        Err(error) => (ErrorMapper::status(&error), error.to_json()),
+    }
+}
+
+/// The status of every failure the HTTP front ends answer, so that the load balancer, Function
+/// URL and local server agree on it.
+pub struct ErrorMapper;
+
+impl ErrorMapper {
+    /// Status answering a request that failed with `error`.
+    pub fn status(error: &ServiceError) -> u16 {
+        match *error {
+            ServiceError::InvalidRequest(_) | ServiceError::BadRequest { .. } | ServiceError::UnknownField(_) => 400,
+            ServiceError::Unauthorized(_) => 403,
+            ServiceError::RateLimited { .. } => 429,
+            _ if error.retryable() => 503,
+            _ => 500,
+        }
+    }
+
+    /// Status answering a validation that rejected the serial with the error codes `errors`: a
+    /// serial that is malformed is `400` even when it also exists, one that only exists `409`, and
+    /// one whose uniqueness could not be checked in time `503`.
+    pub fn rejection_status(errors: &[String]) -> u16 {
+        if errors.iter().any(|error| error != "already_exists" && error != "timeout") {
+            400
+        } else if errors.iter().any(|error| error == "already_exists") {
+            409
+        } else if errors.iter().any(|error| error == "timeout") {
+            503
+        } else {
+            200
+        }
+    }
+}
+
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        429 => "Too Many Requests",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(codes: &[&str]) -> Vec<String> {
+        codes.iter().map(|code| code.to_string()).collect()
+    }
+
+    #[test]
+    fn maps_rejections_to_statuses() {
+        assert_eq!(200, ErrorMapper::rejection_status(&[]));
+        assert_eq!(409, ErrorMapper::rejection_status(&codes(&["already_exists"])));
+        assert_eq!(400, ErrorMapper::rejection_status(&codes(&["invalid_checksum", "already_exists"])));
+        assert_eq!(400, ErrorMapper::rejection_status(&codes(&["too_long"])));
+        assert_eq!(503, ErrorMapper::rejection_status(&codes(&["timeout"])));
+    }
+
+    #[test]
+    fn maps_service_errors_to_statuses() {
+        assert_eq!(400, ErrorMapper::status(&ServiceError::InvalidRequest(String::from("unknown action"))));
+        assert_eq!(403, ErrorMapper::status(&ServiceError::Unauthorized(String::from("no key"))));
+        assert_eq!(429, ErrorMapper::status(&ServiceError::RateLimited { retry_after_seconds: 1 }));
+        assert_eq!(503, ErrorMapper::status(&ServiceError::StoreThrottled(String::from("slow down"))));
+        assert_eq!(500, ErrorMapper::status(&ServiceError::StoreMisconfigured(String::from("no table"))));
+        assert_eq!((409, String::from("{}")), answer("POST", Ok(Answer { body: String::from("{}"), errors: codes(&["already_exists"]) })));
+    }
+}
@@ -0,0 +1,202 @@
+//! Loading serials registered elsewhere into the table, e.g. when migrating from another system.
+//! Serials come inline or from a manifest in S3, read like those of bulk validation. Each has to
+//! pass the format rules; serials the table already holds are left alone, the others are written
+//! in batches and reported one by one.
+
+use std::io::{BufRead, BufReader};
+use std::time::{Duration, Instant};
+
+use rusoto_core::Region;
+use serde_derive::Serialize;
+use schemars::JsonSchema;
+
+use crate::aws;
+use crate::bulk::{self, SeenSerials};
+use crate::context::RequestContext;
+use crate::error::ServiceError;
+use crate::rules::ValidatorRegistry;
+use crate::store::SerialStore;
+use crate::ValidationError;
+
+/// Most serials an event may carry inline; larger imports go through a manifest.
+pub const MAX_INLINE_SERIALS: usize = 1000;
+
+/// Serials checked and written together.
+const CHUNK_SIZE: usize = 1000;
+
+/// Longest reading the manifest or a single store call may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time kept back from the invocation to answer with the report.
+const REPORT_TIME: Duration = Duration::from_secs(2);
+
+/// Where the serials of an import come from.
+pub enum ImportSource {
+    Inline(Vec<String>),
+    /// `s3://<bucket>/<key>` of a manifest in the function's region.
+    Manifest { bucket: String, key: String }
+}
+
+impl ImportSource {
+    /// The manifest at `location`, an `s3://<bucket>/<key>` URL.
+    pub fn manifest(location: &str) -> Result<ImportSource, ServiceError> {
+        location.strip_prefix("s3://")
+            .and_then(|path| path.split_once('/'))
+            .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+            .map(|(bucket, key)| ImportSource::Manifest { bucket: bucket.to_string(), key: key.to_string() })
+            .ok_or_else(|| ServiceError::InvalidRequest(format!("manifest must be an s3://<bucket>/<key> URL, got `{}`", location)))
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportOutcome {
+    Imported,
+    /// Breaks a format rule, is already held or repeats an earlier serial, see `error`.
+    Rejected,
+    /// The table kept refusing the write; importing the serial again may succeed.
+    Unprocessed
+}
+
+#[derive(Serialize, Debug, PartialEq, JsonSchema)]
+pub struct ImportedSerial {
+    #[serde(rename = "serialNumber")]
+    serial_number: String,
+    outcome: ImportOutcome,
+    /// Code of the broken rule, `already_exists`, `duplicate_in_request` or `missing_serial`, when
+    /// rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, JsonSchema)]
+pub struct ImportReport {
+    imported: u64,
+    rejected: u64,
+    unprocessed: u64,
+    /// `false` when the invocation ran out of time; `serials` then covers the serials read so far.
+    complete: bool,
+    serials: Vec<ImportedSerial>
+}
+
+/// Imports the serials of `source` into `store`, stopping before the deadline of the request.
+pub fn run(source: &ImportSource, context: &RequestContext, store: &dyn SerialStore, validators: &ValidatorRegistry) -> Result<ImportReport, ServiceError> {
+    let deadline = context.deadline;
+    let serial_numbers = match *source {
+        ImportSource::Inline(ref serial_numbers) => serial_numbers.clone(),
+        ImportSource::Manifest { ref bucket, ref key } => {
+            let s3_error = |error: String| ServiceError::StoreUnavailable(format!("s3://{}/{}: {}", bucket, key, error));
+            let input = aws::stream_object(&Region::default(), bucket, key, Some(REQUEST_TIMEOUT)).map_err(|error| s3_error(error.to_string()))?;
+            manifest_serials(BufReader::new(input)).map_err(s3_error)?
+        },
+    };
+    let mut report = ImportReport { complete: true, ..Default::default() };
+    let mut seen = SeenSerials::default();
+    for chunk in serial_numbers.chunks(CHUNK_SIZE) {
+        let timeout = match deadline.checked_duration_since(Instant::now() + REPORT_TIME) {
+            Some(remaining) => remaining.min(REQUEST_TIMEOUT),
+            None => {
+                report.complete = false;
+                break;
+            },
+        };
+        import_chunk(chunk, &mut seen, &mut report, store, validators, timeout)?;
+    }
+    context.log(&format!("import finished: {} imported, {} rejected, {} unprocessed, complete: {}", report.imported, report.rejected, report.unprocessed, report.complete));
+    Ok(report)
+}
+
+/// Serials of a manifest, one per line, skipping blank lines and a header.
+fn manifest_serials<R: BufRead>(input: R) -> Result<Vec<String>, String> {
+    let mut serial_numbers = Vec::new();
+    for (index, line) in input.split(b'\n').enumerate() {
+        let line = line.map_err(|error| error.to_string())?;
+        let line = String::from_utf8_lossy(&line);
+        let serial_number = bulk::manifest_serial(&line);
+        if serial_number.is_empty() || (index == 0 && bulk::is_header(serial_number)) {
+            continue;
+        }
+        serial_numbers.push(serial_number.to_string());
+    }
+    Ok(serial_numbers)
+}
+
+fn import_chunk(chunk: &[String], seen: &mut SeenSerials, report: &mut ImportReport, store: &dyn SerialStore, validators: &ValidatorRegistry, timeout: Duration) -> Result<(), ServiceError> {
+    let rule_errors: Vec<Option<ValidationError>> = chunk.iter()
+        .map(|serial_number| if serial_number.trim().chars().all(char::is_control) {
+            Some(ValidationError::MissingSerial)
+        } else if seen.repeats(serial_number) {
+            Some(ValidationError::DuplicateInRequest)
+        } else {
+            validators.blocking().find_map(|validator| validator.validate(serial_number))
+        })
+        .collect();
+    let candidates: Vec<&str> = chunk.iter().zip(&rule_errors)
+        .filter(|&(_, error)| error.is_none())
+        .map(|(serial_number, _)| serial_number.as_str())
+        .collect();
+    // BatchWriteItem cannot be conditional, so serials already held are kept out of the writes
+    let found = store.contains_many(&candidates, Some(timeout)).map_err(ServiceError::from)?;
+    let writes: Vec<&str> = candidates.iter().zip(&found).filter(|&(_, &found)| !found).map(|(serial_number, _)| *serial_number).collect();
+    let written = store.import_many(&writes, Some(timeout)).map_err(ServiceError::from)?;
+
+    let mut found = found.into_iter();
+    let mut written = written.into_iter();
+    for (serial_number, rule_error) in chunk.iter().zip(rule_errors) {
+        let (outcome, error) = match rule_error {
+            Some(error) => (ImportOutcome::Rejected, Some(error.value())),
+            None if found.next().unwrap_or(false) => (ImportOutcome::Rejected, Some(ValidationError::AlreadyExists.value())),
+            None if written.next().unwrap_or(false) => (ImportOutcome::Imported, None),
+            None => (ImportOutcome::Unprocessed, None),
+        };
+        match outcome {
+            ImportOutcome::Imported => report.imported += 1,
+            ImportOutcome::Rejected => report.rejected += 1,
+            ImportOutcome::Unprocessed => report.unprocessed += 1,
+        }
+        report.serials.push(ImportedSerial { serial_number: serial_number.clone(), outcome, error });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn imports_the_serials_that_pass_and_are_not_held() {
+        let store = MemoryStore::new(vec![String::from("serial1")]);
+        let source = ImportSource::Inline(vec![String::from("AB1234"), String::from("serial1"), String::from("i2@4"), String::from("ab1234 "), String::from(" ")]);
+        let context = RequestContext::new(String::from("req-1"), Instant::now() + Duration::from_secs(5));
+        let report = run(&source, &context, &store, &ValidatorRegistry::default()).ok().unwrap();
+        assert_eq!((1, 4, 0, true), (report.imported, report.rejected, report.unprocessed, report.complete));
+        let outcomes: Vec<(&str, ImportOutcome, Option<&str>)> = report.serials.iter().map(|serial| (serial.serial_number.as_str(), serial.outcome, serial.error.as_deref())).collect();
+        assert_eq!(vec![
+            ("AB1234", ImportOutcome::Imported, None),
+            ("serial1", ImportOutcome::Rejected, Some("already_exists")),
+            ("i2@4", ImportOutcome::Rejected, Some("invalid_format")),
+            ("ab1234 ", ImportOutcome::Rejected, Some("duplicate_in_request")),
+            (" ", ImportOutcome::Rejected, Some("missing_serial"))
+        ], outcomes);
+        assert_eq!(true, store.contains("AB1234", None).ok().unwrap());
+    }
+
+    #[test]
+    fn stops_at_the_deadline() {
+        let store = MemoryStore::new(Vec::new());
+        let context = RequestContext::new(String::from("req-1"), Instant::now());
+        let report = run(&ImportSource::Inline(vec![String::from("AB1234")]), &context, &store, &ValidatorRegistry::default()).ok().unwrap();
+        assert_eq!((false, 0), (report.complete, report.serials.len()));
+    }
+
+    #[test]
+    fn reads_manifest_locations_and_lines() {
+        match ImportSource::manifest("s3://migration/legacy/serials.csv").ok().unwrap() {
+            ImportSource::Manifest { bucket, key } => assert_eq!(("migration", "legacy/serials.csv"), (bucket.as_str(), key.as_str())),
+            ImportSource::Inline(_) => panic!("expected a manifest"),
+        }
+        assert_eq!(true, ImportSource::manifest("migration/serials.csv").is_err());
+        assert_eq!(vec!["AB1234", "CD5678"], manifest_serials("serialNumber\nAB1234,x1\n\n\"CD5678\"\n".as_bytes()).ok().unwrap());
+    }
+}
@@ -0,0 +1,226 @@
+//! Checks of the validation event before it is deserialized, so that a missing, mistyped or
+//! oversized field is answered with a `BadRequest` naming it rather than with a serde error about
+//! the whole event. Fields spelled in snake_case are renamed to the camelCase the event uses first.
+
+use serde_json::Value;
+
+use crate::error::ServiceError;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    /// A string of at most this many characters.
+    Text(usize),
+    Flag,
+    /// A non-negative integer.
+    Count,
+    Integer,
+    /// A list of strings of at most this many characters each.
+    TextList(usize)
+}
+
+/// Fields of `ValidationEvent` and what they have to hold. Fields besides `serialNumber`, the
+/// flags, `rules` and `serials` are optional and may be `null`.
+const FIELDS: [(&str, Kind); 33] = [
+    // its length is up to `MAX_SERIAL_LENGTH`, answered with `too_long` rather than a bad request
+    ("serialNumber", Kind::Text(usize::MAX)),
+    ("tenantId", Kind::Text(128)),
+    ("action", Kind::Text(32)),
+    ("bypassToken", Kind::Text(2_048)),
+    ("strategy", Kind::Text(32)),
+    ("includeMeta", Kind::Flag),
+    ("taskToken", Kind::Text(1_024)),
+    ("responseVersion", Kind::Count),
+    ("budgetMs", Kind::Count),
+    ("pendingToken", Kind::Text(2_048)),
+    ("resultToken", Kind::Text(2_048)),
+    ("locale", Kind::Text(256)),
+    ("correlationId", Kind::Text(256)),
+    ("dryRun", Kind::Flag),
+    ("ownerId", Kind::Text(128)),
+    ("fromOwner", Kind::Text(128)),
+    ("status", Kind::Text(64)),
+    ("expectedVersion", Kind::Count),
+    ("productLine", Kind::Text(64)),
+    ("idempotencyKey", Kind::Text(128)),
+    ("adminKey", Kind::Text(256)),
+    ("apiKey", Kind::Text(256)),
+    ("prefix", Kind::Text(128)),
+    ("registeredFrom", Kind::Count),
+    ("registeredTo", Kind::Count),
+    ("limit", Kind::Integer),
+    ("nextToken", Kind::Text(4_096)),
+    ("rules", Kind::TextList(32)),
+    ("ttlSeconds", Kind::Count),
+    ("issuedBy", Kind::Text(128)),
+    ("reason", Kind::Text(1_024)),
+    // like `serialNumber`, too long serials are rejected by the rules
+    ("serials", Kind::TextList(usize::MAX)),
+    ("manifest", Kind::Text(2_048))
+];
+
+/// Actions that work on the serial in `serialNumber`.
+const SERIAL_ACTIONS: [&str; 8] = ["validate", "reserve", "register", "update", "transfer", "confirm", "release", "issueBypassToken"];
+
+/// Checks a validation event. Events of the other sources, told apart by their `Records` or
+/// `requestContext`, are left to their own parsing.
+pub fn check_event(payload: &Value) -> Result<(), ServiceError> {
+    let fields = match *payload {
+        Value::Object(ref fields) if fields.contains_key("Records") || fields.contains_key("requestContext") => return Ok(()),
+        Value::Object(ref fields) => fields,
+        _ => return Err(bad_request("event", "must be a JSON object")),
+    };
+    for &(name, kind) in FIELDS.iter() {
+        match fields.get(name) {
+            None => {},
+            Some(&Value::Null) if is_nullable(name) => {},
+            Some(value) => check_field(name, kind, value)?,
+        }
+    }
+    let action = fields.get("action").and_then(Value::as_str).unwrap_or("validate");
+    if SERIAL_ACTIONS.contains(&action) && !fields.get("serialNumber").is_some_and(Value::is_string) {
+        return Err(bad_request("serialNumber", "is required"));
+    }
+    Ok(())
+}
+
+/// Rejects a validation event holding a field `ValidationEvent` does not know, such as a
+/// misspelled `serailNumber`, which would otherwise be ignored. Checked with `STRICT_INPUT` only,
+/// before `check_event`, so that a typo is named rather than reported as a missing field.
+pub fn check_known_fields(payload: &Value) -> Result<(), ServiceError> {
+    let fields = match *payload {
+        Value::Object(ref fields) if !fields.contains_key("Records") && !fields.contains_key("requestContext") => fields,
+        _ => return Ok(()),
+    };
+    match fields.keys().find(|name| !FIELDS.iter().any(|&(known, _)| known == name.as_str())) {
+        Some(name) => Err(ServiceError::UnknownField(name.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Renames the snake_case fields of a validation event, such as `serial_number`, to their
+/// camelCase spelling, so that every check and type sees one spelling. Giving both spellings of a
+/// field is a bad request. Events of the other sources keep their own spelling.
+pub fn normalize_field_names(payload: &mut Value) -> Result<(), ServiceError> {
+    let fields = match *payload {
+        Value::Object(ref mut fields) if !fields.contains_key("Records") && !fields.contains_key("requestContext") => fields,
+        _ => return Ok(()),
+    };
+    let snake_case: Vec<String> = fields.keys().filter(|name| camel_case(name).is_some()).cloned().collect();
+    for name in snake_case {
+        let renamed = camel_case(&name).unwrap_or_default();
+        if fields.contains_key(&renamed) {
+            return Err(bad_request(&name, &format!("duplicates {}", renamed)));
+        }
+        let value = fields.remove(&name).unwrap_or_default();
+        fields.insert(renamed, value);
+    }
+    Ok(())
+}
+
+/// camelCase spelling of a snake_case `name`, `None` when it is not spelled in snake_case.
+fn camel_case(name: &str) -> Option<String> {
+    if !name.contains('_') || name.starts_with('_') || name.ends_with('_') || name.chars().any(char::is_uppercase) {
+        return None;
+    }
+    let mut words = name.split('_');
+    let mut renamed = words.next().unwrap_or_default().to_string();
+    for word in words {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            renamed.extend(first.to_uppercase());
+            renamed.push_str(chars.as_str());
+        }
+    }
+    Some(renamed)
+}
+
+fn is_nullable(name: &str) -> bool {
+    !matches!(name, "serialNumber" | "includeMeta" | "dryRun" | "rules" | "serials")
+}
+
+fn check_field(name: &str, kind: Kind, value: &Value) -> Result<(), ServiceError> {
+    match kind {
+        Kind::Text(max_length) => check_text(name, max_length, value),
+        Kind::Flag if value.is_boolean() => Ok(()),
+        Kind::Flag => Err(bad_request(name, "must be true or false")),
+        Kind::Count if value.is_u64() => Ok(()),
+        Kind::Count => Err(bad_request(name, "must be a non-negative integer")),
+        Kind::Integer if value.is_i64() => Ok(()),
+        Kind::Integer => Err(bad_request(name, "must be an integer")),
+        Kind::TextList(max_length) => match *value {
+            Value::Array(ref values) => values.iter().try_for_each(|value| check_text(name, max_length, value)),
+            _ => Err(bad_request(name, "must be a list of strings")),
+        },
+    }
+}
+
+fn check_text(name: &str, max_length: usize, value: &Value) -> Result<(), ServiceError> {
+    match value.as_str() {
+        Some(text) if text.chars().count() > max_length => Err(bad_request(name, &format!("must be at most {} characters long", max_length))),
+        Some(_) => Ok(()),
+        None => Err(bad_request(name, "must be a string")),
+    }
+}
+
+fn bad_request(field: &str, reason: &str) -> ServiceError {
+    ServiceError::BadRequest { field: field.to_string(), reason: reason.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn field(outcome: Result<(), ServiceError>) -> String {
+        match outcome {
+            Err(ServiceError::BadRequest { field, .. }) => field,
+            _ => String::new(),
+        }
+    }
+
+    #[test]
+    fn names_missing_and_null_serials() {
+        assert_eq!("serialNumber", field(check_event(&json!({}))));
+        assert_eq!("serialNumber", field(check_event(&json!({"serialNumber": null}))));
+        assert_eq!("serialNumber", field(check_event(&json!({"action": "reserve"}))));
+        assert_eq!(true, check_event(&json!({"action": "generate"})).is_ok());
+    }
+
+    #[test]
+    fn names_mistyped_and_oversized_fields() {
+        assert_eq!("serialNumber", field(check_event(&json!({"serialNumber": 1234}))));
+        assert_eq!("status", field(check_event(&json!({"serialNumber": "AB1234", "status": "s".repeat(65)}))));
+        assert_eq!("dryRun", field(check_event(&json!({"serialNumber": "AB1234", "dryRun": "yes"}))));
+        assert_eq!("expectedVersion", field(check_event(&json!({"serialNumber": "AB1234", "expectedVersion": -1}))));
+        assert_eq!("rules", field(check_event(&json!({"serialNumber": "AB1234", "rules": ["length", 1]}))));
+        assert_eq!("event", field(check_event(&json!("AB1234"))));
+    }
+
+    #[test]
+    fn renames_snake_case_fields() {
+        let mut event = json!({"serial_number": "AB1234", "tenantId": "acme", "include_meta": true, "budget_ms": 50, "Records_": 1});
+        assert_eq!(true, normalize_field_names(&mut event).is_ok());
+        assert_eq!(json!({"serialNumber": "AB1234", "tenantId": "acme", "includeMeta": true, "budgetMs": 50, "Records_": 1}), event);
+        let mut both = json!({"serialNumber": "AB1234", "serial_number": "CD5678"});
+        assert_eq!("serial_number", field(normalize_field_names(&mut both)));
+        let mut record = json!({"Records": [], "event_source": "aws:kinesis"});
+        assert_eq!(true, normalize_field_names(&mut record).is_ok());
+        assert_eq!(true, record.get("event_source").is_some());
+    }
+
+    #[test]
+    fn names_unknown_fields() {
+        match check_known_fields(&json!({"serailNumber": "AB1234", "tenantId": "acme"})) {
+            Err(ServiceError::UnknownField(field)) => assert_eq!("serailNumber", field),
+            _ => panic!(),
+        }
+        assert_eq!(true, check_known_fields(&json!({"serialNumber": "AB1234", "tenantId": null, "productLine": "XK"})).is_ok());
+        assert_eq!(true, check_known_fields(&json!({"Records": [], "unknown": 1})).is_ok());
+    }
+
+    #[test]
+    fn accepts_null_optional_fields_and_other_sources() {
+        assert_eq!(true, check_event(&json!({"serialNumber": "AB1234", "tenantId": null, "unknown": [1]})).is_ok());
+        assert_eq!(true, check_event(&json!({"Records": []})).is_ok());
+    }
+}
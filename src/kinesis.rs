@@ -0,0 +1,150 @@
+//! Validation of serials arriving on a Kinesis stream. Records are validated in parallel and the
+//! ones that could not be answered are reported back, so Lambda retries only those.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::context::RequestContext;
+use crate::error::ServiceError;
+use crate::input;
+use crate::{ValidationEvent, ValidationResult};
+
+#[derive(Deserialize)]
+pub struct KinesisEvent {
+    #[serde(rename = "Records")]
+    records: Vec<KinesisEventRecord>
+}
+
+#[derive(Deserialize)]
+struct KinesisEventRecord {
+    /// `<shardId>:<sequenceNumber>`, unique per record.
+    #[serde(rename = "eventID")]
+    event_id: String,
+    kinesis: KinesisRecord
+}
+
+#[derive(Deserialize)]
+struct KinesisRecord {
+    #[serde(rename = "sequenceNumber")]
+    sequence_number: String,
+    /// Base64 encoded payload: a validation event as JSON, or the bare serial.
+    data: String
+}
+
+/// Partial batch response, read by Lambda when the event source mapping reports batch item failures.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct KinesisBatchResponse {
+    #[serde(rename = "batchItemFailures")]
+    batch_item_failures: Vec<BatchItemFailure>
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct BatchItemFailure {
+    #[serde(rename = "itemIdentifier")]
+    item_identifier: String
+}
+
+/// Validation event carried by a record's payload.
+fn decode(data: &str) -> Result<ValidationEvent, String> {
+    let bytes = base64::decode(data).map_err(|error| error.to_string())?;
+    let payload = String::from_utf8(bytes).map_err(|error| error.to_string())?;
+    let payload = payload.trim();
+    if payload.starts_with('{') {
+        let mut event = serde_json::from_str(payload).map_err(|error| error.to_string())?;
+        input::normalize_field_names(&mut event).map_err(|error| error.message())?;
+        return serde_json::from_value(event).map_err(|error| error.to_string());
+    }
+    serde_json::from_value(serde_json::json!({ "serialNumber": payload })).map_err(|error| error.to_string())
+}
+
+/// Validates every record with at most `concurrency` running at once. `validate` is given the
+/// decoded event and the record's event id. Records whose validation failed with a retryable
+/// error are reported as failures; undecodable records and other errors are logged with the
+/// invocation's `context` and dropped, since retrying them cannot succeed.
+pub fn run<F>(event: &KinesisEvent, context: &RequestContext, concurrency: usize, validate: F) -> KinesisBatchResponse
+    where F: Fn(&ValidationEvent, &str) -> Result<ValidationResult, ServiceError> + Sync
+{
+    let next = AtomicUsize::new(0);
+    let failed = Mutex::new(Vec::new());
+    let (valid, invalid) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, event.records.len().max(1)) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let record = match event.records.get(index) {
+                        Some(record) => record,
+                        None => break,
+                    };
+                    let validation_event = match decode(&record.kinesis.data) {
+                        Ok(validation_event) => validation_event,
+                        Err(error) => {
+                            context.log(&format!("dropping undecodable Kinesis record {}: {}", record.event_id, error));
+                            continue;
+                        },
+                    };
+                    match validate(&validation_event, &record.event_id) {
+                        Ok(ref result) if result.is_valid => valid.fetch_add(1, Ordering::SeqCst),
+                        Ok(_) => invalid.fetch_add(1, Ordering::SeqCst),
+                        Err(ref error) if error.retryable() => {
+                            failed.lock().unwrap().push((index, record.kinesis.sequence_number.clone()));
+                            continue;
+                        },
+                        Err(error) => {
+                            context.log(&format!("dropping Kinesis record {}: {}", record.event_id, error.to_json()));
+                            continue;
+                        },
+                    };
+                }
+            });
+        }
+    });
+
+    let mut failed = failed.into_inner().unwrap();
+    context.log(&format!("validated {} Kinesis records: {} valid, {} invalid, {} to retry", event.records.len(), valid.into_inner(), invalid.into_inner(), failed.len()));
+    // reported in stream order, Lambda resumes the shard from the earliest failure
+    failed.sort();
+    KinesisBatchResponse {
+        batch_item_failures: failed.into_iter().map(|(_, item_identifier)| BatchItemFailure { item_identifier }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use crate::StageTimings;
+
+    fn test_event(payloads: &[&str]) -> KinesisEvent {
+        let records: Vec<serde_json::Value> = payloads.iter().enumerate().map(|(index, payload)| serde_json::json!({
+            "eventID": format!("shardId-000000000000:{}", index),
+            "eventSource": "aws:kinesis",
+            "kinesis": {"sequenceNumber": index.to_string(), "partitionKey": "p", "data": base64::encode(payload)}
+        })).collect();
+        serde_json::from_value(serde_json::json!({ "Records": records })).unwrap()
+    }
+
+    #[test]
+    fn decodes_json_and_bare_serials() {
+        assert_eq!("AB1234", decode(&base64::encode(r#"{"serialNumber": "AB1234", "tenantId": "acme"}"#)).ok().unwrap().serial_number);
+        assert_eq!("CD5678", decode(&base64::encode("CD5678\n")).ok().unwrap().serial_number);
+        assert_eq!(Some(String::from("acme")), decode(&base64::encode(r#"{"serial_number": "AB1234", "tenant_id": "acme"}"#)).ok().unwrap().tenant_id);
+        assert_eq!(true, decode("not base64!").is_err());
+    }
+
+    #[test]
+    fn reports_retryable_failures_in_stream_order() {
+        let event = test_event(&["AB1234", "FAIL01", "CD5678", "FAIL02", "BAD001"]);
+        let context = RequestContext::new(String::from("req-1"), Instant::now());
+        let response = run(&event, &context, 3, |validation_event, _| match validation_event.serial_number.as_str() {
+            "FAIL01" | "FAIL02" => Err(ServiceError::StoreThrottled(String::from("slow down"))),
+            "BAD001" => Err(ServiceError::InvalidRequest(String::from("unknown tenant"))),
+            _ => Ok(ValidationResult { is_valid: true, errors: Vec::new(), error_details: Vec::new(), warnings: Vec::new(), segment_mismatch: None, conflict: None, similar_serials: Vec::new(), uniqueness: None, pending_token: None, bypass: None, meta: None, correlation_id: None, timings: StageTimings::default() }),
+        });
+        let failed: Vec<&str> = response.batch_item_failures.iter().map(|failure| failure.item_identifier.as_str()).collect();
+        assert_eq!(vec!["1", "3"], failed);
+    }
+}
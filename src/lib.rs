@@ -0,0 +1,3 @@
+//! The serial validation rules as a library, without the Lambda function around them.
+
+pub mod core;
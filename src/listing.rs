@@ -0,0 +1,111 @@
+//! The admin `list` action: pages through registered serials by prefix and registration date,
+//! handing callers an opaque `nextToken` to continue from. The admin `lookup` action reads the
+//! item of a single serial.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rusoto_dynamodb::AttributeValue;
+use serde_derive::Serialize;
+use schemars::JsonSchema;
+
+use crate::error::ServiceError;
+use crate::store::{DynamoDbStore, ListQuery, ListedSerial};
+
+/// Items read per page when the caller gives no `limit`.
+pub const DEFAULT_LIMIT: i64 = 100;
+/// Most items read per page.
+pub const MAX_LIMIT: i64 = 1_000;
+
+#[derive(Serialize, JsonSchema)]
+pub struct ListedSerials {
+    pub serials: Vec<ListedSerial>,
+    /// Passed back as `nextToken` for the following page; absent after the last one.
+    #[serde(rename = "nextToken", skip_serializing_if = "Option::is_none")]
+    pub next_token: Option<String>
+}
+
+/// The item of a looked up serial, `null` when no live item holds it.
+#[derive(Serialize, JsonSchema)]
+pub struct LookedUpSerial {
+    pub serial: Option<ListedSerial>
+}
+
+/// Filters and position of a `list` request, as sent by the caller.
+#[derive(Default)]
+pub struct ListRequest<'a> {
+    pub prefix: Option<&'a str>,
+    pub registered_from: Option<u64>,
+    pub registered_to: Option<u64>,
+    pub limit: Option<i64>,
+    pub next_token: Option<&'a str>
+}
+
+pub fn run(store: &DynamoDbStore, request: &ListRequest, timeout: Duration) -> Result<ListedSerials, ServiceError> {
+    let page = store.list(&query(request)?, Some(timeout)).map_err(ServiceError::from)?;
+    Ok(ListedSerials {
+        serials: page.serials,
+        next_token: page.last_evaluated_key.as_ref().map(encode_token)
+    })
+}
+
+pub fn lookup(store: &DynamoDbStore, serial_number: &str, timeout: Duration) -> Result<LookedUpSerial, ServiceError> {
+    if serial_number.is_empty() {
+        return Err(ServiceError::InvalidRequest(String::from("lookup needs a serialNumber")));
+    }
+    store.lookup(serial_number, Some(timeout)).map(|serial| LookedUpSerial { serial }).map_err(ServiceError::from)
+}
+
+fn query(request: &ListRequest) -> Result<ListQuery, ServiceError> {
+    let limit = request.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(ServiceError::InvalidRequest(format!("limit must be 1 to {}", MAX_LIMIT)));
+    }
+    if let (Some(from), Some(to)) = (request.registered_from, request.registered_to) {
+        if from > to {
+            return Err(ServiceError::InvalidRequest(String::from("registeredFrom must not be after registeredTo")));
+        }
+    }
+    Ok(ListQuery {
+        prefix: request.prefix.unwrap_or_default().to_string(),
+        registered_from: request.registered_from,
+        registered_to: request.registered_to,
+        limit,
+        start_key: request.next_token.map(decode_token).transpose()?
+    })
+}
+
+/// `LastEvaluatedKey` as base64url encoded JSON. The key only positions the next read, whose
+/// filters still keep it within the tenant, so it is not signed.
+fn encode_token(key: &HashMap<String, AttributeValue>) -> String {
+    let key = serde_json::to_vec(key).expect("attribute values are always serializable");
+    base64::encode_config(&key, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_token(token: &str) -> Result<HashMap<String, AttributeValue>, ServiceError> {
+    base64::decode_config(token, base64::URL_SAFE_NO_PAD).ok()
+        .and_then(|key| serde_json::from_slice(&key).ok())
+        .ok_or_else(|| ServiceError::InvalidRequest(String::from("malformed nextToken")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::string_value;
+
+    #[test]
+    fn tokens_round_trip_the_last_evaluated_key() {
+        let mut key = HashMap::new();
+        key.insert(String::from("serial_number"), string_value("AB1234"));
+        assert_eq!(key, decode_token(&encode_token(&key)).ok().unwrap());
+        assert_eq!(true, decode_token("not a token").is_err());
+    }
+
+    #[test]
+    fn rejects_limits_out_of_range() {
+        assert_eq!(DEFAULT_LIMIT, query(&ListRequest::default()).ok().unwrap().limit);
+        assert_eq!(true, query(&ListRequest { limit: Some(0), ..Default::default() }).is_err());
+        assert_eq!(true, query(&ListRequest { limit: Some(MAX_LIMIT + 1), ..Default::default() }).is_err());
+        assert_eq!(true, query(&ListRequest { registered_from: Some(2), registered_to: Some(1), ..Default::default() }).is_err());
+    }
+}
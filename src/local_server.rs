@@ -0,0 +1,134 @@
+//! Minimal HTTP server running the Lambda handler locally, built with `--features local-server`.
+//! `POST /validate` takes the same JSON the function is invoked with and answers with the same
+//! response, or with the error contract when the handler fails.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use crate::context::RequestContext;
+use crate::error::ServiceError;
+use crate::http;
+use crate::{handle_payload, http_answer};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:3000";
+
+/// Time a request gets, standing in for the function timeout.
+const REQUEST_BUDGET: Duration = Duration::from_secs(30);
+
+/// Largest request body accepted.
+const MAX_BODY_LENGTH: usize = 1024 * 1024;
+
+struct HttpResponse {
+    status: u16,
+    body: String
+}
+
+/// Serves requests one at a time until the process is stopped.
+pub fn serve(address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    println!("serving POST /validate on http://{}", address);
+    for stream in listener.incoming() {
+        if let Err(error) = stream.and_then(respond) {
+            eprintln!("local request failed: {}", error);
+        }
+    }
+    Ok(())
+}
+
+fn respond(stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match read_request(&mut reader)? {
+        Some((method, path, body)) => route(&method, &path, &body),
+        None => HttpResponse { status: 400, body: String::new() },
+    };
+    write_response(stream, &response)
+}
+
+/// Method, path and body of the next request, `None` when it is malformed.
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<(String, String, Vec<u8>)>> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Ok(None),
+    };
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = match value.trim().parse() {
+                    Ok(length) if length <= MAX_BODY_LENGTH => length,
+                    _ => return Ok(None),
+                };
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Some((method, path, body)))
+}
+
+fn route(method: &str, path: &str, body: &[u8]) -> HttpResponse {
+    match (method, path) {
+        ("POST", "/validate") => invoke(body),
+        (_, "/validate") => HttpResponse { status: 405, body: String::new() },
+        _ => HttpResponse { status: 404, body: String::new() },
+    }
+}
+
+/// Runs the handler the way the Lambda runtime would for an invocation with `body` as event.
+fn invoke(body: &[u8]) -> HttpResponse {
+    let outcome = serde_json::from_slice(body)
+        .map_err(|error| ServiceError::InvalidRequest(format!("malformed event: {}", error)))
+        .and_then(|payload| {
+            let context = RequestContext::new(format!("local-{:016x}", rand::random::<u64>()), Instant::now() + REQUEST_BUDGET);
+            handle_payload(payload, &context)
+        });
+    let (status, body) = http::answer("POST", outcome.map(http_answer));
+    HttpResponse { status, body }
+}
+
+fn write_response<W: Write>(mut stream: W, response: &HttpResponse) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status, http::reason(response.status), response.body.len(), response.body)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_request_body() {
+        let request = "POST /validate HTTP/1.1\r\nHost: localhost\r\nContent-Length: 27\r\n\r\n{\"serialNumber\": \"AB1234\"}\n";
+        let (method, path, body) = read_request(&mut request.as_bytes()).ok().unwrap().unwrap();
+        assert_eq!(("POST", "/validate"), (method.as_str(), path.as_str()));
+        assert_eq!(b"{\"serialNumber\": \"AB1234\"}\n".to_vec(), body);
+    }
+
+    #[test]
+    fn answers_unknown_routes_and_malformed_events() {
+        assert_eq!(404, route("POST", "/", b"{}").status);
+        assert_eq!(405, route("GET", "/validate", b"").status);
+        let response = route("POST", "/validate", b"[");
+        assert_eq!(400, response.status);
+        assert_eq!(true, response.body.contains("InvalidRequest"));
+    }
+
+    #[test]
+    fn writes_the_error_contract_with_a_status() {
+        let mut written = Vec::new();
+        let error = ServiceError::StoreThrottled(String::from("slow down"));
+        write_response(&mut written, &HttpResponse { status: http::ErrorMapper::status(&error), body: error.to_json() }).ok().unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert_eq!(true, written.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert_eq!(true, written.ends_with(&error.to_json()));
+    }
+}
@@ -1,117 +1,1627 @@
 extern crate lambda_runtime as lambda;
+#[macro_use]
+extern crate lazy_static;
 extern crate serde_derive;
 extern crate rusoto_core;
 extern crate rusoto_dynamodb;
 
+mod alb;
+mod alerts;
+mod api_keys;
+mod audit;
+mod aws;
+#[cfg(feature = "bench")]
+mod bench;
+mod bloom;
+mod bulk;
+mod bypass;
+mod callback;
+mod caller;
+mod cli;
+mod config;
+mod context;
+mod describe;
+mod encoding;
+mod error;
+mod events;
+mod function_url;
+mod generate;
+mod hashing;
+mod import;
+mod health;
+mod http;
+mod input;
+mod kinesis;
+mod listing;
+mod messages;
+mod metrics;
+#[cfg(feature = "local-server")]
+mod local_server;
+mod pending;
+mod report;
+mod rate_limit;
+#[cfg(test)]
+mod replay;
+mod result_cache;
+mod results;
+mod rule_config;
+mod secrets;
+mod self_test;
+mod shadow;
+mod similarity;
+mod store;
+mod stream_consumer;
+mod tenant;
+
 use std::error::Error;
+use std::panic;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use serde_derive::{Serialize, Deserialize};
+use schemars::JsonSchema;
 use lambda::{lambda, Context, error::HandlerError};
+use serial_validation::core::{rules, template, ValidationError};
+use serial_validation::core::ranges::{RangeOwner, RangeRule, ReservedRange};
 
-use rusoto_core::Region;
-use rusoto_dynamodb::{DynamoDb, DynamoDbClient, GetItemInput, AttributeValue};
-use std::collections::HashMap;
+use alb::{AlbEvent, AlbResponse};
+use api_keys::{ApiKeySettings, KeyCache};
+use audit::AuditEntry;
+use bloom::{BloomFilter, FilterCache};
+use bulk::{BulkReport, S3Event};
+use bypass::{BypassToken, BYPASSABLE_RULES, MAX_TTL_SECONDS};
+use caller::CallerContext;
+use context::RequestContext;
+use describe::Description;
+use config::Config;
+use error::ServiceError;
+use function_url::{FunctionUrlEvent, FunctionUrlResponse};
+use generate::{GenerateError, GeneratorSettings};
+use hashing::{HashingMode, KeyHasher};
+use import::{ImportReport, ImportSource};
+use health::HealthReport;
+use kinesis::{KinesisBatchResponse, KinesisEvent};
+use listing::{ListedSerials, ListRequest, LookedUpSerial};
+use messages::MessageCatalog;
+use metrics::MetricGuards;
+use pending::PendingError;
+use report::RegistrationReport;
+use result_cache::ResultCache;
+use results::{ResultStatus, StoredResult};
+use rule_config::{RuleConfigCache, RuleConfigSettings};
+use secrets::SecretCache;
+use rules::{validate_serial_alphanumeric, Severity, ValidationStrategy, ValidatorRegistry, UNIQUENESS_CHECK};
+use self_test::SelfTestReport;
+use shadow::Divergence;
+use store::{SerialStore, ConflictingItem, IdempotencyKey, MetadataUpdate, MetadataUpdated, OwnedRegistration, OwnerTransfer, DynamoDbStore, DynamoDbSettings, StoreError, BloomFilteredStore, CachedStore, CircuitBreaker, CircuitBreakerStore, LatencyRoutedStore, ReplicaRouter, FailoverRouter, FailoverStore, SequenceBlocks, SequenceCounter, DynamoDbCounter, RangeCache, RangeSettings};
+#[cfg(feature = "fault-injection")]
+use store::FaultInjectingStore;
+use stream_consumer::stream_handler;
+use template::SegmentMismatch;
+
+/// Time reserved at the end of an invocation to serialize and send the response.
+const DEADLINE_MARGIN_MS: u128 = 250;
+
+/// Longest the health check waits for `DescribeTable` in each region.
+const HEALTH_CHECK_TIMEOUT_MS: u64 = 2_000;
+
+/// Longest the self test waits for the store probe.
+const SELF_TEST_PROBE_TIMEOUT_MS: u64 = 2_000;
+
+/// Lifetime of a bypass token when the admin does not ask for one.
+const DEFAULT_BYPASS_TTL_SECONDS: u64 = 15 * 60;
+
+/// Longest idempotency key stored alongside a generated serial.
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 128;
+
+lazy_static! {
+    /// Survives between invocations served by the same container, as does the state below. Read
+    /// once, so that malformed variables are warned about once per container rather than per request.
+    static ref CONFIG: Config = Config::from_env();
+    static ref STORE_BREAKER: CircuitBreaker = CircuitBreaker::new(CONFIG.circuit_breaker.clone());
+    static ref REPLICA_ROUTER: ReplicaRouter = ReplicaRouter::new(CONFIG.replica_routing.clone());
+    static ref FAILOVER_ROUTER: FailoverRouter = FailoverRouter::new(CONFIG.failover.clone());
+    static ref BLOOM_FILTERS: FilterCache = FilterCache::new();
+    static ref RESULT_CACHE: ResultCache = ResultCache::new();
+    static ref METRIC_GUARDS: MetricGuards = MetricGuards::new(&CONFIG.metrics.clone().unwrap_or_default());
+    static ref API_KEYS: KeyCache = KeyCache::new();
+    static ref RULE_CONFIG: RuleConfigCache = RuleConfigCache::new();
+    static ref SHADOW_RULE_CONFIG: RuleConfigCache = RuleConfigCache::new();
+    static ref SECRETS: SecretCache = SecretCache::new();
+    static ref RESERVED_RANGES: RangeCache = RangeCache::new();
+    static ref SEQUENCE_BLOCKS: SequenceBlocks = SequenceBlocks::new(CONFIG.sequence.as_ref().map_or(1, |sequence| sequence.block_size));
+    static ref MESSAGES: MessageCatalog = messages::load(&CONFIG.messages, Some(Duration::from_millis(MESSAGES_LOAD_TIMEOUT_MS)));
+}
+
+/// Longest a cold start waits for a bloom filter snapshot.
+const BLOOM_LOAD_TIMEOUT_MS: u64 = 2_000;
+
+/// Longest a request waits for the invalidation log of the cached results.
+const INVALIDATIONS_LOAD_TIMEOUT_MS: u64 = 500;
+
+/// Longest a request waits for the digests of the API keys.
+const API_KEYS_LOAD_TIMEOUT_MS: u64 = 2_000;
+
+/// Longest a container waits for the message catalog overrides in S3.
+const MESSAGES_LOAD_TIMEOUT_MS: u64 = 2_000;
+
+/// Longest each call polling the rule configuration may take.
+const RULE_CONFIG_TIMEOUT_MS: u64 = 2_000;
+
+/// Longest a request waits for the reserved ranges.
+const RANGES_LOAD_TIMEOUT_MS: u64 = 2_000;
+
+/// Longest a read of a secret may take.
+const SECRETS_LOAD_TIMEOUT_MS: u64 = 2_000;
+
+/// Longest each call to the rate limit table may take before the request goes ahead unlimited.
+const RATE_LIMIT_TIMEOUT_MS: u64 = 500;
+
+/// Longest the write of an audit item may take.
+const AUDIT_TIMEOUT_MS: u64 = 2_000;
+
+/// Longest each call counting a duplicate attempt or publishing its alert may take.
+const ALERT_TIMEOUT_MS: u64 = 2_000;
+
+/// Longest the publication of a validation event may take.
+const PUBLISH_TIMEOUT_MS: u64 = 2_000;
+
+/// Longest each call to the results table may take.
+const RESULTS_TIMEOUT_MS: u64 = 1000;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    lambda!(validation_handler);
+    if cfg!(feature = "stream-consumer") {
+        lambda!(stream_handler);
+    } else {
+        #[cfg(feature = "bench")]
+        {
+            if std::env::args().any(|arg| arg == "--bench") {
+                bench::run();
+                return Ok(());
+            }
+        }
+        #[cfg(feature = "local-server")]
+        {
+            let mut args = std::env::args().skip_while(|arg| arg != "--serve");
+            if args.next().is_some() {
+                let address = args.next().unwrap_or_else(|| String::from(local_server::DEFAULT_ADDRESS));
+                return local_server::serve(&address).map_err(Box::from);
+            }
+        }
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        if std::env::var_os(cli::RUNTIME_API_VARIABLE).is_none() && !args.is_empty() {
+            std::process::exit(cli::run(&args));
+        }
+        init();
+        lambda!(handler);
+    }
     Ok(())
 }
 
-fn validation_handler(event: ValidationEvent, _ctx: Context) -> Result<ValidationResult, HandlerError> {
-    Ok(validate_serial(event.serial_number.as_str()))
+/// Builds during the Lambda init phase what the first invocation would otherwise build: the rules
+/// and their regular expressions, the container's shared state, the message catalog, the bloom
+/// filter snapshot and the DynamoDB clients of the table, assuming its role when it has one. Logs
+/// how long each part took.
+fn init() {
+    let started = Instant::now();
+    let config = timed("rules", || &*CONFIG);
+    timed("shared state", || {
+        lazy_static::initialize(&STORE_BREAKER);
+        lazy_static::initialize(&REPLICA_ROUTER);
+        lazy_static::initialize(&FAILOVER_ROUTER);
+        lazy_static::initialize(&SEQUENCE_BLOCKS);
+    });
+    timed("messages", || lazy_static::initialize(&MESSAGES));
+    timed("bloom filter", || table_filter(&config.dynamodb.table_name, config));
+    if let Some(ref settings) = config.ranges {
+        timed("reserved ranges", || reserved_ranges(settings));
+    }
+    timed("store", || {
+        store::start_lookup_runtime();
+        table_store(config.dynamodb.clone(), config)
+    });
+    println!("init finished in {} ms", started.elapsed().as_millis());
+}
+
+/// Runs `stage` of `init`, logging how long it took.
+fn timed<T, F: FnOnce() -> T>(stage: &str, run: F) -> T {
+    let started = Instant::now();
+    let value = run();
+    println!("init: {} took {} ms", stage, started.elapsed().as_millis());
+    value
 }
 
-enum ValidationError {
-    InvalidFormat,
-    AlreadyExists
+/// Events the validator is invoked with. S3 and Kinesis events are told apart by the shape of
+/// their `Records`, ALB and Function URL requests by their `requestContext`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Event {
+    Bulk(S3Event),
+    Kinesis(KinesisEvent),
+    Alb(AlbEvent),
+    FunctionUrl(FunctionUrlEvent),
+    Validation(Box<ValidationEvent>)
 }
 
-impl ValidationError {
-    fn value(&self) -> String {
-        match *self {
-            ValidationError::InvalidFormat => String::from("invalid_format"),
-            ValidationError::AlreadyExists => String::from("already_exists"),
+fn handler(payload: serde_json::Value, ctx: Context) -> Result<serde_json::Value, HandlerError> {
+    let started = Instant::now();
+    let context = RequestContext::new(ctx.aws_request_id.clone(), invocation_deadline(&ctx));
+    let outcome = handle_payload(payload, &context).map(|response| {
+        let serialize_started = Instant::now();
+        let body = serde_json::to_value(&response).unwrap_or_default();
+        context.metrics.serialize(elapsed_micros(serialize_started));
+        body
+    });
+    if let Some(ref settings) = CONFIG.metrics {
+        context.metrics.emit(settings, &METRIC_GUARDS, elapsed_micros(started), unix_now_millis());
+    }
+    outcome.map_err(|error| ctx.new_error(&error.to_json()))
+}
+
+/// Answers warmup pings straight away and handles every other payload as an `Event`.
+fn handle_payload(payload: serde_json::Value, context: &RequestContext) -> Result<Response, ServiceError> {
+    catch_panics(context, || handle_unguarded(payload, context))
+}
+
+/// `response` serialized for an HTTP front end, with the errors that decide its status.
+fn http_answer(response: Response) -> http::Answer {
+    let errors = match response {
+        Response::Validation(ref result) => result.errors().to_vec(),
+        _ => Vec::new(),
+    };
+    http::Answer { body: serde_json::to_string(&response).unwrap_or_default(), errors }
+}
+
+/// Runs `handle`, turning a panic into an `InternalError` so that the caller still gets an answer
+/// following the error contract. The panic itself is logged by the panic hook, with its location.
+fn catch_panics<T, F>(context: &RequestContext, handle: F) -> Result<T, ServiceError>
+    where F: FnOnce() -> Result<T, ServiceError>
+{
+    panic::catch_unwind(panic::AssertUnwindSafe(handle)).unwrap_or_else(|panic| {
+        let message = panic.downcast_ref::<&str>().map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        context.log(&format!("panicked: {}", message));
+        Err(ServiceError::Internal(context.request_id.clone()))
+    })
+}
+
+fn handle_unguarded(mut payload: serde_json::Value, context: &RequestContext) -> Result<Response, ServiceError> {
+    let config = &*CONFIG;
+    if is_warmup(&payload, &config.warmup_marker) {
+        return Ok(Response::Warmup(warm_up(config)));
+    }
+    let parse_started = Instant::now();
+    input::normalize_field_names(&mut payload)?;
+    if config.strict_input {
+        input::check_known_fields(&payload)?;
+    }
+    input::check_event(&payload)?;
+    let event = serde_json::from_value(payload).map_err(|error| ServiceError::InvalidRequest(format!("malformed event: {}", error)))?;
+    context.metrics.parse(elapsed_micros(parse_started));
+    handle(event, context)
+}
+
+/// Whether `payload` is a scheduled warmer's ping, i.e. sets `marker` to anything but `false` or `null`.
+fn is_warmup(payload: &serde_json::Value, marker: &str) -> bool {
+    match payload.get(marker) {
+        None | Some(serde_json::Value::Null) | Some(serde_json::Value::Bool(false)) => false,
+        Some(_) => !marker.is_empty(),
+    }
+}
+
+/// Keeps the container warm without reading the table. With `WARMUP_PRELOAD` the shared state and
+/// the DynamoDB client are initialized and the bloom filter snapshot loaded, so the next real
+/// invocation does not pay for them.
+fn warm_up(config: &Config) -> WarmupAcknowledged {
+    if config.warmup_preload {
+        lazy_static::initialize(&STORE_BREAKER);
+        lazy_static::initialize(&REPLICA_ROUTER);
+        lazy_static::initialize(&FAILOVER_ROUTER);
+        lazy_static::initialize(&MESSAGES);
+        table_store(config.dynamodb.clone(), config);
+    }
+    WarmupAcknowledged { warm: true, preloaded: config.warmup_preload }
+}
+
+fn handle(event: Event, context: &RequestContext) -> Result<Response, ServiceError> {
+    match event {
+        Event::Bulk(event) => {
+            context.metrics.request("bulk", None, None);
+            bulk_handler(event, context)
+        },
+        Event::Kinesis(event) => {
+            context.metrics.request("kinesis", None, None);
+            Ok(kinesis_handler(event, context))
+        },
+        // caught here as well, so that HTTP callers get a 500 rather than the load balancer's 502
+        Event::Alb(event) => Ok(Response::Alb(alb::run(&event, CONFIG.strict_input, |validation_event| catch_panics(context, || {
+            validation_handler(validation_event, context).map(http_answer)
+        })))),
+        Event::FunctionUrl(event) => {
+            Ok(Response::FunctionUrl(function_url::run(&event, &CONFIG.cors, CONFIG.strict_input, |validation_event| catch_panics(context, || {
+                validation_handler(validation_event, context).map(http_answer)
+            }))))
+        },
+        Event::Validation(event) => validation_handler(*event, context),
+    }
+}
+
+fn bulk_handler(event: S3Event, context: &RequestContext) -> Result<Response, ServiceError> {
+    let config = request_config();
+    // manifests name no tenant or product line, so their serials are not checked against reserved ranges
+    let store = (context.tables)(config.dynamodb.clone(), &config);
+    // a failed job is retried by Lambda as a whole, overwriting the results of the failed attempt
+    let response = bulk::run(&event, context, &store, &config.validators, &config.bulk, config.results.as_ref()).map(Response::Bulk);
+    log_deployment_error(context, &response);
+    response
+}
+
+fn kinesis_handler(event: KinesisEvent, context: &RequestContext) -> Response {
+    let config = request_config();
+    let ranges = config.ranges.as_ref().and_then(reserved_ranges);
+    let ranged = Mutex::new(HashMap::new());
+    Response::Kinesis(kinesis::run(&event, context, config.kinesis_concurrency, |validation_event, event_id| {
+        let record = RequestContext::new(event_id.to_string(), context.deadline).with_tables(context.tables);
+        let record = record.for_request(validation_event.tenant_id.as_deref(), None, validation_event.locale.as_deref()).correlated(validation_event.correlation_id.as_deref());
+        match record_config(&config, ranges.as_ref(), &ranged, validation_event) {
+            Some(ranged) => validate_event(validation_event, &record, &ranged),
+            None => validate_event(validation_event, &record, &config),
         }
+    }))
+}
+
+/// `config` with the `range` rule for the tenant and product line of one record of a batch, built
+/// once for each owner the batch names and kept in `ranged` for the records that follow. `None`
+/// while no reserved ranges are loaded and `config` applies as it is.
+fn record_config(config: &Config, ranges: Option<&Arc<Vec<ReservedRange>>>, ranged: &Mutex<HashMap<RangeOwner, Arc<Config>>>, event: &ValidationEvent) -> Option<Arc<Config>> {
+    let ranges = ranges?;
+    let owner = RangeOwner { tenant_id: event.tenant_id.clone(), product_line: event.product_line.clone() };
+    let mut ranged = ranged.lock().unwrap();
+    Some(ranged.entry(owner.clone()).or_insert_with(|| {
+        let mut config = config.clone();
+        add_range_rule(&mut config, ranges.clone(), owner);
+        Arc::new(config)
+    }).clone())
+}
+
+/// Hasher of the keys of the audit and rate limit tables, `None` while its key cannot be read.
+fn key_hasher(mode: &HashingMode, refresh_interval: Duration) -> Option<KeyHasher> {
+    match *mode {
+        HashingMode::Sha256 => Some(KeyHasher::Sha256),
+        HashingMode::HmacSha256 { ref secret_id } => SECRETS.get(secret_id, refresh_interval, Instant::now(), |secret_id| {
+            secrets::fetch(secret_id, Some(Duration::from_millis(SECRETS_LOAD_TIMEOUT_MS)))
+        }).map(KeyHasher::HmacSha256),
+    }
+}
+
+/// The container's `Config` with the rules of the rule configuration, when one is set and could
+/// be loaded, and those of the candidate configuration judging validations in the shadow.
+fn request_config() -> Config {
+    let mut config = CONFIG.clone();
+    if let Some((validators, version)) = config.rule_config.as_ref().and_then(|settings| configured_rules(&RULE_CONFIG, settings)) {
+        config.validators = validators;
+        config.rule_config_version = Some(version);
+    }
+    config.shadow_validators = config.shadow_rule_config.as_ref().and_then(|settings| configured_rules(&SHADOW_RULE_CONFIG, settings));
+    config
+}
+
+/// The rules of the configuration polled into `cache` and its version, `None` while none could be loaded.
+fn configured_rules(cache: &RuleConfigCache, settings: &RuleConfigSettings) -> Option<(ValidatorRegistry, String)> {
+    let active = cache.get(settings.poll_interval, Instant::now(), |token| {
+        rule_config::poll(settings, token, Some(Duration::from_millis(RULE_CONFIG_TIMEOUT_MS)))
+    }, |document| {
+        let (names, rule_settings) = config::env_rules();
+        document.registry(names, rule_settings)
+    })?;
+    Some((active.validators.clone(), active.version.clone()))
+}
+
+/// The reserved ranges, `None` while none could be loaded.
+fn reserved_ranges(settings: &RangeSettings) -> Option<Arc<Vec<ReservedRange>>> {
+    RESERVED_RANGES.get(settings.refresh_interval, Instant::now(), || {
+        store::load_ranges(settings, Some(Duration::from_millis(RANGES_LOAD_TIMEOUT_MS)))
+    })
+}
+
+/// Adds the `range` rule for the tenant and product line of `event`, when reserved ranges are
+/// configured. Serials are not checked against the ranges while none could be loaded.
+fn apply_reserved_ranges(config: &mut Config, event: &ValidationEvent) {
+    if let Some(ranges) = config.ranges.as_ref().and_then(reserved_ranges) {
+        add_range_rule(config, ranges, RangeOwner { tenant_id: event.tenant_id.clone(), product_line: event.product_line.clone() });
+    }
+}
+
+/// Holds the active rules, and those judged in the shadow, to `ranges` as seen by `caller`.
+fn add_range_rule(config: &mut Config, ranges: Arc<Vec<ReservedRange>>, caller: RangeOwner) {
+    if let Some((ref mut shadow_validators, _)) = config.shadow_validators {
+        shadow_validators.register(Box::new(RangeRule::new(ranges.clone(), caller.clone())), Severity::Error);
     }
+    config.validators.register(Box::new(RangeRule::new(ranges, caller)), Severity::Error);
 }
 
-#[derive(Serialize, Deserialize)]
+fn validation_handler(event: ValidationEvent, context: &RequestContext) -> Result<Response, ServiceError> {
+    let mut config = request_config();
+    let deadline = context.deadline;
+    let key_holder = match config.api_keys {
+        Some(ref settings) => Some(authorize_caller(&event, settings)?),
+        None => None,
+    };
+    let caller = event.caller.clone().or_else(|| key_holder.map(CallerContext::api_key));
+    let context = &context.for_request(event.tenant_id.as_deref(), caller.clone(), event.locale.as_deref()).correlated(event.correlation_id.as_deref());
+    if let Some(ref caller) = caller {
+        context.log(&format!("called by {}", serde_json::to_string(caller).unwrap_or_default()));
+        let overrides = config.caller_policies.overrides_for(caller);
+        config.validators.override_rules(&overrides);
+        if let Some((ref mut shadow_validators, _)) = config.shadow_validators {
+            shadow_validators.override_rules(&overrides);
+        }
+        config.include_conflict = config.caller_permissions.allows(caller, caller::INCLUDE_CONFLICT);
+    }
+    apply_reserved_ranges(&mut config, &event);
+    context.metrics.request(event.action.as_deref().unwrap_or("validate"), event.tenant_id.as_deref(), Some(&config.validators.version()));
+    if let Some(ref settings) = config.rate_limit {
+        let key = rate_limit::limiter_key(caller.as_ref().map(|caller| caller.principal.as_str()), event.source_ip.as_deref());
+        // callers go unlimited rather than being keyed in clear while the hash key cannot be read
+        let hashed_key = key.and_then(|key| Some(key_hasher(&config.key_hashing, config.secrets_refresh_interval)?.hash(&key)));
+        if let Some(ref key) = hashed_key {
+            rate_limit::check(context, settings, key, unix_now_millis(), Some(Duration::from_millis(RATE_LIMIT_TIMEOUT_MS)))?;
+        }
+    }
+    let response = match event.action.as_deref() {
+        None | Some("validate") => validation_response(&event, context, &config),
+        Some("resolvePending") => {
+            let token = event.pending_token.as_deref().ok_or_else(|| ServiceError::InvalidRequest(String::from("resolvePending needs a pendingToken")))?;
+            validation_response(&pending_event(token)?, context, &config)
+        },
+        Some("getResult") => get_result(&event, context, &config).map(Response::Result),
+        Some("selfTest") => {
+            tenant::resolve(&config.tenants, event.tenant_id.as_deref(), &config.dynamodb).map(|settings| {
+                let store = (context.tables)(settings, &config);
+                Response::SelfTest(self_test::run(context, &store, Duration::from_millis(SELF_TEST_PROBE_TIMEOUT_MS)))
+            })
+        },
+        Some("healthcheck") => {
+            tenant::resolve(&config.tenants, event.tenant_id.as_deref(), &config.dynamodb).map(|settings| {
+                let regions = match config.failover_regions {
+                    Some((ref primary, ref secondary)) => vec![primary.clone(), secondary.clone()],
+                    None => config.replica_regions.clone(),
+                };
+                Response::Health(health::run(&settings, &regions, &config.validators, Duration::from_millis(HEALTH_CHECK_TIMEOUT_MS)))
+            })
+        },
+        Some("generate") => {
+            tenant::resolve(&config.tenants, event.tenant_id.as_deref(), &config.dynamodb).and_then(|settings| {
+                let store = CircuitBreakerStore::new((context.tables)(settings, &config), &STORE_BREAKER);
+                match config.sequence {
+                    Some(ref sequence) => generate_sequenced_serial(&store, &DynamoDbCounter::new(sequence), &SEQUENCE_BLOCKS, &event, &config, deadline),
+                    None if event.product_line.is_some() => Err(ServiceError::InvalidRequest(String::from("productLine needs SEQUENCE_TABLE to be set"))),
+                    None => generate_serial(&store, &config, event.tenant_id.as_deref(), event.idempotency_key.as_deref(), event.dry_run, unix_now(), deadline),
+                }
+            }).map(Response::Generated)
+        },
+        Some("reserve") => {
+            tenant::resolve(&config.tenants, event.tenant_id.as_deref(), &config.dynamodb).and_then(|settings| {
+                let store = CircuitBreakerStore::new((context.tables)(settings, &config), &STORE_BREAKER);
+                reserve_serial(&event.serial_number, &store, &config, event.dry_run, unix_now(), deadline)
+            }).map(Response::Reserved)
+        },
+        Some("register") => {
+            tenant::resolve(&config.tenants, event.tenant_id.as_deref(), &config.dynamodb).and_then(|settings| {
+                let store = CircuitBreakerStore::new((context.tables)(settings, &config), &STORE_BREAKER);
+                register_serial(&event.serial_number, event.owner_id.as_deref(), &store, &config, deadline)
+            }).map(Response::Registered)
+        },
+        Some("update") => {
+            tenant::resolve(&config.tenants, event.tenant_id.as_deref(), &config.dynamodb).and_then(|settings| {
+                let store = CircuitBreakerStore::new((context.tables)(settings, &config), &STORE_BREAKER);
+                update_serial(&event, &store, deadline)
+            }).map(Response::Updated)
+        },
+        Some("transfer") => {
+            tenant::resolve(&config.tenants, event.tenant_id.as_deref(), &config.dynamodb).and_then(|settings| {
+                let store = CircuitBreakerStore::new((context.tables)(settings, &config), &STORE_BREAKER);
+                transfer_serial(&event, &store, deadline)
+            }).map(Response::Transferred)
+        },
+        Some("confirm") => {
+            tenant::resolve(&config.tenants, event.tenant_id.as_deref(), &config.dynamodb).and_then(|settings| {
+                let store = CircuitBreakerStore::new((context.tables)(settings, &config), &STORE_BREAKER);
+                confirm_serial(&event.serial_number, &store, deadline)
+            }).map(Response::Confirmed)
+        },
+        Some("release") => release_serial(&event, context, &config, deadline).map(Response::Released),
+        Some("import") => import_serials(&event, context, &config).map(Response::Import),
+        Some("list") => list_serials(&event, &config, deadline).map(Response::Listed),
+        Some("lookup") => lookup_serial(&event, &config, deadline).map(Response::LookedUp),
+        Some("report") => report_registrations(&event, &config, deadline).map(Response::Report),
+        Some("issueBypassToken") => issue_bypass_token(&event, context, &config, unix_now()).map(Response::BypassToken),
+        Some("describe") => Ok(Response::Described(Box::new(describe::describe(&config)))),
+        Some(action) => Err(ServiceError::InvalidRequest(format!("unknown action `{}`", action))),
+    };
+    log_deployment_error(context, &response);
+    response
+}
+
+/// Logs `outcome` through `context` when it failed for a reason pointing at the deployment, such
+/// as a missing table or denied access, whose message names the table and region.
+fn log_deployment_error<T>(context: &RequestContext, outcome: &Result<T, ServiceError>) {
+    if let Err(ref error) = *outcome {
+        if matches!(*error, ServiceError::StoreMisconfigured(_) | ServiceError::StoreAccessDenied(_)) {
+            context.log(&error.message());
+        }
+    }
+}
+
+/// The validation result for `event`, also reported to the Step Functions task waiting for it.
+fn validation_response(event: &ValidationEvent, context: &RequestContext, config: &Config) -> Result<Response, ServiceError> {
+    let version = response_version(event, config)?;
+    // a pending validation is finished for the tenant and locale it was started with
+    let context = &context.for_request(event.tenant_id.as_deref(), context.caller.clone(), event.locale.as_deref());
+    let outcome = validate_event(event, context, config).map(|mut result| {
+        context.metrics.validation(result.timings.format_checks_micros, result.timings.store_lookup_micros);
+        shadow_validation(event, context, config);
+        if event.include_meta {
+            result.meta = Some(response_meta(&context.request_id, config, result.timings));
+        }
+        result.error_details = error_details(&result.errors, &MESSAGES, event.locale.as_deref());
+        result.correlation_id = context.correlation_id.clone();
+        VersionedResult::new(result, version)
+    });
+    match event.task_token {
+        // the state machine waits for the callback, so a lost callback must fail the invocation
+        Some(ref task_token) => callback::send_task_result(task_token, &outcome).and(outcome),
+        None => outcome
+    }.map(Response::Validation)
+}
+
+/// Judges the serial of `event` by the candidate rules as well, logging and counting where they
+/// differ from the active ones. The response is left to the active rules.
+fn shadow_validation(event: &ValidationEvent, context: &RequestContext, config: &Config) {
+    let (shadow_validators, shadow_version) = match config.shadow_validators {
+        Some(ref shadow) => shadow,
+        None => return,
+    };
+    // serials the input guard turns away reach neither rule set
+    let serial_number = match config.input_guard.sanitize(&event.serial_number) {
+        Ok(serial_number) => serial_number,
+        Err(_) => return,
+    };
+    let divergence = Divergence::between(&config.validators, shadow_validators, serial_number);
+    if let Some(ref divergence) = divergence {
+        let active_version = config.rule_config_version.clone().unwrap_or_else(|| config.validators.version());
+        divergence.log(context, serial_number, &active_version, shadow_version);
+    }
+    context.metrics.shadow(divergence.is_some());
+}
+
+/// The validation event the pending `token` was issued for.
+fn pending_event(token: &str) -> Result<ValidationEvent, ServiceError> {
+    let pending = pending::redeem(token, unix_now()).map_err(|error| ServiceError::InvalidRequest(match error {
+        PendingError::Malformed => String::from("malformed pendingToken"),
+        PendingError::Expired => String::from("pendingToken expired, validate the serial again"),
+    }))?;
+    input::check_event(&pending)?;
+    serde_json::from_value(pending).map_err(|error| ServiceError::InvalidRequest(format!("malformed pendingToken: {}", error)))
+}
+
+/// The state of the work behind the event's `resultToken`. A pending validation is finished by
+/// the first poll that can look its serial up, and its result stored for the polls after it.
+fn get_result(event: &ValidationEvent, context: &RequestContext, config: &Config) -> Result<StoredResult, ServiceError> {
+    let settings = config.results.as_ref().ok_or_else(|| ServiceError::InvalidRequest(String::from("getResult needs RESULTS_TABLE")))?;
+    let token = event.result_token.as_deref().ok_or_else(|| ServiceError::InvalidRequest(String::from("getResult needs a resultToken")))?;
+    let timeout = Some(Duration::from_millis(RESULTS_TIMEOUT_MS));
+    match results::load(settings, token, timeout).map_err(ServiceError::StoreUnavailable)? {
+        Some(stored) if stored.status != ResultStatus::Pending => return Ok(stored),
+        // bulk jobs are finished by the invocation running them
+        stored if token.starts_with(results::BULK_TOKEN_PREFIX) => return stored.ok_or_else(|| ServiceError::InvalidRequest(String::from("unknown or expired resultToken"))),
+        _ => {},
+    }
+    let finished = match validation_response(&pending_event(token)?, context, config) {
+        Ok(response) => StoredResult::complete(token, serde_json::to_value(&response).unwrap_or_default()),
+        // still nothing to answer with, the next poll tries again
+        Err(ref error) if error.retryable() => return Ok(StoredResult::pending(token)),
+        Err(error) => StoredResult::failed(token, &error.to_json()),
+    };
+    if let Err(error) = results::store(settings, &finished, unix_now(), timeout) {
+        context.log(&format!("result of {} could not be stored: {}", token, error));
+    }
+    Ok(finished)
+}
+
+/// Checks the event's `apiKey` against the digests in `settings`, returning the caller it belongs to.
+fn authorize_caller(event: &ValidationEvent, settings: &ApiKeySettings) -> Result<String, ServiceError> {
+    let keys = API_KEYS.get(settings.refresh_interval, Instant::now(), || {
+        api_keys::load(&settings.source, Some(Duration::from_millis(API_KEYS_LOAD_TIMEOUT_MS)))
+    });
+    api_keys::authorize(keys.as_deref(), event.api_key.as_deref())
+}
+
+/// Validates the serial of `event` in the table of its tenant, then audits, alerts and publishes
+/// the outcome as configured.
+fn validate_event(event: &ValidationEvent, context: &RequestContext, config: &Config) -> Result<ValidationResult, ServiceError> {
+    let deadline = context.deadline;
+    let budget = event.budget_ms.map(|budget_ms| Instant::now() + Duration::from_millis(budget_ms)).filter(|budget| *budget < deadline);
+    let outcome = validation_strategy(event, config).and_then(|strategy| {
+        let settings = tenant::resolve(&config.tenants, event.tenant_id.as_deref(), &config.dynamodb)?;
+        let (serial_number, bypass_token) = (event.serial_number.as_str(), event.bypass_token.as_deref());
+        match budget {
+            // a lookup cut short by the caller's budget says nothing about the table, so it stays
+            // away from the circuit breaker
+            Some(budget) => validate_serial(serial_number, context, bypass_token, &(context.tables)(settings, config), config, strategy, Some(budget))
+                .map(|result| defer_uniqueness(result, event)),
+            None => {
+                let store = CircuitBreakerStore::new((context.tables)(settings, config), &STORE_BREAKER);
+                validate_serial(serial_number, context, bypass_token, &store, config, strategy, Some(deadline))
+            },
+        }
+    });
+    if let Some(ref audit_table) = config.audit_table {
+        let (mode, refresh_interval) = (config.key_hashing.clone(), config.secrets_refresh_interval);
+        audit::record_async(context, audit_table, config.audit_ttl_seconds, audit_entry(event, context, &outcome, unix_now()), move || key_hasher(&mode, refresh_interval), Duration::from_millis(AUDIT_TIMEOUT_MS));
+    }
+    if let (Some(settings), Ok(result)) = (config.duplicate_alerts.as_ref(), &outcome) {
+        if result.errors.contains(&ValidationError::AlreadyExists.value()) {
+            let (mode, refresh_interval) = (config.key_hashing.clone(), config.secrets_refresh_interval);
+            alerts::record_duplicate_async(context, settings, &event.serial_number, unix_now(), move || key_hasher(&mode, refresh_interval), Duration::from_millis(ALERT_TIMEOUT_MS));
+        }
+    }
+    if let (true, Ok(ref result)) = (config.publish_events, &outcome) {
+        events::publish_async(context, &config.event_bus_name, &config.event_source, events::VALIDATION_COMPLETED, validation_completed_detail(event, context, result), Duration::from_millis(PUBLISH_TIMEOUT_MS));
+    }
+    outcome
+}
+
+/// Leaves a uniqueness check that timed out within the event's budget `pending`, answering from
+/// the format checks with a token to exchange for the complete result.
+fn defer_uniqueness(mut result: ValidationResult, event: &ValidationEvent) -> ValidationResult {
+    let timeout = ValidationError::Timeout.value();
+    if result.errors.contains(&timeout) {
+        result.errors.retain(|error| *error != timeout);
+        result.is_valid = result.errors.is_empty();
+        result.uniqueness = Some(String::from("pending"));
+        result.pending_token = serde_json::to_value(event).ok().map(|event| pending::issue(&event, unix_now()));
+    }
+    result
+}
+
+fn response_meta(request_id: &str, config: &Config, timings: StageTimings) -> ResponseMeta {
+    ResponseMeta {
+        request_id: request_id.to_string(),
+        version: String::from(env!("CARGO_PKG_VERSION")),
+        rule_set_version: config.validators.version(),
+        rule_config_version: config.rule_config_version.clone(),
+        active_rules: config.validators.names().chain(Some(UNIQUENESS_CHECK).filter(|_| !config.skip_uniqueness)).map(String::from).collect(),
+        timings
+    }
+}
+
+/// Strategy named by the event, falling back to `VALIDATION_STRATEGY`.
+fn validation_strategy(event: &ValidationEvent, config: &Config) -> Result<ValidationStrategy, ServiceError> {
+    match event.strategy.as_deref() {
+        Some(name) => ValidationStrategy::parse(name)
+            .ok_or_else(|| ServiceError::InvalidRequest(format!("unknown strategy `{}`, expected `collect_all` or `fail_fast`", name))),
+        None => Ok(config.validation_strategy),
+    }
+}
+
+/// Response version named by the event's `responseVersion`, falling back to `DEFAULT_RESPONSE_VERSION`.
+fn response_version(event: &ValidationEvent, config: &Config) -> Result<ResponseVersion, ServiceError> {
+    match event.response_version {
+        Some(number) => ResponseVersion::parse(number)
+            .ok_or_else(|| ServiceError::InvalidRequest(format!("unknown responseVersion {}, expected 1 or 2", number))),
+        None => Ok(config.response_version),
+    }
+}
+
+/// When the invocation has to answer by, leaving time to send the response.
+fn invocation_deadline(ctx: &Context) -> Instant {
+    let remaining_millis = ctx.get_time_remaining_millis().saturating_sub(DEADLINE_MARGIN_MS);
+    Instant::now() + Duration::from_millis(remaining_millis as u64)
+}
+
+/// The DynamoDB store described by `settings`, routed across replica regions when configured.
+fn table_store(settings: DynamoDbSettings, config: &Config) -> Box<dyn SerialStore> {
+    let filter = table_filter(&settings.table_name, config);
+    let store: Box<dyn SerialStore> = if let Some((ref primary, ref secondary)) = config.failover_regions {
+        let (primary, secondary) = (DynamoDbStore::in_region(settings.clone(), primary.clone()), DynamoDbStore::in_region(settings.clone(), secondary.clone()));
+        Box::new(FailoverStore::new(primary, secondary, &FAILOVER_ROUTER))
+    } else if config.replica_regions.is_empty() {
+        Box::new(DynamoDbStore::new(settings.clone()))
+    } else {
+        let replicas = config.replica_regions.iter()
+            .map(|region| DynamoDbStore::in_region(settings.clone(), region.clone()))
+            .collect();
+        Box::new(LatencyRoutedStore::new(replicas, &REPLICA_ROUTER))
+    };
+    #[cfg(feature = "fault-injection")]
+    let store: Box<dyn SerialStore> = match config.fault_injection {
+        Some(ref faults) => Box::new(FaultInjectingStore::new(store, faults.clone())),
+        None => store,
+    };
+    let store: Box<dyn SerialStore> = match filter {
+        Some(filter) => Box::new(BloomFilteredStore::new(store, filter, settings.clone())),
+        None => store,
+    };
+    match config.result_cache {
+        Some(ref cache) => {
+            let results = RESULT_CACHE.table(&settings.table_name, cache);
+            results.sync(cache.poll_interval, Instant::now(), || {
+                Ok(result_cache::load(cache, &settings.table_name, Some(Duration::from_millis(INVALIDATIONS_LOAD_TIMEOUT_MS)))?.map(|object| object.log))
+            });
+            Box::new(CachedStore::new(store, results, settings))
+        },
+        None => store,
+    }
+}
+
+/// The bloom filter snapshot of a table, read from S3 at most every `BLOOM_REFRESH_SECONDS`.
+fn table_filter(table_name: &str, config: &Config) -> Option<Arc<BloomFilter>> {
+    let settings = config.bloom.as_ref()?;
+    BLOOM_FILTERS.get(table_name, settings.refresh_interval, Instant::now(), || {
+        let snapshot = bloom::load(settings, table_name, Some(Duration::from_millis(BLOOM_LOAD_TIMEOUT_MS)))?;
+        Ok(snapshot.map(|snapshot| snapshot.filter))
+    })
+}
+
+/// The request's context with the serial and its result.
+fn validation_completed_detail(event: &ValidationEvent, context: &RequestContext, result: &ValidationResult) -> serde_json::Value {
+    let mut detail = context.fields();
+    detail["serialNumber"] = serde_json::json!(event.serial_number);
+    detail["result"] = serde_json::json!(result);
+    detail
+}
+
+fn audit_entry(event: &ValidationEvent, context: &RequestContext, outcome: &Result<ValidationResult, ServiceError>, now: u64) -> AuditEntry {
+    let (outcome, error_codes, bypass_token_id) = match *outcome {
+        Ok(ref result) => (
+            if result.is_valid { "valid" } else { "invalid" },
+            result.errors.clone(),
+            result.bypass.as_ref().map(|bypass| bypass.token_id.clone())
+        ),
+        Err(ref error) => ("error", vec![String::from(error.error_type())], None),
+    };
+    AuditEntry {
+        request_id: context.request_id.clone(),
+        tenant_id: context.tenant_id.clone(),
+        serial_number: event.serial_number.clone(),
+        outcome,
+        error_codes,
+        bypass_token_id,
+        correlation_id: context.correlation_id.clone(),
+        reason: None,
+        timestamp: now
+    }
+}
+
+fn release_audit_entry(event: &ValidationEvent, context: &RequestContext, released: bool, now: u64) -> AuditEntry {
+    AuditEntry {
+        request_id: context.request_id.clone(),
+        tenant_id: context.tenant_id.clone(),
+        serial_number: event.serial_number.clone(),
+        outcome: if released { "released" } else { "not_found" },
+        error_codes: Vec::new(),
+        bypass_token_id: None,
+        correlation_id: context.correlation_id.clone(),
+        reason: event.reason.clone(),
+        timestamp: now
+    }
+}
+
+fn elapsed_micros(since: Instant) -> u64 {
+    since.elapsed().as_micros() as u64
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
+
+fn unix_now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_millis() as u64).unwrap_or(0)
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
 struct ValidationResult {
+    #[serde(rename = "isValid")]
+    is_valid: bool,
+    errors: Vec<String>,
+    /// `errors` with a message for each, in the locale the event asked for.
+    #[serde(rename = "errorDetails", skip_serializing_if = "Vec::is_empty", default)]
+    error_details: Vec<ErrorDetail>,
+    /// Failed rules of `warning` severity, which leave `is_valid` alone.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    warnings: Vec<String>,
+    /// Where the serial departs from the closest of the `SERIAL_TEMPLATES`, when the `template` rule failed it.
+    #[serde(rename = "segmentMismatch", skip_serializing_if = "Option::is_none", default)]
+    segment_mismatch: Option<SegmentMismatch>,
+    /// Who holds the serial when it already exists, for callers granted `includeConflict`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    conflict: Option<ConflictingItem>,
+    /// Registered serials close to one that already exists, nearest first.
+    #[serde(rename = "similarSerials", skip_serializing_if = "Vec::is_empty", default)]
+    similar_serials: Vec<String>,
+    /// Set to `unknown` when the uniqueness check was skipped because the store was unreachable,
+    /// to `skipped` when the `fail_fast` strategy did not look up a serial failing a format rule
+    /// or the input guard rejected the serial, to `pending` when the check did not finish within
+    /// the event's `budgetMs`, and to `disabled` while `RULE_UNIQUENESS` is off.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    uniqueness: Option<String>,
+    /// Exchanged with the `resolvePending` action for the complete result, when `uniqueness` is `pending`.
+    #[serde(rename = "pendingToken", skip_serializing_if = "Option::is_none", default)]
+    pending_token: Option<String>,
+    /// Present when a bypass token lifted one or more format rules for this serial.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    bypass: Option<AppliedBypass>,
+    /// Present when the event asked for it with `includeMeta`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    meta: Option<ResponseMeta>,
+    /// The event's `correlationId`, echoed back.
+    #[serde(rename = "correlationId", skip_serializing_if = "Option::is_none", default)]
+    correlation_id: Option<String>,
+    #[serde(skip)]
+    timings: StageTimings
+}
+
+/// Shapes of the validation result, so consumers written against the first one keep working
+/// while the result grows.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum ResponseVersion {
+    /// `{"isValid": ..., "errors": [...]}` and nothing else.
+    Legacy,
+    /// Every field of `ValidationResult`, with `schemaVersion`.
+    #[default]
+    Current
+}
+
+impl ResponseVersion {
+    fn parse(number: u32) -> Option<ResponseVersion> {
+        match number {
+            1 => Some(ResponseVersion::Legacy),
+            2 => Some(ResponseVersion::Current),
+            _ => None,
+        }
+    }
+
+    fn number(self) -> u32 {
+        match self {
+            ResponseVersion::Legacy => 1,
+            ResponseVersion::Current => 2,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum VersionedResult {
+    Legacy(LegacyValidationResult),
+    Current(Box<CurrentValidationResult>)
+}
+
+#[derive(Serialize)]
+struct LegacyValidationResult {
     #[serde(rename = "isValid")]
     is_valid: bool,
     errors: Vec<String>
 }
 
-#[derive(Serialize, Deserialize)]
-struct ValidationEvent {
+#[derive(Serialize, JsonSchema)]
+struct CurrentValidationResult {
+    #[serde(rename = "schemaVersion")]
+    schema_version: u32,
+    #[serde(flatten)]
+    result: ValidationResult
+}
+
+impl VersionedResult {
+    fn new(result: ValidationResult, version: ResponseVersion) -> VersionedResult {
+        match version {
+            ResponseVersion::Legacy => VersionedResult::Legacy(LegacyValidationResult { is_valid: result.is_valid, errors: result.errors }),
+            ResponseVersion::Current => VersionedResult::Current(Box::new(CurrentValidationResult { schema_version: version.number(), result })),
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        match *self {
+            VersionedResult::Legacy(ref result) => result.is_valid,
+            VersionedResult::Current(ref result) => result.result.is_valid,
+        }
+    }
+
+    fn errors(&self) -> &[String] {
+        match *self {
+            VersionedResult::Legacy(ref result) => &result.errors,
+            VersionedResult::Current(ref result) => &result.result.errors,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, JsonSchema)]
+struct ErrorDetail {
+    code: String,
+    /// Absent for codes the catalog has no message for.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    message: Option<String>
+}
+
+/// Lets consumers correlate a result with the logs and deployment that produced it.
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct ResponseMeta {
+    #[serde(rename = "requestId")]
+    request_id: String,
+    version: String,
+    /// See `ValidatorRegistry::version`.
+    #[serde(rename = "ruleSetVersion")]
+    rule_set_version: String,
+    /// Version of the AppConfig or SSM rule configuration in use, absent without one.
+    #[serde(rename = "ruleConfigVersion", skip_serializing_if = "Option::is_none")]
+    rule_config_version: Option<String>,
+    /// Rules the serial went through, in order, with `uniqueness` last unless it is switched off.
+    #[serde(rename = "activeRules", default)]
+    active_rules: Vec<String>,
+    timings: StageTimings
+}
+
+/// Time spent in each stage of a validation.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, JsonSchema)]
+struct StageTimings {
+    #[serde(rename = "formatChecksMicros")]
+    format_checks_micros: u64,
+    /// Absent when the lookup was skipped.
+    #[serde(rename = "storeLookupMicros", skip_serializing_if = "Option::is_none", default)]
+    store_lookup_micros: Option<u64>
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct AppliedBypass {
+    #[serde(rename = "tokenId")]
+    token_id: String,
+    #[serde(rename = "issuedBy")]
+    issued_by: String,
+    rules: Vec<String>
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct BypassTokenIssued {
+    #[serde(rename = "bypassToken")]
+    bypass_token: String,
+    #[serde(rename = "tokenId")]
+    token_id: String,
+    #[serde(rename = "expiresAt")]
+    expires_at: u64
+}
+
+#[derive(Serialize)]
+struct WarmupAcknowledged {
+    warm: bool,
+    preloaded: bool
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Response {
+    Validation(VersionedResult),
+    BypassToken(BypassTokenIssued),
+    SelfTest(SelfTestReport),
+    Health(HealthReport),
+    Generated(GeneratedSerial),
+    Reserved(ReservedSerial),
+    Registered(RegisteredSerial),
+    Updated(UpdatedSerial),
+    Transferred(TransferredSerial),
+    Listed(ListedSerials),
+    LookedUp(LookedUpSerial),
+    Report(RegistrationReport),
+    Confirmed(ConfirmedSerial),
+    Released(ReleasedSerial),
+    Bulk(BulkReport),
+    Import(ImportReport),
+    Kinesis(KinesisBatchResponse),
+    Alb(AlbResponse),
+    FunctionUrl(FunctionUrlResponse),
+    Warmup(WarmupAcknowledged),
+    Result(StoredResult),
+    Described(Box<Description>)
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct ReservedSerial {
+    #[serde(rename = "serialNumber")]
+    serial_number: String,
+    /// `false` when the serial is registered or held by another reservation.
+    reserved: bool,
+    #[serde(rename = "reservedUntil", skip_serializing_if = "Option::is_none", default)]
+    reserved_until: Option<u64>,
+    /// Set when the reservation was only checked, not written.
+    #[serde(rename = "dryRun", skip_serializing_if = "std::ops::Not::not", default)]
+    dry_run: bool
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct RegisteredSerial {
     #[serde(rename = "serialNumber")]
-    serial_number: String
+    serial_number: String,
+    #[serde(rename = "ownerId")]
+    owner_id: String,
+    /// `false` when a condition of the transaction failed and nothing was written.
+    registered: bool,
+    /// `already_exists` or `owner_record_exists` when `registered` is `false`.
+    #[serde(rename = "failedCondition", skip_serializing_if = "Option::is_none", default)]
+    failed_condition: Option<String>
 }
 
-fn validate_serial(serial_number: &str) -> ValidationResult {
-    let mut result = ValidationResult { is_valid: true, errors: Vec::new() };
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct UpdatedSerial {
+    #[serde(rename = "serialNumber")]
+    serial_number: String,
+    updated: bool,
+    /// Version the item carries now, or the one it carries instead of `expectedVersion` on a conflict.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    version: Option<u64>,
+    /// `conflict` or `not_found` when `updated` is `false`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    error: Option<String>
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct TransferredSerial {
+    #[serde(rename = "serialNumber")]
+    serial_number: String,
+    transferred: bool,
+    /// Version the item carries after the transfer.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    version: Option<u64>,
+    /// `conflict` or `not_found` when `transferred` is `false`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    error: Option<String>
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct ConfirmedSerial {
+    #[serde(rename = "serialNumber")]
+    serial_number: String,
+    /// `false` when there was no live reservation to confirm.
+    confirmed: bool
+}
 
-    if !validate_serial_length(serial_number) {
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct ReleasedSerial {
+    #[serde(rename = "serialNumber")]
+    serial_number: String,
+    released: bool,
+    /// `not_found` when no live item held the serial.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    error: Option<String>
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct GeneratedSerial {
+    #[serde(rename = "serialNumber")]
+    serial_number: String,
+    attempts: u32,
+    /// Set when the serial was only checked, not registered.
+    #[serde(rename = "dryRun", skip_serializing_if = "std::ops::Not::not", default)]
+    dry_run: bool
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+struct ValidationEvent {
+    /// Not needed by the `generate` action.
+    #[serde(rename = "serialNumber", default)]
+    serial_number: String,
+    /// Required when the deployment serves several tenants, see `tenant::resolve`.
+    #[serde(rename = "tenantId", default)]
+    tenant_id: Option<String>,
+    /// `validate` (the default), `generate`, `reserve`, `register`, `update`, `transfer`, `confirm`, `release`, `import`, `selfTest`, `healthcheck`, `list`, `lookup`, `report`, `issueBypassToken`, `resolvePending`, `getResult` or `describe`.
+    #[serde(default)]
+    action: Option<String>,
+    #[serde(rename = "bypassToken", default)]
+    bypass_token: Option<String>,
+    /// `collect_all` or `fail_fast`, overriding `VALIDATION_STRATEGY` for this request.
+    #[serde(default)]
+    strategy: Option<String>,
+    /// Adds the `meta` block to the validation result.
+    #[serde(rename = "includeMeta", default)]
+    include_meta: bool,
+    /// Step Functions `.waitForTaskToken` token to report the result to.
+    #[serde(rename = "taskToken", default)]
+    task_token: Option<String>,
+    /// `1` for the legacy result shape, `2` for the current one, overriding `DEFAULT_RESPONSE_VERSION`.
+    #[serde(rename = "responseVersion", default)]
+    response_version: Option<u32>,
+    /// Milliseconds the validation may take; a uniqueness check not done by then is left `pending`.
+    #[serde(rename = "budgetMs", default)]
+    budget_ms: Option<u64>,
+    /// Token of a `pending` result, exchanged by the `resolvePending` action.
+    #[serde(rename = "pendingToken", default)]
+    pending_token: Option<String>,
+    /// Token polled by the `getResult` action: the `pendingToken` of a validation, or
+    /// `bulk:s3://<bucket>/<key>` of a bulk job.
+    #[serde(rename = "resultToken", default)]
+    result_token: Option<String>,
+    /// Language tag or `Accept-Language` list choosing the language of `errorDetails`, taken from
+    /// the `Accept-Language` header of HTTP requests that do not set it.
+    #[serde(default)]
+    locale: Option<String>,
+    /// Echoed in the result and carried by log lines, events and audit entries; taken from the
+    /// `x-correlation-id` header of HTTP requests that do not set it.
+    #[serde(rename = "correlationId", default)]
+    correlation_id: Option<String>,
+    /// Makes `generate` and `reserve` report what they would do without writing to the table.
+    #[serde(rename = "dryRun", default)]
+    dry_run: bool,
+    /// Owner recorded by the `register` action, or set by `update` and `transfer`.
+    #[serde(rename = "ownerId", default)]
+    owner_id: Option<String>,
+    /// Owner the serial must have for `transfer` to apply.
+    #[serde(rename = "fromOwner", default)]
+    from_owner: Option<String>,
+    /// Status set by the `update` action.
+    #[serde(default)]
+    status: Option<String>,
+    /// Version the item must carry for `update` to apply.
+    #[serde(rename = "expectedVersion", default)]
+    expected_version: Option<u64>,
+    /// Product line `generate` takes the next number of when serials come from a sequence, also
+    /// the prefix of the serial; `GENERATE_PREFIX` when unset.
+    #[serde(rename = "productLine", default)]
+    product_line: Option<String>,
+    /// Client token making retried `generate` requests return the serial of the first one.
+    #[serde(rename = "idempotencyKey", default)]
+    idempotency_key: Option<String>,
+    #[serde(rename = "adminKey", default)]
+    admin_key: Option<String>,
+    /// Caller's key, required when `API_KEYS_SECRET_ID` or `API_KEYS_PARAMETER` is set; taken from
+    /// the `x-api-key` header of HTTP requests that do not set it.
+    #[serde(rename = "apiKey", default)]
+    api_key: Option<String>,
+    /// Set from the authorizer of HTTP API requests, never from the event itself.
+    #[serde(skip)]
+    caller: Option<CallerContext>,
+    /// Address of HTTP requests, for rate limiting callers that are not identified.
+    #[serde(skip)]
+    source_ip: Option<String>,
+    // list and report parameters
+    #[serde(default)]
+    prefix: Option<String>,
+    #[serde(rename = "registeredFrom", default)]
+    registered_from: Option<u64>,
+    #[serde(rename = "registeredTo", default)]
+    registered_to: Option<u64>,
+    #[serde(default)]
+    limit: Option<i64>,
+    #[serde(rename = "nextToken", default)]
+    next_token: Option<String>,
+    // issueBypassToken parameters
+    #[serde(default)]
+    rules: Vec<String>,
+    #[serde(rename = "ttlSeconds", default)]
+    ttl_seconds: Option<u64>,
+    #[serde(rename = "issuedBy", default)]
+    issued_by: Option<String>,
+    #[serde(default)]
+    reason: Option<String>,
+    // import parameters: the serials inline, or the location of a manifest
+    #[serde(default)]
+    serials: Vec<String>,
+    #[serde(default)]
+    manifest: Option<String>
+}
+
+fn validate_serial(serial_number: &str, context: &RequestContext, bypass_token: Option<&str>, store: &dyn SerialStore, config: &Config, strategy: ValidationStrategy, deadline: Option<Instant>) -> Result<ValidationResult, ServiceError> {
+    let mut result = ValidationResult { is_valid: true, errors: Vec::new(), error_details: Vec::new(), warnings: Vec::new(), segment_mismatch: None, conflict: None, similar_serials: Vec::new(), uniqueness: None, pending_token: None, bypass: None, meta: None, correlation_id: None, timings: StageTimings::default() };
+    let format_checks_started = Instant::now();
+
+    let serial_number = match config.input_guard.sanitize(serial_number) {
+        Ok(serial_number) => serial_number,
+        Err(error) => {
+            // neither the rules nor the store get to see it
+            result.is_valid = false;
+            result.errors.push(error.value());
+            result.uniqueness = Some(String::from("skipped"));
+            result.timings.format_checks_micros = elapsed_micros(format_checks_started);
+            return Ok(result);
+        },
+    };
+
+    let bypass = match (bypass_token, config.bypass_token_secret.as_ref()) {
+        (Some(token), Some(secret)) => BypassToken::verify(secret, token, serial_number, context.tenant_id.as_deref(), unix_now()).ok(),
+        _ => None,
+    };
+    if bypass_token.is_some() && bypass.is_none() {
         result.is_valid = false;
-        result.errors.push(ValidationError::InvalidFormat.value());
+        result.errors.push(ValidationError::InvalidBypassToken.value());
     }
+    let mut bypassed_rules = Vec::new();
 
-    if !validate_serial_alphanumeric(serial_number) {
-        result.is_valid = false;
-        result.errors.push(ValidationError::InvalidFormat.value());
+    for (validator, severity) in config.validators.iter() {
+        let error = match validator.validate(serial_number) {
+            Some(error) => error,
+            None => continue,
+        };
+        if severity == Severity::Warning {
+            result.warnings.push(error.value());
+        } else if BYPASSABLE_RULES.contains(&validator.name()) && bypass.as_ref().is_some_and(|token| token.allows(validator.name())) {
+            bypassed_rules.push(validator.name().to_string());
+        } else {
+            result.is_valid = false;
+            result.errors.push(error.value());
+            if result.segment_mismatch.is_none() {
+                result.segment_mismatch = validator.segment_mismatch(serial_number);
+            }
+        }
     }
 
-    if !validate_serial_unique(serial_number) {
-        result.is_valid = false;
-        result.errors.push(ValidationError::AlreadyExists.value());
+    if let Some(token) = bypass {
+        if !bypassed_rules.is_empty() {
+            bypass::audit(context, "bypass_honored", &token);
+            result.bypass = Some(AppliedBypass { token_id: token.token_id, issued_by: token.issued_by, rules: bypassed_rules });
+        }
+    }
+
+    result.timings.format_checks_micros = elapsed_micros(format_checks_started);
+
+    if strategy == ValidationStrategy::FailFast && !result.is_valid {
+        result.uniqueness = Some(String::from("skipped"));
+        return Ok(result);
+    }
+    if config.skip_uniqueness {
+        // is_valid reflects the format checks only
+        result.uniqueness = Some(String::from("disabled"));
+        return Ok(result);
     }
 
-    return result;
+    let store_lookup_started = Instant::now();
+    let unique = if config.include_conflict {
+        // the same read, widened to the attributes telling who holds the serial
+        lookup_timeout(deadline).and_then(|timeout| store.conflicting_item(serial_number, timeout)).map(|conflict| {
+            let unique = conflict.is_none();
+            result.conflict = conflict;
+            unique
+        })
+    } else {
+        validate_serial_unique(serial_number, store, deadline)
+    };
+    result.timings.store_lookup_micros = Some(elapsed_micros(store_lookup_started));
+    match unique {
+        Ok(true) => {},
+        Ok(false) => {
+            result.is_valid = false;
+            result.errors.push(ValidationError::AlreadyExists.value());
+            result.similar_serials = similar_serials(serial_number, context, store, config, deadline);
+        },
+        Err(StoreError::Timeout) => {
+            result.is_valid = false;
+            result.errors.push(ValidationError::Timeout.value());
+        },
+        Err(StoreError::Unavailable(_)) | Err(StoreError::Throttled(_)) | Err(StoreError::CircuitOpen) if config.degrade_on_store_error => {
+            // is_valid reflects the format checks only
+            result.uniqueness = Some(String::from("unknown"));
+        },
+        Err(error) => return Err(error.into()),
+    }
+
+    Ok(result)
 }
 
-fn validate_serial_length(serial_number: &str) -> bool {
-    serial_number.chars().count() >= 6
+/// Each of `errors` with its message from `catalog`.
+fn error_details(errors: &[String], catalog: &MessageCatalog, locale: Option<&str>) -> Vec<ErrorDetail> {
+    errors.iter().map(|code| ErrorDetail { code: code.clone(), message: catalog.message(code, locale).map(String::from) }).collect()
 }
 
-fn validate_serial_alphanumeric(serial_number: &str) -> bool {
-    serial_number.chars().all(char::is_alphanumeric)
+/// Suggestions for a serial that already exists. They are a courtesy, so failing to find them
+/// is only logged.
+fn similar_serials(serial_number: &str, context: &RequestContext, store: &dyn SerialStore, config: &Config, deadline: Option<Instant>) -> Vec<String> {
+    if config.similar_serials.limit == 0 {
+        return Vec::new();
+    }
+    let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+    match store.similar_candidates(serial_number, timeout) {
+        Ok(candidates) => similarity::closest(serial_number, candidates, &config.similar_serials),
+        Err(error) => {
+            context.log(&format!("no similar serials: {:?}", error));
+            Vec::new()
+        },
+    }
 }
 
-fn validate_serial_unique(serial_number: &str) -> bool {
-    let mut query_key: HashMap<String, AttributeValue> = HashMap::new();
-    query_key.insert(String::from("serial_number"), AttributeValue {
-        s: Some(serial_number.to_string()),
-        ..Default::default()
+fn generate_serial(store: &dyn SerialStore, config: &Config, tenant_id: Option<&str>, idempotency_key: Option<&str>, dry_run: bool, now: u64, deadline: Instant) -> Result<GeneratedSerial, ServiceError> {
+    if !validate_serial_alphanumeric(&config.generator.prefix) {
+        return Err(ServiceError::InvalidRequest(String::from("GENERATE_PREFIX must be alphanumeric")));
+    }
+    if idempotency_key.is_some_and(|key| key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH) {
+        return Err(ServiceError::InvalidRequest(format!("idempotencyKey must be 1 to {} characters", MAX_IDEMPOTENCY_KEY_LENGTH)));
+    }
+    let idempotency_key = idempotency_key.map(|key| IdempotencyKey {
+        key: key.to_string(),
+        expires_at: now + config.generator.idempotency_ttl_seconds
     });
+    let timeout = store_timeout(deadline)?;
+    let mut rng = generate::request_rng(tenant_id, idempotency_key.as_ref());
+    match generate::generate(store, &config.generator, &mut rng, idempotency_key.as_ref(), dry_run, Some(timeout)) {
+        Ok((serial_number, attempts)) => Ok(GeneratedSerial { serial_number, attempts, dry_run }),
+        Err(GenerateError::Exhausted(attempts)) => Err(ServiceError::GenerationExhausted(attempts)),
+        Err(GenerateError::Store(error)) => Err(error.into()),
+    }
+}
+
+/// Generates a serial from the next number of the counter of the event's `productLine`, or of
+/// `GENERATE_PREFIX` without one, which is also the prefix of the serial.
+fn generate_sequenced_serial(store: &dyn SerialStore, counter: &dyn SequenceCounter, blocks: &SequenceBlocks, event: &ValidationEvent, config: &Config, deadline: Instant) -> Result<GeneratedSerial, ServiceError> {
+    let settings = GeneratorSettings { prefix: event.product_line.clone().unwrap_or_else(|| config.generator.prefix.clone()), ..config.generator.clone() };
+    if !validate_serial_alphanumeric(&settings.prefix) {
+        return Err(ServiceError::InvalidRequest(String::from("productLine and GENERATE_PREFIX must be alphanumeric")));
+    }
+    if event.idempotency_key.is_some() {
+        // a retried request would take the next number rather than find the serial of the first
+        return Err(ServiceError::InvalidRequest(String::from("idempotencyKey is not supported for serials generated from a sequence")));
+    }
+    // product lines of different tenants count separately
+    let counter_name = format!("{}#{}", event.tenant_id.as_deref().unwrap_or_default(), settings.prefix);
+    let timeout = store_timeout(deadline)?;
+    match generate::generate_sequenced(store, counter, blocks, &settings, &counter_name, event.dry_run, Some(timeout)) {
+        Ok((serial_number, attempts)) => Ok(GeneratedSerial { serial_number, attempts, dry_run: event.dry_run }),
+        Err(GenerateError::Exhausted(attempts)) => Err(ServiceError::GenerationExhausted(attempts)),
+        Err(GenerateError::Store(error)) => Err(error.into()),
+    }
+}
+
+/// Remaining time before `deadline` as a store timeout.
+fn store_timeout(deadline: Instant) -> Result<Duration, ServiceError> {
+    deadline.checked_duration_since(Instant::now())
+        .ok_or_else(|| ServiceError::StoreUnavailable(String::from("no time left to reach the store")))
+}
+
+/// Holds a serial that passes the format rules for `RESERVATION_TTL_SECONDS`. A `dry_run` only
+/// checks whether the reservation would succeed.
+fn reserve_serial(serial_number: &str, store: &dyn SerialStore, config: &Config, dry_run: bool, now: u64, deadline: Instant) -> Result<ReservedSerial, ServiceError> {
+    let serial_number = accepted_serial(serial_number, config)
+        .ok_or_else(|| ServiceError::InvalidRequest(String::from("only serials passing the format rules can be reserved")))?;
+    let reserved_until = now + config.reservation_ttl_seconds;
+    let timeout = Some(store_timeout(deadline)?);
+    let reserved = if dry_run {
+        // a reservation is written under the same condition as a registration
+        store.would_register(serial_number, None, timeout).map(|registration| registration == store::Registration::Registered)
+    } else {
+        store.reserve(serial_number, reserved_until, timeout)
+    }.map_err(ServiceError::from)?;
+    Ok(ReservedSerial {
+        serial_number: serial_number.to_string(),
+        reserved,
+        reserved_until: if reserved { Some(reserved_until) } else { None },
+        dry_run
+    })
+}
 
-    let query_serials = GetItemInput {
-        key: query_key,
-        table_name: String::from("assets"),
-        ..Default::default()
+/// `serial_number` as the rules see it, when it passes the input guard and every rule of `error` severity.
+fn accepted_serial<'a>(serial_number: &'a str, config: &Config) -> Option<&'a str> {
+    config.input_guard.sanitize(serial_number).ok().filter(|serial_number| config.validators.accepts(serial_number))
+}
+
+/// Registers a serial that passes the format rules together with the record of its owner, both
+/// or neither.
+fn register_serial(serial_number: &str, owner_id: Option<&str>, store: &dyn SerialStore, config: &Config, deadline: Instant) -> Result<RegisteredSerial, ServiceError> {
+    let owner_id = owner_id.filter(|owner_id| !owner_id.trim().is_empty())
+        .ok_or_else(|| ServiceError::InvalidRequest(String::from("ownerId is required")))?;
+    let serial_number = accepted_serial(serial_number, config)
+        .ok_or_else(|| ServiceError::InvalidRequest(String::from("only serials passing the format rules can be registered")))?;
+    let registration = store.register_owned(serial_number, owner_id, Some(store_timeout(deadline)?)).map_err(ServiceError::from)?;
+    let failed_condition = match registration {
+        OwnedRegistration::Registered => None,
+        OwnedRegistration::Rejected(condition) => Some(condition.code().to_string()),
     };
+    Ok(RegisteredSerial {
+        serial_number: serial_number.to_string(),
+        owner_id: owner_id.to_string(),
+        registered: failed_condition.is_none(),
+        failed_condition
+    })
+}
 
-    let client = DynamoDbClient::new(Region::EuCentral1);
+/// Changes the metadata of a registered serial unless another update got there first.
+fn update_serial(event: &ValidationEvent, store: &dyn SerialStore, deadline: Instant) -> Result<UpdatedSerial, ServiceError> {
+    let expected_version = event.expected_version
+        .ok_or_else(|| ServiceError::InvalidRequest(String::from("expectedVersion is required")))?;
+    let update = MetadataUpdate { status: event.status.clone(), owner_id: event.owner_id.clone() };
+    if update == MetadataUpdate::default() {
+        return Err(ServiceError::InvalidRequest(String::from("update needs a status or ownerId")));
+    }
+    let outcome = store.update_metadata(&event.serial_number, &update, expected_version, Some(store_timeout(deadline)?)).map_err(ServiceError::from)?;
+    let (updated, version, error) = match outcome {
+        MetadataUpdated::Updated { version } => (true, Some(version), None),
+        MetadataUpdated::Conflict { version } => (false, Some(version), Some("conflict")),
+        MetadataUpdated::NotFound => (false, None, Some("not_found")),
+    };
+    Ok(UpdatedSerial { serial_number: event.serial_number.clone(), updated, version, error: error.map(String::from) })
+}
 
-    match client.get_item(query_serials).sync() {
-        Ok(result) => {
-            match result.item {
-                Some(_) => false, // invalid, serial_number was found
-                None => true // valid, serial_number was not found
-            }
+/// Hands the event's serial from `fromOwner` over to `ownerId`.
+fn transfer_serial(event: &ValidationEvent, store: &dyn SerialStore, deadline: Instant) -> Result<TransferredSerial, ServiceError> {
+    let owner = |owner: &Option<String>, name: &str| owner.clone().filter(|owner| !owner.trim().is_empty())
+        .ok_or_else(|| ServiceError::InvalidRequest(format!("{} is required", name)));
+    let (from_owner, to_owner) = (owner(&event.from_owner, "fromOwner")?, owner(&event.owner_id, "ownerId")?);
+    let outcome = store.transfer_owner(&event.serial_number, &from_owner, &to_owner, Some(store_timeout(deadline)?)).map_err(ServiceError::from)?;
+    let (transferred, version, error) = match outcome {
+        OwnerTransfer::Transferred { version } => (true, Some(version), None),
+        OwnerTransfer::Conflict => (false, None, Some("conflict")),
+        OwnerTransfer::NotFound => (false, None, Some("not_found")),
+    };
+    Ok(TransferredSerial { serial_number: event.serial_number.clone(), transferred, version, error: error.map(String::from) })
+}
+
+fn confirm_serial(serial_number: &str, store: &dyn SerialStore, deadline: Instant) -> Result<ConfirmedSerial, ServiceError> {
+    let confirmed = store.confirm(serial_number, Some(store_timeout(deadline)?)).map_err(ServiceError::from)?;
+    Ok(ConfirmedSerial { serial_number: serial_number.to_string(), confirmed })
+}
+
+/// Tombstones the event's serial for admins, with the `reason` it was released for, and audits it.
+fn release_serial(event: &ValidationEvent, context: &RequestContext, config: &Config, deadline: Instant) -> Result<ReleasedSerial, ServiceError> {
+    if !is_admin(event, config) {
+        return Err(ServiceError::Unauthorized(String::from("release requires a valid adminKey")));
+    }
+    let reason = event.reason.as_deref().filter(|reason| !reason.trim().is_empty())
+        .ok_or_else(|| ServiceError::InvalidRequest(String::from("reason is required")))?;
+    let settings = tenant::resolve(&config.tenants, event.tenant_id.as_deref(), &config.dynamodb)?;
+    let store = CircuitBreakerStore::new((context.tables)(settings, config), &STORE_BREAKER);
+    let released = store.release(&event.serial_number, reason, Some(store_timeout(deadline)?)).map_err(ServiceError::from)?;
+    context.log(&format!("release of {}: {}", event.serial_number, if released { "released" } else { "not_found" }));
+    if let Some(ref audit_table) = config.audit_table {
+        let (mode, refresh_interval) = (config.key_hashing.clone(), config.secrets_refresh_interval);
+        audit::record_async(context, audit_table, config.audit_ttl_seconds, release_audit_entry(event, context, released, unix_now()), move || key_hasher(&mode, refresh_interval), Duration::from_millis(AUDIT_TIMEOUT_MS));
+    }
+    Ok(ReleasedSerial { serial_number: event.serial_number.clone(), released, error: if released { None } else { Some(String::from("not_found")) } })
+}
+
+/// Loads the serials of the event, inline or from its manifest, into the table for callers
+/// granted the `import` permission.
+fn import_serials(event: &ValidationEvent, context: &RequestContext, config: &Config) -> Result<ImportReport, ServiceError> {
+    if !context.caller.as_ref().is_some_and(|caller| config.caller_permissions.allows(caller, caller::IMPORT)) {
+        return Err(ServiceError::Unauthorized(String::from("import requires the import permission")));
+    }
+    let source = match (event.manifest.as_deref(), event.serials.len()) {
+        (Some(location), 0) => ImportSource::manifest(location)?,
+        (None, count) if count > import::MAX_INLINE_SERIALS => {
+            return Err(ServiceError::InvalidRequest(format!("at most {} serials can be imported inline, use a manifest", import::MAX_INLINE_SERIALS)));
         },
-        Err(error) => {
-            panic!("Error: {:?}", error);
+        (None, count) if count > 0 => ImportSource::Inline(event.serials.clone()),
+        _ => return Err(ServiceError::InvalidRequest(String::from("import needs either serials or a manifest"))),
+    };
+    let settings = tenant::resolve(&config.tenants, event.tenant_id.as_deref(), &config.dynamodb)?;
+    let store = CircuitBreakerStore::new((context.tables)(settings, config), &STORE_BREAKER);
+    import::run(&source, context, &store, &config.validators)
+}
+
+/// Whether the event carries the `ADMIN_API_KEY`.
+fn is_admin(event: &ValidationEvent, config: &Config) -> bool {
+    match (config.admin_api_key.as_ref(), event.admin_key.as_ref()) {
+        (Some(expected), Some(given)) => constant_time_eq::constant_time_eq(expected.as_bytes(), given.as_bytes()),
+        _ => false,
+    }
+}
+
+/// One page of the serials of the event's tenant, for admins only.
+fn list_serials(event: &ValidationEvent, config: &Config, deadline: Instant) -> Result<ListedSerials, ServiceError> {
+    if !is_admin(event, config) {
+        return Err(ServiceError::Unauthorized(String::from("list requires a valid adminKey")));
+    }
+    let settings = tenant::resolve(&config.tenants, event.tenant_id.as_deref(), &config.dynamodb)?;
+    let request = ListRequest {
+        prefix: event.prefix.as_deref(),
+        registered_from: event.registered_from,
+        registered_to: event.registered_to,
+        limit: event.limit,
+        next_token: event.next_token.as_deref()
+    };
+    listing::run(&home_store(settings, config), &request, store_timeout(deadline)?)
+}
+
+/// Every attribute of the item holding the event's serial, for admins only.
+fn lookup_serial(event: &ValidationEvent, config: &Config, deadline: Instant) -> Result<LookedUpSerial, ServiceError> {
+    if !is_admin(event, config) {
+        return Err(ServiceError::Unauthorized(String::from("lookup requires a valid adminKey")));
+    }
+    let settings = tenant::resolve(&config.tenants, event.tenant_id.as_deref(), &config.dynamodb)?;
+    listing::lookup(&home_store(settings, config), &event.serial_number, store_timeout(deadline)?)
+}
+
+/// Registrations of the event's tenant per day of the requested period, for admins only.
+fn report_registrations(event: &ValidationEvent, config: &Config, deadline: Instant) -> Result<RegistrationReport, ServiceError> {
+    if !is_admin(event, config) {
+        return Err(ServiceError::Unauthorized(String::from("report requires a valid adminKey")));
+    }
+    let settings = tenant::resolve(&config.tenants, event.tenant_id.as_deref(), &config.dynamodb)?;
+    report::run(&home_store(settings, config), event.registered_from, event.registered_to, store_timeout(deadline)?)
+}
+
+/// The table in the region writes go to, read by admin actions that need to see every write.
+fn home_store(settings: DynamoDbSettings, config: &Config) -> DynamoDbStore {
+    if let Some((ref primary, _)) = config.failover_regions {
+        return DynamoDbStore::in_region(settings, primary.clone());
+    }
+    match config.replica_regions.first() {
+        Some(region) => DynamoDbStore::in_region(settings, region.clone()),
+        None => DynamoDbStore::new(settings),
+    }
+}
+
+fn issue_bypass_token(event: &ValidationEvent, context: &RequestContext, config: &Config, now: u64) -> Result<BypassTokenIssued, ServiceError> {
+    if !is_admin(event, config) {
+        return Err(ServiceError::Unauthorized(String::from("issueBypassToken requires a valid adminKey")));
+    }
+    if !config.tenants.is_empty() && !event.tenant_id.as_ref().is_some_and(|tenant_id| config.tenants.contains_key(tenant_id)) {
+        return Err(ServiceError::InvalidRequest(String::from("tenantId must name an allowed tenant")));
+    }
+    let secret = config.bypass_token_secret.as_ref()
+        .ok_or_else(|| ServiceError::InvalidRequest(String::from("bypass tokens are not enabled")))?;
+
+    if event.rules.is_empty() || event.rules.iter().any(|rule| !BYPASSABLE_RULES.contains(&rule.as_str())) {
+        return Err(ServiceError::InvalidRequest(format!("rules must be a non-empty subset of {:?}", BYPASSABLE_RULES)));
+    }
+    let issued_by = event.issued_by.clone().filter(|value| !value.trim().is_empty())
+        .ok_or_else(|| ServiceError::InvalidRequest(String::from("issuedBy is required")))?;
+    let reason = event.reason.clone().filter(|value| !value.trim().is_empty())
+        .ok_or_else(|| ServiceError::InvalidRequest(String::from("reason is required")))?;
+
+    // validation verifies tokens against the sanitized serial
+    let serial_number = config.input_guard.sanitize(&event.serial_number)
+        .map_err(|error| ServiceError::InvalidRequest(format!("serialNumber is rejected with {}", error.value())))?;
+
+    let ttl = event.ttl_seconds.unwrap_or(DEFAULT_BYPASS_TTL_SECONDS).min(MAX_TTL_SECONDS);
+    let token = BypassToken {
+        token_id: format!("{:016x}", rand::random::<u64>()),
+        serial_number: serial_number.to_string(),
+        tenant_id: event.tenant_id.clone(),
+        rules: event.rules.clone(),
+        expires_at: now + ttl,
+        issued_by,
+        reason
+    };
+    bypass::audit(context, "bypass_issued", &token);
+
+    Ok(BypassTokenIssued { bypass_token: token.sign(secret), token_id: token.token_id, expires_at: token.expires_at })
+}
+
+fn validate_serial_unique(serial_number: &str, store: &dyn SerialStore, deadline: Option<Instant>) -> Result<bool, StoreError> {
+    let timeout = lookup_timeout(deadline)?;
+
+    // valid only if serial_number was not found; with a bloom filter most new serials are
+    // answered here without a round trip, and only possible hits reach GetItem
+    store.contains(serial_number, timeout).map(|found| !found)
+}
+
+/// What is left until `deadline`, failing once it passed.
+fn lookup_timeout(deadline: Option<Instant>) -> Result<Option<Duration>, StoreError> {
+    // the remaining budget is handed to the store so the request is abandoned before Lambda kills us
+    match deadline {
+        Some(deadline) => {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(StoreError::Timeout);
+            }
+            Ok(Some(deadline - now))
         },
+        None => Ok(None)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rules::validate_serial_length;
+    use store::{MemoryStore, FailingStore};
+
+    fn test_store() -> MemoryStore {
+        MemoryStore::new(vec![String::from("serial1"), String::from("serial2"), String::from("serial3")])
+    }
+
+    fn test_context() -> RequestContext {
+        RequestContext::new(String::from("req-1"), Instant::now())
+    }
 
     #[test]
     fn validation_result_for_invalid_length() {
         let test_serial = "i234";
-        let validation_result = validate_serial(test_serial);
+        let validation_result = validate_serial(test_serial, &test_context(), None, &test_store(), &Config::default(), ValidationStrategy::CollectAll, None).ok().unwrap();
         assert_eq!(false, validation_result.is_valid);
         assert_eq!(true, validation_result.errors.contains(&String::from("invalid_format")))
     }
@@ -119,7 +1629,7 @@ mod tests {
     #[test]
     fn validation_result_for_invalid_characters() {
         let test_serial = "i234@";
-        let validation_result = validate_serial(test_serial);
+        let validation_result = validate_serial(test_serial, &test_context(), None, &test_store(), &Config::default(), ValidationStrategy::CollectAll, None).ok().unwrap();
         assert_eq!(false, validation_result.is_valid);
         assert_eq!(true, validation_result.errors.contains(&String::from("invalid_format")))
     }
@@ -127,19 +1637,542 @@ mod tests {
     #[test]
     fn validation_result_for_already_existing_serial() {
         let test_serial = "serial1";
-        let validation_result = validate_serial(test_serial);
+        let validation_result = validate_serial(test_serial, &test_context(), None, &test_store(), &Config::default(), ValidationStrategy::CollectAll, None).ok().unwrap();
         assert_eq!(false, validation_result.is_valid);
         assert_eq!(true, validation_result.errors.contains(&String::from("already_exists")))
     }
 
+    #[test]
+    fn validation_result_suggests_similar_serials() {
+        let config = Config { similar_serials: similarity::SimilaritySettings { limit: 1, max_distance: 1 }, ..Default::default() };
+        let validation_result = validate_serial("serial1", &test_context(), None, &test_store(), &config, config.validation_strategy, None).ok().unwrap();
+        assert_eq!(vec!["serial2"], validation_result.similar_serials);
+        let validation_result = validate_serial("serial1", &test_context(), None, &test_store(), &Config::default(), ValidationStrategy::CollectAll, None).ok().unwrap();
+        assert_eq!(true, validation_result.similar_serials.is_empty());
+    }
+
+    #[test]
+    fn validation_result_names_the_conflict_when_permitted() {
+        let store = test_store();
+        let update = MetadataUpdate { status: Some(String::from("active")), owner_id: Some(String::from("owner-1")) };
+        store.update_metadata("serial1", &update, 0, None).ok().unwrap();
+        let config = Config { include_conflict: true, ..Default::default() };
+        let validation_result = validate_serial("serial1", &test_context(), None, &store, &config, config.validation_strategy, None).ok().unwrap();
+        assert_eq!(vec!["already_exists"], validation_result.errors);
+        let conflict = validation_result.conflict.unwrap();
+        assert_eq!((Some("owner-1"), Some("active")), (conflict.owner_id.as_deref(), conflict.status.as_deref()));
+        assert_eq!(None, validate_serial("a12345bbc", &test_context(), None, &store, &config, config.validation_strategy, None).ok().unwrap().conflict);
+        assert_eq!(None, validate_serial("serial1", &test_context(), None, &store, &Config::default(), ValidationStrategy::CollectAll, None).ok().unwrap().conflict);
+    }
+
+    #[test]
+    fn validation_result_for_overlong_and_padded_serials() {
+        let validation_result = validate_serial(&"A".repeat(129), &test_context(), None, &FailingStore, &Config::default(), ValidationStrategy::CollectAll, None).ok().unwrap();
+        assert_eq!(vec!["too_long"], validation_result.errors);
+        assert_eq!(Some(String::from("skipped")), validation_result.uniqueness);
+        let validation_result = validate_serial(" serial1\n", &test_context(), None, &test_store(), &Config::default(), ValidationStrategy::CollectAll, None).ok().unwrap();
+        assert_eq!(vec!["already_exists"], validation_result.errors);
+    }
+
+    #[test]
+    fn validation_result_for_missing_serials() {
+        for serial_number in &["", "   ", "\t\u{0}\n"] {
+            let validation_result = validate_serial(serial_number, &test_context(), None, &FailingStore, &Config::default(), ValidationStrategy::CollectAll, None).ok().unwrap();
+            assert_eq!(vec!["missing_serial"], validation_result.errors);
+            assert_eq!(Some(String::from("skipped")), validation_result.uniqueness);
+        }
+    }
+
     #[test]
     fn validation_result_for_valid_serial() {
         let test_serial = "a12345bbc";
-        let validation_result = validate_serial(test_serial);
+        let validation_result = validate_serial(test_serial, &test_context(), None, &test_store(), &Config::default(), ValidationStrategy::CollectAll, None).ok().unwrap();
         assert_eq!(true, validation_result.is_valid);
         assert_eq!(true, validation_result.errors.is_empty())
     }
 
+    #[test]
+    fn validation_result_for_configured_rules() {
+        let settings = rules::RuleSettings { blocklist: vec![String::from("a12345bbc")], ..Default::default() };
+        let config = Config { validators: rules::ValidatorRegistry::from_names(&["blocklist"], &settings).ok().unwrap(), ..Default::default() };
+        let validation_result = validate_serial("A12345BBC", &test_context(), None, &test_store(), &config, config.validation_strategy, None).ok().unwrap();
+        assert_eq!(vec!["blocklisted"], validation_result.errors);
+        assert_eq!(true, validate_serial("i2@", &test_context(), None, &test_store(), &config, config.validation_strategy, None).ok().unwrap().is_valid);
+    }
+
+    #[test]
+    fn validation_result_names_the_mismatched_template_segment() {
+        let settings = rules::RuleSettings { templates: vec![String::from("AAA-####")], ..Default::default() };
+        let config = Config { validators: rules::ValidatorRegistry::from_names(&["template"], &settings).ok().unwrap(), ..Default::default() };
+        let validation_result = validate_serial("ABC-12", &test_context(), None, &test_store(), &config, config.validation_strategy, None).ok().unwrap();
+        assert_eq!(vec!["invalid_format"], validation_result.errors);
+        assert_eq!(Some((3, String::from("12"))), validation_result.segment_mismatch.map(|mismatch| (mismatch.segment, mismatch.found)));
+    }
+
+    #[test]
+    fn validation_result_for_warning_rules() {
+        let settings = rules::RuleSettings { deprecated_prefixes: vec![String::from("ZZ")], ..Default::default() };
+        let config = Config { validators: rules::ValidatorRegistry::from_names(&["length", "deprecated_prefix"], &settings).ok().unwrap(), ..Default::default() };
+        let validation_result = validate_serial("zz1234", &test_context(), None, &test_store(), &config, config.validation_strategy, None).ok().unwrap();
+        assert_eq!(true, validation_result.is_valid);
+        assert_eq!(vec!["deprecated_prefix"], validation_result.warnings);
+        assert_eq!(true, validation_result.errors.is_empty());
+    }
+
+    #[test]
+    fn times_the_stages_that_ran() {
+        let config = Config::default();
+        let validation_result = validate_serial("serial1@", &test_context(), None, &test_store(), &config, ValidationStrategy::FailFast, None).ok().unwrap();
+        assert_eq!(None, validation_result.timings.store_lookup_micros);
+        let mut validation_result = validate_serial("serial4", &test_context(), None, &test_store(), &config, ValidationStrategy::FailFast, None).ok().unwrap();
+        assert_eq!(true, validation_result.timings.store_lookup_micros.is_some());
+
+        validation_result.meta = Some(response_meta("request-1", &config, validation_result.timings));
+        let json = serde_json::to_value(&validation_result).unwrap();
+        assert_eq!("request-1", json["meta"]["requestId"]);
+        assert_eq!(serde_json::json!(config.validators.version()), json["meta"]["ruleSetVersion"]);
+        assert_eq!(true, json["meta"]["timings"]["storeLookupMicros"].is_u64());
+        assert_eq!(None, json.get("timings"));
+    }
+
+    #[test]
+    fn fail_fast_skips_the_lookup_of_malformed_serials() {
+        let validation_result = validate_serial("serial1@", &test_context(), None, &FailingStore, &Config::default(), ValidationStrategy::FailFast, None).ok().unwrap();
+        assert_eq!(vec!["invalid_format"], validation_result.errors);
+        assert_eq!(Some(String::from("skipped")), validation_result.uniqueness);
+
+        let validation_result = validate_serial("serial1", &test_context(), None, &test_store(), &Config::default(), ValidationStrategy::FailFast, None).ok().unwrap();
+        assert_eq!(vec!["already_exists"], validation_result.errors);
+    }
+
+    #[test]
+    fn legacy_results_keep_the_first_shape() {
+        let mut result = validate_serial("serial1", &test_context(), None, &test_store(), &Config::default(), ValidationStrategy::CollectAll, None).ok().unwrap();
+        result.error_details = error_details(&result.errors, &MessageCatalog::embedded("en"), None);
+        let legacy = serde_json::to_value(VersionedResult::new(result, ResponseVersion::Legacy)).unwrap();
+        assert_eq!(serde_json::json!({"isValid": false, "errors": ["already_exists"]}), legacy);
+    }
+
+    #[test]
+    fn current_results_name_their_schema_version() {
+        let result = validate_serial("serial1", &test_context(), None, &test_store(), &Config::default(), ValidationStrategy::CollectAll, None).ok().unwrap();
+        let current = serde_json::to_value(VersionedResult::new(result, ResponseVersion::Current)).unwrap();
+        assert_eq!(2, current["schemaVersion"]);
+        assert_eq!(false, current["isValid"]);
+        assert_eq!(true, ResponseVersion::parse(3).is_none());
+    }
+
+    #[test]
+    fn error_details_carry_messages_in_the_requested_locale() {
+        let catalog = MessageCatalog::embedded("en");
+        let errors = vec![String::from("already_exists"), String::from("custom_rule")];
+        let details = error_details(&errors, &catalog, Some("de"));
+        assert_eq!(Some("Diese Seriennummer ist bereits registriert."), details[0].message.as_deref());
+        assert_eq!(ErrorDetail { code: String::from("custom_rule"), message: None }, details[1]);
+    }
+
+    #[test]
+    fn validation_result_for_expired_deadline() {
+        let test_serial = "a12345bbc";
+        let validation_result = validate_serial(test_serial, &test_context(), None, &test_store(), &Config::default(), ValidationStrategy::CollectAll, Some(Instant::now())).ok().unwrap();
+        assert_eq!(false, validation_result.is_valid);
+        assert_eq!(true, validation_result.errors.contains(&String::from("timeout")))
+    }
+
+    #[test]
+    fn validation_result_for_unreachable_store_in_degraded_mode() {
+        let test_serial = "a12345bbc";
+        let config = Config { degrade_on_store_error: true, ..Default::default() };
+        let validation_result = validate_serial(test_serial, &test_context(), None, &FailingStore, &config, config.validation_strategy, None).ok().unwrap();
+        assert_eq!(true, validation_result.is_valid);
+        assert_eq!(Some(String::from("unknown")), validation_result.uniqueness)
+    }
+
+    #[test]
+    fn validation_result_with_uniqueness_switched_off() {
+        let config = Config { skip_uniqueness: true, ..Default::default() };
+        let mut validation_result = validate_serial("serial1", &test_context(), None, &FailingStore, &config, config.validation_strategy, None).ok().unwrap();
+        assert_eq!((true, Some("disabled")), (validation_result.is_valid, validation_result.uniqueness.as_deref()));
+        assert_eq!(None, validation_result.timings.store_lookup_micros);
+
+        validation_result.meta = Some(response_meta("request-1", &config, validation_result.timings));
+        let json = serde_json::to_value(&validation_result).unwrap();
+        assert_eq!(serde_json::json!(["length", "alphanumeric"]), json["meta"]["activeRules"]);
+        let meta = response_meta("request-1", &Config::default(), validation_result.timings);
+        assert_eq!(vec!["length", "alphanumeric", "uniqueness"], meta.active_rules);
+    }
+
+    #[test]
+    fn validation_result_for_unreachable_store_and_invalid_format_in_degraded_mode() {
+        let test_serial = "i234@";
+        let config = Config { degrade_on_store_error: true, ..Default::default() };
+        let validation_result = validate_serial(test_serial, &test_context(), None, &FailingStore, &config, config.validation_strategy, None).ok().unwrap();
+        assert_eq!(false, validation_result.is_valid);
+        assert_eq!(true, validation_result.errors.contains(&String::from("invalid_format")))
+    }
+
+    #[test]
+    fn validation_error_for_unreachable_store_without_degraded_mode() {
+        let test_serial = "a12345bbc";
+        let validation_error = validate_serial(test_serial, &test_context(), None, &FailingStore, &Config::default(), ValidationStrategy::CollectAll, None).err().unwrap();
+        assert_eq!("StoreUnavailable", validation_error.error_type());
+        assert_eq!(true, validation_error.retryable())
+    }
+
+    #[test]
+    fn lookups_past_the_budget_are_left_pending() {
+        let event = ValidationEvent { serial_number: String::from("serial4"), budget_ms: Some(0), ..Default::default() };
+        let timed_out = validate_serial("serial4", &test_context(), None, &test_store(), &Config::default(), ValidationStrategy::CollectAll, Some(Instant::now())).ok().unwrap();
+        assert_eq!(vec![ValidationError::Timeout.value()], timed_out.errors);
+        let pending = defer_uniqueness(timed_out, &event);
+        assert_eq!((true, Some("pending")), (pending.is_valid, pending.uniqueness.as_deref()));
+
+        let resolved = pending_event(&pending.pending_token.unwrap()).ok().unwrap();
+        assert_eq!(("serial4", None, None), (resolved.serial_number.as_str(), resolved.action, resolved.budget_ms));
+        assert_eq!(true, matches!(pending_event("garbage"), Err(ServiceError::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn finished_lookups_are_not_pending() {
+        let event = ValidationEvent { serial_number: String::from("serial1"), ..Default::default() };
+        let exists = defer_uniqueness(validate_serial("serial1", &test_context(), None, &test_store(), &Config::default(), ValidationStrategy::CollectAll, None).ok().unwrap(), &event);
+        assert_eq!((vec![ValidationError::AlreadyExists.value()], None), (exists.errors, exists.pending_token));
+    }
+
+    fn bypass_config() -> Config {
+        Config {
+            bypass_token_secret: Some(String::from("secret")),
+            admin_api_key: Some(String::from("admin-key")),
+            ..Default::default()
+        }
+    }
+
+    fn bypass_event(serial_number: &str, rules: Vec<&str>) -> ValidationEvent {
+        ValidationEvent {
+            serial_number: String::from(serial_number),
+            action: Some(String::from("issueBypassToken")),
+            admin_key: Some(String::from("admin-key")),
+            rules: rules.into_iter().map(String::from).collect(),
+            issued_by: Some(String::from("admin")),
+            reason: Some(String::from("damaged label")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validation_result_for_bypassed_format_rule() {
+        let config = bypass_config();
+        let issued = issue_bypass_token(&bypass_event("i234", vec!["length"]), &test_context(), &config, unix_now()).ok().unwrap();
+        let validation_result = validate_serial("i234", &test_context(), Some(&issued.bypass_token), &test_store(), &config, config.validation_strategy, None).ok().unwrap();
+        assert_eq!(true, validation_result.is_valid);
+        assert_eq!(vec![String::from("length")], validation_result.bypass.unwrap().rules)
+    }
+
+    #[test]
+    fn validation_result_for_bypass_token_scoped_to_another_rule() {
+        let config = bypass_config();
+        let issued = issue_bypass_token(&bypass_event("i234@", vec!["length"]), &test_context(), &config, unix_now()).ok().unwrap();
+        let validation_result = validate_serial("i234@", &test_context(), Some(&issued.bypass_token), &test_store(), &config, config.validation_strategy, None).ok().unwrap();
+        assert_eq!(false, validation_result.is_valid);
+        assert_eq!(true, validation_result.errors.contains(&String::from("invalid_format")))
+    }
+
+    #[test]
+    fn validation_result_for_bypass_token_of_another_serial() {
+        let config = bypass_config();
+        let issued = issue_bypass_token(&bypass_event("i234", vec!["length"]), &test_context(), &config, unix_now()).ok().unwrap();
+        let validation_result = validate_serial("i235", &test_context(), Some(&issued.bypass_token), &test_store(), &config, config.validation_strategy, None).ok().unwrap();
+        assert_eq!(false, validation_result.is_valid);
+        assert_eq!(true, validation_result.errors.contains(&String::from("invalid_bypass_token")))
+    }
+
+    #[test]
+    fn bypass_token_requires_the_admin_key() {
+        let mut event = bypass_event("i234", vec!["length"]);
+        event.admin_key = Some(String::from("guess"));
+        let issue_error = issue_bypass_token(&event, &test_context(), &bypass_config(), unix_now()).err().unwrap();
+        assert_eq!("Unauthorized", issue_error.error_type())
+    }
+
+    #[test]
+    fn bypass_token_rejects_unknown_rules() {
+        let issue_error = issue_bypass_token(&bypass_event("i234", vec!["uniqueness"]), &test_context(), &bypass_config(), unix_now()).err().unwrap();
+        assert_eq!("InvalidRequest", issue_error.error_type())
+    }
+
+    #[test]
+    fn validation_completed_detail_for_a_duplicate() {
+        let event = ValidationEvent { serial_number: String::from("serial1"), ..Default::default() };
+        let result = validate_serial("serial1", &test_context(), None, &test_store(), &Config::default(), ValidationStrategy::CollectAll, None).ok().unwrap();
+        let context = RequestContext::new(String::from("req-1"), Instant::now()).for_request(Some("acme"), None, None);
+        let detail = validation_completed_detail(&event, &context, &result);
+        assert_eq!(("req-1", "acme", "serial1"), (detail["requestId"].as_str().unwrap(), detail["tenantId"].as_str().unwrap(), detail["serialNumber"].as_str().unwrap()));
+        assert_eq!(false, detail["result"]["isValid"]);
+        assert_eq!("already_exists", detail["result"]["errors"][0])
+    }
+
+    #[test]
+    fn audit_entry_for_a_duplicate() {
+        let event = ValidationEvent { serial_number: String::from("serial1"), ..Default::default() };
+        let outcome = validate_serial("serial1", &test_context(), None, &test_store(), &Config::default(), ValidationStrategy::CollectAll, None);
+        let entry = audit_entry(&event, &RequestContext::new(String::from("req-1"), Instant::now()), &outcome, 1_000);
+        assert_eq!("invalid", entry.outcome);
+        assert_eq!(vec![String::from("already_exists")], entry.error_codes)
+    }
+
+    #[test]
+    fn audit_entry_carries_the_correlation_id() {
+        let event = ValidationEvent { serial_number: String::from("serial1"), ..Default::default() };
+        let outcome = validate_serial("serial1", &test_context(), None, &test_store(), &Config::default(), ValidationStrategy::CollectAll, None);
+        let context = RequestContext::new(String::from("req-1"), Instant::now()).correlated(Some("trace-9"));
+        assert_eq!(Some(String::from("trace-9")), audit_entry(&event, &context, &outcome, 1_000).correlation_id);
+        let detail = validation_completed_detail(&event, &context, outcome.as_ref().ok().unwrap());
+        assert_eq!("trace-9", detail["correlationId"]);
+    }
+
+    #[test]
+    fn audit_entry_for_an_unanswered_validation() {
+        let event = ValidationEvent { serial_number: String::from("serial1"), ..Default::default() };
+        let outcome = validate_serial("serial1", &test_context(), None, &FailingStore, &Config::default(), ValidationStrategy::CollectAll, None);
+        let entry = audit_entry(&event, &RequestContext::new(String::from("req-1"), Instant::now()), &outcome, 1_000);
+        assert_eq!("error", entry.outcome);
+        assert_eq!(vec![String::from("StoreUnavailable")], entry.error_codes)
+    }
+
+    #[test]
+    fn generated_serial_passes_validation() {
+        let store = test_store();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let generated = generate_serial(&store, &Config::default(), None, None, false, 1_000, deadline).ok().unwrap();
+        assert_eq!(1, generated.attempts);
+        let validation_result = validate_serial(&generated.serial_number, &test_context(), None, &MemoryStore::new(Vec::new()), &Config::default(), ValidationStrategy::CollectAll, None).ok().unwrap();
+        assert_eq!(true, validation_result.is_valid);
+        assert_eq!(true, store.contains(&generated.serial_number, None).ok().unwrap())
+    }
+
+    #[test]
+    fn retried_generate_requests_return_the_original_serial() {
+        let store = test_store();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let first = generate_serial(&store, &Config::default(), None, Some("order-17"), false, unix_now(), deadline).ok().unwrap();
+        let retried = generate_serial(&store, &Config::default(), None, Some("order-17"), false, unix_now(), deadline).ok().unwrap();
+        assert_eq!(first.serial_number, retried.serial_number);
+        assert_eq!(first.attempts, retried.attempts);
+
+        let error = generate_serial(&store, &Config::default(), None, Some(""), false, unix_now(), deadline).err().unwrap();
+        assert_eq!("InvalidRequest", error.error_type());
+    }
+
+    #[test]
+    fn sequenced_serials_count_per_tenant_and_product_line() {
+        let store = MemoryStore::new(Vec::new());
+        let counter = store::MemoryCounter::default();
+        let blocks = SequenceBlocks::new(10);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let config = Config::default();
+        let mut event: ValidationEvent = serde_json::from_value(serde_json::json!({"action": "generate", "productLine": "PX"})).unwrap();
+        let first = generate_sequenced_serial(&store, &counter, &blocks, &event, &config, deadline).ok().unwrap();
+        let second = generate_sequenced_serial(&store, &counter, &blocks, &event, &config, deadline).ok().unwrap();
+        assert_eq!(("PX00000001", "PX00000002"), (&first.serial_number[..10], &second.serial_number[..10]));
+        assert_eq!(true, validate_serial(&second.serial_number, &test_context(), None, &store, &config, config.validation_strategy, None).ok().unwrap().errors == vec!["already_exists"]);
+
+        event.tenant_id = Some(String::from("acme"));
+        assert_eq!("PX00000001", &generate_sequenced_serial(&MemoryStore::new(Vec::new()), &counter, &blocks, &event, &config, deadline).ok().unwrap().serial_number[..10]);
+        event.idempotency_key = Some(String::from("order-17"));
+        assert_eq!("InvalidRequest", generate_sequenced_serial(&store, &counter, &blocks, &event, &config, deadline).err().unwrap().error_type());
+        event.idempotency_key = None;
+        event.product_line = Some(String::from("P-X"));
+        assert_eq!("InvalidRequest", generate_sequenced_serial(&store, &counter, &blocks, &event, &config, deadline).err().unwrap().error_type());
+    }
+
+    #[test]
+    fn reserved_serials_are_taken_until_the_reservation_expires() {
+        let store = test_store();
+        let config = Config { reservation_ttl_seconds: 900, ..Default::default() };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let reservation = reserve_serial("AB1234", &store, &config, false, unix_now(), deadline).ok().unwrap();
+        assert_eq!(true, reservation.reserved);
+        let validation_result = validate_serial("AB1234", &test_context(), None, &store, &config, config.validation_strategy, None).ok().unwrap();
+        assert_eq!(vec!["already_exists"], validation_result.errors);
+        assert_eq!(false, reserve_serial("AB1234", &store, &config, false, unix_now(), deadline).ok().unwrap().reserved);
+        assert_eq!(false, reserve_serial("serial1", &store, &config, false, unix_now(), deadline).ok().unwrap().reserved);
+
+        let expired = reserve_serial("CD5678", &store, &config, false, 0, deadline).ok().unwrap();
+        assert_eq!(Some(900), expired.reserved_until);
+        assert_eq!(true, validate_serial("CD5678", &test_context(), None, &store, &config, config.validation_strategy, None).ok().unwrap().is_valid);
+    }
+
+    #[test]
+    fn dry_run_reservations_leave_the_serial_available() {
+        let store = test_store();
+        let config = Config { reservation_ttl_seconds: 900, ..Default::default() };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let reservation = reserve_serial("AB1234", &store, &config, true, unix_now(), deadline).ok().unwrap();
+        assert_eq!(true, reservation.reserved);
+        assert_eq!(true, reservation.dry_run);
+        assert_eq!(true, validate_serial("AB1234", &test_context(), None, &store, &config, config.validation_strategy, None).ok().unwrap().is_valid);
+        assert_eq!(false, reserve_serial("serial1", &store, &config, true, unix_now(), deadline).ok().unwrap().reserved);
+    }
+
+    #[test]
+    fn registers_serials_with_their_owner() {
+        let store = test_store();
+        let config = Config::default();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let registration = register_serial("AB1234", Some("owner-1"), &store, &config, deadline).ok().unwrap();
+        assert_eq!(true, registration.registered);
+        assert_eq!(None, registration.failed_condition);
+
+        let duplicate = register_serial("AB1234", Some("owner-2"), &store, &config, deadline).ok().unwrap();
+        assert_eq!(false, duplicate.registered);
+        assert_eq!(Some(String::from("already_exists")), duplicate.failed_condition);
+        assert_eq!(true, register_serial("CD5678", None, &store, &config, deadline).is_err());
+    }
+
+    #[test]
+    fn updates_report_conflicting_versions() {
+        let store = test_store();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let event: ValidationEvent = serde_json::from_value(serde_json::json!({
+            "action": "update", "serialNumber": "serial1", "status": "retired", "expectedVersion": 0
+        })).unwrap();
+        let updated = update_serial(&event, &store, deadline).ok().unwrap();
+        assert_eq!((true, Some(1), None), (updated.updated, updated.version, updated.error));
+
+        let stale = update_serial(&event, &store, deadline).ok().unwrap();
+        assert_eq!((false, Some(1), Some(String::from("conflict"))), (stale.updated, stale.version, stale.error));
+
+        let unversioned = ValidationEvent { expected_version: None, ..event };
+        assert_eq!(true, update_serial(&unversioned, &store, deadline).is_err());
+    }
+
+    #[test]
+    fn transfers_only_from_the_current_owner() {
+        let store = test_store();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        store.update_metadata("serial1", &MetadataUpdate { status: None, owner_id: Some(String::from("acme-fleet")) }, 0, None).ok().unwrap();
+        let event: ValidationEvent = serde_json::from_value(serde_json::json!({
+            "action": "transfer", "serialNumber": "serial1", "fromOwner": "acme-fleet", "ownerId": "globex"
+        })).unwrap();
+        let transferred = transfer_serial(&event, &store, deadline).ok().unwrap();
+        assert_eq!((true, Some(2), None), (transferred.transferred, transferred.version, transferred.error));
+
+        let stale = transfer_serial(&event, &store, deadline).ok().unwrap();
+        assert_eq!((false, Some(String::from("conflict"))), (stale.transferred, stale.error));
+
+        let unknown = ValidationEvent { serial_number: String::from("CD5678"), ..event };
+        assert_eq!(Some(String::from("not_found")), transfer_serial(&unknown, &store, deadline).ok().unwrap().error);
+        let ownerless = ValidationEvent { from_owner: None, ..unknown };
+        assert_eq!(true, transfer_serial(&ownerless, &store, deadline).is_err());
+    }
+
+    #[test]
+    fn imports_need_the_import_permission() {
+        let mut event = ValidationEvent { action: Some(String::from("import")), ..Default::default() };
+        let config = Config { caller_permissions: caller::CallerPermissions::parse(&["migration:import"]).ok().unwrap(), ..Default::default() };
+        let caller = |group: &str| CallerContext { groups: vec![String::from(group)], ..CallerContext::api_key(String::from("u-1")) };
+        let context = |group: &str| RequestContext::new(String::from("req-1"), Instant::now() + Duration::from_secs(5)).for_request(None, Some(caller(group)), None);
+        match import_serials(&event, &context("qa"), &config) {
+            Err(ServiceError::Unauthorized(_)) => {},
+            _ => panic!("expected the import to be refused"),
+        }
+        match import_serials(&event, &context("migration"), &config) {
+            Err(ServiceError::InvalidRequest(message)) => assert_eq!("import needs either serials or a manifest", message),
+            _ => panic!("expected the import to need serials"),
+        }
+        event.serials = vec![String::from("AB1234"); import::MAX_INLINE_SERIALS + 1];
+        assert_eq!(true, import_serials(&event, &context("migration"), &config).is_err());
+    }
+
+    #[test]
+    fn listing_needs_the_admin_key() {
+        let event = ValidationEvent { action: Some(String::from("list")), admin_key: Some(String::from("wrong-key")), ..Default::default() };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        match list_serials(&event, &bypass_config(), deadline) {
+            Err(ServiceError::Unauthorized(_)) => {},
+            _ => panic!("expected the listing to be refused"),
+        }
+    }
+
+    #[test]
+    fn releasing_needs_the_admin_key_and_a_reason() {
+        let mut event = ValidationEvent {
+            serial_number: String::from("serial1"),
+            action: Some(String::from("release")),
+            admin_key: Some(String::from("admin-key")),
+            reason: Some(String::from("  ")),
+            ..Default::default()
+        };
+        let context = RequestContext::new(String::from("req-1"), Instant::now());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        match release_serial(&event, &context, &bypass_config(), deadline) {
+            Err(ServiceError::InvalidRequest(message)) => assert_eq!("reason is required", message),
+            _ => panic!("expected the release to need a reason"),
+        }
+        event.admin_key = Some(String::from("wrong-key"));
+        match release_serial(&event, &context, &bypass_config(), deadline) {
+            Err(ServiceError::Unauthorized(_)) => {},
+            _ => panic!("expected the release to be refused"),
+        }
+    }
+
+    #[test]
+    fn audits_releases_with_their_reason() {
+        let event = ValidationEvent {
+            serial_number: String::from("serial1"),
+            action: Some(String::from("release")),
+            reason: Some(String::from("damaged label")),
+            ..Default::default()
+        };
+        let context = RequestContext::new(String::from("req-1"), Instant::now());
+        let entry = release_audit_entry(&event, &context, false, 1_000);
+        assert_eq!(("not_found", Some(String::from("damaged label"))), (entry.outcome, entry.reason));
+    }
+
+    #[test]
+    fn confirms_only_live_reservations() {
+        let store = test_store();
+        let config = Config { reservation_ttl_seconds: 900, ..Default::default() };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        reserve_serial("AB1234", &store, &config, false, unix_now(), deadline).ok().unwrap();
+        assert_eq!(true, confirm_serial("AB1234", &store, deadline).ok().unwrap().confirmed);
+        assert_eq!(false, confirm_serial("AB1234", &store, deadline).ok().unwrap().confirmed);
+        assert_eq!(false, confirm_serial("serial1", &store, deadline).ok().unwrap().confirmed);
+
+        let error = reserve_serial("AB12", &store, &config, false, unix_now(), deadline).err().unwrap();
+        assert_eq!("InvalidRequest", error.error_type());
+    }
+
+    #[test]
+    fn kinesis_records_are_held_to_the_ranges_of_their_tenant() {
+        let owner = RangeOwner { tenant_id: Some(String::from("acme")), product_line: None };
+        let ranges = Arc::new(vec![ReservedRange::new("XK100000", "XK199999", owner).unwrap()]);
+        let config = Config::default();
+        let records: Vec<serde_json::Value> = [r#"{"serialNumber": "XK150000", "tenantId": "globex"}"#, r#"{"serialNumber": "XK150001", "tenantId": "acme"}"#, r#"{"serialNumber": "XK150002", "tenantId": "globex"}"#].iter()
+            .enumerate()
+            .map(|(index, payload)| serde_json::json!({
+                "eventID": format!("shardId-000000000000:{}", index),
+                "eventSource": "aws:kinesis",
+                "kinesis": {"sequenceNumber": index.to_string(), "partitionKey": "p", "data": base64::encode(payload)}
+            }))
+            .collect();
+        let event: KinesisEvent = serde_json::from_value(serde_json::json!({ "Records": records })).unwrap();
+        let ranged = Mutex::new(HashMap::new());
+        let errors = Mutex::new(HashMap::new());
+        kinesis::run(&event, &test_context(), 2, |validation_event, _| {
+            let ranged = record_config(&config, Some(&ranges), &ranged, validation_event).unwrap();
+            let result = validate_serial(&validation_event.serial_number, &test_context(), None, &test_store(), &ranged, ValidationStrategy::CollectAll, None);
+            errors.lock().unwrap().insert(validation_event.serial_number.clone(), result.as_ref().ok().unwrap().errors.clone());
+            result
+        });
+        let errors = errors.into_inner().unwrap();
+        assert_eq!(vec![String::from("range_conflict")], errors["XK150000"]);
+        assert_eq!(Vec::<String>::new(), errors["XK150001"]);
+        assert_eq!(vec![String::from("range_conflict")], errors["XK150002"]);
+        // the two globex records share one config
+        assert_eq!(2, ranged.into_inner().unwrap().len());
+    }
+
     #[test]
     fn validates_length_of_four_characters_as_invalid() {
         let test_serial = "i234";
@@ -192,14 +2225,61 @@ mod tests {
     #[test]
     fn validates_existing_serial1_as_invalid() {
         let test_serial = "serial1";
-        let validation_result = validate_serial_unique(test_serial);
-        assert_eq!(false, validation_result);
+        let validation_result = validate_serial_unique(test_serial, &test_store(), None);
+        assert_eq!(false, validation_result.ok().unwrap());
     }
 
     #[test]
     fn validates_new_serial4_as_valid() {
         let test_serial = "serial4";
-        let validation_result = validate_serial_unique(test_serial);
-        assert_eq!(true, validation_result);
+        let validation_result = validate_serial_unique(test_serial, &test_store(), None);
+        assert_eq!(true, validation_result.ok().unwrap());
+    }
+
+    #[test]
+    fn tells_http_requests_from_validation_events() {
+        let request = serde_json::json!({
+            "requestContext": {"elb": {"targetGroupArn": "arn:aws:elasticloadbalancing:eu-central-1:123456789012:targetgroup/validator/abc"}},
+            "httpMethod": "POST",
+            "path": "/",
+            "headers": {"content-type": "application/json"},
+            "body": "{\"serialNumber\": \"serial4\"}",
+            "isBase64Encoded": false
+        });
+        assert_eq!(true, matches!(serde_json::from_value(request), Ok(Event::Alb(_))));
+        let request = serde_json::json!({
+            "version": "2.0",
+            "requestContext": {"http": {"method": "POST", "path": "/"}},
+            "headers": {"content-type": "application/json"},
+            "body": "{\"serialNumber\": \"serial4\"}",
+            "isBase64Encoded": false
+        });
+        assert_eq!(true, matches!(serde_json::from_value(request), Ok(Event::FunctionUrl(_))));
+        assert_eq!(true, matches!(serde_json::from_value(serde_json::json!({"serialNumber": "serial4"})), Ok(Event::Validation(_))));
+    }
+
+    #[test]
+    fn detects_warmup_payloads_by_their_marker() {
+        assert_eq!(true, is_warmup(&serde_json::json!({"warmer": true}), "warmer"));
+        assert_eq!(true, is_warmup(&serde_json::json!({"source": "serverless-plugin-warmup"}), "source"));
+        assert_eq!(false, is_warmup(&serde_json::json!({"warmer": false}), "warmer"));
+        assert_eq!(false, is_warmup(&serde_json::json!({"serialNumber": "serial1"}), "warmer"));
+        assert_eq!(false, is_warmup(&serde_json::json!({"warmer": true}), ""));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn warmup_without_preload_only_acknowledges() {
+        let acknowledged = warm_up(&Config::default());
+        assert_eq!((true, false), (acknowledged.warm, acknowledged.preloaded));
+    }
+
+    #[test]
+    fn panics_become_internal_errors() {
+        let panicked: Result<(), ServiceError> = catch_panics(&RequestContext::new(String::from("request1"), Instant::now()), || panic!("boom"));
+        let error = panicked.err().unwrap();
+        assert_eq!(true, matches!(error, ServiceError::Internal(_)));
+        assert_eq!((false, 500), (error.retryable(), http::ErrorMapper::status(&error)));
+        assert_eq!(false, error.to_json().contains("boom"));
+        assert_eq!(Some(1), catch_panics(&RequestContext::new(String::from("request2"), Instant::now()), || Ok(1)).ok());
+    }
+}
@@ -0,0 +1,153 @@
+//! Human readable messages for the error codes of validation results, by locale. The catalog
+//! embedded in the binary can be extended or overridden from S3 and the environment.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rusoto_core::Region;
+
+use crate::aws;
+use crate::context::log_shared;
+
+/// Messages per locale and error code, as read from JSON: `{"en": {"invalid_format": "..."}}`.
+type Messages = HashMap<String, HashMap<String, String>>;
+
+const EMBEDDED: &str = include_str!("messages.json");
+
+/// Where overrides of the embedded catalog come from.
+#[derive(Clone, Debug)]
+pub struct MessageSettings {
+    /// Locale answered in when the caller asks for none the catalog has.
+    pub default_locale: String,
+    /// S3 object holding overrides, read once per container.
+    pub bucket: Option<String>,
+    pub key: String,
+    /// Overrides given inline, taking precedence over those from S3.
+    pub overrides: Option<String>
+}
+
+impl Default for MessageSettings {
+    fn default() -> MessageSettings {
+        MessageSettings {
+            default_locale: String::from("en"),
+            bucket: None,
+            key: String::from("messages.json"),
+            overrides: None
+        }
+    }
+}
+
+pub struct MessageCatalog {
+    messages: Messages,
+    default_locale: String
+}
+
+impl MessageCatalog {
+    pub fn embedded(default_locale: &str) -> MessageCatalog {
+        MessageCatalog {
+            messages: parse(EMBEDDED).expect("embedded message catalog is valid"),
+            default_locale: default_locale.to_lowercase()
+        }
+    }
+
+    /// Replaces the messages `overrides` has, keeping the others.
+    fn extend(&mut self, overrides: Messages) {
+        for (locale, messages) in overrides {
+            self.messages.entry(locale).or_default().extend(messages);
+        }
+    }
+
+    /// Message for `code` in the first of the `locales` (an `Accept-Language` value or a single
+    /// tag) the catalog has it in, trying `de` for `de-AT`, then in the default locale.
+    pub fn message(&self, code: &str, locales: Option<&str>) -> Option<&str> {
+        let preferred = locales.map(preferred_locales).unwrap_or_default();
+        preferred.iter()
+            .flat_map(|locale| vec![locale.as_str(), locale.split('-').next().unwrap_or_default()])
+            .chain(Some(self.default_locale.as_str()))
+            .filter_map(|locale| self.messages.get(locale)?.get(code))
+            .map(String::as_str)
+            .next()
+    }
+}
+
+/// The embedded catalog with the overrides of `settings`. Overrides that cannot be read are
+/// logged and skipped, leaving the embedded messages in place.
+pub fn load(settings: &MessageSettings, timeout: Option<Duration>) -> MessageCatalog {
+    let mut catalog = MessageCatalog::embedded(&settings.default_locale);
+    if let Some(ref bucket) = settings.bucket {
+        let source = format!("s3://{}/{}", bucket, settings.key);
+        match aws::get_object(&Region::default(), bucket, &settings.key, timeout) {
+            Ok(Some(object)) => match parse(&String::from_utf8_lossy(&object.body)) {
+                Ok(messages) => catalog.extend(messages),
+                Err(error) => log_shared(&format!("ignoring malformed message catalog {}: {}", source, error)),
+            },
+            Ok(None) => log_shared(&format!("message catalog {} does not exist", source)),
+            Err(error) => log_shared(&format!("message catalog {} could not be read: {}", source, error)),
+        }
+    }
+    if let Some(ref overrides) = settings.overrides {
+        match parse(overrides) {
+            Ok(messages) => catalog.extend(messages),
+            Err(error) => log_shared(&format!("ignoring malformed ERROR_MESSAGES: {}", error)),
+        }
+    }
+    catalog
+}
+
+/// Reads a catalog, lower-casing its locales.
+fn parse(json: &str) -> Result<Messages, serde_json::Error> {
+    let messages: Messages = serde_json::from_str(json)?;
+    Ok(messages.into_iter().map(|(locale, messages)| (locale.to_lowercase(), messages)).collect())
+}
+
+/// Lower-cased language tags of an `Accept-Language` value, most preferred first. Wildcards and
+/// tags with a quality of zero are left out.
+fn preferred_locales(value: &str) -> Vec<String> {
+    let mut locales: Vec<(String, f32)> = value.split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let locale = parts.next()?.trim().to_lowercase();
+            let quality = parts.filter_map(|parameter| parameter.trim().strip_prefix("q="))
+                .next()
+                .map(|quality| quality.trim().parse().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            Some((locale, quality))
+        })
+        .filter(|&(ref locale, quality)| !locale.is_empty() && locale != "*" && quality > 0.0)
+        .collect();
+    // stable, so tags of equal quality keep their order
+    locales.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    locales.into_iter().map(|(locale, _)| locale).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_accept_language_by_quality() {
+        assert_eq!(vec!["fr-ch", "de", "en"], preferred_locales("en;q=0.5, fr-CH, de;q=0.8, *;q=0.1, it;q=0"));
+        assert_eq!(true, preferred_locales(" ").is_empty());
+    }
+
+    #[test]
+    fn falls_back_to_the_language_and_the_default_locale() {
+        let catalog = MessageCatalog::embedded("en");
+        assert_eq!(Some("Diese Seriennummer ist bereits registriert."), catalog.message("already_exists", Some("de-AT")));
+        assert_eq!(Some("This serial number is already registered."), catalog.message("already_exists", Some("xx, yy;q=0.5")));
+        assert_eq!(Some("This serial number is already registered."), catalog.message("already_exists", None));
+        assert_eq!(None, catalog.message("unknown_code", Some("de")));
+    }
+
+    #[test]
+    fn overrides_replace_single_messages() {
+        let settings = MessageSettings {
+            overrides: Some(String::from(r#"{"DE": {"already_exists": "Schon vergeben."}, "nl": {"timeout": "Time-out."}}"#)),
+            ..Default::default()
+        };
+        let catalog = load(&settings, None);
+        assert_eq!(Some("Schon vergeben."), catalog.message("already_exists", Some("de")));
+        assert_eq!(Some("Die Seriennummer hat nicht das erwartete Format."), catalog.message("invalid_format", Some("de")));
+        assert_eq!(Some("Time-out."), catalog.message("timeout", Some("nl-BE")));
+    }
+}
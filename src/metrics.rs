@@ -0,0 +1,231 @@
+//! Stage timings of each invocation, written to the function log in CloudWatch's embedded metric
+//! format (EMF), which CloudWatch turns into metrics without any call from the function. Each
+//! line carries the action, tenant and rule set version as dimensions. Tenants are reported under
+//! their own id only when listed, and a container reports only so many distinct actions and rule
+//! set versions; all other values are reported as `other`, so that a stream of tenant ids cannot
+//! create a metric each.
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use serde_json::{json, Map, Value};
+
+/// Reported for dimension values past a guard's limit.
+pub const OTHER: &str = "other";
+
+/// Reported for dimensions the invocation had no value for, e.g. requests without a tenant.
+pub const NONE: &str = "none";
+
+/// Dimension sets every line is aggregated under.
+const DIMENSION_SETS: [&[&str]; 3] = [&["Action"], &["Action", "TenantId"], &["Action", "RuleSetVersion"]];
+
+/// Actions a container reports before further ones become `other`.
+const MAX_ACTIONS: usize = 32;
+
+#[derive(Clone, Debug)]
+pub struct MetricsSettings {
+    pub namespace: String,
+    /// Tenants reported under their own id; every other tenant is reported as `other`, also while
+    /// none are listed. Admitting tenants as containers see them would let each container add its
+    /// own, and report a tenant under its id in some containers and as `other` in the rest.
+    pub tenants: Vec<String>,
+    pub max_rule_set_versions: usize
+}
+
+impl Default for MetricsSettings {
+    fn default() -> MetricsSettings {
+        MetricsSettings { namespace: String::new(), tenants: Vec::new(), max_rule_set_versions: 5 }
+    }
+}
+
+/// Admits the first `limit` distinct values of a dimension, reporting the others as `other`.
+pub struct DimensionGuard {
+    limit: usize,
+    seen: Mutex<HashSet<String>>
+}
+
+impl DimensionGuard {
+    pub fn new(limit: usize) -> DimensionGuard {
+        DimensionGuard { limit, seen: Mutex::new(HashSet::new()) }
+    }
+
+    pub fn admit(&self, value: &str) -> String {
+        let mut seen = self.seen.lock().unwrap();
+        if seen.contains(value) {
+            return value.to_string();
+        }
+        if seen.len() < self.limit {
+            seen.insert(value.to_string());
+            return value.to_string();
+        }
+        String::from(OTHER)
+    }
+}
+
+/// The dimension values a container has reported so far, shared by every invocation it serves.
+pub struct MetricGuards {
+    allowed_tenants: Vec<String>,
+    actions: DimensionGuard,
+    rule_set_versions: DimensionGuard
+}
+
+impl MetricGuards {
+    pub fn new(settings: &MetricsSettings) -> MetricGuards {
+        MetricGuards {
+            allowed_tenants: settings.tenants.clone(),
+            actions: DimensionGuard::new(MAX_ACTIONS),
+            rule_set_versions: DimensionGuard::new(settings.max_rule_set_versions)
+        }
+    }
+
+    fn tenant(&self, tenant_id: Option<&str>) -> String {
+        match tenant_id {
+            None => String::from(NONE),
+            Some(tenant_id) if self.allowed_tenants.iter().any(|allowed| allowed == tenant_id) => tenant_id.to_string(),
+            Some(_) => String::from(OTHER),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Recorded {
+    action: Option<String>,
+    tenant_id: Option<String>,
+    rule_set_version: Option<String>,
+    parse_micros: Option<u64>,
+    rules_micros: Option<u64>,
+    store_micros: Option<u64>,
+    serialize_micros: Option<u64>,
+    /// Whether the candidate rules judged the serial differently, when they ran.
+    shadow_diverged: Option<bool>
+}
+
+/// What an invocation recorded on its way, filled in by the stages that ran.
+#[derive(Debug, Default)]
+pub struct InvocationMetrics {
+    recorded: Mutex<Recorded>
+}
+
+impl InvocationMetrics {
+    /// Names the request being handled. Invocations that never name one, e.g. warmups, are not reported.
+    pub fn request(&self, action: &str, tenant_id: Option<&str>, rule_set_version: Option<&str>) {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded.action = Some(action.to_string());
+        recorded.tenant_id = tenant_id.map(String::from);
+        recorded.rule_set_version = rule_set_version.map(String::from);
+    }
+
+    /// Reading the payload into an event.
+    pub fn parse(&self, micros: u64) {
+        self.recorded.lock().unwrap().parse_micros = Some(micros);
+    }
+
+    /// The format rules, and the lookup in the store unless it was skipped.
+    pub fn validation(&self, rules_micros: u64, store_micros: Option<u64>) {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded.rules_micros = Some(rules_micros);
+        recorded.store_micros = store_micros;
+    }
+
+    /// The candidate rules of shadow evaluation, and whether they differed from the active ones.
+    pub fn shadow(&self, diverged: bool) {
+        self.recorded.lock().unwrap().shadow_diverged = Some(diverged);
+    }
+
+    /// Turning the response into JSON.
+    pub fn serialize(&self, micros: u64) {
+        self.recorded.lock().unwrap().serialize_micros = Some(micros);
+    }
+
+    /// The EMF line of the invocation, `None` unless a request was named.
+    pub fn document(&self, settings: &MetricsSettings, guards: &MetricGuards, total_micros: u64, timestamp_millis: u64) -> Option<Value> {
+        let recorded = self.recorded.lock().unwrap().clone();
+        let action = recorded.action.as_deref()?;
+        let mut line = Map::new();
+        line.insert(String::from("Action"), Value::from(guards.actions.admit(action)));
+        line.insert(String::from("TenantId"), Value::from(guards.tenant(recorded.tenant_id.as_deref())));
+        line.insert(String::from("RuleSetVersion"), Value::from(match recorded.rule_set_version {
+            Some(ref version) => guards.rule_set_versions.admit(version),
+            None => String::from(NONE),
+        }));
+        let stages = [
+            ("ParseTime", recorded.parse_micros),
+            ("RulesTime", recorded.rules_micros),
+            ("StoreTime", recorded.store_micros),
+            ("SerializeTime", recorded.serialize_micros),
+            ("TotalTime", Some(total_micros))
+        ];
+        let mut metrics = Vec::new();
+        for (name, micros) in stages.iter() {
+            if let Some(micros) = micros {
+                line.insert(name.to_string(), Value::from(*micros));
+                metrics.push(json!({"Name": name, "Unit": "Microseconds"}));
+            }
+        }
+        // 0 for agreeing rule sets too, so that the average is the share of diverging validations
+        if let Some(diverged) = recorded.shadow_diverged {
+            line.insert(String::from("ShadowDivergence"), Value::from(diverged as u64));
+            metrics.push(json!({"Name": "ShadowDivergence", "Unit": "Count"}));
+        }
+        line.insert(String::from("_aws"), json!({
+            "Timestamp": timestamp_millis,
+            "CloudWatchMetrics": [{"Namespace": settings.namespace, "Dimensions": DIMENSION_SETS, "Metrics": metrics}]
+        }));
+        Some(Value::Object(line))
+    }
+
+    /// Writes the EMF line of the invocation to the function log.
+    pub fn emit(&self, settings: &MetricsSettings, guards: &MetricGuards, total_micros: u64, timestamp_millis: u64) {
+        if let Some(line) = self.document(settings, guards, total_micros, timestamp_millis) {
+            println!("{}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_stage_timings_with_their_dimensions() {
+        let settings = MetricsSettings { namespace: String::from("SerialValidation"), tenants: vec![String::from("acme")], ..Default::default() };
+        let guards = MetricGuards::new(&settings);
+        let metrics = InvocationMetrics::default();
+        assert_eq!(None, metrics.document(&settings, &guards, 10, 1_000));
+
+        metrics.parse(5);
+        metrics.request("validate", Some("acme"), Some("a1b2c3"));
+        metrics.validation(20, None);
+        metrics.serialize(3);
+        let line = metrics.document(&settings, &guards, 100, 1_000).unwrap();
+        assert_eq!(json!("validate"), line["Action"]);
+        assert_eq!(json!("acme"), line["TenantId"]);
+        assert_eq!(json!("a1b2c3"), line["RuleSetVersion"]);
+        assert_eq!(json!(20), line["RulesTime"]);
+        assert_eq!(None, line.get("StoreTime"));
+        assert_eq!(json!(100), line["TotalTime"]);
+        let directive = &line["_aws"]["CloudWatchMetrics"][0];
+        assert_eq!(json!("SerialValidation"), directive["Namespace"]);
+        assert_eq!(json!(["Action", "TenantId"]), directive["Dimensions"][1]);
+        assert_eq!(4, directive["Metrics"].as_array().unwrap().len());
+
+        metrics.shadow(true);
+        let line = metrics.document(&settings, &guards, 100, 1_000).unwrap();
+        assert_eq!(json!(1), line["ShadowDivergence"]);
+        assert_eq!(5, line["_aws"]["CloudWatchMetrics"][0]["Metrics"].as_array().unwrap().len());
+    }
+
+    #[test]
+    fn reports_values_past_the_limits_as_other() {
+        let settings = MetricsSettings { max_rule_set_versions: 1, ..Default::default() };
+        let guards = MetricGuards::new(&settings);
+        assert_eq!(OTHER, guards.tenant(Some("acme")));
+        assert_eq!(NONE, guards.tenant(None));
+        assert_eq!("a1b2c3", guards.rule_set_versions.admit("a1b2c3"));
+        assert_eq!(OTHER, guards.rule_set_versions.admit("d4e5f6"));
+
+        let guards = MetricGuards::new(&MetricsSettings { tenants: vec![String::from("initech")], ..settings });
+        assert_eq!(OTHER, guards.tenant(Some("acme")));
+        assert_eq!("initech", guards.tenant(Some("initech")));
+    }
+}
@@ -0,0 +1,68 @@
+//! Validations answered within the caller's `budgetMs` before their uniqueness check finished.
+//! The answer carries a pending token holding the fields of the event that shape the result;
+//! exchanging it with the `resolvePending` action validates the serial again, without a budget.
+//! The token holds nothing the caller could not send itself, so it is encoded but not signed.
+
+use serde_json::{Map, Value};
+
+/// How long a pending token can be exchanged.
+pub const PENDING_TTL_SECONDS: u64 = 15 * 60;
+
+/// Fields of the validation event carried by a pending token.
+const CARRIED_FIELDS: [&str; 7] = ["serialNumber", "tenantId", "bypassToken", "strategy", "includeMeta", "responseVersion", "locale"];
+
+#[derive(Debug, PartialEq)]
+pub enum PendingError {
+    Malformed,
+    Expired
+}
+
+/// Token for the validation `event` (as JSON), exchangeable until `now` plus `PENDING_TTL_SECONDS`.
+pub fn issue(event: &Value, now: u64) -> String {
+    let mut fields: Map<String, Value> = CARRIED_FIELDS.iter()
+        .filter_map(|&name| event.get(name).filter(|value| !value.is_null()).map(|value| (name.to_string(), value.clone())))
+        .collect();
+    fields.insert(String::from("expiresAt"), Value::from(now + PENDING_TTL_SECONDS));
+    base64::encode_config(&Value::Object(fields).to_string(), base64::URL_SAFE_NO_PAD)
+}
+
+/// The validation event a token was issued for, as JSON, unless it expired by `now`.
+pub fn redeem(token: &str, now: u64) -> Result<Value, PendingError> {
+    let payload = base64::decode_config(token, base64::URL_SAFE_NO_PAD).map_err(|_| PendingError::Malformed)?;
+    let mut fields = match serde_json::from_slice(&payload) {
+        Ok(Value::Object(fields)) => fields,
+        _ => return Err(PendingError::Malformed),
+    };
+    match fields.remove("expiresAt").and_then(|expires_at| expires_at.as_u64()) {
+        Some(expires_at) if expires_at > now => {},
+        Some(_) => return Err(PendingError::Expired),
+        None => return Err(PendingError::Malformed),
+    }
+    if fields.keys().any(|name| !CARRIED_FIELDS.contains(&name.as_str())) {
+        return Err(PendingError::Malformed);
+    }
+    Ok(Value::Object(fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn carries_the_fields_shaping_the_result() {
+        let event = json!({"serialNumber": "AB1234", "tenantId": "acme", "locale": null, "apiKey": "secret", "budgetMs": 50});
+        let token = issue(&event, 1_000);
+        assert_eq!(Ok(json!({"serialNumber": "AB1234", "tenantId": "acme"})), redeem(&token, 1_000 + PENDING_TTL_SECONDS - 1));
+        assert_eq!(Err(PendingError::Expired), redeem(&token, 1_000 + PENDING_TTL_SECONDS));
+    }
+
+    #[test]
+    fn rejects_tokens_it_did_not_issue() {
+        assert_eq!(Err(PendingError::Malformed), redeem("garbage!", 0));
+        let forged = base64::encode_config(r#"{"serialNumber": "AB1234", "expiresAt": 10, "adminKey": "guess"}"#, base64::URL_SAFE_NO_PAD);
+        assert_eq!(Err(PendingError::Malformed), redeem(&forged, 0));
+        let endless = base64::encode_config(r#"{"serialNumber": "AB1234"}"#, base64::URL_SAFE_NO_PAD);
+        assert_eq!(Err(PendingError::Malformed), redeem(&endless, 0));
+    }
+}
@@ -0,0 +1,188 @@
+//! Token buckets limiting how often each caller may call, so that serials cannot be enumerated by
+//! brute force. The buckets live in a DynamoDB table keyed by `limiter_key`, shared by every
+//! container, and are taken from with conditional `UpdateItem` calls.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rusoto_dynamodb::{AttributeValue, DynamoDb, DynamoDbClient, GetItemInput, UpdateItemError, UpdateItemInput};
+
+use crate::context::RequestContext;
+use crate::error::ServiceError;
+use crate::store::{client_in, string_value, number_value, DEFAULT_REGION};
+
+/// Rounds of reading and updating a bucket other requests of the caller keep changing.
+const UPDATE_ATTEMPTS: u32 = 3;
+
+#[derive(Clone, Debug)]
+pub struct RateLimitSettings {
+    pub table_name: String,
+    /// Requests a caller may make at once, the size of the bucket.
+    pub burst: f64,
+    /// Requests a caller may make per second once the bucket is empty.
+    pub per_second: f64
+}
+
+/// Tokens left in a caller's bucket when it was last taken from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bucket {
+    pub tokens: f64,
+    /// Milliseconds since the epoch, never going back when containers' clocks disagree.
+    pub updated_at: u64,
+    /// Counts the writes to the bucket, each one conditional on the version it read, 0 for
+    /// buckets written before it was kept.
+    pub version: u64
+}
+
+impl Bucket {
+    /// Takes a token from `bucket`, a new caller's bucket being full. Fails with the seconds until
+    /// a token is back when the bucket is empty.
+    pub fn take(bucket: Option<Bucket>, settings: &RateLimitSettings, now: u64) -> Result<Bucket, u64> {
+        let (tokens, updated_at, version) = match bucket {
+            Some(bucket) => {
+                let elapsed = now.saturating_sub(bucket.updated_at) as f64 / 1000.0;
+                ((bucket.tokens + elapsed * settings.per_second).min(settings.burst), now.max(bucket.updated_at), bucket.version)
+            },
+            None => (settings.burst, now, 0),
+        };
+        if tokens < 1.0 {
+            return Err(((1.0 - tokens) / settings.per_second).ceil().max(1.0) as u64);
+        }
+        Ok(Bucket { tokens: tokens - 1.0, updated_at, version: version + 1 })
+    }
+}
+
+/// Bucket key of a caller, preferring who the authorizer or API key says it is over its address.
+/// It is hashed before it is written, see `hashing`.
+pub fn limiter_key(principal: Option<&str>, source_ip: Option<&str>) -> Option<String> {
+    principal.map(|principal| format!("caller#{}", principal))
+        .or_else(|| source_ip.map(|source_ip| format!("ip#{}", source_ip)))
+}
+
+/// Takes a token from the bucket under `key`, failing with `RateLimited` when it is empty. The
+/// limit is not enforced while the table cannot be read or written; failures are logged.
+pub fn check(context: &RequestContext, settings: &RateLimitSettings, key: &str, now: u64, timeout: Option<Duration>) -> Result<(), ServiceError> {
+    let client = client_in(&DEFAULT_REGION);
+    for _ in 0..UPDATE_ATTEMPTS {
+        let current = match read(&client, settings, key, timeout) {
+            Ok(current) => current,
+            Err(error) => {
+                context.log(&format!("rate limit of {} could not be read: {}", key, error));
+                return Ok(());
+            },
+        };
+        let taken = Bucket::take(current, settings, now)
+            .map_err(|retry_after_seconds| ServiceError::RateLimited { retry_after_seconds })?;
+        match write(&client, settings, key, current, taken, timeout) {
+            Ok(()) => return Ok(()),
+            // another request took from the bucket in between, so take from what it left
+            Err(UpdateItemError::ConditionalCheckFailed(_)) => {},
+            Err(error) => {
+                context.log(&format!("rate limit of {} could not be updated: {:?}", key, error));
+                return Ok(());
+            },
+        }
+    }
+    Err(ServiceError::RateLimited { retry_after_seconds: 1 })
+}
+
+fn key_of(key: &str) -> HashMap<String, AttributeValue> {
+    let mut item_key = HashMap::new();
+    item_key.insert(String::from("limiter_key"), string_value(key));
+    item_key
+}
+
+fn read(client: &DynamoDbClient, settings: &RateLimitSettings, key: &str, timeout: Option<Duration>) -> Result<Option<Bucket>, String> {
+    let input = GetItemInput {
+        table_name: settings.table_name.clone(),
+        key: key_of(key),
+        consistent_read: Some(true),
+        ..Default::default()
+    };
+    let mut request = client.get_item(input);
+    if let Some(timeout) = timeout {
+        request.set_timeout(timeout);
+    }
+    let item = match request.sync().map_err(|error| format!("{:?}", error))?.item {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+    let number = |name: &str| item.get(name).and_then(|value| value.n.as_ref()).and_then(|value| value.parse().ok());
+    match (number("tokens"), number("updated_at")) {
+        (Some(tokens), Some(updated_at)) => Ok(Some(Bucket { tokens, updated_at: updated_at as u64, version: number("version").unwrap_or(0.0) as u64 })),
+        _ => Err(String::from("tokens or updated_at missing from the bucket")),
+    }
+}
+
+/// Stores `taken` unless the bucket changed since it was read as `current`, told by its version
+/// rather than `updated_at`, which writes within the same millisecond leave as it was.
+fn write(client: &DynamoDbClient, settings: &RateLimitSettings, key: &str, current: Option<Bucket>, taken: Bucket, timeout: Option<Duration>) -> Result<(), UpdateItemError> {
+    let mut values = HashMap::new();
+    values.insert(String::from(":tokens"), AttributeValue { n: Some(taken.tokens.to_string()), ..Default::default() });
+    values.insert(String::from(":now"), number_value(taken.updated_at));
+    // an untouched bucket is full again by then, so it may as well go
+    let refill_seconds = (settings.burst / settings.per_second).ceil() as u64;
+    values.insert(String::from(":expires_at"), number_value(taken.updated_at / 1000 + refill_seconds + 60));
+    values.insert(String::from(":one"), number_value(1));
+    let condition = match current {
+        // buckets written before versions were kept get theirs with this write
+        Some(Bucket { updated_at, version: 0, .. }) => {
+            values.insert(String::from(":read_at"), number_value(updated_at));
+            "attribute_not_exists(#version) AND updated_at = :read_at"
+        },
+        Some(current) => {
+            values.insert(String::from(":read_version"), number_value(current.version));
+            "#version = :read_version"
+        },
+        None => "attribute_not_exists(limiter_key)",
+    };
+    let mut names = HashMap::new();
+    names.insert(String::from("#version"), String::from("version"));
+    let update = UpdateItemInput {
+        table_name: settings.table_name.clone(),
+        key: key_of(key),
+        update_expression: Some(String::from("SET tokens = :tokens, updated_at = :now, expires_at = :expires_at ADD #version :one")),
+        condition_expression: Some(String::from(condition)),
+        expression_attribute_names: Some(names),
+        expression_attribute_values: Some(values),
+        ..Default::default()
+    };
+    let mut request = client.update_item(update);
+    if let Some(timeout) = timeout {
+        request.set_timeout(timeout);
+    }
+    request.sync().map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> RateLimitSettings {
+        RateLimitSettings { table_name: String::from("rate-limits"), burst: 2.0, per_second: 0.5 }
+    }
+
+    #[test]
+    fn empties_the_bucket_and_refills_it_over_time() {
+        let first = Bucket::take(None, &settings(), 10_000).ok().unwrap();
+        assert_eq!(Bucket { tokens: 1.0, updated_at: 10_000, version: 1 }, first);
+        let second = Bucket::take(Some(first), &settings(), 10_000).ok().unwrap();
+        assert_eq!(Err(2), Bucket::take(Some(second), &settings(), 10_000));
+        assert_eq!(Err(1), Bucket::take(Some(second), &settings(), 11_000));
+        assert_eq!(true, Bucket::take(Some(second), &settings(), 12_000).is_ok());
+        assert_eq!(Ok(Bucket { tokens: 1.0, updated_at: 60_000, version: 3 }), Bucket::take(Some(second), &settings(), 60_000));
+    }
+
+    #[test]
+    fn keeps_the_bucket_time_when_the_clock_is_behind() {
+        let bucket = Bucket { tokens: 1.5, updated_at: 20_000, version: 4 };
+        assert_eq!(Ok(Bucket { tokens: 0.5, updated_at: 20_000, version: 5 }), Bucket::take(Some(bucket), &settings(), 19_000));
+    }
+
+    #[test]
+    fn keys_callers_before_addresses() {
+        assert_eq!(Some(String::from("caller#u-1")), limiter_key(Some("u-1"), Some("203.0.113.7")));
+        assert_eq!(Some(String::from("ip#203.0.113.7")), limiter_key(None, Some("203.0.113.7")));
+        assert_eq!(None, limiter_key(None, None));
+    }
+}
@@ -0,0 +1,59 @@
+//! Replays the events in `fixtures/events` through the handler and compares each outcome with its
+//! snapshot in `fixtures/snapshots`, so that changes to event parsing or to the response schema
+//! show up in review. The replayed requests reach every table as a `MemoryStore` holding `REGISTERED`.
+//!
+//! After an intended change, `UPDATE_SNAPSHOTS=1 cargo test replay` rewrites the snapshots.
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::context::RequestContext;
+use crate::handle_payload;
+use crate::store::{DynamoDbSettings, MemoryStore, SerialStore};
+
+/// Serials registered in the store the replayed events are validated against.
+const REGISTERED: [&str; 2] = ["AB123456", "AB654321"];
+
+/// The store standing in for DynamoDB in the replayed requests.
+fn replay_store(_settings: DynamoDbSettings, _config: &Config) -> Box<dyn SerialStore> {
+    Box::new(MemoryStore::new(REGISTERED.iter().map(|serial_number| serial_number.to_string()).collect()))
+}
+
+/// The response to `payload`, or the error contract it failed with.
+fn replay(payload: Value) -> Value {
+    let context = RequestContext::new(String::from("replay"), Instant::now() + Duration::from_secs(30)).with_tables(replay_store);
+    match handle_payload(payload, &context) {
+        Ok(response) => json!({ "response": serde_json::to_value(&response).unwrap() }),
+        Err(error) => json!({ "error": serde_json::from_str::<Value>(&error.to_json()).unwrap() }),
+    }
+}
+
+#[test]
+fn replays_fixtures_against_their_snapshots() {
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+    let mut events: Vec<_> = fs::read_dir(fixtures.join("events")).unwrap().map(|entry| entry.unwrap().path()).collect();
+    events.sort();
+    assert_eq!(false, events.is_empty());
+
+    let mut mismatched = Vec::new();
+    for event in events {
+        let name = event.file_name().unwrap().to_owned();
+        let outcome = replay(serde_json::from_slice(&fs::read(&event).unwrap()).unwrap());
+        let snapshot = fixtures.join("snapshots").join(&name);
+        if update {
+            fs::write(&snapshot, serde_json::to_string_pretty(&outcome).unwrap() + "\n").unwrap();
+            continue;
+        }
+        let expected: Option<Value> = fs::read(&snapshot).ok().map(|snapshot| serde_json::from_slice(&snapshot).unwrap());
+        if expected.as_ref() != Some(&outcome) {
+            eprintln!("{} answered\n{}", name.to_string_lossy(), serde_json::to_string_pretty(&outcome).unwrap());
+            mismatched.push(name.to_string_lossy().into_owned());
+        }
+    }
+    assert_eq!(Vec::<String>::new(), mismatched, "outcomes differ from their snapshots, rerun with UPDATE_SNAPSHOTS=1 if intended");
+}
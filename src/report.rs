@@ -0,0 +1,75 @@
+//! The admin `report` action: counts the serials registered in a period per UTC date, for the
+//! weekly compliance report.
+
+use std::time::Duration;
+
+use serde_derive::Serialize;
+use schemars::JsonSchema;
+
+use crate::error::ServiceError;
+use crate::store::{DynamoDbStore, SECONDS_PER_DAY};
+
+/// Longest period a report may cover, bounding the queries it runs.
+pub const MAX_REPORT_DAYS: u64 = 366;
+
+#[derive(Serialize, JsonSchema)]
+pub struct RegistrationReport {
+    pub from: u64,
+    pub to: u64,
+    pub total: u64,
+    /// Every UTC date of the period, in order, including those without registrations.
+    pub days: Vec<DayCount>
+}
+
+#[derive(Serialize, Debug, PartialEq, JsonSchema)]
+pub struct DayCount {
+    pub date: String,
+    pub count: u64
+}
+
+pub fn run(store: &DynamoDbStore, from: Option<u64>, to: Option<u64>, timeout: Duration) -> Result<RegistrationReport, ServiceError> {
+    let (from, to) = period(from, to)?;
+    let counts = store.count_registered(from, to, Some(timeout)).map_err(ServiceError::from)?;
+    Ok(summarize(from, to, counts))
+}
+
+/// The requested period, both ends inclusive.
+fn period(from: Option<u64>, to: Option<u64>) -> Result<(u64, u64), ServiceError> {
+    let (from, to) = match (from, to) {
+        (Some(from), Some(to)) if from <= to => (from, to),
+        _ => return Err(ServiceError::InvalidRequest(String::from("report needs registeredFrom no later than registeredTo"))),
+    };
+    if to / SECONDS_PER_DAY - from / SECONDS_PER_DAY >= MAX_REPORT_DAYS {
+        return Err(ServiceError::InvalidRequest(format!("a report covers at most {} days", MAX_REPORT_DAYS)));
+    }
+    Ok((from, to))
+}
+
+fn summarize(from: u64, to: u64, counts: Vec<(String, u64)>) -> RegistrationReport {
+    RegistrationReport {
+        from,
+        to,
+        total: counts.iter().map(|&(_, count)| count).sum(),
+        days: counts.into_iter().map(|(date, count)| DayCount { date, count }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_the_period() {
+        assert_eq!(true, period(Some(10), Some(5)).is_err());
+        assert_eq!(true, period(None, Some(5)).is_err());
+        assert_eq!(true, period(Some(0), Some(MAX_REPORT_DAYS * SECONDS_PER_DAY)).is_err());
+        assert_eq!((0, MAX_REPORT_DAYS * SECONDS_PER_DAY - 1), period(Some(0), Some(MAX_REPORT_DAYS * SECONDS_PER_DAY - 1)).ok().unwrap());
+    }
+
+    #[test]
+    fn totals_the_daily_counts() {
+        let report = summarize(0, 2 * SECONDS_PER_DAY - 1, vec![(String::from("1970-01-01"), 3), (String::from("1970-01-02"), 0)]);
+        assert_eq!(3, report.total);
+        assert_eq!(DayCount { date: String::from("1970-01-02"), count: 0 }, report.days[1]);
+    }
+}
@@ -0,0 +1,315 @@
+//! Lookup results kept by the container, keyed like the bloom filter: by the normalized serial
+//! when the table is queried through its index. The stream consumer appends the keys of every
+//! changed item to an invalidation log in S3, one per table, which containers poll to drop the
+//! results those changes made stale.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rusoto_core::Region;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::aws::{self, AwsError};
+use crate::context::log_shared;
+
+#[derive(Clone, Debug)]
+pub struct ResultCacheSettings {
+    pub bucket: String,
+    /// Prepended to the table name to form the invalidation log's object key.
+    pub prefix: String,
+    /// Longest a result is used, even when no invalidation reached the container.
+    pub ttl: Duration,
+    /// How often the container reads the invalidation log again.
+    pub poll_interval: Duration,
+    /// Results kept per table before expired ones are dropped, and all of them if none expired.
+    pub max_entries: usize,
+    /// Keys the stream consumer keeps in the log. Containers that polled before the oldest of
+    /// them was appended drop every result.
+    pub log_length: usize
+}
+
+impl Default for ResultCacheSettings {
+    fn default() -> ResultCacheSettings {
+        ResultCacheSettings {
+            bucket: String::new(),
+            prefix: String::from("invalidations/"),
+            ttl: Duration::from_secs(300),
+            poll_interval: Duration::from_secs(5),
+            max_entries: 100_000,
+            log_length: 10_000
+        }
+    }
+}
+
+impl ResultCacheSettings {
+    pub fn object_key(&self, table_name: &str) -> String {
+        format!("{}{}.invalidations.json", self.prefix, table_name)
+    }
+}
+
+/// Keys of changed items, each with the sequence number it was appended under.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct InvalidationLog {
+    /// Sequence number of the last key appended, 0 for an empty log.
+    pub sequence: u64,
+    pub keys: VecDeque<(u64, String)>
+}
+
+impl InvalidationLog {
+    /// Appends `keys`, keeping the last `log_length` of the log.
+    pub fn append(&mut self, keys: &[String], log_length: usize) {
+        for key in keys {
+            self.sequence += 1;
+            self.keys.push_back((self.sequence, key.clone()));
+        }
+        while self.keys.len() > log_length {
+            self.keys.pop_front();
+        }
+    }
+
+    /// Keys appended after `sequence`, `None` when some of them were dropped from the log already
+    /// or the log was started over.
+    fn since(&self, sequence: u64) -> Option<Vec<&str>> {
+        let oldest = self.keys.front().map_or(self.sequence + 1, |(oldest, _)| *oldest);
+        if self.sequence < sequence || (sequence < self.sequence && oldest > sequence + 1) {
+            return None;
+        }
+        Some(self.keys.iter().filter(|(appended, _)| *appended > sequence).map(|(_, key)| key.as_str()).collect())
+    }
+}
+
+/// A log read from S3, with the ETag its replacement has to match.
+pub struct LogObject {
+    pub log: InvalidationLog,
+    pub etag: Option<String>
+}
+
+/// Reads the invalidation log of `table_name`, `None` when nothing was appended yet.
+pub fn load(settings: &ResultCacheSettings, table_name: &str, timeout: Option<Duration>) -> Result<Option<LogObject>, AwsError> {
+    let object = match aws::get_object(&Region::default(), &settings.bucket, &settings.object_key(table_name), timeout)? {
+        Some(object) => object,
+        None => return Ok(None),
+    };
+    let log = serde_json::from_slice(&object.body).map_err(|error| {
+        AwsError::MalformedResponse(format!("s3://{}/{} is not an invalidation log: {}", settings.bucket, settings.object_key(table_name), error))
+    })?;
+    Ok(Some(LogObject { log, etag: object.etag }))
+}
+
+/// Writes the log unless another writer replaced the one read as `etag` in the meantime.
+/// Returns `false` when it lost that race.
+pub fn save(settings: &ResultCacheSettings, table_name: &str, log: &InvalidationLog, etag: Option<&str>, timeout: Option<Duration>) -> Result<bool, AwsError> {
+    let body = serde_json::to_vec(log).map_err(|error| AwsError::MalformedResponse(error.to_string()))?;
+    aws::put_object(&Region::default(), &settings.bucket, &settings.object_key(table_name), body, etag, timeout)
+}
+
+#[derive(Default)]
+struct Entries {
+    found: HashMap<String, (bool, Instant)>,
+    /// Sequence number of the log up to which invalidations were applied, `None` before the
+    /// first successful poll.
+    sequence: Option<u64>,
+    polled_at: Option<Instant>,
+    /// Changes with every invalidation, so that a lookup started before one is not cached.
+    generation: u64
+}
+
+impl Entries {
+    fn clear(&mut self) {
+        self.found.clear();
+        self.generation += 1;
+    }
+}
+
+/// The results of one table.
+pub struct TableResults {
+    entries: Mutex<Entries>,
+    ttl: Duration,
+    max_entries: usize
+}
+
+impl TableResults {
+    fn new(settings: &ResultCacheSettings) -> TableResults {
+        TableResults { entries: Mutex::new(Entries::default()), ttl: settings.ttl, max_entries: settings.max_entries.max(1) }
+    }
+
+    /// Whether the serial under `key` was found, `None` unless a result younger than the TTL is kept.
+    pub fn get(&self, key: &str, now: Instant) -> Option<bool> {
+        let entries = self.entries.lock().unwrap();
+        entries.found.get(key)
+            .filter(|(_, cached_at)| now.duration_since(*cached_at) < self.ttl)
+            .map(|(found, _)| *found)
+    }
+
+    /// Tag of the current state of the results, to be handed to `put` with a lookup's answer.
+    pub fn generation(&self) -> u64 {
+        self.entries.lock().unwrap().generation
+    }
+
+    /// Keeps the answer of a lookup started at `generation`. Answers that an invalidation may
+    /// have overtaken, and those given before the first successful poll, are not kept.
+    pub fn put(&self, key: &str, found: bool, generation: u64, now: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.generation != generation || entries.sequence.is_none() {
+            return;
+        }
+        if entries.found.len() >= self.max_entries {
+            let ttl = self.ttl;
+            entries.found.retain(|_, (_, cached_at)| now.duration_since(*cached_at) < ttl);
+            if entries.found.len() >= self.max_entries {
+                entries.clear();
+                return;
+            }
+        }
+        entries.found.insert(key.to_string(), (found, now));
+    }
+
+    /// Drops the result of a serial this container just wrote.
+    pub fn forget(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.found.remove(key);
+        entries.generation += 1;
+    }
+
+    /// Applies the keys appended to the log since the last poll, reading it at most every
+    /// `poll_interval`. When the log cannot be read, or keys were dropped from it before this
+    /// container saw them, every result is dropped instead. The log is read without holding the
+    /// results, lookups keep being answered meanwhile and those it overtakes are skipped by their
+    /// generation.
+    pub fn sync<F>(&self, poll_interval: Duration, now: Instant, load: F)
+        where F: FnOnce() -> Result<Option<InvalidationLog>, AwsError>
+    {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            if entries.polled_at.is_some_and(|polled_at| now.duration_since(polled_at) < poll_interval) {
+                return;
+            }
+            entries.polled_at = Some(now);
+        }
+        let loaded = load();
+        let mut entries = self.entries.lock().unwrap();
+        let log = match loaded {
+            Ok(log) => log.unwrap_or_default(),
+            Err(error) => {
+                log_shared(&format!("invalidation log could not be read, dropping cached results: {}", error));
+                entries.clear();
+                entries.sequence = None;
+                return;
+            },
+        };
+        match entries.sequence.and_then(|sequence| log.since(sequence)) {
+            Some(keys) => {
+                if !keys.is_empty() {
+                    keys.iter().for_each(|key| { entries.found.remove(*key); });
+                    entries.generation += 1;
+                }
+            },
+            None => entries.clear(),
+        }
+        entries.sequence = Some(log.sequence);
+    }
+}
+
+/// Results loaded by this container, one set per table, shared by every invocation it serves.
+pub struct ResultCache {
+    tables: Mutex<HashMap<String, Arc<TableResults>>>
+}
+
+impl ResultCache {
+    pub fn new() -> ResultCache {
+        ResultCache { tables: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn table(&self, table_name: &str, settings: &ResultCacheSettings) -> Arc<TableResults> {
+        self.tables.lock().unwrap()
+            .entry(table_name.to_string())
+            .or_insert_with(|| Arc::new(TableResults::new(settings)))
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(keys: &[&str]) -> InvalidationLog {
+        let mut log = InvalidationLog::default();
+        log.append(&keys.iter().map(|key| key.to_string()).collect::<Vec<_>>(), 3);
+        log
+    }
+
+    #[test]
+    fn keeps_the_tail_of_the_log() {
+        let log = log(&["A", "B", "C", "D"]);
+        assert_eq!(4, log.sequence);
+        assert_eq!(Some(vec!["C", "D"]), log.since(2));
+        assert_eq!(Some(Vec::<&str>::new()), log.since(4));
+        assert_eq!(None, log.since(0));
+    }
+
+    #[test]
+    fn drops_results_named_in_the_log() {
+        let results = TableResults::new(&ResultCacheSettings::default());
+        let start = Instant::now();
+        let poll_interval = Duration::from_secs(5);
+
+        // nothing is kept before the log was read
+        results.put("AB1234", false, results.generation(), start);
+        assert_eq!(None, results.get("AB1234", start));
+
+        results.sync(poll_interval, start, || Ok(None));
+        results.put("AB1234", false, results.generation(), start);
+        results.put("CD5678", true, results.generation(), start);
+        assert_eq!(Some(false), results.get("AB1234", start));
+
+        let later = start + Duration::from_secs(10);
+        results.sync(poll_interval, later, || Ok(Some(log(&["AB1234"]))));
+        assert_eq!(None, results.get("AB1234", later));
+        assert_eq!(Some(true), results.get("CD5678", later));
+        // expired
+        assert_eq!(None, results.get("CD5678", start + Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn skips_lookups_overtaken_by_an_invalidation() {
+        let results = TableResults::new(&ResultCacheSettings::default());
+        let start = Instant::now();
+        results.sync(Duration::from_secs(5), start, || Ok(None));
+        let generation = results.generation();
+        results.sync(Duration::from_secs(5), start + Duration::from_secs(10), || Ok(Some(log(&["AB1234"]))));
+        results.put("AB1234", false, generation, start);
+        assert_eq!(None, results.get("AB1234", start));
+    }
+
+    #[test]
+    fn answers_lookups_while_reading_the_log() {
+        let results = TableResults::new(&ResultCacheSettings::default());
+        let start = Instant::now();
+        results.sync(Duration::from_secs(5), start, || Ok(None));
+        results.put("AB1234", false, results.generation(), start);
+        results.sync(Duration::from_secs(5), start + Duration::from_secs(10), || {
+            assert_eq!(Some(false), results.get("AB1234", start));
+            // a second poll within the interval leaves the read to the first one
+            results.sync(Duration::from_secs(5), start + Duration::from_secs(11), || panic!("read the log twice"));
+            results.put("CD5678", false, results.generation(), start);
+            Ok(Some(log(&["CD5678"])))
+        });
+        assert_eq!((Some(false), None), (results.get("AB1234", start), results.get("CD5678", start)));
+    }
+
+    #[test]
+    fn drops_everything_when_the_log_cannot_be_followed() {
+        let results = TableResults::new(&ResultCacheSettings::default());
+        let start = Instant::now();
+        results.sync(Duration::from_secs(5), start, || Ok(None));
+        results.put("AB1234", false, results.generation(), start);
+        // keys 1 to 3 of the log are gone
+        results.sync(Duration::from_secs(5), start + Duration::from_secs(10), || Ok(Some(log(&["A", "B", "C", "D", "E", "F"]))));
+        assert_eq!(None, results.get("AB1234", start));
+
+        results.put("AB1234", false, results.generation(), start);
+        results.sync(Duration::from_secs(5), start + Duration::from_secs(20), || Err(AwsError::MalformedResponse(String::new())));
+        assert_eq!(None, results.get("AB1234", start));
+    }
+}
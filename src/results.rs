@@ -0,0 +1,168 @@
+//! Results of work answered before it was done, kept in a DynamoDB table for the `getResult`
+//! action to poll: validations left `pending` by their budget, keyed by their pending token, and
+//! bulk jobs, keyed by `bulk:s3://<bucket>/<key>` of their manifest. Items expire through TTL on
+//! `expires_at`.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rusoto_dynamodb::{AttributeValue, DynamoDb, GetItemInput, PutItemInput};
+use serde_derive::Serialize;
+use schemars::JsonSchema;
+use serde_json::Value;
+
+use crate::store::{client_in, string_value, number_value, DEFAULT_REGION};
+
+/// Prefix of the tokens of bulk jobs, which are the manifest's location.
+pub const BULK_TOKEN_PREFIX: &str = "bulk:";
+
+#[derive(Clone, Debug)]
+pub struct ResultsSettings {
+    pub table_name: String,
+    /// How long a result can be polled after it was last written.
+    pub ttl_seconds: u64
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ResultStatus {
+    Pending,
+    Complete,
+    Failed
+}
+
+impl ResultStatus {
+    fn value(self) -> &'static str {
+        match self {
+            ResultStatus::Pending => "pending",
+            ResultStatus::Complete => "complete",
+            ResultStatus::Failed => "failed",
+        }
+    }
+
+    fn parse(value: &str) -> Option<ResultStatus> {
+        match value {
+            "pending" => Some(ResultStatus::Pending),
+            "complete" => Some(ResultStatus::Complete),
+            "failed" => Some(ResultStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// What `getResult` answers: the result once complete, the error contract once failed.
+#[derive(Serialize, Debug, PartialEq, JsonSchema)]
+pub struct StoredResult {
+    #[serde(rename = "resultToken")]
+    pub token: String,
+    pub status: ResultStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>
+}
+
+impl StoredResult {
+    pub fn pending(token: &str) -> StoredResult {
+        StoredResult { token: token.to_string(), status: ResultStatus::Pending, result: None, error: None }
+    }
+
+    pub fn complete(token: &str, result: Value) -> StoredResult {
+        StoredResult { token: token.to_string(), status: ResultStatus::Complete, result: Some(result), error: None }
+    }
+
+    /// `error` being the error contract as JSON, see `ServiceError::to_json`.
+    pub fn failed(token: &str, error: &str) -> StoredResult {
+        StoredResult { token: token.to_string(), status: ResultStatus::Failed, result: None, error: serde_json::from_str(error).ok() }
+    }
+
+    fn to_item(&self, settings: &ResultsSettings, now: u64) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+        item.insert(String::from("result_token"), string_value(&self.token));
+        item.insert(String::from("status"), string_value(self.status.value()));
+        if let Some(ref result) = self.result {
+            item.insert(String::from("result"), string_value(&result.to_string()));
+        }
+        if let Some(ref error) = self.error {
+            item.insert(String::from("error"), string_value(&error.to_string()));
+        }
+        item.insert(String::from("updated_at"), number_value(now));
+        item.insert(String::from("expires_at"), number_value(now + settings.ttl_seconds));
+        item
+    }
+
+    fn from_item(token: &str, item: &HashMap<String, AttributeValue>) -> Option<StoredResult> {
+        let string = |name: &str| item.get(name).and_then(|value| value.s.as_deref());
+        let json = |name: &str| string(name).and_then(|value| serde_json::from_str(value).ok());
+        Some(StoredResult {
+            token: token.to_string(),
+            status: ResultStatus::parse(string("status")?)?,
+            result: json("result"),
+            error: json("error")
+        })
+    }
+}
+
+/// Token of the bulk job validating the manifest `s3://<bucket>/<key>`.
+pub fn bulk_token(bucket: &str, key: &str) -> String {
+    format!("{}s3://{}/{}", BULK_TOKEN_PREFIX, bucket, key)
+}
+
+/// Writes `result`, replacing what was stored under its token.
+pub fn store(settings: &ResultsSettings, result: &StoredResult, now: u64, timeout: Option<Duration>) -> Result<(), String> {
+    let input = PutItemInput {
+        table_name: settings.table_name.clone(),
+        item: result.to_item(settings, now),
+        ..Default::default()
+    };
+    let mut request = client_in(&DEFAULT_REGION).put_item(input);
+    if let Some(timeout) = timeout {
+        request.set_timeout(timeout);
+    }
+    request.sync().map(|_| ()).map_err(|error| format!("{:?}", error))
+}
+
+/// The result stored under `token`, `None` when there is none or it expired.
+pub fn load(settings: &ResultsSettings, token: &str, timeout: Option<Duration>) -> Result<Option<StoredResult>, String> {
+    let mut key = HashMap::new();
+    key.insert(String::from("result_token"), string_value(token));
+    let input = GetItemInput {
+        table_name: settings.table_name.clone(),
+        key,
+        consistent_read: Some(true),
+        ..Default::default()
+    };
+    let mut request = client_in(&DEFAULT_REGION).get_item(input);
+    if let Some(timeout) = timeout {
+        request.set_timeout(timeout);
+    }
+    let item = request.sync().map_err(|error| format!("{:?}", error))?.item;
+    match item {
+        Some(item) => StoredResult::from_item(token, &item).map(Some).ok_or_else(|| format!("malformed result stored under {}", token)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let settings = ResultsSettings { table_name: String::from("results"), ttl_seconds: 60 };
+        let complete = StoredResult::complete("token1", json!({"isValid": true, "errors": []}));
+        let item = complete.to_item(&settings, 1_000);
+        assert_eq!(Some("1060"), item.get("expires_at").and_then(|value| value.n.as_deref()));
+        assert_eq!(Some(complete), StoredResult::from_item("token1", &item));
+
+        let failed = StoredResult::failed("token2", r#"{"errorType": "InvalidRequest"}"#);
+        assert_eq!(Some(failed), StoredResult::from_item("token2", &StoredResult::failed("token2", r#"{"errorType": "InvalidRequest"}"#).to_item(&settings, 0)));
+        assert_eq!(r#"{"resultToken":"token3","status":"pending"}"#, serde_json::to_string(&StoredResult::pending("token3")).unwrap());
+    }
+
+    #[test]
+    fn names_bulk_jobs_after_their_manifest() {
+        assert_eq!("bulk:s3://uploads/manifests/batch 1.csv", bulk_token("uploads", "manifests/batch 1.csv"));
+    }
+}
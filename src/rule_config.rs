@@ -0,0 +1,292 @@
+//! Rule sets kept in AWS AppConfig or SSM Parameter Store, so that length bounds, blocklists and
+//! the other rule settings can change without a deployment. The configuration is a JSON document
+//! whose fields replace the environment's:
+//!
+//! ```json
+//! {"rules": ["length", "blocklist"], "minLength": 8, "maxLength": 20, "blocklist": ["AB1234"]}
+//! ```
+//!
+//! Each container reads it when first needed and polls it again every interval, keeping the rule
+//! set it has while a poll fails or brings an invalid document.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rusoto_core::Region;
+use rusoto_core::signature::SignedRequest;
+use serde_derive::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::aws::{self, AwsError};
+use crate::context::log_shared;
+use crate::rules::{self, Charset, RuleSettings, ValidatorRegistry};
+
+/// Where the rule set is kept.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuleConfigSource {
+    /// Application, environment and configuration profile, by name or id.
+    AppConfig { application: String, environment: String, profile: String },
+    /// Name of an SSM parameter.
+    Parameter(String)
+}
+
+impl RuleConfigSource {
+    /// `<application>/<environment>/<profile>`, as in `RULES_APPCONFIG`.
+    pub fn app_config(value: &str) -> Result<RuleConfigSource, String> {
+        match value.trim().split('/').collect::<Vec<_>>()[..] {
+            [application, environment, profile] if !application.is_empty() && !environment.is_empty() && !profile.is_empty() => Ok(RuleConfigSource::AppConfig {
+                application: application.to_string(),
+                environment: environment.to_string(),
+                profile: profile.to_string()
+            }),
+            _ => Err(format!("expected <application>/<environment>/<profile>, got `{}`", value)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RuleConfigSettings {
+    pub source: RuleConfigSource,
+    /// How long a container uses the rule set before polling it again.
+    pub poll_interval: Duration
+}
+
+/// Rule settings of the configuration document, each replacing the environment's when given.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RuleDocument {
+    #[serde(default)]
+    rules: Option<Vec<String>>,
+    #[serde(rename = "minLength", default)]
+    min_length: Option<usize>,
+    #[serde(rename = "maxLength", default)]
+    max_length: Option<usize>,
+    #[serde(default)]
+    blocklist: Option<Vec<String>>,
+    #[serde(default)]
+    pattern: Option<String>,
+    #[serde(rename = "deprecatedPrefixes", default)]
+    deprecated_prefixes: Option<Vec<String>>,
+    #[serde(default)]
+    charset: Option<String>,
+    #[serde(default)]
+    templates: Option<Vec<String>>,
+    /// Severities by rule name, `error` or `warning`.
+    #[serde(default)]
+    severities: Option<HashMap<String, String>>
+}
+
+impl RuleDocument {
+    pub fn parse(json: &str) -> Result<RuleDocument, String> {
+        serde_json::from_str(json).map_err(|error| error.to_string())
+    }
+
+    /// The rules of `names` and `settings`, as read from the environment, with the document's
+    /// settings in place of theirs.
+    pub fn registry(&self, names: Vec<String>, mut settings: RuleSettings) -> Result<ValidatorRegistry, String> {
+        let names = self.rules.clone().unwrap_or(names);
+        settings.min_length = self.min_length.or(settings.min_length);
+        settings.max_length = self.max_length.or(settings.max_length);
+        settings.blocklist = self.blocklist.clone().unwrap_or(settings.blocklist);
+        settings.pattern = self.pattern.clone().or(settings.pattern);
+        settings.deprecated_prefixes = self.deprecated_prefixes.clone().unwrap_or(settings.deprecated_prefixes);
+        settings.templates = self.templates.clone().unwrap_or(settings.templates);
+        if let Some(ref charset) = self.charset {
+            settings.charset = Charset::parse(charset)?;
+        }
+        if let Some(ref severities) = self.severities {
+            let entries: Vec<String> = severities.iter().map(|(rule, severity)| format!("{}={}", rule, severity)).collect();
+            settings.severities = rules::parse_severities(&entries)?;
+        }
+        ValidatorRegistry::from_names(&names, &settings)
+    }
+}
+
+/// The rule set in use and the version of the configuration it came from: the AppConfig version
+/// label, the SSM parameter version, or a digest of the document when AppConfig has no label.
+#[derive(Debug)]
+pub struct ActiveRules {
+    pub document: RuleDocument,
+    pub version: String
+}
+
+/// What a poll brought: a new rule set, or `None` when the configuration has not changed.
+pub struct Poll {
+    pub rules: Option<ActiveRules>,
+    /// AppConfig token for the next poll.
+    pub next_token: Option<String>
+}
+
+/// Reads the configuration, passing AppConfig the token of the previous poll so that it only
+/// sends the document again when it changed.
+pub fn poll(settings: &RuleConfigSettings, token: Option<&str>, timeout: Option<Duration>) -> Result<Poll, AwsError> {
+    let region = Region::default();
+    match settings.source {
+        RuleConfigSource::AppConfig { ref application, ref environment, ref profile } => {
+            let request = |method: &str, path: &str| {
+                let mut request = SignedRequest::new(method, "appconfig", &region, path);
+                request.set_hostname(Some(format!("appconfigdata.{}.amazonaws.com", region.name())));
+                request
+            };
+            let token = match token {
+                Some(token) => token.to_string(),
+                None => {
+                    let session = json!({
+                        "ApplicationIdentifier": application,
+                        "EnvironmentIdentifier": environment,
+                        "ConfigurationProfileIdentifier": profile,
+                        "RequiredMinimumPollIntervalInSeconds": settings.poll_interval.as_secs().max(15)
+                    });
+                    let response = aws::call_rest(request("POST", "/configurationsessions"), Some(&session), timeout)?;
+                    let body: serde_json::Value = serde_json::from_slice(&response.body).unwrap_or(serde_json::Value::Null);
+                    body["InitialConfigurationToken"].as_str().map(String::from)
+                        .ok_or_else(|| AwsError::MalformedResponse(String::from("no InitialConfigurationToken in the session")))?
+                },
+            };
+            let mut latest = request("GET", "/configuration");
+            latest.add_param("configuration_token", &token);
+            let response = aws::call_rest(latest, None, timeout)?;
+            let next_token = response.headers.get("next-poll-configuration-token").map(String::from);
+            // an empty body means the configuration is the one of the previous poll
+            if response.body.is_empty() {
+                return Ok(Poll { rules: None, next_token });
+            }
+            let json = String::from_utf8_lossy(&response.body);
+            let version = response.headers.get("version-label").map(String::from)
+                .unwrap_or_else(|| Sha256::digest(json.as_bytes()).iter().take(6).map(|byte| format!("{:02x}", byte)).collect());
+            Ok(Poll { rules: Some(active_rules(&json, version)?), next_token })
+        },
+        RuleConfigSource::Parameter(ref name) => {
+            let response = aws::call_json("ssm", "AmazonSSM.GetParameter", "1.1", &region, &json!({"Name": name, "WithDecryption": true}), timeout)?;
+            let json = response["Parameter"]["Value"].as_str()
+                .ok_or_else(|| AwsError::MalformedResponse(format!("parameter {} holds no string", name)))?;
+            let version = response["Parameter"]["Version"].as_u64().map(|version| version.to_string()).unwrap_or_default();
+            Ok(Poll { rules: Some(active_rules(json, version)?), next_token: None })
+        },
+    }
+}
+
+fn active_rules(json: &str, version: String) -> Result<ActiveRules, AwsError> {
+    let document = RuleDocument::parse(json)
+        .map_err(|error| AwsError::MalformedResponse(format!("rule configuration {} is not a rule document: {}", version, error)))?;
+    Ok(ActiveRules { document, version })
+}
+
+/// The rules built from a rule set, with the version of the configuration they came from.
+pub struct ConfiguredRules {
+    pub validators: ValidatorRegistry,
+    pub version: String
+}
+
+struct CachedRules {
+    active: Option<Arc<ConfiguredRules>>,
+    polled_at: Instant,
+    next_token: Option<String>
+}
+
+/// The rule set loaded by this container, shared by every invocation it serves.
+pub struct RuleConfigCache {
+    rules: Mutex<Option<CachedRules>>
+}
+
+impl RuleConfigCache {
+    pub fn new() -> RuleConfigCache {
+        RuleConfigCache { rules: Mutex::new(None) }
+    }
+
+    /// The rules of the rule set, polled again once it is `poll_interval` old and built again only
+    /// when the poll brings another version. Documents `build` rejects are logged and skipped like
+    /// failed polls, leaving the rules built before in place; `None` while none could be built yet.
+    pub fn get<P, B>(&self, poll_interval: Duration, now: Instant, poll: P, build: B) -> Option<Arc<ConfiguredRules>>
+        where P: FnOnce(Option<&str>) -> Result<Poll, AwsError>,
+              B: FnOnce(&RuleDocument) -> Result<ValidatorRegistry, String>
+    {
+        let mut cached = self.rules.lock().unwrap();
+        if let Some(ref cached) = *cached {
+            if now.duration_since(cached.polled_at) < poll_interval {
+                return cached.active.clone();
+            }
+        }
+        let (active, token) = match cached.take() {
+            Some(cached) => (cached.active, cached.next_token),
+            None => (None, None),
+        };
+        let (active, next_token) = match poll(token.as_deref()) {
+            Ok(Poll { rules: Some(ref rules), next_token }) if active.as_ref().is_some_and(|active| active.version == rules.version) => (active, next_token),
+            Ok(Poll { rules: Some(rules), next_token }) => match build(&rules.document) {
+                Ok(validators) => (Some(Arc::new(ConfiguredRules { validators, version: rules.version })), next_token),
+                Err(error) => {
+                    log_shared(&format!("ignoring invalid rule configuration {}: {}", rules.version, error));
+                    (active, next_token)
+                },
+            },
+            Ok(Poll { rules: None, next_token }) => (active, next_token),
+            Err(error) => {
+                // a new session is started with the next poll, the token may have expired
+                log_shared(&format!("rule configuration could not be polled: {}", error));
+                (active, None)
+            },
+        };
+        *cached = Some(CachedRules { active: active.clone(), polled_at: now, next_token });
+        active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll_of(json: &str, version: &str) -> Result<Poll, AwsError> {
+        Ok(Poll { rules: Some(ActiveRules { document: RuleDocument::parse(json).ok().unwrap(), version: String::from(version) }), next_token: Some(String::from("next")) })
+    }
+
+    #[test]
+    fn document_replaces_the_settings_it_has() {
+        let document = RuleDocument::parse(r#"{"minLength": 8, "blocklist": ["ab12345678"], "rules": ["length", "blocklist"]}"#).ok().unwrap();
+        let settings = RuleSettings { blocklist: vec![String::from("zz12345678")], ..Default::default() };
+        let registry = document.registry(vec![String::from("length")], settings).ok().unwrap();
+        assert_eq!(false, registry.accepts("AB1234"));
+        assert_eq!(false, registry.accepts("AB12345678"));
+        assert_eq!(true, registry.accepts("ZZ12345678"));
+        let unknown = RuleDocument::parse(r#"{"rules": ["spelling"]}"#).ok().unwrap();
+        assert_eq!(true, unknown.registry(Vec::new(), RuleSettings::default()).is_err());
+        assert_eq!(true, RuleDocument::parse(r#"{"minLength": "8"}"#).is_err());
+    }
+
+    #[test]
+    fn keeps_the_rules_it_has_until_a_valid_document_arrives() {
+        let cache = RuleConfigCache::new();
+        let interval = Duration::from_secs(45);
+        let start = Instant::now();
+        let build = |document: &RuleDocument| document.registry(Vec::new(), RuleSettings::default());
+        let first = cache.get(interval, start, |token| { assert_eq!(None, token); poll_of(r#"{"rules": ["length"]}"#, "1") }, build);
+        assert_eq!(Some(String::from("1")), first.map(|rules| rules.version.clone()));
+        let cached = cache.get(interval, start + Duration::from_secs(10), |_| panic!("polled before the interval"), build);
+        assert_eq!(Some(String::from("1")), cached.map(|rules| rules.version.clone()));
+        let invalid = cache.get(interval, start + interval, |token| { assert_eq!(Some("next"), token); poll_of(r#"{"rules": ["spelling"]}"#, "2") }, build);
+        assert_eq!(Some(String::from("1")), invalid.map(|rules| rules.version.clone()));
+        let unchanged = cache.get(interval, start + interval * 2, |_| Ok(Poll { rules: None, next_token: None }), build);
+        assert_eq!(Some(String::from("1")), unchanged.map(|rules| rules.version.clone()));
+    }
+
+    #[test]
+    fn builds_the_rules_once_per_version() {
+        let cache = RuleConfigCache::new();
+        let interval = Duration::from_secs(45);
+        let start = Instant::now();
+        let build = |document: &RuleDocument| document.registry(Vec::new(), RuleSettings::default());
+        let first = cache.get(interval, start, |_| poll_of(r#"{"rules": ["length"]}"#, "1"), build).unwrap();
+        let same = cache.get(interval, start + interval, |_| poll_of(r#"{"rules": ["length"]}"#, "1"), |_| panic!("built the same version again")).unwrap();
+        assert_eq!(true, Arc::ptr_eq(&first, &same));
+        let next = cache.get(interval, start + interval * 2, |_| poll_of(r#"{"rules": ["length", "blocklist"]}"#, "2"), build);
+        assert_eq!(Some(String::from("2")), next.map(|rules| rules.version.clone()));
+    }
+
+    #[test]
+    fn reads_app_config_sources() {
+        assert_eq!(Ok(RuleConfigSource::AppConfig { application: String::from("serials"), environment: String::from("prod"), profile: String::from("rules") }), RuleConfigSource::app_config("serials/prod/rules"));
+        assert_eq!(true, RuleConfigSource::app_config("serials/prod").is_err());
+        assert_eq!(true, RuleConfigSource::app_config("serials//rules").is_err());
+    }
+}
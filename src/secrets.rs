@@ -0,0 +1,90 @@
+//! Secrets read from Secrets Manager, such as the salt of the audit log's serial hashes, so that
+//! they need not live in environment variables. Each container reads a secret when first needed
+//! and again once it is older than the refresh interval, picking up rotated versions.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rusoto_core::Region;
+use serde_json::json;
+
+use crate::aws::{self, AwsError};
+use crate::context::log_shared;
+
+/// The current version of a secret.
+#[derive(Debug, PartialEq)]
+pub struct Secret {
+    pub value: String,
+    /// Changes with every rotation, so that what was derived from the secret can name the version
+    /// it was derived from.
+    pub version_id: String
+}
+
+/// Reads the `AWSCURRENT` version of a secret holding a string.
+pub fn fetch(secret_id: &str, timeout: Option<Duration>) -> Result<Secret, AwsError> {
+    let response = aws::call_json("secretsmanager", "secretsmanager.GetSecretValue", "1.1", &Region::default(), &json!({"SecretId": secret_id}), timeout)?;
+    match (response["SecretString"].as_str(), response["VersionId"].as_str()) {
+        (Some(value), Some(version_id)) => Ok(Secret { value: value.to_string(), version_id: version_id.to_string() }),
+        _ => Err(AwsError::MalformedResponse(format!("secret {} holds no string", secret_id))),
+    }
+}
+
+/// The secrets read by this container, shared by every invocation it serves.
+pub struct SecretCache {
+    secrets: Mutex<HashMap<String, (Arc<Secret>, Instant)>>
+}
+
+impl SecretCache {
+    pub fn new() -> SecretCache {
+        SecretCache { secrets: Mutex::new(HashMap::new()) }
+    }
+
+    /// The secret `secret_id`, read again once it is `refresh_interval` old. A failed read keeps
+    /// the version read before until the next attempt, as during a rotation the previous version
+    /// stays valid; `None` while the secret could not be read yet.
+    pub fn get<F>(&self, secret_id: &str, refresh_interval: Duration, now: Instant, fetch: F) -> Option<Arc<Secret>>
+        where F: FnOnce(&str) -> Result<Secret, AwsError>
+    {
+        let mut secrets = self.secrets.lock().unwrap();
+        if let Some((secret, read_at)) = secrets.get(secret_id) {
+            if now.duration_since(*read_at) < refresh_interval {
+                return Some(secret.clone());
+            }
+        }
+        match fetch(secret_id) {
+            Ok(secret) => {
+                let secret = Arc::new(secret);
+                secrets.insert(secret_id.to_string(), (secret.clone(), now));
+                Some(secret)
+            },
+            Err(error) => {
+                log_shared(&format!("secret {} could not be read: {}", secret_id, error));
+                let (secret, read_at) = secrets.get_mut(secret_id)?;
+                *read_at = now;
+                Some(secret.clone())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret(version_id: &str) -> Result<Secret, AwsError> {
+        Ok(Secret { value: String::from("pepper"), version_id: String::from(version_id) })
+    }
+
+    #[test]
+    fn picks_up_rotated_versions_after_the_interval() {
+        let cache = SecretCache::new();
+        let refresh = Duration::from_secs(300);
+        let start = Instant::now();
+        assert_eq!(None, cache.get("salt", refresh, start, |_| Err(AwsError::HttpDispatch(String::from("down")))));
+        assert_eq!("v1", cache.get("salt", refresh, start, |_| secret("v1")).unwrap().version_id);
+        assert_eq!("v1", cache.get("salt", refresh, start + Duration::from_secs(60), |_| panic!("read before the interval")).unwrap().version_id);
+        assert_eq!("v1", cache.get("salt", refresh, start + refresh, |_| Err(AwsError::HttpDispatch(String::from("down")))).unwrap().version_id);
+        assert_eq!("v2", cache.get("salt", refresh, start + refresh * 2, |id| { assert_eq!("salt", id); secret("v2") }).unwrap().version_id);
+    }
+}
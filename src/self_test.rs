@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+use serde_derive::Serialize;
+use schemars::JsonSchema;
+
+use crate::bypass::BypassToken;
+use crate::config::Config;
+use crate::context::RequestContext;
+use crate::rules::{ValidationStrategy, RULE_LENGTH};
+use crate::store::{SerialStore, MemoryStore, FailingStore};
+use crate::{validate_serial, StageTimings, ValidationEvent, ValidationResult};
+
+const SYNTHETIC_SERIAL: &str = "SELFTEST1";
+const SYNTHETIC_SECRET: &str = "self-test-secret";
+
+#[derive(Serialize, JsonSchema)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    pub checks: Vec<SelfTestCheck>
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SelfTestCheck {
+    pub subsystem: &'static str,
+    pub passed: bool,
+    /// What went wrong, for failed checks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>
+}
+
+/// Runs the pipeline against synthetic data and probes `store`, reporting each subsystem separately.
+pub fn run(context: &RequestContext, store: &dyn SerialStore, probe_timeout: Duration) -> SelfTestReport {
+    let checks = vec![
+        check("rules", check_rules(context)),
+        check("serialization", check_serialization()),
+        check("error_mapping", check_error_mapping(context)),
+        check("signing", check_signing()),
+        check("store_probe", store.probe(Some(probe_timeout)).map_err(|error| format!("{:?}", error))),
+    ];
+    SelfTestReport { passed: checks.iter().all(|check| check.passed), checks }
+}
+
+fn check(subsystem: &'static str, outcome: Result<(), String>) -> SelfTestCheck {
+    SelfTestCheck { subsystem, passed: outcome.is_ok(), detail: outcome.err() }
+}
+
+fn expect(condition: bool, detail: &str) -> Result<(), String> {
+    if condition { Ok(()) } else { Err(String::from(detail)) }
+}
+
+fn check_rules(context: &RequestContext) -> Result<(), String> {
+    let store = MemoryStore::new(vec![String::from(SYNTHETIC_SERIAL)]);
+    let config = Config::default();
+    let validate = |serial_number: &str| {
+        validate_serial(serial_number, context, None, &store, &config, config.validation_strategy, None).map_err(|error| error.to_json())
+    };
+
+    let valid = validate("SELFTEST2")?;
+    expect(valid.is_valid && valid.errors.is_empty(), "a well-formed new serial was rejected")?;
+    let malformed = validate("ST@1")?;
+    expect(malformed.errors.contains(&String::from("invalid_format")), "a malformed serial was accepted")?;
+    let duplicate = validate(SYNTHETIC_SERIAL)?;
+    expect(duplicate.errors.contains(&String::from("already_exists")), "a registered serial was accepted")
+}
+
+fn check_serialization() -> Result<(), String> {
+    let event: ValidationEvent = serde_json::from_str(r#"{"serialNumber": "SELFTEST1"}"#).map_err(|error| error.to_string())?;
+    expect(event.serial_number == SYNTHETIC_SERIAL, "serialNumber was not read from the event")?;
+
+    let result = ValidationResult { is_valid: true, errors: Vec::new(), error_details: Vec::new(), warnings: Vec::new(), segment_mismatch: None, conflict: None, similar_serials: Vec::new(), uniqueness: None, pending_token: None, bypass: None, meta: None, correlation_id: None, timings: StageTimings::default() };
+    let json = serde_json::to_value(&result).map_err(|error| error.to_string())?;
+    expect(json.get("isValid") == Some(&serde_json::Value::Bool(true)), "isValid is missing from the response")
+}
+
+fn check_error_mapping(context: &RequestContext) -> Result<(), String> {
+    let error = match validate_serial("SELFTEST2", context, None, &FailingStore, &Config::default(), ValidationStrategy::CollectAll, None) {
+        Ok(_) => return Err(String::from("an unreachable store did not fail the validation")),
+        Err(error) => error,
+    };
+    let contract: serde_json::Value = serde_json::from_str(&error.to_json()).map_err(|error| error.to_string())?;
+    expect(contract["errorType"] == "StoreUnavailable", "unexpected errorType for an unreachable store")?;
+    expect(contract["retryable"] == true && contract["throttle"] == false, "unexpected retry flags for an unreachable store")
+}
+
+fn check_signing() -> Result<(), String> {
+    let token = BypassToken {
+        token_id: String::from("self-test"),
+        serial_number: String::from(SYNTHETIC_SERIAL),
+        tenant_id: None,
+        rules: vec![String::from(RULE_LENGTH)],
+        expires_at: 2,
+        issued_by: String::from("self-test"),
+        reason: String::from("self-test")
+    };
+    let signed = token.sign(SYNTHETIC_SECRET);
+    expect(BypassToken::verify(SYNTHETIC_SECRET, &signed, SYNTHETIC_SERIAL, None, 1) == Ok(token), "a signed token did not verify")?;
+    expect(BypassToken::verify("another-secret", &signed, SYNTHETIC_SERIAL, None, 1).is_err(), "a token verified with the wrong secret")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn passes_with_a_reachable_store() {
+        let report = run(&RequestContext::new(String::from("req-1"), Instant::now()), &MemoryStore::new(Vec::new()), Duration::from_secs(1));
+        assert_eq!(true, report.passed);
+        assert_eq!(5, report.checks.len());
+    }
+
+    #[test]
+    fn reports_an_unreachable_store() {
+        let report = run(&RequestContext::new(String::from("req-1"), Instant::now()), &FailingStore, Duration::from_secs(1));
+        assert_eq!(false, report.passed);
+        let probe = report.checks.iter().find(|check| check.subsystem == "store_probe").unwrap();
+        assert_eq!(false, probe.passed);
+        assert_eq!(true, probe.detail.is_some());
+    }
+}
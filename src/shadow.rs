@@ -0,0 +1,98 @@
+//! Shadow evaluation of a candidate rule configuration. Validations also run the serial through
+//! the candidate's format rules; the response is built from the active rules alone, and serials
+//! the two judge differently are logged and counted, so that a configuration can be tried on live
+//! traffic before it is rolled out.
+
+use serde_json::json;
+
+use serial_validation::core::rules::{Severity, ValidatorRegistry};
+
+use crate::context::RequestContext;
+
+/// Codes of the format rules a serial failed, by severity, sorted so that rule sets applying the
+/// same rules in another order agree.
+#[derive(Debug, Default, PartialEq)]
+pub struct RuleOutcome {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>
+}
+
+impl RuleOutcome {
+    /// What `validators` say of `serial_number`. Bypass tokens are not applied, both rule sets
+    /// being judged as written.
+    pub fn of(validators: &ValidatorRegistry, serial_number: &str) -> RuleOutcome {
+        let mut outcome = RuleOutcome::default();
+        for (validator, severity) in validators.iter() {
+            if let Some(error) = validator.validate(serial_number) {
+                match severity {
+                    Severity::Warning => outcome.warnings.push(error.value()),
+                    Severity::Error => outcome.errors.push(error.value()),
+                }
+            }
+        }
+        outcome.errors.sort();
+        outcome.errors.dedup();
+        outcome.warnings.sort();
+        outcome.warnings.dedup();
+        outcome
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// A serial the candidate rules judged differently from the active ones.
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    pub active: RuleOutcome,
+    pub candidate: RuleOutcome
+}
+
+impl Divergence {
+    /// How the rule sets differ on `serial_number`, `None` when they agree.
+    pub fn between(active: &ValidatorRegistry, candidate: &ValidatorRegistry, serial_number: &str) -> Option<Divergence> {
+        let (active, candidate) = (RuleOutcome::of(active, serial_number), RuleOutcome::of(candidate, serial_number));
+        if active == candidate { None } else { Some(Divergence { active, candidate }) }
+    }
+
+    /// Whether the candidate would flip `isValid`, rather than only report other codes.
+    pub fn changes_validity(&self) -> bool {
+        self.active.is_valid() != self.candidate.is_valid()
+    }
+
+    /// Writes the divergence to the function log as a JSON line carrying the request context.
+    pub fn log(&self, context: &RequestContext, serial_number: &str, active_version: &str, candidate_version: &str) {
+        let mut line = context.fields();
+        line["message"] = json!("shadow rules diverged");
+        line["serialNumber"] = json!(serial_number);
+        line["changesValidity"] = json!(self.changes_validity());
+        line["active"] = json!({"version": active_version, "errors": self.active.errors, "warnings": self.active.warnings});
+        line["candidate"] = json!({"version": candidate_version, "errors": self.candidate.errors, "warnings": self.candidate.warnings});
+        eprintln!("{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_validation::core::rules::RuleSettings;
+
+    fn registry(names: &[&str], settings: RuleSettings) -> ValidatorRegistry {
+        ValidatorRegistry::from_names(names, &settings).ok().unwrap()
+    }
+
+    #[test]
+    fn reports_serials_the_rule_sets_judge_differently() {
+        let active = registry(&["length", "alphanumeric"], RuleSettings::default());
+        let reordered = registry(&["alphanumeric", "length"], RuleSettings::default());
+        assert_eq!(None, Divergence::between(&active, &reordered, "AB12@"));
+
+        let candidate = registry(&["length", "alphanumeric", "blocklist"], RuleSettings { blocklist: vec![String::from("AB12345678")], ..Default::default() });
+        assert_eq!(None, Divergence::between(&active, &candidate, "CD12345678"));
+        let divergence = Divergence::between(&active, &candidate, "AB12345678").unwrap();
+        assert_eq!(true, divergence.active.is_valid());
+        assert_eq!(false, divergence.candidate.is_valid());
+        assert_eq!(true, divergence.changes_validity());
+    }
+}
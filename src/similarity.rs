@@ -0,0 +1,59 @@
+//! Near-miss suggestions for serials that already exist: registered serials within a small edit
+//! distance of the one given, which support staff check for typos.
+
+/// How many suggestions to return and how far they may be from the serial.
+#[derive(Clone, Debug, Default)]
+pub struct SimilaritySettings {
+    /// Most suggestions returned; `0` turns suggestions off.
+    pub limit: usize,
+    /// Largest edit distance of a suggestion.
+    pub max_distance: usize
+}
+
+/// Levenshtein distance: single character insertions, deletions and substitutions turning `a` into `b`.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + if a == *b { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The candidates closest to `serial_number`, nearest first, ignoring case and the serial itself.
+pub fn closest(serial_number: &str, candidates: Vec<String>, settings: &SimilaritySettings) -> Vec<String> {
+    let serial_number = serial_number.trim().to_uppercase();
+    let mut ranked: Vec<(usize, String)> = candidates.into_iter()
+        .map(|candidate| (levenshtein(&serial_number, &candidate.trim().to_uppercase()), candidate))
+        .filter(|&(distance, _)| distance > 0 && distance <= settings.max_distance)
+        .collect();
+    ranked.sort();
+    ranked.dedup_by(|a, b| a.1 == b.1);
+    ranked.into_iter().take(settings.limit).map(|(_, candidate)| candidate).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_edits() {
+        assert_eq!(0, levenshtein("AB1234", "AB1234"));
+        assert_eq!(1, levenshtein("AB1234", "AB1235"));
+        assert_eq!(1, levenshtein("AB1234", "AB12345"));
+        assert_eq!(2, levenshtein("AB1234", "BA1234"));
+        assert_eq!(6, levenshtein("", "AB1234"));
+    }
+
+    #[test]
+    fn ranks_the_nearest_candidates_first() {
+        let settings = SimilaritySettings { limit: 2, max_distance: 2 };
+        let candidates = vec![String::from("AB9999"), String::from("AB1243"), String::from("ab1234"), String::from("AB1235"), String::from("AB1200")];
+        assert_eq!(vec!["AB1235", "AB1200"], closest("AB1234", candidates, &settings));
+    }
+}
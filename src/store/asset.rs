@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use rusoto_dynamodb::AttributeValue;
+use serde::de::DeserializeOwned;
+use serde_derive::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+
+use crate::context::log_shared;
+
+use super::IdempotencyKey;
+
+/// Attributes of an item of the assets table besides its keys and index attributes, whose names
+/// depend on `DynamoDbSettings`. Field names are the attribute names.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Asset {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub registered_at: Option<u64>,
+    /// TTL attribute of reservations; confirming a reservation removes it.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub reserved_until: Option<u64>,
+    /// Unix time at which the item was tombstoned, see `DeletedPolicy`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub deleted_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub idempotency_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub idempotency_expires_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub owner_id: Option<String>,
+    /// Bumped by every metadata update; items without it are at version `0`.
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub version: u64
+}
+
+/// Attributes of an ownership record besides its keys.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AssetOwner {
+    pub owner_id: String,
+    pub registered_at: u64
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+impl Asset {
+    /// Attributes of a serial registered at `now` under `idempotency_key`.
+    pub fn registration(now: u64, idempotency_key: Option<&IdempotencyKey>) -> Asset {
+        Asset {
+            registered_at: Some(now),
+            idempotency_key: idempotency_key.map(|idempotency_key| idempotency_key.key.clone()),
+            idempotency_expires_at: idempotency_key.map(|idempotency_key| idempotency_key.expires_at),
+            ..Default::default()
+        }
+    }
+
+    /// Reads the attributes of a stored item. An item whose attributes do not have the expected
+    /// types is logged and read as a plain registration, which keeps its serial taken.
+    pub fn read(item: &HashMap<String, AttributeValue>) -> Asset {
+        from_item(item).unwrap_or_else(|error| {
+            log_shared(&format!("reading malformed item as a registration: {}", error));
+            Asset::default()
+        })
+    }
+
+    /// Whether the item still holds its serial. Expired reservations linger until DynamoDB's TTL
+    /// process deletes them, which can take days, but no longer count; neither do tombstones
+    /// deleted at or before `released_before`.
+    pub fn is_live(&self, now: u64, released_before: Option<u64>) -> bool {
+        if let (Some(deleted_at), Some(released_before)) = (self.deleted_at, released_before) {
+            if deleted_at <= released_before {
+                return false;
+            }
+        }
+        self.reserved_until.is_none_or(|reserved_until| reserved_until > now)
+    }
+
+    /// Idempotency key and its expiry stored on a registered serial.
+    pub fn idempotency_key(&self) -> Option<(&str, u64)> {
+        Some((self.idempotency_key.as_deref()?, self.idempotency_expires_at?))
+    }
+}
+
+/// Attributes of `value`, which has to serialize to a map. Unset options are left out.
+pub fn to_item<T: serde::Serialize>(value: &T) -> HashMap<String, AttributeValue> {
+    match serde_json::to_value(value) {
+        Ok(Value::Object(fields)) => fields.into_iter()
+            .filter_map(|(name, value)| attribute_value(value).map(|value| (name, value)))
+            .collect(),
+        _ => panic!("items are serialized from structs"),
+    }
+}
+
+/// Reads `T` from the attributes of an item, ignoring the attributes `T` does not name.
+pub fn from_item<T: DeserializeOwned>(item: &HashMap<String, AttributeValue>) -> Result<T, serde_json::Error> {
+    let fields: Map<String, Value> = item.iter().map(|(name, value)| (name.clone(), json_value(value))).collect();
+    serde_json::from_value(Value::Object(fields))
+}
+
+fn attribute_value(value: Value) -> Option<AttributeValue> {
+    let attribute = match value {
+        Value::Null => return None,
+        Value::Bool(value) => AttributeValue { bool: Some(value), ..Default::default() },
+        Value::Number(value) => AttributeValue { n: Some(value.to_string()), ..Default::default() },
+        Value::String(value) => AttributeValue { s: Some(value), ..Default::default() },
+        Value::Array(values) => AttributeValue { l: Some(values.into_iter().filter_map(attribute_value).collect()), ..Default::default() },
+        Value::Object(fields) => AttributeValue { m: Some(to_item(&fields)), ..Default::default() },
+    };
+    Some(attribute)
+}
+
+fn json_value(value: &AttributeValue) -> Value {
+    if let Some(ref value) = value.s {
+        Value::String(value.clone())
+    } else if let Some(ref value) = value.n {
+        // DynamoDB numbers are strings on the wire
+        serde_json::from_str::<Value>(value).ok().filter(Value::is_number).unwrap_or_else(|| Value::String(value.clone()))
+    } else if let Some(value) = value.bool {
+        Value::Bool(value)
+    } else if let Some(ref values) = value.l {
+        Value::Array(values.iter().map(json_value).collect())
+    } else if let Some(ref fields) = value.m {
+        Value::Object(fields.iter().map(|(name, value)| (name.clone(), json_value(value))).collect())
+    } else if let Some(ref values) = value.ss {
+        Value::Array(values.iter().cloned().map(Value::String).collect())
+    } else {
+        Value::Null
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assets_round_trip_through_items() {
+        let asset = Asset { registered_at: Some(1_000), status: Some(String::from("retired")), version: 3, ..Default::default() };
+        let item = to_item(&asset);
+        assert_eq!(Some("1000"), item["registered_at"].n.as_deref());
+        assert_eq!(Some("retired"), item["status"].s.as_deref());
+        assert_eq!(false, item.contains_key("reserved_until"));
+        assert_eq!(asset, from_item(&item).unwrap());
+    }
+
+    #[test]
+    fn ignores_attributes_outside_the_struct() {
+        let mut item = to_item(&AssetOwner { owner_id: String::from("owner-1"), registered_at: 1_000 });
+        item.insert(String::from("serial_number"), AttributeValue { s: Some(String::from("AB1234")), ..Default::default() });
+        assert_eq!(Asset { registered_at: Some(1_000), owner_id: Some(String::from("owner-1")), ..Default::default() }, Asset::read(&item));
+    }
+
+    #[test]
+    fn reads_malformed_items_as_registrations() {
+        let mut item = HashMap::new();
+        item.insert(String::from("reserved_until"), AttributeValue { s: Some(String::from("soon")), ..Default::default() });
+        assert_eq!(true, Asset::read(&item).is_live(1_000, None));
+    }
+}
@@ -0,0 +1,214 @@
+//! Credentials of a role in the account holding the table, for tables living in another account.
+//! The role is assumed with STS `AssumeRole` and its temporary credentials are kept by the
+//! container, which assumes it again `refresh_margin` before they expire.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use futures::future::{self, FutureResult};
+use lazy_static::lazy_static;
+use rusoto_core::{CredentialsError, ProvideAwsCredentials, Region};
+use rusoto_core::credential::AwsCredentials;
+
+use crate::aws::{self, AwsError};
+use crate::context::log_shared;
+
+/// Names the sessions in the role's CloudTrail entries.
+const SESSION_NAME: &str = "serial-validation";
+
+/// Longest an `AssumeRole` call may take.
+const ASSUME_ROLE_TIMEOUT_MS: u64 = 2_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssumeRoleSettings {
+    pub role_arn: String,
+    /// Required by roles whose trust policy asks for one.
+    pub external_id: Option<String>,
+    /// How long the credentials of a session are valid, 15 minutes to 1 hour.
+    pub session_duration: Duration,
+    /// How long before the credentials expire a new session is started.
+    pub refresh_margin: Duration
+}
+
+impl Default for AssumeRoleSettings {
+    fn default() -> AssumeRoleSettings {
+        AssumeRoleSettings {
+            role_arn: String::new(),
+            external_id: None,
+            session_duration: Duration::from_secs(3_600),
+            refresh_margin: Duration::from_secs(300)
+        }
+    }
+}
+
+/// Temporary credentials of a session of the role.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoleCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: String,
+    pub expires_at: Instant
+}
+
+/// Starts a session of the role, with the function's own credentials.
+pub fn assume_role(settings: &AssumeRoleSettings, timeout: Option<Duration>) -> Result<RoleCredentials, AwsError> {
+    // counted from before the call, the session never outlives what is assumed here
+    let requested_at = Instant::now();
+    let duration_seconds = settings.session_duration.as_secs().to_string();
+    let mut params = vec![("RoleArn", settings.role_arn.as_str()), ("RoleSessionName", SESSION_NAME), ("DurationSeconds", duration_seconds.as_str())];
+    if let Some(ref external_id) = settings.external_id {
+        params.push(("ExternalId", external_id.as_str()));
+    }
+    let xml = aws::call_query("sts", "AssumeRole", "2011-06-15", &Region::default(), &params, timeout)?;
+    credentials_of(&xml, requested_at + settings.session_duration)
+        .ok_or_else(|| AwsError::MalformedResponse(format!("AssumeRole of {} returned no credentials", settings.role_arn)))
+}
+
+fn credentials_of(xml: &str, expires_at: Instant) -> Option<RoleCredentials> {
+    Some(RoleCredentials {
+        access_key_id: aws::xml_element(xml, "AccessKeyId")?.to_string(),
+        secret_access_key: aws::xml_element(xml, "SecretAccessKey")?.to_string(),
+        session_token: aws::xml_element(xml, "SessionToken")?.to_string(),
+        expires_at
+    })
+}
+
+/// The sessions started by this container, one per role, shared by every invocation it serves.
+pub struct RoleCredentialCache {
+    sessions: Mutex<HashMap<String, RoleCredentials>>,
+    /// Held by the one call assuming a role, so that STS is never waited on with `sessions` locked.
+    refreshing: Mutex<()>
+}
+
+impl RoleCredentialCache {
+    pub fn new() -> RoleCredentialCache {
+        RoleCredentialCache { sessions: Mutex::new(HashMap::new()), refreshing: Mutex::new(()) }
+    }
+
+    /// Credentials of the role, assumed again once they expire within `refresh_margin`. One call
+    /// assumes the role at a time; the others keep the credentials in hand while they are valid and
+    /// wait for it otherwise. A failed refresh keeps the credentials as long as they are valid, to
+    /// be refreshed by the next call.
+    pub fn get<F>(&self, settings: &AssumeRoleSettings, now: Instant, assume: F) -> Result<RoleCredentials, AwsError>
+        where F: FnOnce(&AssumeRoleSettings) -> Result<RoleCredentials, AwsError>
+    {
+        let held = self.fresh(settings, now);
+        if let Ok(credentials) = held {
+            return Ok(credentials);
+        }
+        let _refreshing = match self.refreshing.try_lock() {
+            Ok(refreshing) => refreshing,
+            Err(_) => match held {
+                Err(Some(credentials)) if now < credentials.expires_at => return Ok(credentials),
+                _ => self.refreshing.lock().unwrap(),
+            },
+        };
+        // the call that held the lock before may have started a session in the meantime
+        if let Ok(credentials) = self.fresh(settings, now) {
+            return Ok(credentials);
+        }
+        match assume(settings) {
+            Ok(credentials) => {
+                self.sessions.lock().unwrap().insert(settings.role_arn.clone(), credentials.clone());
+                Ok(credentials)
+            },
+            Err(error) => {
+                log_shared(&format!("role {} could not be assumed: {}", settings.role_arn, error));
+                match self.sessions.lock().unwrap().get(&settings.role_arn) {
+                    Some(credentials) if now < credentials.expires_at => Ok(credentials.clone()),
+                    _ => Err(error),
+                }
+            },
+        }
+    }
+
+    /// The credentials of the role when they do not expire within `refresh_margin`, otherwise
+    /// those in hand, if any.
+    fn fresh(&self, settings: &AssumeRoleSettings, now: Instant) -> Result<RoleCredentials, Option<RoleCredentials>> {
+        match self.sessions.lock().unwrap().get(&settings.role_arn) {
+            Some(credentials) if now + settings.refresh_margin < credentials.expires_at => Ok(credentials.clone()),
+            held => Err(held.cloned()),
+        }
+    }
+}
+
+lazy_static! {
+    static ref ROLE_CREDENTIALS: RoleCredentialCache = RoleCredentialCache::new();
+}
+
+/// Credentials of the role, starting a session when the container has none that is fresh enough.
+pub fn role_credentials(settings: &AssumeRoleSettings) -> Result<RoleCredentials, AwsError> {
+    ROLE_CREDENTIALS.get(settings, Instant::now(), |settings| assume_role(settings, Some(Duration::from_millis(ASSUME_ROLE_TIMEOUT_MS))))
+}
+
+/// Signs the requests of a client with the credentials of the role. Stores refresh the credentials
+/// when they are created, outside of any request, so that signing rarely has to wait on STS.
+#[derive(Clone)]
+pub struct AssumedRoleProvider {
+    settings: AssumeRoleSettings
+}
+
+impl AssumedRoleProvider {
+    pub fn new(settings: AssumeRoleSettings) -> AssumedRoleProvider {
+        AssumedRoleProvider { settings }
+    }
+}
+
+impl ProvideAwsCredentials for AssumedRoleProvider {
+    type Future = FutureResult<AwsCredentials, CredentialsError>;
+
+    fn credentials(&self) -> Self::Future {
+        future::result(role_credentials(&self.settings)
+            .map(|credentials| AwsCredentials::new(credentials.access_key_id, credentials.secret_access_key, Some(credentials.session_token), None))
+            .map_err(|error| CredentialsError::new(error.to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(access_key_id: &str, expires_at: Instant) -> Result<RoleCredentials, AwsError> {
+        Ok(RoleCredentials { access_key_id: access_key_id.to_string(), secret_access_key: String::from("secret"), session_token: String::from("token"), expires_at })
+    }
+
+    #[test]
+    fn reads_the_credentials_of_the_session() {
+        let xml = "<AssumeRoleResponse><AssumeRoleResult><Credentials><AccessKeyId>ASIA1</AccessKeyId><SecretAccessKey>secret</SecretAccessKey>\
+            <SessionToken>token</SessionToken><Expiration>2026-10-16T12:00:00Z</Expiration></Credentials></AssumeRoleResult></AssumeRoleResponse>";
+        let expires_at = Instant::now();
+        assert_eq!(credentials("ASIA1", expires_at).ok(), credentials_of(xml, expires_at));
+        assert_eq!(None, credentials_of("<AssumeRoleResponse/>", expires_at));
+    }
+
+    #[test]
+    fn assumes_the_role_again_before_the_credentials_expire() {
+        let cache = RoleCredentialCache::new();
+        let settings = AssumeRoleSettings { role_arn: String::from("arn:aws:iam::123456789012:role/assets-reader"), ..Default::default() };
+        let start = Instant::now();
+        let expires_at = start + settings.session_duration;
+        let down = |_: &AssumeRoleSettings| Err(AwsError::HttpDispatch(String::from("down")));
+
+        assert_eq!(true, cache.get(&settings, start, down).is_err());
+        assert_eq!("ASIA1", cache.get(&settings, start, |_| credentials("ASIA1", expires_at)).ok().unwrap().access_key_id);
+        assert_eq!("ASIA1", cache.get(&settings, start + Duration::from_secs(3_000), |_| panic!("assumed while fresh")).ok().unwrap().access_key_id);
+        // within the margin a failed refresh keeps the credentials until they expire
+        let refresh = start + Duration::from_secs(3_400);
+        assert_eq!("ASIA1", cache.get(&settings, refresh, down).ok().unwrap().access_key_id);
+        assert_eq!("ASIA2", cache.get(&settings, refresh, |_| credentials("ASIA2", refresh + settings.session_duration)).ok().unwrap().access_key_id);
+        assert_eq!(true, cache.get(&settings, refresh + settings.session_duration, down).is_err());
+    }
+
+    #[test]
+    fn keeps_the_credentials_in_hand_while_another_call_refreshes_them() {
+        let cache = RoleCredentialCache::new();
+        let settings = AssumeRoleSettings { role_arn: String::from("arn:aws:iam::123456789012:role/assets-reader"), ..Default::default() };
+        let start = Instant::now();
+        cache.get(&settings, start, |_| credentials("ASIA1", start + settings.session_duration)).ok().unwrap();
+
+        let _refreshing = cache.refreshing.lock().unwrap();
+        let refresh = start + Duration::from_secs(3_400);
+        assert_eq!("ASIA1", cache.get(&settings, refresh, |_| panic!("assumed during another refresh")).ok().unwrap().access_key_id);
+    }
+}
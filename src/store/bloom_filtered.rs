@@ -0,0 +1,193 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::bloom::BloomFilter;
+
+use super::{ConflictingItem, DynamoDbSettings, IdempotencyKey, MetadataUpdate, MetadataUpdated, OwnedRegistration, OwnerTransfer, Registration, SerialStore, StoreError};
+
+/// Answers lookups of serials the bloom filter has never seen without reaching the store.
+/// Possible hits, and every write, still go to the store.
+pub struct BloomFilteredStore<S: SerialStore> {
+    inner: S,
+    filter: Arc<BloomFilter>,
+    settings: DynamoDbSettings
+}
+
+impl<S: SerialStore> BloomFilteredStore<S> {
+    pub fn new(inner: S, filter: Arc<BloomFilter>, settings: DynamoDbSettings) -> BloomFilteredStore<S> {
+        BloomFilteredStore { inner, filter, settings }
+    }
+
+    /// Keeps the filter current with this container's own writes until the next snapshot has them.
+    fn learn<T>(&self, serial_number: &str, result: &Result<T, StoreError>) {
+        if result.is_ok() {
+            self.filter.insert(&self.settings.filter_key(serial_number));
+        }
+    }
+}
+
+impl<S: SerialStore> SerialStore for BloomFilteredStore<S> {
+    fn contains(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        if !self.filter.might_contain(&self.settings.filter_key(serial_number)) {
+            return Ok(false);
+        }
+        self.inner.contains(serial_number, timeout)
+    }
+
+    fn conflicting_item(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Option<ConflictingItem>, StoreError> {
+        if !self.filter.might_contain(&self.settings.filter_key(serial_number)) {
+            return Ok(None);
+        }
+        self.inner.conflicting_item(serial_number, timeout)
+    }
+
+    fn contains_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        let maybe: Vec<bool> = serial_numbers.iter()
+            .map(|serial_number| self.filter.might_contain(&self.settings.filter_key(serial_number)))
+            .collect();
+        let possible_hits: Vec<&str> = serial_numbers.iter().zip(&maybe)
+            .filter(|&(_, &maybe)| maybe)
+            .map(|(&serial_number, _)| serial_number)
+            .collect();
+        let mut found = self.inner.contains_many(&possible_hits, timeout)?.into_iter();
+        Ok(maybe.into_iter().map(|maybe| maybe && found.next().unwrap_or(false)).collect())
+    }
+
+    fn register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        let result = self.inner.register(serial_number, idempotency_key, timeout);
+        self.learn(serial_number, &result);
+        result
+    }
+
+    fn would_register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        self.inner.would_register(serial_number, idempotency_key, timeout)
+    }
+
+    fn register_owned(&self, serial_number: &str, owner_id: &str, timeout: Option<Duration>) -> Result<OwnedRegistration, StoreError> {
+        let result = self.inner.register_owned(serial_number, owner_id, timeout);
+        self.learn(serial_number, &result);
+        result
+    }
+
+    fn update_metadata(&self, serial_number: &str, update: &MetadataUpdate, expected_version: u64, timeout: Option<Duration>) -> Result<MetadataUpdated, StoreError> {
+        self.inner.update_metadata(serial_number, update, expected_version, timeout)
+    }
+
+    fn reserve(&self, serial_number: &str, expires_at: u64, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        let result = self.inner.reserve(serial_number, expires_at, timeout);
+        self.learn(serial_number, &result);
+        result
+    }
+
+    fn confirm(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.inner.confirm(serial_number, timeout)
+    }
+
+    fn release(&self, serial_number: &str, reason: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.inner.release(serial_number, reason, timeout)
+    }
+
+    fn transfer_owner(&self, serial_number: &str, from_owner: &str, to_owner: &str, timeout: Option<Duration>) -> Result<OwnerTransfer, StoreError> {
+        self.inner.transfer_owner(serial_number, from_owner, to_owner, timeout)
+    }
+
+    fn import_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        let written = self.inner.import_many(serial_numbers, timeout)?;
+        // serials the table kept refusing are not in the store, so the filter must not claim them
+        for (serial_number, _) in serial_numbers.iter().zip(&written).filter(|&(_, &written)| written) {
+            self.filter.insert(&self.settings.filter_key(serial_number));
+        }
+        Ok(written)
+    }
+
+    /// The probe key is never in the filter, so the probe has to bypass it to reach the store.
+    fn probe(&self, timeout: Option<Duration>) -> Result<(), StoreError> {
+        self.inner.probe(timeout)
+    }
+
+    fn similar_candidates(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Vec<String>, StoreError> {
+        self.inner.similar_candidates(serial_number, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{MemoryStore, FailingStore};
+
+    #[test]
+    fn answers_unseen_serials_without_the_store() {
+        let filter = Arc::new(BloomFilter::with_capacity(100, 0.01));
+        let store = BloomFilteredStore::new(FailingStore, filter, DynamoDbSettings::default());
+        assert_eq!(false, store.contains("AB1234", None).ok().unwrap());
+        assert_eq!(true, store.probe(None).is_err());
+    }
+
+    #[test]
+    fn asks_the_store_about_possible_hits() {
+        let filter = Arc::new(BloomFilter::with_capacity(100, 0.01));
+        filter.insert("serial1");
+        filter.insert("AB1234");
+        let store = BloomFilteredStore::new(MemoryStore::new(vec![String::from("serial1")]), filter, DynamoDbSettings::default());
+        assert_eq!(true, store.contains("serial1", None).ok().unwrap());
+        assert_eq!(false, store.contains("AB1234", None).ok().unwrap());
+    }
+
+    #[test]
+    fn batches_only_possible_hits() {
+        let filter = Arc::new(BloomFilter::with_capacity(100, 0.01));
+        filter.insert("serial1");
+        let store = BloomFilteredStore::new(MemoryStore::new(vec![String::from("serial1"), String::from("serial2")]), filter, DynamoDbSettings::default());
+        assert_eq!(vec![false, true, false], store.contains_many(&["AB1234", "serial1", "serial2"], None).ok().unwrap());
+    }
+
+    #[test]
+    fn learns_serials_registered_through_it() {
+        let filter = Arc::new(BloomFilter::with_capacity(100, 0.01));
+        let store = BloomFilteredStore::new(MemoryStore::new(Vec::new()), filter, DynamoDbSettings::default());
+        store.register("AB1234", None, None).ok().unwrap();
+        assert_eq!(true, store.contains("AB1234", None).ok().unwrap());
+    }
+
+    #[test]
+    fn learns_serials_imported_through_it() {
+        let filter = Arc::new(BloomFilter::with_capacity(100, 0.01));
+        let store = BloomFilteredStore::new(MemoryStore::new(vec![String::from("AB1234")]), filter, DynamoDbSettings::default());
+        store.import_many(&["AB1234", "CD5678"], None).ok().unwrap();
+        assert_eq!(vec![true, true], store.contains_many(&["AB1234", "CD5678"], None).ok().unwrap());
+    }
+
+    #[test]
+    fn does_not_learn_unprocessed_imports() {
+        let filter = Arc::new(BloomFilter::with_capacity(100, 0.01));
+        let store = BloomFilteredStore::new(UnprocessedStore, filter.clone(), DynamoDbSettings::default());
+        assert_eq!(vec![true, false], store.import_many(&["AB1234", "CD5678"], None).ok().unwrap());
+        assert_eq!(true, filter.might_contain(&DynamoDbSettings::default().filter_key("AB1234")));
+        assert_eq!(false, filter.might_contain(&DynamoDbSettings::default().filter_key("CD5678")));
+    }
+
+    /// Writes the first serial of every import and leaves the rest unprocessed.
+    struct UnprocessedStore;
+
+    impl SerialStore for UnprocessedStore {
+        fn contains(&self, _serial_number: &str, _timeout: Option<Duration>) -> Result<bool, StoreError> {
+            Ok(false)
+        }
+
+        fn register(&self, _serial_number: &str, _idempotency_key: Option<&IdempotencyKey>, _timeout: Option<Duration>) -> Result<Registration, StoreError> {
+            Ok(Registration::Registered)
+        }
+
+        fn reserve(&self, _serial_number: &str, _expires_at: u64, _timeout: Option<Duration>) -> Result<bool, StoreError> {
+            Ok(true)
+        }
+
+        fn confirm(&self, _serial_number: &str, _timeout: Option<Duration>) -> Result<bool, StoreError> {
+            Ok(true)
+        }
+
+        fn import_many(&self, serial_numbers: &[&str], _timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+            Ok(serial_numbers.iter().enumerate().map(|(index, _)| index == 0).collect())
+        }
+    }
+}
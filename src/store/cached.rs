@@ -0,0 +1,181 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::result_cache::TableResults;
+
+use super::{ConflictingItem, DynamoDbSettings, IdempotencyKey, MetadataUpdate, MetadataUpdated, OwnedRegistration, OwnerTransfer, Registration, SerialStore, StoreError};
+
+/// Answers lookups from the results this container kept, asking the store about the others.
+/// Every write goes to the store and drops the kept result of its serial.
+pub struct CachedStore<S: SerialStore> {
+    inner: S,
+    results: Arc<TableResults>,
+    settings: DynamoDbSettings
+}
+
+impl<S: SerialStore> CachedStore<S> {
+    pub fn new(inner: S, results: Arc<TableResults>, settings: DynamoDbSettings) -> CachedStore<S> {
+        CachedStore { inner, results, settings }
+    }
+
+    fn forget<T>(&self, serial_number: &str, result: Result<T, StoreError>) -> Result<T, StoreError> {
+        self.results.forget(&self.settings.filter_key(serial_number));
+        result
+    }
+}
+
+impl<S: SerialStore> SerialStore for CachedStore<S> {
+    fn contains(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        let key = self.settings.filter_key(serial_number);
+        if let Some(found) = self.results.get(&key, Instant::now()) {
+            return Ok(found);
+        }
+        let generation = self.results.generation();
+        let found = self.inner.contains(serial_number, timeout)?;
+        self.results.put(&key, found, generation, Instant::now());
+        Ok(found)
+    }
+
+    fn contains_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        let now = Instant::now();
+        let keys: Vec<String> = serial_numbers.iter().map(|serial_number| self.settings.filter_key(serial_number)).collect();
+        let kept: Vec<Option<bool>> = keys.iter().map(|key| self.results.get(key, now)).collect();
+        let missing: Vec<&str> = serial_numbers.iter().zip(&kept)
+            .filter(|&(_, kept)| kept.is_none())
+            .map(|(&serial_number, _)| serial_number)
+            .collect();
+        let generation = self.results.generation();
+        let mut looked_up = self.inner.contains_many(&missing, timeout)?.into_iter();
+        // answers are matched to the serials by position, so an answer of another length places none
+        let found: Option<Vec<bool>> = kept.iter().map(|kept| kept.or_else(|| looked_up.next())).collect();
+        let found = found.filter(|_| looked_up.next().is_none())
+            .ok_or_else(|| StoreError::Unavailable(format!("the store did not answer each of {} lookups", missing.len())))?;
+        let now = Instant::now();
+        for ((key, kept), &found) in keys.iter().zip(&kept).zip(&found) {
+            if kept.is_none() {
+                self.results.put(key, found, generation, now);
+            }
+        }
+        Ok(found)
+    }
+
+    /// The item is only read for serials that may be registered; the others need no lookup.
+    fn conflicting_item(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Option<ConflictingItem>, StoreError> {
+        if self.results.get(&self.settings.filter_key(serial_number), Instant::now()) == Some(false) {
+            return Ok(None);
+        }
+        self.inner.conflicting_item(serial_number, timeout)
+    }
+
+    fn register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        self.forget(serial_number, self.inner.register(serial_number, idempotency_key, timeout))
+    }
+
+    fn would_register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        self.inner.would_register(serial_number, idempotency_key, timeout)
+    }
+
+    fn register_owned(&self, serial_number: &str, owner_id: &str, timeout: Option<Duration>) -> Result<OwnedRegistration, StoreError> {
+        self.forget(serial_number, self.inner.register_owned(serial_number, owner_id, timeout))
+    }
+
+    fn update_metadata(&self, serial_number: &str, update: &MetadataUpdate, expected_version: u64, timeout: Option<Duration>) -> Result<MetadataUpdated, StoreError> {
+        self.inner.update_metadata(serial_number, update, expected_version, timeout)
+    }
+
+    fn reserve(&self, serial_number: &str, expires_at: u64, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.forget(serial_number, self.inner.reserve(serial_number, expires_at, timeout))
+    }
+
+    fn confirm(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.forget(serial_number, self.inner.confirm(serial_number, timeout))
+    }
+
+    fn release(&self, serial_number: &str, reason: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.forget(serial_number, self.inner.release(serial_number, reason, timeout))
+    }
+
+    fn transfer_owner(&self, serial_number: &str, from_owner: &str, to_owner: &str, timeout: Option<Duration>) -> Result<OwnerTransfer, StoreError> {
+        self.inner.transfer_owner(serial_number, from_owner, to_owner, timeout)
+    }
+
+    fn import_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        let result = self.inner.import_many(serial_numbers, timeout);
+        serial_numbers.iter().for_each(|serial_number| self.results.forget(&self.settings.filter_key(serial_number)));
+        result
+    }
+
+    /// Always reaches the store, which is what a probe is for.
+    fn probe(&self, timeout: Option<Duration>) -> Result<(), StoreError> {
+        self.inner.probe(timeout)
+    }
+
+    fn similar_candidates(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Vec<String>, StoreError> {
+        self.inner.similar_candidates(serial_number, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::result_cache::{ResultCache, ResultCacheSettings};
+    use crate::store::MemoryStore;
+
+    fn results() -> Arc<TableResults> {
+        let results = ResultCache::new().table("assets", &ResultCacheSettings::default());
+        results.sync(Duration::from_secs(5), Instant::now(), || Ok(None));
+        results
+    }
+
+    #[test]
+    fn answers_repeated_lookups_from_the_kept_results() {
+        let results = results();
+        let store = CachedStore::new(MemoryStore::new(vec![String::from("serial1")]), results.clone(), DynamoDbSettings::default());
+        assert_eq!(vec![true, false], store.contains_many(&["serial1", "AB1234"], None).ok().unwrap());
+        assert_eq!(Some(true), results.get("serial1", Instant::now()));
+        assert_eq!(Some(false), results.get("AB1234", Instant::now()));
+        assert_eq!(false, store.contains("AB1234", None).ok().unwrap());
+    }
+
+    #[test]
+    fn keeps_no_results_from_a_short_answer() {
+        let results = results();
+        let store = CachedStore::new(ShortStore, results.clone(), DynamoDbSettings::default());
+        assert_eq!(true, store.contains_many(&["serial1", "AB1234"], None).is_err());
+        assert_eq!(None, results.get("serial1", Instant::now()));
+        assert_eq!(None, results.get("AB1234", Instant::now()));
+    }
+
+    #[test]
+    fn drops_results_of_serials_written_through_it() {
+        let store = CachedStore::new(MemoryStore::new(Vec::new()), results(), DynamoDbSettings::default());
+        assert_eq!(false, store.contains("AB1234", None).ok().unwrap());
+        store.register("AB1234", None, None).ok().unwrap();
+        assert_eq!(true, store.contains("AB1234", None).ok().unwrap());
+    }
+
+    /// Answers batch lookups for one serial less than asked about.
+    struct ShortStore;
+
+    impl SerialStore for ShortStore {
+        fn contains(&self, _serial_number: &str, _timeout: Option<Duration>) -> Result<bool, StoreError> {
+            Ok(false)
+        }
+
+        fn contains_many(&self, serial_numbers: &[&str], _timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+            Ok(vec![false; serial_numbers.len().saturating_sub(1)])
+        }
+
+        fn register(&self, _serial_number: &str, _idempotency_key: Option<&IdempotencyKey>, _timeout: Option<Duration>) -> Result<Registration, StoreError> {
+            Ok(Registration::Registered)
+        }
+
+        fn reserve(&self, _serial_number: &str, _expires_at: u64, _timeout: Option<Duration>) -> Result<bool, StoreError> {
+            Ok(true)
+        }
+
+        fn confirm(&self, _serial_number: &str, _timeout: Option<Duration>) -> Result<bool, StoreError> {
+            Ok(true)
+        }
+    }
+}
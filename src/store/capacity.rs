@@ -0,0 +1,136 @@
+//! Backpressure on the batch reads and writes of bulk work, so that manifests and imports stay
+//! within the capacity the table was given. Each container tracks the `ConsumedCapacity` DynamoDB
+//! reports for them against a budget per table and delays the next batch once consumption nears it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+/// Capacity units per second the batch paths of a container may consume; unset is unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CapacityBudget {
+    pub read_units: Option<f64>,
+    pub write_units: Option<f64>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CapacityKind {
+    Read,
+    Write
+}
+
+/// Share of a second's budget consumed ahead of the refill from which batches are slowed down.
+const SLOW_DOWN_AT: f64 = 0.8;
+
+/// Units left of a budget refilling at `per_second`, going negative when a batch consumed more
+/// than was left.
+struct Bucket {
+    per_second: f64,
+    units: f64,
+    updated_at: Instant
+}
+
+impl Bucket {
+    fn new(per_second: f64, now: Instant) -> Bucket {
+        Bucket { per_second, units: per_second, updated_at: now }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.units = (self.units + elapsed * self.per_second).min(self.per_second);
+        self.updated_at = now;
+    }
+}
+
+/// Consumption of the batch paths against a `CapacityBudget`, shared by every store of a table.
+pub struct CapacityThrottle {
+    read: Option<Mutex<Bucket>>,
+    write: Option<Mutex<Bucket>>
+}
+
+impl CapacityThrottle {
+    pub fn new(budget: CapacityBudget, now: Instant) -> CapacityThrottle {
+        let bucket = |per_second: Option<f64>| per_second.filter(|per_second| *per_second > 0.0).map(|per_second| Mutex::new(Bucket::new(per_second, now)));
+        CapacityThrottle { read: bucket(budget.read_units), write: bucket(budget.write_units) }
+    }
+
+    fn bucket(&self, kind: CapacityKind) -> Option<&Mutex<Bucket>> {
+        match kind {
+            CapacityKind::Read => self.read.as_ref(),
+            CapacityKind::Write => self.write.as_ref(),
+        }
+    }
+
+    /// Counts `units` DynamoDB reported as consumed by a batch.
+    pub fn record(&self, kind: CapacityKind, units: f64, now: Instant) {
+        if let Some(bucket) = self.bucket(kind) {
+            let mut bucket = bucket.lock().unwrap();
+            bucket.refill(now);
+            bucket.units -= units;
+        }
+    }
+
+    /// How long the next batch of `kind` should wait: nothing while less than `SLOW_DOWN_AT` of a
+    /// second's budget was consumed ahead of the refill, otherwise until the budget caught up
+    /// again. The wait grows with the overshoot, so batches slow down progressively.
+    pub fn delay(&self, kind: CapacityKind, now: Instant) -> Duration {
+        let bucket = match self.bucket(kind) {
+            Some(bucket) => bucket,
+            None => return Duration::from_secs(0),
+        };
+        let mut bucket = bucket.lock().unwrap();
+        bucket.refill(now);
+        let reserve = bucket.per_second - bucket.per_second * SLOW_DOWN_AT;
+        if bucket.units >= reserve {
+            return Duration::from_secs(0);
+        }
+        Duration::from_secs_f64((reserve - bucket.units) / bucket.per_second)
+    }
+}
+
+lazy_static! {
+    // survives between invocations served by the same container
+    static ref THROTTLES: Mutex<HashMap<String, Arc<CapacityThrottle>>> = Mutex::new(HashMap::new());
+}
+
+/// The throttle of `table_name`, `None` when `budget` sets no limit. Tenants sharing a table share
+/// its budget.
+pub fn throttle_for(table_name: &str, budget: CapacityBudget) -> Option<Arc<CapacityThrottle>> {
+    if budget.read_units.is_none() && budget.write_units.is_none() {
+        return None;
+    }
+    let mut throttles = THROTTLES.lock().unwrap();
+    let throttle = throttles.entry(table_name.to_string()).or_insert_with(|| Arc::new(CapacityThrottle::new(budget, Instant::now())));
+    Some(throttle.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slows_down_progressively_near_the_budget() {
+        let now = Instant::now();
+        let throttle = CapacityThrottle::new(CapacityBudget { read_units: Some(100.0), write_units: None }, now);
+        throttle.record(CapacityKind::Read, 70.0, now);
+        assert_eq!(Duration::from_secs(0), throttle.delay(CapacityKind::Read, now));
+        throttle.record(CapacityKind::Read, 20.0, now);
+        assert_eq!(Duration::from_millis(100), throttle.delay(CapacityKind::Read, now));
+        throttle.record(CapacityKind::Read, 100.0, now);
+        assert_eq!(Duration::from_millis(1_100), throttle.delay(CapacityKind::Read, now));
+        assert_eq!(Duration::from_millis(100), throttle.delay(CapacityKind::Read, now + Duration::from_secs(1)));
+
+        throttle.record(CapacityKind::Write, 1_000.0, now);
+        assert_eq!(Duration::from_secs(0), throttle.delay(CapacityKind::Write, now));
+    }
+
+    #[test]
+    fn shares_a_throttle_per_table() {
+        let budget = CapacityBudget { read_units: None, write_units: Some(25.0) };
+        let throttle = throttle_for("capacity-test", budget).unwrap();
+        assert_eq!(true, Arc::ptr_eq(&throttle, &throttle_for("capacity-test", budget).unwrap()));
+        assert_eq!(true, throttle_for("capacity-test", CapacityBudget::default()).is_none());
+    }
+}
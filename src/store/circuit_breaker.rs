@@ -0,0 +1,332 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{ConflictingItem, IdempotencyKey, MetadataUpdate, MetadataUpdated, OwnedRegistration, OwnerTransfer, Registration, SerialStore, StoreError};
+
+/// Thresholds controlling when the breaker opens and how it recovers.
+#[derive(Clone, Debug)]
+pub struct CircuitBreakerSettings {
+    /// Consecutive store failures after which the breaker opens. `0` disables the breaker.
+    pub failure_threshold: u32,
+    /// How long the breaker stays open before letting probes through.
+    pub open_duration: Duration,
+    /// Successful probes needed while half-open before the breaker closes again.
+    pub half_open_probes: u32
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> CircuitBreakerSettings {
+        CircuitBreakerSettings {
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+            half_open_probes: 1
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Closed { failures: u32 },
+    Open { since: Instant },
+    HalfOpen { successes: u32 }
+}
+
+/// Breaker state shared by every invocation handled by this Lambda container.
+pub struct CircuitBreaker {
+    settings: CircuitBreakerSettings,
+    state: Mutex<State>
+}
+
+impl CircuitBreaker {
+    pub fn new(settings: CircuitBreakerSettings) -> CircuitBreaker {
+        CircuitBreaker {
+            settings,
+            state: Mutex::new(State::Closed { failures: 0 })
+        }
+    }
+
+    /// Whether a call may reach the store at `now`. Moves an expired open breaker to half-open.
+    fn allows(&self, now: Instant) -> bool {
+        if self.settings.failure_threshold == 0 {
+            return true;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Open { since } if now.duration_since(since) < self.settings.open_duration => false,
+            State::Open { .. } => {
+                *state = State::HalfOpen { successes: 0 };
+                true
+            },
+            _ => true
+        }
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        *state = match *state {
+            State::HalfOpen { successes } if successes + 1 < self.settings.half_open_probes => {
+                State::HalfOpen { successes: successes + 1 }
+            },
+            _ => State::Closed { failures: 0 }
+        };
+    }
+
+    fn record_failure(&self, now: Instant) {
+        if self.settings.failure_threshold == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        *state = match *state {
+            State::Closed { failures } if failures + 1 < self.settings.failure_threshold => {
+                State::Closed { failures: failures + 1 }
+            },
+            // a failed probe reopens the breaker straight away
+            _ => State::Open { since: now }
+        };
+    }
+}
+
+/// Wraps a store so that it fails fast with `StoreError::CircuitOpen` while the breaker is open.
+pub struct CircuitBreakerStore<'a, S: SerialStore> {
+    inner: S,
+    breaker: &'a CircuitBreaker
+}
+
+impl<'a, S: SerialStore> CircuitBreakerStore<'a, S> {
+    pub fn new(inner: S, breaker: &'a CircuitBreaker) -> CircuitBreakerStore<'a, S> {
+        CircuitBreakerStore { inner, breaker }
+    }
+}
+
+impl<'a, S: SerialStore> SerialStore for CircuitBreakerStore<'a, S> {
+    fn contains(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        if !self.breaker.allows(Instant::now()) {
+            return Err(StoreError::CircuitOpen);
+        }
+
+        let result = self.inner.contains(serial_number, timeout);
+        self.record(&result);
+        result
+    }
+
+    fn conflicting_item(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Option<ConflictingItem>, StoreError> {
+        if !self.breaker.allows(Instant::now()) {
+            return Err(StoreError::CircuitOpen);
+        }
+
+        let result = self.inner.conflicting_item(serial_number, timeout);
+        self.record(&result);
+        result
+    }
+
+    fn contains_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        if !self.breaker.allows(Instant::now()) {
+            return Err(StoreError::CircuitOpen);
+        }
+
+        let result = self.inner.contains_many(serial_numbers, timeout);
+        self.record(&result);
+        result
+    }
+
+    fn register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        if !self.breaker.allows(Instant::now()) {
+            return Err(StoreError::CircuitOpen);
+        }
+
+        let result = self.inner.register(serial_number, idempotency_key, timeout);
+        self.record(&result);
+        result
+    }
+
+    fn would_register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        if !self.breaker.allows(Instant::now()) {
+            return Err(StoreError::CircuitOpen);
+        }
+
+        let result = self.inner.would_register(serial_number, idempotency_key, timeout);
+        self.record(&result);
+        result
+    }
+
+    fn register_owned(&self, serial_number: &str, owner_id: &str, timeout: Option<Duration>) -> Result<OwnedRegistration, StoreError> {
+        if !self.breaker.allows(Instant::now()) {
+            return Err(StoreError::CircuitOpen);
+        }
+
+        let result = self.inner.register_owned(serial_number, owner_id, timeout);
+        self.record(&result);
+        result
+    }
+
+    fn update_metadata(&self, serial_number: &str, update: &MetadataUpdate, expected_version: u64, timeout: Option<Duration>) -> Result<MetadataUpdated, StoreError> {
+        if !self.breaker.allows(Instant::now()) {
+            return Err(StoreError::CircuitOpen);
+        }
+
+        let result = self.inner.update_metadata(serial_number, update, expected_version, timeout);
+        self.record(&result);
+        result
+    }
+
+    fn reserve(&self, serial_number: &str, expires_at: u64, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        if !self.breaker.allows(Instant::now()) {
+            return Err(StoreError::CircuitOpen);
+        }
+
+        let result = self.inner.reserve(serial_number, expires_at, timeout);
+        self.record(&result);
+        result
+    }
+
+    fn confirm(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        if !self.breaker.allows(Instant::now()) {
+            return Err(StoreError::CircuitOpen);
+        }
+
+        let result = self.inner.confirm(serial_number, timeout);
+        self.record(&result);
+        result
+    }
+
+    fn release(&self, serial_number: &str, reason: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        if !self.breaker.allows(Instant::now()) {
+            return Err(StoreError::CircuitOpen);
+        }
+
+        let result = self.inner.release(serial_number, reason, timeout);
+        self.record(&result);
+        result
+    }
+
+    fn transfer_owner(&self, serial_number: &str, from_owner: &str, to_owner: &str, timeout: Option<Duration>) -> Result<OwnerTransfer, StoreError> {
+        if !self.breaker.allows(Instant::now()) {
+            return Err(StoreError::CircuitOpen);
+        }
+
+        let result = self.inner.transfer_owner(serial_number, from_owner, to_owner, timeout);
+        self.record(&result);
+        result
+    }
+
+    fn import_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        if !self.breaker.allows(Instant::now()) {
+            return Err(StoreError::CircuitOpen);
+        }
+
+        let result = self.inner.import_many(serial_numbers, timeout);
+        self.record(&result);
+        result
+    }
+
+    fn similar_candidates(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Vec<String>, StoreError> {
+        if !self.breaker.allows(Instant::now()) {
+            return Err(StoreError::CircuitOpen);
+        }
+
+        let result = self.inner.similar_candidates(serial_number, timeout);
+        self.record(&result);
+        result
+    }
+}
+
+impl<'a, S: SerialStore> CircuitBreakerStore<'a, S> {
+    fn record<T>(&self, result: &Result<T, StoreError>) {
+        match *result {
+            Ok(_) | Err(StoreError::Misconfigured(_)) | Err(StoreError::AccessDenied(_)) => self.breaker.record_success(),
+            Err(_) => self.breaker.record_failure(Instant::now()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::FailingStore;
+
+    fn test_breaker() -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerSettings {
+            failure_threshold: 2,
+            open_duration: Duration::from_secs(10),
+            half_open_probes: 2
+        })
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = test_breaker();
+        let now = Instant::now();
+        breaker.record_failure(now);
+        assert_eq!(true, breaker.allows(now));
+        breaker.record_failure(now);
+        assert_eq!(false, breaker.allows(now));
+    }
+
+    #[test]
+    fn success_resets_the_failure_count() {
+        let breaker = test_breaker();
+        let now = Instant::now();
+        breaker.record_failure(now);
+        breaker.record_success();
+        breaker.record_failure(now);
+        assert_eq!(true, breaker.allows(now));
+    }
+
+    #[test]
+    fn lets_probes_through_after_the_open_duration() {
+        let breaker = test_breaker();
+        let now = Instant::now();
+        breaker.record_failure(now);
+        breaker.record_failure(now);
+        assert_eq!(true, breaker.allows(now + Duration::from_secs(11)));
+        assert_eq!(State::HalfOpen { successes: 0 }, *breaker.state.lock().unwrap());
+    }
+
+    #[test]
+    fn closes_after_enough_successful_probes() {
+        let breaker = test_breaker();
+        let now = Instant::now();
+        breaker.record_failure(now);
+        breaker.record_failure(now);
+        breaker.allows(now + Duration::from_secs(11));
+        breaker.record_success();
+        assert_eq!(State::HalfOpen { successes: 1 }, *breaker.state.lock().unwrap());
+        breaker.record_success();
+        assert_eq!(State::Closed { failures: 0 }, *breaker.state.lock().unwrap());
+    }
+
+    #[test]
+    fn failed_probe_reopens() {
+        let breaker = test_breaker();
+        let now = Instant::now();
+        breaker.record_failure(now);
+        breaker.record_failure(now);
+        let later = now + Duration::from_secs(11);
+        breaker.allows(later);
+        breaker.record_failure(later);
+        assert_eq!(false, breaker.allows(later + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn zero_threshold_disables_the_breaker() {
+        let breaker = CircuitBreaker::new(CircuitBreakerSettings { failure_threshold: 0, ..Default::default() });
+        let now = Instant::now();
+        breaker.record_failure(now);
+        breaker.record_failure(now);
+        assert_eq!(true, breaker.allows(now));
+    }
+
+    #[test]
+    fn store_fails_fast_once_open() {
+        let breaker = test_breaker();
+        let store = CircuitBreakerStore::new(FailingStore, &breaker);
+        assert_eq!(true, store.contains("serial1", None).is_err());
+        assert_eq!(true, store.contains("serial1", None).is_err());
+        match store.contains("serial1", None) {
+            Err(StoreError::CircuitOpen) => {},
+            other => panic!("expected an open circuit, got {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Counters handing out increasing numbers, one per product line, for serials generated from a
+//! sequence. The counters live in a DynamoDB table shared by every container and are moved with
+//! `UpdateItem` `ADD`, which DynamoDB applies atomically. A container takes a block of numbers at
+//! a time and hands them out itself, so that most serials cost no call to the counters table.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rusoto_dynamodb::{AttributeValue, DynamoDb, DynamoDbClient, GetItemInput, UpdateItemInput};
+
+use super::{client_in, send, string_value, StoreError, DEFAULT_REGION};
+
+#[derive(Clone, Debug)]
+pub struct SequenceSettings {
+    /// Table keyed by `counter`, holding the last number handed out in `value`.
+    pub table_name: String,
+    /// Numbers a container takes from a counter at once.
+    pub block_size: u64
+}
+
+pub trait SequenceCounter: Send + Sync {
+    /// Adds `count` to the counter `name`, which starts at zero, returning its new value.
+    fn add(&self, name: &str, count: u64, timeout: Option<Duration>) -> Result<u64, StoreError>;
+
+    /// Value of the counter `name` without changing it, zero before the first `add`.
+    fn current(&self, name: &str, timeout: Option<Duration>) -> Result<u64, StoreError>;
+}
+
+pub struct DynamoDbCounter {
+    client: Arc<DynamoDbClient>,
+    table_name: String
+}
+
+impl DynamoDbCounter {
+    pub fn new(settings: &SequenceSettings) -> DynamoDbCounter {
+        DynamoDbCounter { client: client_in(&DEFAULT_REGION), table_name: settings.table_name.clone() }
+    }
+
+    fn key_of(name: &str) -> HashMap<String, AttributeValue> {
+        let mut key = HashMap::new();
+        key.insert(String::from("counter"), string_value(name));
+        key
+    }
+}
+
+fn counter_value(item: Option<HashMap<String, AttributeValue>>) -> Result<u64, StoreError> {
+    match item.as_ref().and_then(|item| item.get("value")) {
+        Some(value) => value.n.as_ref().and_then(|value| value.parse().ok())
+            .ok_or_else(|| StoreError::Misconfigured(String::from("counter value is not a number"))),
+        None => Ok(0),
+    }
+}
+
+impl SequenceCounter for DynamoDbCounter {
+    fn add(&self, name: &str, count: u64, timeout: Option<Duration>) -> Result<u64, StoreError> {
+        let mut names = HashMap::new();
+        names.insert(String::from("#value"), String::from("value"));
+        let mut values = HashMap::new();
+        values.insert(String::from(":count"), AttributeValue { n: Some(count.to_string()), ..Default::default() });
+        let input = UpdateItemInput {
+            table_name: self.table_name.clone(),
+            key: DynamoDbCounter::key_of(name),
+            update_expression: Some(String::from("ADD #value :count")),
+            expression_attribute_names: Some(names),
+            expression_attribute_values: Some(values),
+            return_values: Some(String::from("UPDATED_NEW")),
+            ..Default::default()
+        };
+        counter_value(send(self.client.update_item(input), timeout)?.attributes)
+    }
+
+    fn current(&self, name: &str, timeout: Option<Duration>) -> Result<u64, StoreError> {
+        let input = GetItemInput {
+            table_name: self.table_name.clone(),
+            key: DynamoDbCounter::key_of(name),
+            consistent_read: Some(true),
+            ..Default::default()
+        };
+        counter_value(send(self.client.get_item(input), timeout)?.item)
+    }
+}
+
+/// Counters kept in memory, for the tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryCounter {
+    values: Mutex<HashMap<String, u64>>
+}
+
+#[cfg(test)]
+impl SequenceCounter for MemoryCounter {
+    fn add(&self, name: &str, count: u64, _timeout: Option<Duration>) -> Result<u64, StoreError> {
+        let mut values = self.values.lock().unwrap();
+        let value = values.entry(name.to_string()).or_insert(0);
+        *value += count;
+        Ok(*value)
+    }
+
+    fn current(&self, name: &str, _timeout: Option<Duration>) -> Result<u64, StoreError> {
+        Ok(self.values.lock().unwrap().get(name).cloned().unwrap_or(0))
+    }
+}
+
+/// The numbers a container took from each counter and has not handed out yet. Every number is
+/// handed out once across containers, increasing within a container; containers taking blocks
+/// of more than one number hand them out interleaved, and numbers left when a container goes
+/// away are skipped.
+pub struct SequenceBlocks {
+    block_size: u64,
+    blocks: Mutex<HashMap<String, Range<u64>>>
+}
+
+impl SequenceBlocks {
+    /// Blocks of `block_size` numbers, at least one.
+    pub fn new(block_size: u64) -> SequenceBlocks {
+        SequenceBlocks { block_size: block_size.max(1), blocks: Mutex::new(HashMap::new()) }
+    }
+
+    /// The next number of the counter `name`, taking a block from `counter` once the container's
+    /// block is used up.
+    pub fn next(&self, counter: &dyn SequenceCounter, name: &str, timeout: Option<Duration>) -> Result<u64, StoreError> {
+        let mut blocks = self.blocks.lock().unwrap();
+        if let Some(number) = blocks.get_mut(name).and_then(Iterator::next) {
+            return Ok(number);
+        }
+        let block_size = self.block_size;
+        let last = counter.add(name, block_size, timeout)?;
+        let mut block = (last + 1 - block_size)..(last + 1);
+        let number = block.next().unwrap();
+        blocks.insert(name.to_string(), block);
+        Ok(number)
+    }
+
+    /// The number `next` would hand out, without taking it.
+    pub fn peek(&self, counter: &dyn SequenceCounter, name: &str, timeout: Option<Duration>) -> Result<u64, StoreError> {
+        if let Some(number) = self.blocks.lock().unwrap().get(name).and_then(|block| block.clone().next()) {
+            return Ok(number);
+        }
+        Ok(counter.current(name, timeout)? + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hands_out_blocks_taken_from_the_counter() {
+        let counter = MemoryCounter::default();
+        let blocks = SequenceBlocks::new(3);
+        assert_eq!(1, blocks.peek(&counter, "AB", None).ok().unwrap());
+        let numbers: Vec<u64> = (0..4).map(|_| blocks.next(&counter, "AB", None).ok().unwrap()).collect();
+        assert_eq!(vec![1, 2, 3, 4], numbers);
+        assert_eq!(6, counter.current("AB", None).ok().unwrap());
+        assert_eq!(5, blocks.peek(&counter, "AB", None).ok().unwrap());
+
+        let other = SequenceBlocks::new(0);
+        assert_eq!(7, other.next(&counter, "AB", None).ok().unwrap());
+        assert_eq!(8, other.next(&counter, "AB", None).ok().unwrap());
+        assert_eq!(1, other.next(&counter, "CD", None).ok().unwrap());
+    }
+}
@@ -0,0 +1,1628 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::{future, stream, Future, Stream};
+use futures::future::Loop;
+use futures::sync::oneshot;
+use lazy_static::lazy_static;
+use tokio::runtime::Runtime;
+use tokio::timer::Delay;
+use rusoto_core::{Client, HttpClient, Region, RusotoFuture, CredentialsError, HttpDispatchError};
+use rusoto_core::request::BufferedHttpResponse;
+use rusoto_dynamodb::{DynamoDb, DynamoDbClient, BatchGetItemInput, BatchGetItemError, BatchWriteItemInput, BatchWriteItemError, ConsumedCapacity, PutRequest, WriteRequest, DescribeTableInput, DescribeTableError, KeysAndAttributes, GetItemInput, GetItemError, PutItemInput, PutItemError, QueryInput, QueryError, ScanInput, ScanError, UpdateItemInput, UpdateItemError, AttributeValue};
+use std::collections::HashMap;
+use serde_derive::Serialize;
+use schemars::JsonSchema;
+
+use crate::aws::{self, AwsError};
+use crate::context::log_shared;
+use super::asset::{to_item, Asset, AssetOwner};
+use super::assumed_role::{self, AssumedRoleProvider, AssumeRoleSettings};
+use super::capacity::{self, CapacityBudget, CapacityKind, CapacityThrottle};
+use super::{ConflictingItem, FailedCondition, IdempotencyKey, MetadataUpdate, MetadataUpdated, OwnedRegistration, OwnerTransfer, Registration, SerialStore, StoreError, registration_of_existing, unix_now};
+
+/// Filters of a listing page. An empty `prefix` lists every serial of the table, or of the
+/// partition and key prefix of a tenant.
+#[derive(Clone, Debug, Default)]
+pub struct ListQuery {
+    pub prefix: String,
+    pub registered_from: Option<u64>,
+    pub registered_to: Option<u64>,
+    /// Items read for the page. Filtered items count too, so a page may come back short or empty.
+    pub limit: i64,
+    /// `LastEvaluatedKey` of the previous page.
+    pub start_key: Option<HashMap<String, AttributeValue>>
+}
+
+#[derive(Serialize, Debug, PartialEq, JsonSchema)]
+pub struct ListedSerial {
+    #[serde(rename = "serialNumber")]
+    pub serial_number: String,
+    #[serde(rename = "registeredAt", skip_serializing_if = "Option::is_none")]
+    pub registered_at: Option<u64>,
+    #[serde(rename = "reservedUntil", skip_serializing_if = "Option::is_none")]
+    pub reserved_until: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(rename = "ownerId", skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<String>,
+    pub version: u64
+}
+
+pub struct ListPage {
+    pub serials: Vec<ListedSerial>,
+    /// Where the next page starts, `None` after the last one.
+    pub last_evaluated_key: Option<HashMap<String, AttributeValue>>
+}
+
+/// Read behind a listing page: a query when the key layout allows one, a scan otherwise.
+enum ListRead {
+    Query(QueryInput),
+    Scan(ScanInput)
+}
+
+/// Describes how serial numbers are laid out in the table.
+#[derive(Clone, Debug)]
+pub struct DynamoDbSettings {
+    pub table_name: String,
+    /// Partition key attribute. Holds the serial number unless `sort_key` is set.
+    pub partition_key: String,
+    /// Sort key attribute holding the serial number for composite keys; the partition key then holds `partition_value`.
+    pub sort_key: Option<String>,
+    /// Partition key value used with composite keys, e.g. the tenant id.
+    pub partition_value: Option<String>,
+    /// Global secondary index queried instead of reading the item by its key.
+    pub index_name: Option<String>,
+    /// Partition key attribute of `index_name`, holding the normalized serial number.
+    pub index_key: String,
+    /// Prepended to serial numbers in keys, isolating tenants that share a table.
+    pub key_prefix: String,
+    /// Lookups of a multi-serial check in flight at once, bounding the read capacity it consumes.
+    pub lookup_concurrency: usize,
+    /// Global secondary index on `similarity_key`, queried for near-miss suggestions.
+    pub similarity_index_name: Option<String>,
+    /// Partition key attribute of `similarity_index_name`, holding the first characters of the normalized serial.
+    pub similarity_key: String,
+    /// Characters of the normalized serial stored in `similarity_key`.
+    pub similarity_prefix_length: usize,
+    /// Table receiving the ownership records of `register_owned`, keyed like `table_name`.
+    pub owners_table_name: String,
+    /// Whether tombstoned items, those carrying `deleted_at`, still hold their serial.
+    pub deleted_policy: DeletedPolicy,
+    /// Global secondary index on `registered_day_key` with `registered_at` as sort key, queried by reports.
+    pub registered_index_name: Option<String>,
+    /// Partition key attribute of `registered_index_name`, holding the key prefix and UTC date of the registration.
+    pub registered_day_key: String,
+    /// Capacity units per second the batch reads and writes of bulk work may consume.
+    pub capacity_budget: CapacityBudget,
+    /// Role assumed for the table when it lives in another account; the function's own role otherwise.
+    pub assume_role: Option<AssumeRoleSettings>
+}
+
+/// What a tombstone means for the serial it holds. Items are tombstoned with a `deleted_at` unix
+/// time instead of being removed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DeletedPolicy {
+    /// Tombstoned serials stay taken, as if they were never deleted.
+    #[default]
+    TreatDeletedAsTaken,
+    /// Tombstoned serials can be registered again straight away.
+    TreatDeletedAsAvailable,
+    /// Tombstoned serials can be registered again the given number of days after their deletion.
+    BlockedForDays(u64)
+}
+
+impl DeletedPolicy {
+    /// Parses `treat_deleted_as_taken`, `treat_deleted_as_available` or `blocked_for_days`, the
+    /// last blocking serials for `blocked_days`.
+    pub fn parse(name: &str, blocked_days: u64) -> Option<DeletedPolicy> {
+        match name {
+            "treat_deleted_as_taken" => Some(DeletedPolicy::TreatDeletedAsTaken),
+            "treat_deleted_as_available" => Some(DeletedPolicy::TreatDeletedAsAvailable),
+            "blocked_for_days" => Some(DeletedPolicy::BlockedForDays(blocked_days)),
+            _ => None,
+        }
+    }
+
+    /// Latest deletion time at which a tombstone no longer holds its serial at `now`; `None`
+    /// when tombstones always hold it.
+    fn released_before(&self, now: u64) -> Option<u64> {
+        match *self {
+            DeletedPolicy::TreatDeletedAsTaken => None,
+            DeletedPolicy::TreatDeletedAsAvailable => Some(now),
+            DeletedPolicy::BlockedForDays(days) => Some(now.saturating_sub(days * SECONDS_PER_DAY)),
+        }
+    }
+}
+
+impl Default for DynamoDbSettings {
+    fn default() -> DynamoDbSettings {
+        DynamoDbSettings {
+            table_name: String::from("assets"),
+            partition_key: String::from("serial_number"),
+            sort_key: None,
+            partition_value: None,
+            index_name: None,
+            index_key: String::from("serial_normalized"),
+            key_prefix: String::new(),
+            lookup_concurrency: 4,
+            similarity_index_name: None,
+            similarity_key: String::from("serial_prefix"),
+            similarity_prefix_length: 4,
+            owners_table_name: String::from("asset_owners"),
+            deleted_policy: DeletedPolicy::default(),
+            registered_index_name: None,
+            registered_day_key: String::from("registered_day"),
+            capacity_budget: CapacityBudget::default(),
+            assume_role: None
+        }
+    }
+}
+
+impl DynamoDbSettings {
+    /// Attribute holding the serial number.
+    fn serial_attribute(&self) -> &str {
+        self.sort_key.as_deref().unwrap_or(&self.partition_key)
+    }
+
+    /// Value of `similarity_key` for a serial: the key prefix and the first characters of its normalized form.
+    fn similarity_prefix(&self, serial_number: &str) -> String {
+        let prefix: String = normalize_serial(serial_number).chars().take(self.similarity_prefix_length).collect();
+        format!("{}{}", self.key_prefix, prefix)
+    }
+
+    /// Value of `registered_day_key` for a registration at `unix_seconds`.
+    fn registered_day(&self, unix_seconds: u64) -> String {
+        format!("{}{}", self.key_prefix, utc_date(unix_seconds))
+    }
+
+    /// Bloom filter key of a serial: what `contains` matches on, i.e. the normalized form when
+    /// an index is configured and the key value otherwise.
+    pub fn filter_key(&self, serial_number: &str) -> String {
+        match self.index_name {
+            Some(_) => format!("{}{}", self.key_prefix, normalize_serial(serial_number)),
+            None => format!("{}{}", self.key_prefix, serial_number),
+        }
+    }
+
+    /// Bloom filter keys of a stored item, covering lookups by key and through the index.
+    pub fn filter_keys(&self, item: &HashMap<String, AttributeValue>) -> Vec<String> {
+        [self.serial_attribute(), self.index_key.as_str()].iter()
+            .filter_map(|attribute| item.get(*attribute).and_then(|value| value.s.clone()))
+            .collect()
+    }
+}
+
+/// Canonical form of a serial number as stored in the normalized index.
+pub fn normalize_serial(serial_number: &str) -> String {
+    serial_number.trim().to_uppercase()
+}
+
+/// `YYYY-MM-DD` date of a unix time in UTC.
+pub fn utc_date(unix_seconds: u64) -> String {
+    // days to civil date, after Howard Hinnant's `civil_from_days`
+    let days = unix_seconds / SECONDS_PER_DAY + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+pub const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+pub fn string_value(value: &str) -> AttributeValue {
+    AttributeValue {
+        s: Some(value.to_string()),
+        ..Default::default()
+    }
+}
+
+pub fn number_value(value: u64) -> AttributeValue {
+    AttributeValue {
+        n: Some(value.to_string()),
+        ..Default::default()
+    }
+}
+
+// attribute names of `Asset` fields used in expressions
+const RESERVED_UNTIL: &str = "reserved_until";
+const DELETED_AT: &str = "deleted_at";
+const DELETED_REASON: &str = "deleted_reason";
+const STATUS: &str = "status";
+const OWNER_ID: &str = "owner_id";
+const REGISTERED_AT: &str = "registered_at";
+const VERSION: &str = "version";
+
+/// Message of an `AccessDeniedException`, which this rusoto release only knows as an unknown error.
+fn access_denied(response: &BufferedHttpResponse) -> Option<String> {
+    let body: serde_json::Value = serde_json::from_slice(&response.body).ok()?;
+    match aws::json_error(response.status.as_u16(), &body) {
+        AwsError::Service { ref code, message, .. } if code == "AccessDeniedException" => Some(message),
+        _ => None,
+    }
+}
+
+/// Errors of rusoto's DynamoDB operations, which report requests abandoned after their timeout
+/// as dispatch errors like any other.
+pub trait DispatchError {
+    fn is_dispatch(&self) -> bool;
+}
+
+macro_rules! store_error_from {
+    ($error:ident) => {
+        impl DispatchError for $error {
+            fn is_dispatch(&self) -> bool {
+                matches!(*self, $error::HttpDispatch(_))
+            }
+        }
+
+        impl From<$error> for StoreError {
+            fn from(error: $error) -> StoreError {
+                match error {
+                    $error::ProvisionedThroughputExceeded(message) => StoreError::Throttled(message),
+                    $error::ResourceNotFound(message) => StoreError::Misconfigured(message),
+                    $error::Validation(message) => StoreError::Misconfigured(message),
+                    $error::Credentials(error) => StoreError::Misconfigured(error.to_string()),
+                    $error::Unknown(ref response) if access_denied(response).is_some() => StoreError::AccessDenied(access_denied(response).unwrap_or_default()),
+                    error => StoreError::Unavailable(format!("{:?}", error)),
+                }
+            }
+        }
+    };
+}
+
+store_error_from!(GetItemError);
+store_error_from!(QueryError);
+store_error_from!(PutItemError);
+store_error_from!(UpdateItemError);
+store_error_from!(ScanError);
+store_error_from!(BatchGetItemError);
+store_error_from!(BatchWriteItemError);
+
+impl DispatchError for DescribeTableError {
+    fn is_dispatch(&self) -> bool {
+        matches!(*self, DescribeTableError::HttpDispatch(_))
+    }
+}
+
+impl From<DescribeTableError> for StoreError {
+    fn from(error: DescribeTableError) -> StoreError {
+        match error {
+            DescribeTableError::ResourceNotFound(message) => StoreError::Misconfigured(message),
+            DescribeTableError::Validation(message) => StoreError::Misconfigured(message),
+            DescribeTableError::Credentials(error) => StoreError::Misconfigured(error.to_string()),
+            DescribeTableError::Unknown(ref response) if access_denied(response).is_some() => StoreError::AccessDenied(access_denied(response).unwrap_or_default()),
+            error => StoreError::Unavailable(format!("{:?}", error)),
+        }
+    }
+}
+
+/// Store error for a call made without rusoto, by its DynamoDB error code.
+impl From<AwsError> for StoreError {
+    fn from(error: AwsError) -> StoreError {
+        match error {
+            AwsError::Timeout => StoreError::Timeout,
+            AwsError::Service { ref code, ref message, .. } => match code.as_str() {
+                "ProvisionedThroughputExceededException" | "ThrottlingException" | "RequestLimitExceeded" => StoreError::Throttled(message.clone()),
+                "ResourceNotFoundException" | "ValidationException" => StoreError::Misconfigured(format!("{}: {}", code, message)),
+                "AccessDeniedException" => StoreError::AccessDenied(message.clone()),
+                _ => StoreError::Unavailable(error.to_string()),
+            },
+            AwsError::Credentials(message) => StoreError::Misconfigured(message),
+            error => StoreError::Unavailable(error.to_string()),
+        }
+    }
+}
+
+/// `TransactWriteItems`, called by hand since this rusoto release predates transactions.
+const TRANSACT_WRITE_ITEMS: &str = "DynamoDB_20120810.TransactWriteItems";
+
+/// Position of the first transaction item whose condition cancelled the transaction, with the
+/// item as returned with `ReturnValuesOnConditionCheckFailure: ALL_OLD`. `None` when it was
+/// cancelled for another reason.
+fn condition_check_failure(body: &serde_json::Value) -> Option<(usize, HashMap<String, AttributeValue>)> {
+    let (position, reason) = body.get("CancellationReasons")?.as_array()?.iter().enumerate()
+        .find(|(_, reason)| reason.get("Code").and_then(|code| code.as_str()) == Some("ConditionalCheckFailed"))?;
+    Some((position, reason.get("Item").and_then(|item| serde_json::from_value(item.clone()).ok()).unwrap_or_default()))
+}
+
+/// Most keys a single BatchGetItem request may carry.
+const BATCH_GET_LIMIT: usize = 100;
+
+/// Most items read from the similarity index for one suggestion lookup.
+const SIMILARITY_READ_LIMIT: i64 = 200;
+
+/// Rounds of re-requesting the keys DynamoDB left unprocessed before giving up as throttled.
+const BATCH_GET_ATTEMPTS: u32 = 5;
+
+/// Most puts a single BatchWriteItem request may carry.
+const BATCH_WRITE_LIMIT: usize = 25;
+
+/// Rounds of re-sending the puts DynamoDB left unprocessed before reporting them as not written.
+const BATCH_WRITE_ATTEMPTS: u32 = 5;
+
+lazy_static! {
+    /// Drives concurrent lookups; hyper needs an executor for the connections it opens.
+    static ref LOOKUP_RUNTIME: Runtime = Runtime::new().expect("failed to start the lookup runtime");
+    // survive between invocations served by the same container, keeping their connections open
+    static ref CLIENTS: Mutex<HashMap<String, Arc<DynamoDbClient>>> = Mutex::new(HashMap::new());
+    static ref ROLE_CLIENTS: Mutex<HashMap<String, RoleClients>> = Mutex::new(HashMap::new());
+    /// Tables already logged as read without credentials, so that a store built for every request
+    /// does not log it again.
+    static ref UNCREDENTIALED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// The container's client for `region`, created on first use. Custom regions are told apart by
+/// their endpoint.
+pub fn client_in(region: &Region) -> Arc<DynamoDbClient> {
+    let mut clients = CLIENTS.lock().unwrap();
+    clients.entry(format!("{:?}", region)).or_insert_with(|| Arc::new(DynamoDbClient::new(region.clone()))).clone()
+}
+
+/// A DynamoDB client, and the one signing the calls made without it.
+type RoleClients = (Arc<DynamoDbClient>, Arc<Client>);
+
+/// The container's clients for `region` signing with the credentials of `role`.
+fn role_clients_in(region: &Region, role: &AssumeRoleSettings) -> RoleClients {
+    let mut clients = ROLE_CLIENTS.lock().unwrap();
+    clients.entry(format!("{:?} {}", region, role.role_arn)).or_insert_with(|| {
+        let http_client = || HttpClient::new().expect("failed to create the HTTP client");
+        let dynamodb = DynamoDbClient::new_with(http_client(), AssumedRoleProvider::new(role.clone()), region.clone());
+        (Arc::new(dynamodb), Arc::new(Client::new_with(AssumedRoleProvider::new(role.clone()), http_client())))
+    }).clone()
+}
+
+/// Starts the runtime concurrent lookups are driven by.
+pub fn start_lookup_runtime() {
+    lazy_static::initialize(&LOOKUP_RUNTIME);
+}
+
+type Lookup<T> = Box<dyn Future<Item = T, Error = StoreError> + Send>;
+
+/// A DynamoDB request that failed, or was abandoned once its timeout elapsed.
+enum Failure<E> {
+    TimedOut,
+    Failed(E)
+}
+
+impl<E: Into<StoreError>> From<Failure<E>> for StoreError {
+    fn from(failure: Failure<E>) -> StoreError {
+        match failure {
+            Failure::TimedOut => StoreError::Timeout,
+            Failure::Failed(error) => error.into(),
+        }
+    }
+}
+
+/// Tells the dispatch error of a request abandoned after `timeout` apart from other failures by
+/// the time the request took.
+fn failure<E: DispatchError>(error: E, started: Instant, timeout: Option<Duration>) -> Failure<E> {
+    if error.is_dispatch() && aws::elapsed(started, timeout) { Failure::TimedOut } else { Failure::Failed(error) }
+}
+
+/// Starts a DynamoDB request without waiting for it, abandoning it after `timeout`.
+fn dispatch<T, E>(mut request: RusotoFuture<T, E>, timeout: Option<Duration>) -> Lookup<T>
+    where T: Send + 'static,
+          E: From<CredentialsError> + From<HttpDispatchError> + DispatchError + Into<StoreError> + Send + 'static
+{
+    if let Some(timeout) = timeout {
+        request.set_timeout(timeout);
+    }
+    // requests waiting for a slot are sent, and start their timeout, when first polled
+    Box::new(future::lazy(move || {
+        let started = Instant::now();
+        request.map_err(move |error| failure(error, started, timeout).into())
+    }))
+}
+
+/// Runs `lookups` with at most `concurrency` in flight, returning their results in any order.
+fn run_bounded<T: Send + 'static>(lookups: Vec<Lookup<T>>, concurrency: usize) -> Result<Vec<T>, StoreError> {
+    let lookups = stream::iter_ok(lookups).buffer_unordered(concurrency.max(1)).collect();
+    oneshot::spawn(lookups, &LOOKUP_RUNTIME.executor()).wait()
+}
+
+/// Waits out `backoff` and whatever `throttle` asks of the next batch of `kind`, as of when the
+/// batch is about to be sent rather than when it was queued.
+fn pace(throttle: Option<Arc<CapacityThrottle>>, kind: CapacityKind, backoff: Duration) -> Lookup<()> {
+    Box::new(future::lazy(move || {
+        let now = Instant::now();
+        let wait = throttle.map(|throttle| throttle.delay(kind, now)).unwrap_or_default().max(backoff);
+        if wait == Duration::from_secs(0) {
+            return Box::new(future::ok(())) as Lookup<()>;
+        }
+        Box::new(Delay::new(now + wait).map_err(|error| StoreError::Unavailable(error.to_string())))
+    }))
+}
+
+/// Counts the capacity a batch consumed against `throttle`.
+fn record_consumed(throttle: Option<&Arc<CapacityThrottle>>, kind: CapacityKind, consumed: &[ConsumedCapacity]) {
+    if let Some(throttle) = throttle {
+        let units = consumed.iter().filter_map(|consumed| consumed.capacity_units).sum();
+        throttle.record(kind, units, Instant::now());
+    }
+}
+
+/// Runs a DynamoDB request to completion, abandoning it after `timeout`, with the error rusoto
+/// reported for callers that handle some of them.
+fn sync<T, E>(mut request: RusotoFuture<T, E>, timeout: Option<Duration>) -> Result<T, Failure<E>>
+    where T: Send + 'static,
+          E: From<CredentialsError> + From<HttpDispatchError> + DispatchError + Send + 'static
+{
+    if let Some(timeout) = timeout {
+        request.set_timeout(timeout);
+    }
+    let started = Instant::now();
+    request.sync().map_err(|error| failure(error, started, timeout))
+}
+
+/// Runs a DynamoDB request to completion, abandoning it after `timeout`.
+pub fn send<T, E>(request: RusotoFuture<T, E>, timeout: Option<Duration>) -> Result<T, StoreError>
+    where T: Send + 'static,
+          E: From<CredentialsError> + From<HttpDispatchError> + DispatchError + Into<StoreError> + Send + 'static
+{
+    sync(request, timeout).map_err(Into::into)
+}
+
+/// Region of the table unless `REPLICA_REGIONS` routes reads elsewhere.
+pub const DEFAULT_REGION: Region = Region::EuCentral1;
+
+pub struct DynamoDbStore {
+    /// Shared with the batch reads still in flight.
+    client: Arc<DynamoDbClient>,
+    /// For the calls made without `client`.
+    region: Region,
+    /// Signs the calls made without `client`, with the same credentials.
+    signer: Arc<Client>,
+    /// Paces the batch paths when the settings give them a capacity budget.
+    throttle: Option<Arc<CapacityThrottle>>,
+    settings: DynamoDbSettings
+}
+
+impl DynamoDbStore {
+    pub fn new(settings: DynamoDbSettings) -> DynamoDbStore {
+        DynamoDbStore::in_region(settings, DEFAULT_REGION)
+    }
+
+    /// A store of the table in `region`. With `assume_role` set, the role's credentials are
+    /// refreshed here when they are about to expire, rather than while signing a request.
+    pub fn in_region(settings: DynamoDbSettings, region: Region) -> DynamoDbStore {
+        let (client, signer) = match settings.assume_role {
+            Some(ref role) => {
+                if let Err(error) = assumed_role::role_credentials(role) {
+                    if UNCREDENTIALED.lock().unwrap().insert(format!("{} {}", settings.table_name, region.name())) {
+                        log_shared(&format!("table {} in {} is read without credentials: {}", settings.table_name, region.name(), error));
+                    }
+                }
+                role_clients_in(&region, role)
+            },
+            None => (client_in(&region), Arc::new(Client::shared())),
+        };
+        DynamoDbStore {
+            client,
+            region,
+            signer,
+            throttle: capacity::throttle_for(&settings.table_name, settings.capacity_budget),
+            settings
+        }
+    }
+
+    /// Runs `call`, naming the table and region in failures that point at the deployment rather
+    /// than at DynamoDB, which DynamoDB's messages leave out. The handler logs them with the request.
+    fn diagnosed<T, F>(&self, call: F) -> Result<T, StoreError>
+        where F: FnOnce() -> Result<T, StoreError>
+    {
+        let located = |message: String| format!("table {} in {}: {}", self.settings.table_name, self.region.name(), message);
+        match call() {
+            Err(StoreError::Misconfigured(message)) => Err(StoreError::Misconfigured(located(message))),
+            Err(StoreError::AccessDenied(message)) => Err(StoreError::AccessDenied(located(message))),
+            result => result,
+        }
+    }
+
+    fn item_key(&self, serial_number: &str) -> HashMap<String, AttributeValue> {
+        let serial_number = &format!("{}{}", self.settings.key_prefix, serial_number);
+        let mut key: HashMap<String, AttributeValue> = HashMap::new();
+        match self.settings.sort_key {
+            Some(ref sort_key) => {
+                let partition_value = self.settings.partition_value.as_deref().unwrap_or_default();
+                key.insert(self.settings.partition_key.clone(), string_value(partition_value));
+                key.insert(sort_key.clone(), string_value(serial_number));
+            },
+            None => {
+                key.insert(self.settings.partition_key.clone(), string_value(serial_number));
+            }
+        }
+        key
+    }
+
+    /// Item holding the serial with the attributes of `asset`, including its normalized form and
+    /// the other index attributes configured.
+    fn new_item(&self, serial_number: &str, asset: &Asset) -> HashMap<String, AttributeValue> {
+        let mut item = to_item(asset);
+        item.extend(self.item_key(serial_number));
+        if self.settings.index_name.is_some() {
+            let normalized = format!("{}{}", self.settings.key_prefix, normalize_serial(serial_number));
+            item.insert(self.settings.index_key.clone(), string_value(&normalized));
+        }
+        if self.settings.similarity_index_name.is_some() {
+            item.insert(self.settings.similarity_key.clone(), string_value(&self.settings.similarity_prefix(serial_number)));
+        }
+        if let (Some(_), Some(registered_at)) = (self.settings.registered_index_name.as_ref(), asset.registered_at) {
+            item.insert(self.settings.registered_day_key.clone(), string_value(&self.settings.registered_day(registered_at)));
+        }
+        item
+    }
+
+    /// Tells a replay from a collision once the conditional put found the serial registered.
+    fn existing_registration(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, now: u64, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        if idempotency_key.is_none() {
+            return Ok(Registration::AlreadyRegistered);
+        }
+        let read_serial = GetItemInput {
+            key: self.item_key(serial_number),
+            table_name: self.settings.table_name.clone(),
+            consistent_read: Some(true),
+            ..Default::default()
+        };
+        let asset = send(self.client.get_item(read_serial), timeout)?.item.map(|item| Asset::read(&item)).unwrap_or_default();
+        Ok(registration_of_existing(asset.idempotency_key(), idempotency_key, now))
+    }
+
+    /// Projection of existence checks: the serial and the attributes `is_live` reads. It keeps the
+    /// rest of large items off the wire; DynamoDB still charges reads on the whole item.
+    fn existence_projection(&self) -> (String, HashMap<String, String>) {
+        let mut names = HashMap::new();
+        names.insert(String::from("#serial"), self.settings.serial_attribute().to_string());
+        names.insert(String::from("#reserved_until"), String::from(RESERVED_UNTIL));
+        names.insert(String::from("#deleted_at"), String::from(DELETED_AT));
+        (String::from("#serial, #reserved_until, #deleted_at"), names)
+    }
+
+    /// The attributes of the item holding `serial_number` that `existence_projection` and
+    /// `attributes` name, unless the item no longer holds it.
+    fn live_asset(&self, serial_number: &str, attributes: &[&str], timeout: Option<Duration>) -> Result<Option<Asset>, StoreError> {
+        let (mut projection, mut names) = self.existence_projection();
+        for attribute in attributes {
+            projection.push_str(&format!(", #{}", attribute));
+            names.insert(format!("#{}", attribute), attribute.to_string());
+        }
+        let read_item = GetItemInput {
+            key: self.item_key(serial_number),
+            table_name: self.settings.table_name.clone(),
+            projection_expression: Some(projection),
+            expression_attribute_names: Some(names),
+            ..Default::default()
+        };
+        let now = unix_now();
+        let released_before = self.settings.deleted_policy.released_before(now);
+        let item = send(self.client.get_item(read_item), timeout)?.item;
+        Ok(item.map(|item| Asset::read(&item)).filter(|asset| asset.is_live(now, released_before)))
+    }
+
+    /// The live item holding `serial_number` with all its attributes, read consistently.
+    pub fn lookup(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Option<ListedSerial>, StoreError> {
+        let read_item = GetItemInput {
+            key: self.item_key(serial_number),
+            table_name: self.settings.table_name.clone(),
+            consistent_read: Some(true),
+            ..Default::default()
+        };
+        let now = unix_now();
+        let released_before = self.settings.deleted_policy.released_before(now);
+        Ok(send(self.client.get_item(read_item), timeout)?.item
+            .filter(|item| Asset::read(item).is_live(now, released_before))
+            .and_then(|item| self.listed_serial(&item)))
+    }
+
+    /// Stored serials (key prefix included) of the live items among `serial_numbers`, which must
+    /// be distinct and fit a single request. Keys DynamoDB leaves unprocessed are requested again.
+    fn batch_get(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Lookup<HashSet<String>> {
+        let client = self.client.clone();
+        let table_name = self.settings.table_name.clone();
+        let serial_attribute = self.settings.serial_attribute().to_string();
+        let keys: Vec<HashMap<String, AttributeValue>> = serial_numbers.iter().map(|serial_number| self.item_key(serial_number)).collect();
+        let now = unix_now();
+        let released_before = self.settings.deleted_policy.released_before(now);
+        let (projection, names) = self.existence_projection();
+        let throttle = self.throttle.clone();
+        Box::new(future::loop_fn((keys, HashSet::new(), 0), move |(keys, mut found, attempt): (_, HashSet<String>, u32)| {
+            let backoff = if attempt > 0 { Duration::from_millis(50 << attempt) } else { Duration::from_secs(0) };
+            let (client, table_name, serial_attribute, throttle) = (client.clone(), table_name.clone(), serial_attribute.clone(), throttle.clone());
+            let (projection, names) = (projection.clone(), names.clone());
+            pace(throttle.clone(), CapacityKind::Read, backoff).and_then(move |_| {
+                let mut request_items = HashMap::new();
+                let read = KeysAndAttributes { keys, projection_expression: Some(projection), expression_attribute_names: Some(names), ..Default::default() };
+                request_items.insert(table_name.clone(), read);
+                let input = BatchGetItemInput { request_items, return_consumed_capacity: Some(String::from("TOTAL")) };
+                dispatch(client.batch_get_item(input), timeout)
+                    .and_then(move |output| {
+                        record_consumed(throttle.as_ref(), CapacityKind::Read, output.consumed_capacity.as_deref().unwrap_or_default());
+                        let items = output.responses.and_then(|mut responses| responses.remove(&table_name)).unwrap_or_default();
+                        found.extend(items.into_iter()
+                            .filter(|item| Asset::read(item).is_live(now, released_before))
+                            .filter_map(|item| item.get(&serial_attribute).and_then(|value| value.s.clone())));
+                        let keys = output.unprocessed_keys
+                            .and_then(|mut unprocessed| unprocessed.remove(&table_name))
+                            .map(|unprocessed| unprocessed.keys)
+                            .unwrap_or_default();
+                        if keys.is_empty() {
+                            Ok(Loop::Break(found))
+                        } else if attempt + 1 >= BATCH_GET_ATTEMPTS {
+                            Err(StoreError::Throttled(format!("{} keys left unprocessed by BatchGetItem", keys.len())))
+                        } else {
+                            Ok(Loop::Continue((keys, found, attempt + 1)))
+                        }
+                    })
+            })
+        }))
+    }
+
+    /// Puts a registration of each serial with `BatchWriteItem`, sending the puts left unprocessed
+    /// or throttled again with backoff. Resolves to the stored serials that were not written.
+    fn batch_put(&self, serial_numbers: &[&str], now: u64, timeout: Option<Duration>) -> Lookup<HashSet<String>> {
+        let client = self.client.clone();
+        let table_name = self.settings.table_name.clone();
+        let serial_attribute = self.settings.serial_attribute().to_string();
+        let writes: Vec<WriteRequest> = serial_numbers.iter()
+            .map(|serial_number| WriteRequest { put_request: Some(PutRequest { item: self.new_item(serial_number, &Asset::registration(now, None)) }), ..Default::default() })
+            .collect();
+        let throttle = self.throttle.clone();
+        Box::new(future::loop_fn((writes, 0), move |(writes, attempt): (Vec<WriteRequest>, u32)| {
+            let backoff = if attempt > 0 { Duration::from_millis(50 << attempt) } else { Duration::from_secs(0) };
+            let (client, table_name, serial_attribute, throttle) = (client.clone(), table_name.clone(), serial_attribute.clone(), throttle.clone());
+            pace(throttle.clone(), CapacityKind::Write, backoff).and_then(move |_| {
+                let mut request_items = HashMap::new();
+                request_items.insert(table_name.clone(), writes.clone());
+                let input = BatchWriteItemInput { request_items, return_consumed_capacity: Some(String::from("TOTAL")), ..Default::default() };
+                dispatch(client.batch_write_item(input), timeout)
+                    .then(move |result| {
+                        let unprocessed = match result {
+                            Ok(output) => {
+                                record_consumed(throttle.as_ref(), CapacityKind::Write, output.consumed_capacity.as_deref().unwrap_or_default());
+                                output.unprocessed_items.and_then(|mut unprocessed| unprocessed.remove(&table_name)).unwrap_or_default()
+                            },
+                            Err(StoreError::Throttled(_)) | Err(StoreError::Timeout) | Err(StoreError::Unavailable(_)) => writes,
+                            Err(error) => return Err(error),
+                        };
+                        if unprocessed.is_empty() {
+                            Ok(Loop::Break(HashSet::new()))
+                        } else if attempt + 1 >= BATCH_WRITE_ATTEMPTS {
+                            Ok(Loop::Break(unprocessed.iter()
+                                .filter_map(|write| write.put_request.as_ref()?.item.get(&serial_attribute)?.s.clone())
+                                .collect()))
+                        } else {
+                            Ok(Loop::Continue((unprocessed, attempt + 1)))
+                        }
+                    })
+            })
+        }))
+    }
+
+    /// Status of the table as reported by `DescribeTable`, e.g. `ACTIVE`. Fails when the table is
+    /// missing or the function may not describe it.
+    pub fn table_status(&self, timeout: Option<Duration>) -> Result<String, StoreError> {
+        let describe = DescribeTableInput { table_name: self.settings.table_name.clone() };
+        let output = send(self.client.describe_table(describe), timeout)?;
+        Ok(output.table.and_then(|table| table.table_status).unwrap_or_default())
+    }
+
+    /// Bloom filter keys of every item in the table, read page by page.
+    pub fn scan_filter_keys(&self, timeout: Option<Duration>) -> Result<Vec<String>, StoreError> {
+        let mut names = HashMap::new();
+        names.insert(String::from("#serial"), self.settings.serial_attribute().to_string());
+        names.insert(String::from("#normalized"), self.settings.index_key.clone());
+        let mut keys = Vec::new();
+        let mut exclusive_start_key = None;
+        loop {
+            let scan = ScanInput {
+                table_name: self.settings.table_name.clone(),
+                projection_expression: Some(String::from("#serial, #normalized")),
+                expression_attribute_names: Some(names.clone()),
+                exclusive_start_key,
+                ..Default::default()
+            };
+            let page = send(self.client.scan(scan), timeout)?;
+            for item in page.items.unwrap_or_default() {
+                keys.extend(self.settings.filter_keys(&item));
+            }
+            match page.last_evaluated_key {
+                Some(last_evaluated_key) => exclusive_start_key = Some(last_evaluated_key),
+                None => return Ok(keys),
+            }
+        }
+    }
+
+    /// Live serials registered from `from` to `to`, both inclusive, counted per UTC date on the
+    /// registration index with one query per date, `lookup_concurrency` at a time.
+    pub fn count_registered(&self, from: u64, to: u64, timeout: Option<Duration>) -> Result<Vec<(String, u64)>, StoreError> {
+        let index_name = self.settings.registered_index_name.as_ref()
+            .ok_or_else(|| StoreError::Misconfigured(String::from("reports need REGISTERED_INDEX_NAME")))?;
+        let now = unix_now();
+        let counts = (from / SECONDS_PER_DAY..=to / SECONDS_PER_DAY).enumerate()
+            .map(|(position, day)| {
+                let day_start = day * SECONDS_PER_DAY;
+                let query = self.registered_query(index_name, from.max(day_start), to.min(day_start + SECONDS_PER_DAY - 1), now);
+                let count = self.count_pages(query, timeout);
+                Box::new(count.map(move |count| (position, utc_date(day_start), count))) as Lookup<_>
+            })
+            .collect();
+        let mut counts = run_bounded(counts, self.settings.lookup_concurrency)?;
+        counts.sort_by_key(|&(position, _, _)| position);
+        Ok(counts.into_iter().map(|(_, date, count)| (date, count)).collect())
+    }
+
+    /// Sums the `Count` of every page of `query`.
+    fn count_pages(&self, query: QueryInput, timeout: Option<Duration>) -> Lookup<u64> {
+        let client = self.client.clone();
+        Box::new(future::loop_fn((query, 0), move |(query, total): (QueryInput, u64)| {
+            dispatch(client.query(query.clone()), timeout).map(move |output| {
+                let total = total + output.count.unwrap_or(0) as u64;
+                match output.last_evaluated_key {
+                    Some(last_evaluated_key) => Loop::Continue((QueryInput { exclusive_start_key: Some(last_evaluated_key), ..query }, total)),
+                    None => Loop::Break(total),
+                }
+            })
+        }))
+    }
+
+    /// Count of the live serials registered from `from` to `to` on the date of `from`.
+    fn registered_query(&self, index_name: &str, from: u64, to: u64, now: u64) -> QueryInput {
+        let mut names = HashMap::new();
+        names.insert(String::from("#day"), self.settings.registered_day_key.clone());
+        names.insert(String::from("#registered_at"), String::from(REGISTERED_AT));
+        let mut values = HashMap::new();
+        values.insert(String::from(":day"), string_value(&self.settings.registered_day(from)));
+        values.insert(String::from(":from"), number_value(from));
+        values.insert(String::from(":to"), number_value(to));
+
+        let mut filter_expression = self.live_expression(&mut names, &mut values, now);
+        if let (Some(_), Some(partition_value)) = (self.settings.sort_key.as_ref(), self.settings.partition_value.as_ref()) {
+            names.insert(String::from("#partition"), self.settings.partition_key.clone());
+            values.insert(String::from(":partition"), string_value(partition_value));
+            filter_expression.push_str(" AND #partition = :partition");
+        }
+
+        QueryInput {
+            table_name: self.settings.table_name.clone(),
+            index_name: Some(index_name.to_string()),
+            key_condition_expression: Some(String::from("#day = :day AND #registered_at BETWEEN :from AND :to")),
+            filter_expression: Some(filter_expression),
+            expression_attribute_names: Some(names),
+            expression_attribute_values: Some(values),
+            select: Some(String::from("COUNT")),
+            ..Default::default()
+        }
+    }
+
+    /// One page of the live serials matching `query`. Composite keys are queried within the
+    /// partition, a prefix covering the similarity prefix is queried on the similarity index, and
+    /// anything else scans the table.
+    pub fn list(&self, query: &ListQuery, timeout: Option<Duration>) -> Result<ListPage, StoreError> {
+        let (items, last_evaluated_key) = match self.list_read(query, unix_now()) {
+            ListRead::Query(input) => send(self.client.query(input), timeout).map(|output| (output.items, output.last_evaluated_key))?,
+            ListRead::Scan(input) => send(self.client.scan(input), timeout).map(|output| (output.items, output.last_evaluated_key))?,
+        };
+        Ok(ListPage {
+            serials: items.unwrap_or_default().iter().filter_map(|item| self.listed_serial(item)).collect(),
+            last_evaluated_key
+        })
+    }
+
+    fn list_read(&self, query: &ListQuery, now: u64) -> ListRead {
+        let mut names = HashMap::new();
+        let mut values = HashMap::new();
+        let mut filters = vec![self.live_expression(&mut names, &mut values, now)];
+        if let Some(registered_from) = query.registered_from {
+            names.insert(String::from("#registered_at"), String::from(REGISTERED_AT));
+            values.insert(String::from(":registered_from"), number_value(registered_from));
+            filters.push(String::from("#registered_at >= :registered_from"));
+        }
+        if let Some(registered_to) = query.registered_to {
+            names.insert(String::from("#registered_at"), String::from(REGISTERED_AT));
+            values.insert(String::from(":registered_to"), number_value(registered_to));
+            filters.push(String::from("#registered_at <= :registered_to"));
+        }
+        let serial_prefix = format!("{}{}", self.settings.key_prefix, query.prefix);
+        let prefix_condition = if serial_prefix.is_empty() {
+            None
+        } else {
+            names.insert(String::from("#serial"), self.settings.serial_attribute().to_string());
+            values.insert(String::from(":serial_prefix"), string_value(&serial_prefix));
+            Some("begins_with(#serial, :serial_prefix)")
+        };
+
+        if let (Some(_), Some(partition_value)) = (self.settings.sort_key.as_ref(), self.settings.partition_value.as_ref()) {
+            names.insert(String::from("#partition"), self.settings.partition_key.clone());
+            values.insert(String::from(":partition"), string_value(partition_value));
+            let key_condition = match prefix_condition {
+                Some(prefix_condition) => format!("#partition = :partition AND {}", prefix_condition),
+                None => String::from("#partition = :partition"),
+            };
+            return ListRead::Query(QueryInput {
+                table_name: self.settings.table_name.clone(),
+                key_condition_expression: Some(key_condition),
+                filter_expression: Some(filters.join(" AND ")),
+                expression_attribute_names: Some(names),
+                expression_attribute_values: Some(values),
+                limit: Some(query.limit),
+                exclusive_start_key: query.start_key.clone(),
+                ..Default::default()
+            });
+        }
+
+        filters.extend(prefix_condition.map(String::from));
+        match self.settings.similarity_index_name {
+            Some(ref index_name) if normalize_serial(&query.prefix).chars().count() >= self.settings.similarity_prefix_length => {
+                names.insert(String::from("#prefix"), self.settings.similarity_key.clone());
+                values.insert(String::from(":prefix"), string_value(&self.settings.similarity_prefix(&query.prefix)));
+                ListRead::Query(QueryInput {
+                    table_name: self.settings.table_name.clone(),
+                    index_name: Some(index_name.clone()),
+                    key_condition_expression: Some(String::from("#prefix = :prefix")),
+                    filter_expression: Some(filters.join(" AND ")),
+                    expression_attribute_names: Some(names),
+                    expression_attribute_values: Some(values),
+                    limit: Some(query.limit),
+                    exclusive_start_key: query.start_key.clone(),
+                    ..Default::default()
+                })
+            },
+            _ => ListRead::Scan(ScanInput {
+                table_name: self.settings.table_name.clone(),
+                filter_expression: Some(filters.join(" AND ")),
+                expression_attribute_names: Some(names),
+                expression_attribute_values: Some(values),
+                limit: Some(query.limit),
+                exclusive_start_key: query.start_key.clone(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn listed_serial(&self, item: &HashMap<String, AttributeValue>) -> Option<ListedSerial> {
+        let stored = item.get(self.settings.serial_attribute())?.s.as_deref()?;
+        let asset = Asset::read(item);
+        Some(ListedSerial {
+            serial_number: stored.strip_prefix(self.settings.key_prefix.as_str()).unwrap_or(stored).to_string(),
+            registered_at: asset.registered_at,
+            reserved_until: asset.reserved_until,
+            status: asset.status,
+            owner_id: asset.owner_id,
+            version: asset.version
+        })
+    }
+
+    /// Filter or condition expression matching items that still hold their serial, see `is_live`.
+    fn live_expression(&self, names: &mut HashMap<String, String>, values: &mut HashMap<String, AttributeValue>, now: u64) -> String {
+        names.insert(String::from("#reserved_until"), String::from(RESERVED_UNTIL));
+        values.insert(String::from(":now"), number_value(now));
+        let mut expression = String::from("(attribute_not_exists(#reserved_until) OR #reserved_until > :now)");
+        if let Some(released_before) = self.settings.deleted_policy.released_before(now) {
+            names.insert(String::from("#deleted_at"), String::from(DELETED_AT));
+            values.insert(String::from(":released_before"), number_value(released_before));
+            expression.push_str(" AND (attribute_not_exists(#deleted_at) OR #deleted_at > :released_before)");
+        }
+        expression
+    }
+
+    /// Conditional put of `item` that succeeds unless the serial is registered or reserved. It
+    /// replaces tombstones released by the `deleted_policy`.
+    fn put_if_available(&self, item: HashMap<String, AttributeValue>, now: u64) -> PutItemInput {
+        let mut names = HashMap::new();
+        names.insert(String::from("#key"), self.settings.partition_key.clone());
+        let mut values = HashMap::new();
+        let live = self.live_expression(&mut names, &mut values, now);
+        PutItemInput {
+            table_name: self.settings.table_name.clone(),
+            item,
+            condition_expression: Some(format!("attribute_not_exists(#key) OR NOT ({})", live)),
+            expression_attribute_names: Some(names),
+            expression_attribute_values: Some(values),
+            ..Default::default()
+        }
+    }
+
+    /// Tombstone of a live item that was not tombstoned before, recording why it was released.
+    fn release_if_live(&self, serial_number: &str, reason: &str, now: u64) -> UpdateItemInput {
+        let mut names = HashMap::new();
+        names.insert(String::from("#key"), self.settings.partition_key.clone());
+        names.insert(String::from("#deleted_at"), String::from(DELETED_AT));
+        names.insert(String::from("#deleted_reason"), String::from(DELETED_REASON));
+        let mut values = HashMap::new();
+        values.insert(String::from(":reason"), string_value(reason));
+        let live = self.live_expression(&mut names, &mut values, now);
+        UpdateItemInput {
+            table_name: self.settings.table_name.clone(),
+            key: self.item_key(serial_number),
+            update_expression: Some(String::from("SET #deleted_at = :now, #deleted_reason = :reason")),
+            condition_expression: Some(format!("attribute_exists(#key) AND attribute_not_exists(#deleted_at) AND {}", live)),
+            expression_attribute_names: Some(names),
+            expression_attribute_values: Some(values),
+            ..Default::default()
+        }
+    }
+
+    /// Change of the owner of a live item, conditional on it being owned by `from_owner`.
+    fn transfer_if_owned(&self, serial_number: &str, from_owner: &str, to_owner: &str, now: u64) -> UpdateItemInput {
+        let mut names = HashMap::new();
+        names.insert(String::from("#owner_id"), String::from(OWNER_ID));
+        names.insert(String::from("#version"), String::from(VERSION));
+        names.insert(String::from("#updated_at"), String::from("updated_at"));
+        let mut values = HashMap::new();
+        values.insert(String::from(":from_owner"), string_value(from_owner));
+        values.insert(String::from(":to_owner"), string_value(to_owner));
+        values.insert(String::from(":one"), number_value(1));
+        let live = self.live_expression(&mut names, &mut values, now);
+        UpdateItemInput {
+            table_name: self.settings.table_name.clone(),
+            key: self.item_key(serial_number),
+            update_expression: Some(String::from("SET #owner_id = :to_owner, #updated_at = :now ADD #version :one")),
+            condition_expression: Some(format!("#owner_id = :from_owner AND {}", live)),
+            expression_attribute_names: Some(names),
+            expression_attribute_values: Some(values),
+            return_values: Some(String::from("UPDATED_NEW")),
+            ..Default::default()
+        }
+    }
+
+    /// Update of the metadata of a live item, conditional on its version being `expected_version`.
+    fn update_if_version(&self, serial_number: &str, update: &MetadataUpdate, expected_version: u64, now: u64) -> UpdateItemInput {
+        let mut names = HashMap::new();
+        names.insert(String::from("#version"), String::from(VERSION));
+        names.insert(String::from("#updated_at"), String::from("updated_at"));
+        let mut values = HashMap::new();
+        values.insert(String::from(":expected"), number_value(expected_version));
+        values.insert(String::from(":next"), number_value(expected_version + 1));
+        let live = self.live_expression(&mut names, &mut values, now);
+
+        let mut assignments = vec![String::from("#version = :next"), String::from("#updated_at = :now")];
+        for &(attribute, ref value) in &[(STATUS, &update.status), (OWNER_ID, &update.owner_id)] {
+            if let Some(value) = value {
+                names.insert(format!("#{}", attribute), attribute.to_string());
+                values.insert(format!(":{}", attribute), string_value(value));
+                assignments.push(format!("#{0} = :{0}", attribute));
+            }
+        }
+
+        let version_matches = if expected_version == 0 {
+            names.insert(String::from("#key"), self.settings.partition_key.clone());
+            "attribute_exists(#key) AND (attribute_not_exists(#version) OR #version = :expected)"
+        } else {
+            "#version = :expected"
+        };
+        UpdateItemInput {
+            table_name: self.settings.table_name.clone(),
+            key: self.item_key(serial_number),
+            update_expression: Some(format!("SET {}", assignments.join(", "))),
+            condition_expression: Some(format!("{} AND {}", version_matches, live)),
+            expression_attribute_names: Some(names),
+            expression_attribute_values: Some(values),
+            ..Default::default()
+        }
+    }
+
+    /// Live serials sharing the similarity prefix of `serial_number`, within our partition.
+    fn similarity_query(&self, index_name: &str, serial_number: &str, now: u64) -> QueryInput {
+        let mut names = HashMap::new();
+        names.insert(String::from("#prefix"), self.settings.similarity_key.clone());
+        names.insert(String::from("#serial"), self.settings.serial_attribute().to_string());
+        let mut values = HashMap::new();
+        values.insert(String::from(":prefix"), string_value(&self.settings.similarity_prefix(serial_number)));
+
+        let mut filter_expression = self.live_expression(&mut names, &mut values, now);
+        if let (Some(_), Some(partition_value)) = (self.settings.sort_key.as_ref(), self.settings.partition_value.as_ref()) {
+            names.insert(String::from("#partition"), self.settings.partition_key.clone());
+            values.insert(String::from(":partition"), string_value(partition_value));
+            filter_expression.push_str(" AND #partition = :partition");
+        }
+
+        QueryInput {
+            table_name: self.settings.table_name.clone(),
+            index_name: Some(index_name.to_string()),
+            key_condition_expression: Some(String::from("#prefix = :prefix")),
+            filter_expression: Some(filter_expression),
+            projection_expression: Some(String::from("#serial")),
+            expression_attribute_names: Some(names),
+            expression_attribute_values: Some(values),
+            limit: Some(SIMILARITY_READ_LIMIT),
+            ..Default::default()
+        }
+    }
+
+    fn index_query(&self, index_name: &str, serial_number: &str, now: u64) -> QueryInput {
+        let mut names = HashMap::new();
+        names.insert(String::from("#serial"), self.settings.index_key.clone());
+        let mut values = HashMap::new();
+        let serial_number = format!("{}{}", self.settings.key_prefix, normalize_serial(serial_number));
+        values.insert(String::from(":serial"), string_value(&serial_number));
+
+        let mut filter_expression = self.live_expression(&mut names, &mut values, now);
+        // with composite keys the index spans every partition, so keep the lookup within ours
+        if let (Some(_), Some(partition_value)) = (self.settings.sort_key.as_ref(), self.settings.partition_value.as_ref()) {
+            names.insert(String::from("#partition"), self.settings.partition_key.clone());
+            values.insert(String::from(":partition"), string_value(partition_value));
+            filter_expression.push_str(" AND #partition = :partition");
+        }
+
+        QueryInput {
+            table_name: self.settings.table_name.clone(),
+            index_name: Some(index_name.to_string()),
+            key_condition_expression: Some(String::from("#serial = :serial")),
+            filter_expression: Some(filter_expression),
+            expression_attribute_names: Some(names),
+            expression_attribute_values: Some(values),
+            select: Some(String::from("COUNT")),
+            ..Default::default()
+        }
+    }
+}
+
+impl SerialStore for DynamoDbStore {
+    fn contains(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.diagnosed(|| {
+            match self.settings.index_name {
+                Some(ref index_name) => {
+                    let query = self.index_query(index_name, serial_number, unix_now());
+                    send(self.client.query(query), timeout).map(|result| result.count.unwrap_or(0) > 0)
+                },
+                None => self.live_asset(serial_number, &[], timeout).map(|asset| asset.is_some()),
+            }
+        })
+    }
+
+    /// Reads the item with the owner, registration time and status added to the projection of
+    /// `contains`. Tables queried through an index report a conflicting item without them.
+    fn conflicting_item(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Option<ConflictingItem>, StoreError> {
+        if self.settings.index_name.is_some() {
+            return self.contains(serial_number, timeout).map(|found| if found { Some(ConflictingItem::default()) } else { None });
+        }
+        self.diagnosed(|| {
+            let asset = self.live_asset(serial_number, &[OWNER_ID, REGISTERED_AT, STATUS], timeout)?;
+            Ok(asset.map(|asset| ConflictingItem { owner_id: asset.owner_id, registered_at: asset.registered_at, status: asset.status }))
+        })
+    }
+
+    /// Reads by key in batches of 100, or queries the index one serial at a time, running up to
+    /// `lookup_concurrency` requests at once.
+    fn contains_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        self.diagnosed(|| {
+            let concurrency = self.settings.lookup_concurrency;
+            if let Some(ref index_name) = self.settings.index_name {
+                let now = unix_now();
+                let queries: Vec<Lookup<(usize, bool)>> = serial_numbers.iter().enumerate()
+                    .map(|(position, serial_number)| {
+                        let (client, throttle) = (self.client.clone(), self.throttle.clone());
+                        let input = QueryInput { return_consumed_capacity: Some(String::from("TOTAL")), ..self.index_query(index_name, serial_number, now) };
+                        let query = pace(throttle.clone(), CapacityKind::Read, Duration::from_secs(0))
+                            .and_then(move |_| dispatch(client.query(input), timeout))
+                            .map(move |result| {
+                                record_consumed(throttle.as_ref(), CapacityKind::Read, result.consumed_capacity.as_slice());
+                                (position, result.count.unwrap_or(0) > 0)
+                            });
+                        Box::new(query) as Lookup<_>
+                    })
+                    .collect();
+                let mut contained = vec![false; serial_numbers.len()];
+                for (position, found) in run_bounded(queries, concurrency)? {
+                    contained[position] = found;
+                }
+                return Ok(contained);
+            }
+
+            let mut requested = HashSet::new();
+            // a request naming the same key twice is rejected as a whole
+            let distinct: Vec<&str> = serial_numbers.iter().cloned().filter(|serial_number| requested.insert(*serial_number)).collect();
+            let batches = distinct.chunks(BATCH_GET_LIMIT).map(|batch| self.batch_get(batch, timeout)).collect();
+            let found: HashSet<String> = run_bounded(batches, concurrency)?.into_iter().flatten().collect();
+            Ok(serial_numbers.iter()
+                .map(|serial_number| found.contains(&format!("{}{}", self.settings.key_prefix, serial_number)))
+                .collect())
+        })
+    }
+
+    fn import_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        self.diagnosed(|| {
+            let now = unix_now();
+            let mut requested = HashSet::new();
+            // like reads, a batch putting the same key twice is rejected as a whole
+            let distinct: Vec<&str> = serial_numbers.iter().cloned().filter(|serial_number| requested.insert(*serial_number)).collect();
+            let batches = distinct.chunks(BATCH_WRITE_LIMIT).map(|batch| self.batch_put(batch, now, timeout)).collect();
+            let unwritten: HashSet<String> = run_bounded(batches, self.settings.lookup_concurrency)?.into_iter().flatten().collect();
+            Ok(serial_numbers.iter()
+                .map(|serial_number| !unwritten.contains(&format!("{}{}", self.settings.key_prefix, serial_number)))
+                .collect())
+        })
+    }
+
+    /// Serials sharing the first `similarity_prefix_length` characters, read from the similarity
+    /// index; none without one. Typos within the prefix go unnoticed.
+    fn similar_candidates(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Vec<String>, StoreError> {
+        self.diagnosed(|| {
+            let index_name = match self.settings.similarity_index_name {
+                Some(ref index_name) => index_name,
+                None => return Ok(Vec::new()),
+            };
+            let output = send(self.client.query(self.similarity_query(index_name, serial_number, unix_now())), timeout)?;
+            let serial_attribute = self.settings.serial_attribute();
+            Ok(output.items.unwrap_or_default().into_iter()
+                .filter_map(|item| item.get(serial_attribute).and_then(|value| value.s.clone()))
+                .map(|stored| stored.strip_prefix(self.settings.key_prefix.as_str()).map(String::from).unwrap_or(stored))
+                .collect())
+        })
+    }
+
+    fn register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        self.diagnosed(|| {
+            let now = unix_now();
+            let put_serial = self.put_if_available(self.new_item(serial_number, &Asset::registration(now, idempotency_key)), now);
+
+            match sync(self.client.put_item(put_serial), timeout) {
+                Ok(_) => Ok(Registration::Registered),
+                Err(Failure::Failed(PutItemError::ConditionalCheckFailed(_))) => self.existing_registration(serial_number, idempotency_key, now, timeout),
+                Err(error) => Err(error.into()),
+            }
+        })
+    }
+
+    /// Runs the condition of `register` as a `ConditionCheck`, the only transaction item that
+    /// writes nothing, so the answer is the one the conditional put would get.
+    fn would_register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        self.diagnosed(|| {
+            let now = unix_now();
+            let put = self.put_if_available(self.item_key(serial_number), now);
+            let payload = serde_json::json!({
+                "TransactItems": [{
+                    "ConditionCheck": {
+                        "TableName": put.table_name,
+                        "Key": put.item,
+                        "ConditionExpression": put.condition_expression,
+                        "ExpressionAttributeNames": put.expression_attribute_names,
+                        "ExpressionAttributeValues": put.expression_attribute_values,
+                        "ReturnValuesOnConditionCheckFailure": "ALL_OLD"
+                    }
+                }]
+            });
+            let (status, body) = aws::call_json_unchecked(&self.signer, "dynamodb", TRANSACT_WRITE_ITEMS, "1.0", &self.region, &payload, timeout)?;
+            if (200..300).contains(&status) {
+                return Ok(Registration::Registered);
+            }
+            match condition_check_failure(&body) {
+                Some((_, item)) => Ok(registration_of_existing(Asset::read(&item).idempotency_key(), idempotency_key, now)),
+                None => Err(aws::json_error(status, &body).into()),
+            }
+        })
+    }
+
+    /// Puts the serial into `table_name` and its ownership record into `owners_table_name` with
+    /// `TransactWriteItems`; the cancellation reasons, listed in item order, tell which put failed.
+    fn register_owned(&self, serial_number: &str, owner_id: &str, timeout: Option<Duration>) -> Result<OwnedRegistration, StoreError> {
+        self.diagnosed(|| {
+            let now = unix_now();
+            let asset = self.put_if_available(self.new_item(serial_number, &Asset::registration(now, None)), now);
+            let mut ownership = to_item(&AssetOwner { owner_id: owner_id.to_string(), registered_at: now });
+            ownership.extend(self.item_key(serial_number));
+            let payload = serde_json::json!({
+                "TransactItems": [{
+                    "Put": {
+                        "TableName": asset.table_name,
+                        "Item": asset.item,
+                        "ConditionExpression": asset.condition_expression,
+                        "ExpressionAttributeNames": asset.expression_attribute_names,
+                        "ExpressionAttributeValues": asset.expression_attribute_values
+                    }
+                }, {
+                    "Put": {
+                        "TableName": self.settings.owners_table_name,
+                        "Item": ownership,
+                        "ConditionExpression": "attribute_not_exists(#key)",
+                        "ExpressionAttributeNames": {"#key": self.settings.partition_key}
+                    }
+                }]
+            });
+            let (status, body) = aws::call_json_unchecked(&self.signer, "dynamodb", TRANSACT_WRITE_ITEMS, "1.0", &self.region, &payload, timeout)?;
+            if (200..300).contains(&status) {
+                return Ok(OwnedRegistration::Registered);
+            }
+            match condition_check_failure(&body) {
+                Some((0, _)) => Ok(OwnedRegistration::Rejected(FailedCondition::SerialTaken)),
+                Some(_) => Ok(OwnedRegistration::Rejected(FailedCondition::OwnerRecordExists)),
+                None => Err(aws::json_error(status, &body).into()),
+            }
+        })
+    }
+
+    /// A failed condition is told apart from a missing item by reading the item back.
+    fn update_metadata(&self, serial_number: &str, update: &MetadataUpdate, expected_version: u64, timeout: Option<Duration>) -> Result<MetadataUpdated, StoreError> {
+        self.diagnosed(|| {
+            let now = unix_now();
+            match sync(self.client.update_item(self.update_if_version(serial_number, update, expected_version, now)), timeout) {
+                Ok(_) => return Ok(MetadataUpdated::Updated { version: expected_version + 1 }),
+                Err(Failure::Failed(UpdateItemError::ConditionalCheckFailed(_))) => {},
+                Err(error) => return Err(error.into()),
+            }
+
+            let read_item = GetItemInput {
+                key: self.item_key(serial_number),
+                table_name: self.settings.table_name.clone(),
+                consistent_read: Some(true),
+                ..Default::default()
+            };
+            match send(self.client.get_item(read_item), timeout)?.item.map(|item| Asset::read(&item)) {
+                Some(asset) if asset.is_live(now, self.settings.deleted_policy.released_before(now)) => Ok(MetadataUpdated::Conflict { version: asset.version }),
+                _ => Ok(MetadataUpdated::NotFound),
+            }
+        })
+    }
+
+    fn reserve(&self, serial_number: &str, expires_at: u64, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.diagnosed(|| {
+            let now = unix_now();
+            let asset = Asset { reserved_until: Some(expires_at), ..Asset::registration(now, None) };
+            let item = self.new_item(serial_number, &asset);
+
+            match sync(self.client.put_item(self.put_if_available(item, now)), timeout) {
+                Ok(_) => Ok(true),
+                Err(Failure::Failed(PutItemError::ConditionalCheckFailed(_))) => Ok(false),
+                Err(error) => Err(error.into()),
+            }
+        })
+    }
+
+    fn confirm(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.diagnosed(|| {
+            let mut names = HashMap::new();
+            names.insert(String::from("#reserved_until"), String::from(RESERVED_UNTIL));
+            let mut values = HashMap::new();
+            values.insert(String::from(":now"), number_value(unix_now()));
+            let confirm_reservation = UpdateItemInput {
+                table_name: self.settings.table_name.clone(),
+                key: self.item_key(serial_number),
+                update_expression: Some(String::from("REMOVE #reserved_until")),
+                // fails for missing items, confirmed reservations and expired ones alike
+                condition_expression: Some(String::from("#reserved_until > :now")),
+                expression_attribute_names: Some(names),
+                expression_attribute_values: Some(values),
+                ..Default::default()
+            };
+
+            match sync(self.client.update_item(confirm_reservation), timeout) {
+                Ok(_) => Ok(true),
+                Err(Failure::Failed(UpdateItemError::ConditionalCheckFailed(_))) => Ok(false),
+                Err(error) => Err(error.into()),
+            }
+        })
+    }
+
+    fn transfer_owner(&self, serial_number: &str, from_owner: &str, to_owner: &str, timeout: Option<Duration>) -> Result<OwnerTransfer, StoreError> {
+        self.diagnosed(|| {
+            let now = unix_now();
+            match sync(self.client.update_item(self.transfer_if_owned(serial_number, from_owner, to_owner, now)), timeout) {
+                Ok(output) => {
+                    let version = output.attributes.map(|attributes| Asset::read(&attributes).version).unwrap_or(0);
+                    return Ok(OwnerTransfer::Transferred { version });
+                },
+                Err(Failure::Failed(UpdateItemError::ConditionalCheckFailed(_))) => {},
+                Err(error) => return Err(error.into()),
+            }
+            // tells a serial owned by someone else from one no live item holds
+            match self.live_asset(serial_number, &[], timeout)? {
+                Some(_) => Ok(OwnerTransfer::Conflict),
+                None => Ok(OwnerTransfer::NotFound),
+            }
+        })
+    }
+
+    fn release(&self, serial_number: &str, reason: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.diagnosed(|| {
+            match sync(self.client.update_item(self.release_if_live(serial_number, reason, unix_now())), timeout) {
+                Ok(_) => Ok(true),
+                // missing, expired and already released items alike
+                Err(Failure::Failed(UpdateItemError::ConditionalCheckFailed(_))) => Ok(false),
+                Err(error) => Err(error.into()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn composite_settings() -> DynamoDbSettings {
+        DynamoDbSettings {
+            partition_key: String::from("tenant_id"),
+            sort_key: Some(String::from("serial_number")),
+            partition_value: Some(String::from("acme")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn item_key_uses_the_serial_as_partition_key_by_default() {
+        let store = DynamoDbStore::new(DynamoDbSettings::default());
+        let key = store.item_key("serial1");
+        assert_eq!(1, key.len());
+        assert_eq!(Some(String::from("serial1")), key["serial_number"].s);
+    }
+
+    #[test]
+    fn paces_batches_only_with_a_capacity_budget() {
+        assert_eq!(true, DynamoDbStore::new(DynamoDbSettings::default()).throttle.is_none());
+        let budget = CapacityBudget { read_units: Some(50.0), write_units: None };
+        let store = DynamoDbStore::new(DynamoDbSettings { table_name: String::from("paced"), capacity_budget: budget, ..Default::default() });
+        assert_eq!(true, store.throttle.is_some());
+    }
+
+    #[test]
+    fn item_key_uses_partition_and_sort_key_for_composite_keys() {
+        let store = DynamoDbStore::new(composite_settings());
+        let key = store.item_key("serial1");
+        assert_eq!(Some(String::from("acme")), key["tenant_id"].s);
+        assert_eq!(Some(String::from("serial1")), key["serial_number"].s);
+    }
+
+    #[test]
+    fn item_key_prefixes_the_serial() {
+        let store = DynamoDbStore::new(DynamoDbSettings { key_prefix: String::from("acme#"), ..Default::default() });
+        let key = store.item_key("serial1");
+        assert_eq!(Some(String::from("acme#serial1")), key["serial_number"].s);
+    }
+
+    #[test]
+    fn shares_a_client_per_region() {
+        let local = Region::Custom { name: String::from("eu-west-1"), endpoint: String::from("http://localhost:8000") };
+        assert_eq!(true, Arc::ptr_eq(&client_in(&Region::EuWest1), &client_in(&Region::EuWest1)));
+        assert_eq!(false, Arc::ptr_eq(&client_in(&Region::EuWest1), &client_in(&local)));
+    }
+
+    #[test]
+    fn new_item_carries_the_normalized_serial_for_the_index() {
+        let store = DynamoDbStore::new(DynamoDbSettings { index_name: Some(String::from("by_serial")), ..Default::default() });
+        let item = store.new_item("serial1", &Asset::registration(1_000, None));
+        assert_eq!(Some(String::from("serial1")), item["serial_number"].s);
+        assert_eq!(Some(String::from("SERIAL1")), item["serial_normalized"].s);
+        assert_eq!(Some(String::from("1000")), item["registered_at"].n);
+    }
+
+    #[test]
+    fn new_item_carries_the_idempotency_key() {
+        let store = DynamoDbStore::new(DynamoDbSettings::default());
+        let idempotency_key = IdempotencyKey { key: String::from("retry-1"), expires_at: 2_000 };
+        let item = store.new_item("serial1", &Asset::registration(1_000, Some(&idempotency_key)));
+        assert_eq!(Some(("retry-1", 2_000)), Asset::read(&item).idempotency_key());
+        assert_eq!(None, Asset::read(&store.new_item("serial1", &Asset::registration(1_000, None))).idempotency_key());
+    }
+
+    #[test]
+    fn filter_keys_cover_the_lookups_of_contains() {
+        let settings = DynamoDbSettings { key_prefix: String::from("acme#"), ..Default::default() };
+        let indexed = DynamoDbSettings { index_name: Some(String::from("by_serial")), ..settings.clone() };
+        let item = DynamoDbStore::new(indexed.clone()).new_item("ab1234", &Asset::registration(1_000, None));
+        assert_eq!(vec!["acme#ab1234", "acme#AB1234"], settings.filter_keys(&item));
+        assert_eq!(true, settings.filter_keys(&item).contains(&settings.filter_key("ab1234")));
+        assert_eq!(true, settings.filter_keys(&item).contains(&indexed.filter_key(" Ab1234 ")));
+    }
+
+    #[test]
+    fn existence_checks_read_only_what_liveness_needs() {
+        let store = DynamoDbStore::new(composite_settings());
+        let (projection, names) = store.existence_projection();
+        let mut item = store.new_item("serial1", &Asset { status: Some(String::from("retired")), ..Asset::registration(1_000, None) });
+        item.retain(|name, _| projection.split(", ").any(|placeholder| names[placeholder] == *name));
+        assert_eq!(vec!["serial_number"], item.keys().collect::<Vec<_>>());
+        item.insert(String::from(RESERVED_UNTIL), number_value(900));
+        assert_eq!(false, Asset::read(&item).is_live(1_000, None));
+    }
+
+    #[test]
+    fn expired_reservations_are_not_live() {
+        let mut item = DynamoDbStore::new(DynamoDbSettings::default()).new_item("serial1", &Asset::registration(1_000, None));
+        assert_eq!(true, Asset::read(&item).is_live(1_000, None));
+        item.insert(String::from(RESERVED_UNTIL), number_value(1_900));
+        assert_eq!(true, Asset::read(&item).is_live(1_000, None));
+        assert_eq!(false, Asset::read(&item).is_live(1_900, None));
+    }
+
+    #[test]
+    fn tombstones_hold_serials_as_the_policy_says() {
+        let mut item = DynamoDbStore::new(DynamoDbSettings::default()).new_item("serial1", &Asset::registration(1_000, None));
+        item.insert(String::from(DELETED_AT), number_value(1_000));
+        let day = 24 * 60 * 60;
+        let now = 1_000 + 2 * day;
+        assert_eq!(true, Asset::read(&item).is_live(now, DeletedPolicy::TreatDeletedAsTaken.released_before(now)));
+        assert_eq!(false, Asset::read(&item).is_live(now, DeletedPolicy::TreatDeletedAsAvailable.released_before(now)));
+        assert_eq!(true, Asset::read(&item).is_live(now, DeletedPolicy::BlockedForDays(3).released_before(now)));
+        assert_eq!(false, Asset::read(&item).is_live(now, DeletedPolicy::BlockedForDays(2).released_before(now)));
+    }
+
+    #[test]
+    fn registrations_replace_released_tombstones() {
+        let settings = DynamoDbSettings { deleted_policy: DeletedPolicy::BlockedForDays(1), ..Default::default() };
+        let put = DynamoDbStore::new(settings).put_if_available(HashMap::new(), 100_000);
+        assert_eq!(
+            Some(String::from("attribute_not_exists(#key) OR NOT ((attribute_not_exists(#reserved_until) OR #reserved_until > :now) AND (attribute_not_exists(#deleted_at) OR #deleted_at > :released_before))")),
+            put.condition_expression
+        );
+        assert_eq!(Some("13600"), put.expression_attribute_values.as_ref().unwrap()[":released_before"].n.as_deref());
+    }
+
+    #[test]
+    fn index_query_matches_the_normalized_serial_within_the_partition() {
+        let store = DynamoDbStore::new(DynamoDbSettings { index_name: Some(String::from("by_serial")), ..composite_settings() });
+        let query = store.index_query("by_serial", " serial1 ", 1_000);
+        assert_eq!(Some(String::from("by_serial")), query.index_name);
+        assert_eq!(Some(String::from("SERIAL1")), query.expression_attribute_values.as_ref().unwrap()[":serial"].s);
+        assert_eq!(Some(String::from("(attribute_not_exists(#reserved_until) OR #reserved_until > :now) AND #partition = :partition")), query.filter_expression);
+    }
+
+    #[test]
+    fn bounded_lookups_never_exceed_the_concurrency() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let most_in_flight = Arc::new(AtomicUsize::new(0));
+        let lookups: Vec<Lookup<usize>> = (0..12usize).map(|lookup| {
+            let (in_flight, most_in_flight) = (in_flight.clone(), most_in_flight.clone());
+            Box::new(future::lazy(move || {
+                most_in_flight.fetch_max(in_flight.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                Delay::new(Instant::now() + Duration::from_millis(10))
+                    .map_err(|error| StoreError::Unavailable(error.to_string()))
+                    .map(move |_| {
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                        lookup
+                    })
+            })) as Lookup<usize>
+        }).collect();
+        let mut results = run_bounded(lookups, 3).ok().unwrap();
+        results.sort();
+        assert_eq!((0..12).collect::<Vec<usize>>(), results);
+        assert_eq!(3, most_in_flight.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn bounded_lookups_fail_with_the_first_error() {
+        let lookups: Vec<Lookup<bool>> = vec![Box::new(future::ok(true)), Box::new(future::err(StoreError::Throttled(String::from("slow down"))))];
+        match run_bounded(lookups, 2) {
+            Err(StoreError::Throttled(_)) => {},
+            other => panic!("expected throttling, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn similarity_query_matches_the_normalized_prefix() {
+        let settings = DynamoDbSettings { similarity_index_name: Some(String::from("by_prefix")), key_prefix: String::from("acme#"), ..Default::default() };
+        let store = DynamoDbStore::new(settings);
+        assert_eq!(Some(String::from("acme#AB12")), store.new_item(" ab1234 ", &Asset::registration(1_000, None))["serial_prefix"].s);
+        let query = store.similarity_query("by_prefix", "ab1299", 1_000);
+        assert_eq!(Some(String::from("acme#AB12")), query.expression_attribute_values.as_ref().unwrap()[":prefix"].s);
+        assert_eq!(Some(String::from("by_prefix")), query.index_name);
+    }
+
+    #[test]
+    fn reads_the_item_failing_a_condition_check() {
+        let cancelled = serde_json::json!({
+            "__type": "com.amazonaws.dynamodb.v20120810#TransactionCanceledException",
+            "CancellationReasons": [{"Code": "ConditionalCheckFailed", "Item": {"serial_number": {"S": "AB1234"}, "idempotency_key": {"S": "retry-1"}, "idempotency_expires_at": {"N": "2000"}}}]
+        });
+        let (position, item) = condition_check_failure(&cancelled).unwrap();
+        assert_eq!(0, position);
+        assert_eq!(Some(("retry-1", 2_000)), Asset::read(&item).idempotency_key());
+
+        let owner_taken = serde_json::json!({"CancellationReasons": [{"Code": "None"}, {"Code": "ConditionalCheckFailed"}]});
+        assert_eq!(Some(1), condition_check_failure(&owner_taken).map(|(position, _)| position));
+
+        let throttled = serde_json::json!({"CancellationReasons": [{"Code": "ThrottlingError"}]});
+        assert_eq!(true, condition_check_failure(&throttled).is_none());
+    }
+
+    #[test]
+    fn metadata_updates_are_conditional_on_the_version() {
+        let store = DynamoDbStore::new(DynamoDbSettings::default());
+        let update = MetadataUpdate { status: Some(String::from("retired")), owner_id: None };
+        let first = store.update_if_version("AB1234", &update, 0, 1_000);
+        assert_eq!(Some(String::from("SET #version = :next, #updated_at = :now, #status = :status")), first.update_expression);
+        assert_eq!(true, first.condition_expression.unwrap().starts_with("attribute_exists(#key) AND (attribute_not_exists(#version)"));
+
+        let later = store.update_if_version("AB1234", &update, 3, 1_000);
+        assert_eq!(true, later.condition_expression.unwrap().starts_with("#version = :expected AND"));
+        assert_eq!(Some("4"), later.expression_attribute_values.as_ref().unwrap()[":next"].n.as_deref());
+    }
+
+    #[test]
+    fn releases_only_live_items_once() {
+        let store = DynamoDbStore::new(DynamoDbSettings { deleted_policy: DeletedPolicy::BlockedForDays(30), ..Default::default() });
+        let release = store.release_if_live("AB1234", "device destroyed", 1_000);
+        assert_eq!(Some(String::from("SET #deleted_at = :now, #deleted_reason = :reason")), release.update_expression);
+        assert_eq!(true, release.condition_expression.unwrap().starts_with("attribute_exists(#key) AND attribute_not_exists(#deleted_at) AND (attribute_not_exists(#reserved_until)"));
+        let values = release.expression_attribute_values.unwrap();
+        assert_eq!((Some("1000"), Some("device destroyed")), (values[":now"].n.as_deref(), values[":reason"].s.as_deref()));
+    }
+
+    #[test]
+    fn transfers_only_from_the_current_owner() {
+        let store = DynamoDbStore::new(DynamoDbSettings::default());
+        let transfer = store.transfer_if_owned("AB1234", "acme-fleet", "globex", 1_000);
+        assert_eq!(Some(String::from("SET #owner_id = :to_owner, #updated_at = :now ADD #version :one")), transfer.update_expression);
+        assert_eq!(Some(String::from("#owner_id = :from_owner AND (attribute_not_exists(#reserved_until) OR #reserved_until > :now)")), transfer.condition_expression);
+        let values = transfer.expression_attribute_values.unwrap();
+        assert_eq!((Some("acme-fleet"), Some("globex")), (values[":from_owner"].s.as_deref(), values[":to_owner"].s.as_deref()));
+    }
+
+    #[test]
+    fn lists_composite_keys_within_the_partition() {
+        let store = DynamoDbStore::new(composite_settings());
+        let query = ListQuery { prefix: String::from("AB"), limit: 50, ..Default::default() };
+        match store.list_read(&query, 1_000) {
+            ListRead::Query(input) => {
+                assert_eq!(None, input.index_name);
+                assert_eq!(Some(String::from("#partition = :partition AND begins_with(#serial, :serial_prefix)")), input.key_condition_expression);
+                assert_eq!(Some(50), input.limit);
+            },
+            ListRead::Scan(_) => panic!("expected a query"),
+        }
+    }
+
+    #[test]
+    fn lists_short_prefixes_with_a_bounded_scan() {
+        let settings = DynamoDbSettings { similarity_index_name: Some(String::from("by_prefix")), key_prefix: String::from("acme#"), ..Default::default() };
+        let store = DynamoDbStore::new(settings);
+        let query = ListQuery { prefix: String::from("AB"), registered_from: Some(500), limit: 10, ..Default::default() };
+        match store.list_read(&query, 1_000) {
+            ListRead::Scan(input) => {
+                assert_eq!(Some("acme#AB"), input.expression_attribute_values.as_ref().unwrap()[":serial_prefix"].s.as_deref());
+                assert_eq!(true, input.filter_expression.unwrap().ends_with("AND #registered_at >= :registered_from AND begins_with(#serial, :serial_prefix)"));
+            },
+            ListRead::Query(_) => panic!("expected a scan"),
+        }
+        match store.list_read(&ListQuery { prefix: String::from("ab12"), limit: 10, ..Default::default() }, 1_000) {
+            ListRead::Query(input) => assert_eq!(Some(String::from("by_prefix")), input.index_name),
+            ListRead::Scan(_) => panic!("expected an index query"),
+        }
+    }
+
+    #[test]
+    fn formats_utc_dates() {
+        assert_eq!("1970-01-01", utc_date(0));
+        assert_eq!("2000-02-29", utc_date(951_782_400));
+        assert_eq!("2024-12-31", utc_date(1_735_689_599));
+        assert_eq!("2025-01-01", utc_date(1_735_689_600));
+    }
+
+    #[test]
+    fn registered_query_covers_one_date_of_the_tenant() {
+        let settings = DynamoDbSettings { registered_index_name: Some(String::from("by_day")), key_prefix: String::from("acme#"), ..Default::default() };
+        let store = DynamoDbStore::new(settings);
+        assert_eq!(Some("acme#2025-01-01"), store.new_item("AB1234", &Asset::registration(1_735_689_600, None))["registered_day"].s.as_deref());
+
+        let query = store.registered_query("by_day", 1_735_689_600, 1_735_775_999, 1_800_000_000);
+        let values = query.expression_attribute_values.unwrap();
+        assert_eq!(Some("acme#2025-01-01"), values[":day"].s.as_deref());
+        assert_eq!(Some(String::from("#day = :day AND #registered_at BETWEEN :from AND :to")), query.key_condition_expression);
+    }
+
+    #[test]
+    fn tells_access_denied_from_other_configuration_errors() {
+        let service_error = |code: &str| StoreError::from(AwsError::Service { status: 400, code: code.to_string(), message: String::from("denied") });
+        assert_eq!(true, matches!(service_error("AccessDeniedException"), StoreError::AccessDenied(ref message) if message == "denied"));
+        assert_eq!(true, matches!(service_error("ResourceNotFoundException"), StoreError::Misconfigured(_)));
+        assert_eq!(true, matches!(service_error("InternalServerError"), StoreError::Unavailable(_)));
+    }
+
+    #[test]
+    fn tells_timeouts_by_the_time_the_request_took() {
+        let dispatch_error = || GetItemError::HttpDispatch(HttpDispatchError::from(std::io::Error::other("connection reset")));
+        let timeout = Some(Duration::from_millis(50));
+        let started = Instant::now() - Duration::from_millis(60);
+        assert_eq!(true, matches!(StoreError::from(failure(dispatch_error(), started, timeout)), StoreError::Timeout));
+        assert_eq!(true, matches!(StoreError::from(failure(dispatch_error(), Instant::now(), timeout)), StoreError::Unavailable(_)));
+        assert_eq!(true, matches!(StoreError::from(failure(dispatch_error(), started, None)), StoreError::Unavailable(_)));
+        let throttled = GetItemError::ProvisionedThroughputExceeded(String::from("slow down"));
+        assert_eq!(true, matches!(StoreError::from(failure(throttled, started, timeout)), StoreError::Throttled(_)));
+    }
+}
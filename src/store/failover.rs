@@ -0,0 +1,226 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::context::log_shared;
+
+use super::{ConflictingItem, IdempotencyKey, MetadataUpdate, MetadataUpdated, OwnedRegistration, OwnerTransfer, Registration, SerialStore, StoreError};
+
+#[derive(Clone, Debug)]
+pub struct FailoverSettings {
+    /// Consecutive failures of the primary region before calls go to the secondary one.
+    pub failure_threshold: u32,
+    /// How often the primary region is probed while failed over.
+    pub probe_interval: Duration,
+    /// Probes slower than this keep the calls on the secondary region.
+    pub probe_timeout: Duration
+}
+
+impl Default for FailoverSettings {
+    fn default() -> FailoverSettings {
+        FailoverSettings {
+            // below the circuit breaker's threshold, so that the secondary region gets a chance
+            // before lookups fail fast
+            failure_threshold: 3,
+            probe_interval: Duration::from_secs(30),
+            probe_timeout: Duration::from_secs(1)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Route {
+    Primary { consecutive_failures: u32 },
+    /// Failed over, with the primary region last probed at the given time.
+    Secondary { probed_at: Instant }
+}
+
+/// Region the calls of this container go to, shared by every invocation it handles.
+pub struct FailoverRouter {
+    settings: FailoverSettings,
+    route: Mutex<Route>
+}
+
+impl FailoverRouter {
+    pub fn new(settings: FailoverSettings) -> FailoverRouter {
+        FailoverRouter { settings, route: Mutex::new(Route::Primary { consecutive_failures: 0 }) }
+    }
+
+    /// Whether a probe of the primary region is due at `now`.
+    fn probe_due(&self, now: Instant) -> bool {
+        match *self.route.lock().unwrap() {
+            Route::Secondary { probed_at } => now.duration_since(probed_at) >= self.settings.probe_interval,
+            Route::Primary { .. } => false,
+        }
+    }
+
+    /// Fails back after a successful probe, and waits for the next one otherwise.
+    fn record_probe(&self, healthy: bool, now: Instant) {
+        let mut route = self.route.lock().unwrap();
+        *route = if healthy {
+            log_shared("primary region answered its probe, failing back");
+            Route::Primary { consecutive_failures: 0 }
+        } else {
+            Route::Secondary { probed_at: now }
+        };
+    }
+
+    fn on_primary(&self) -> bool {
+        matches!(*self.route.lock().unwrap(), Route::Primary { .. })
+    }
+
+    /// Counts the outcome of a call to the primary region, failing over once too many in a row
+    /// failed. Answers that say nothing about the region's health leave the count alone.
+    fn record<T>(&self, result: &Result<T, StoreError>, now: Instant) {
+        let mut route = self.route.lock().unwrap();
+        let consecutive_failures = match *route {
+            Route::Primary { consecutive_failures } => consecutive_failures,
+            Route::Secondary { .. } => return,
+        };
+        *route = match *result {
+            Ok(_) => Route::Primary { consecutive_failures: 0 },
+            Err(StoreError::Unavailable(_)) | Err(StoreError::Throttled(_)) | Err(StoreError::Timeout) => {
+                if consecutive_failures + 1 >= self.settings.failure_threshold {
+                    log_shared(&format!("primary region failed {} calls in a row, failing over", consecutive_failures + 1));
+                    Route::Secondary { probed_at: now }
+                } else {
+                    Route::Primary { consecutive_failures: consecutive_failures + 1 }
+                }
+            },
+            Err(_) => return,
+        };
+    }
+}
+
+/// Sends every call to the primary region until it fails `failure_threshold` times in a row, then
+/// to the secondary one until a probe finds the primary region healthy again. Writes fail over
+/// too: while containers disagree on the region, a serial could be registered in both.
+pub struct FailoverStore<'a, S: SerialStore> {
+    primary: S,
+    secondary: S,
+    router: &'a FailoverRouter
+}
+
+impl<'a, S: SerialStore> FailoverStore<'a, S> {
+    pub fn new(primary: S, secondary: S, router: &'a FailoverRouter) -> FailoverStore<'a, S> {
+        FailoverStore { primary, secondary, router }
+    }
+
+    /// Runs `call` against the region currently routed to.
+    fn call<T, F: Fn(&S) -> Result<T, StoreError>>(&self, call: F) -> Result<T, StoreError> {
+        let now = Instant::now();
+        if self.router.probe_due(now) {
+            let healthy = self.primary.probe(Some(self.router.settings.probe_timeout)).is_ok();
+            self.router.record_probe(healthy, now);
+        }
+        if !self.router.on_primary() {
+            return call(&self.secondary);
+        }
+        let result = call(&self.primary);
+        self.router.record(&result, Instant::now());
+        result
+    }
+}
+
+impl<'a, S: SerialStore> SerialStore for FailoverStore<'a, S> {
+    fn contains(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.call(|store| store.contains(serial_number, timeout))
+    }
+
+    fn contains_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        self.call(|store| store.contains_many(serial_numbers, timeout))
+    }
+
+    fn conflicting_item(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Option<ConflictingItem>, StoreError> {
+        self.call(|store| store.conflicting_item(serial_number, timeout))
+    }
+
+    fn register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        self.call(|store| store.register(serial_number, idempotency_key, timeout))
+    }
+
+    fn would_register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        self.call(|store| store.would_register(serial_number, idempotency_key, timeout))
+    }
+
+    fn register_owned(&self, serial_number: &str, owner_id: &str, timeout: Option<Duration>) -> Result<OwnedRegistration, StoreError> {
+        self.call(|store| store.register_owned(serial_number, owner_id, timeout))
+    }
+
+    fn update_metadata(&self, serial_number: &str, update: &MetadataUpdate, expected_version: u64, timeout: Option<Duration>) -> Result<MetadataUpdated, StoreError> {
+        self.call(|store| store.update_metadata(serial_number, update, expected_version, timeout))
+    }
+
+    fn reserve(&self, serial_number: &str, expires_at: u64, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.call(|store| store.reserve(serial_number, expires_at, timeout))
+    }
+
+    fn confirm(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.call(|store| store.confirm(serial_number, timeout))
+    }
+
+    fn release(&self, serial_number: &str, reason: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.call(|store| store.release(serial_number, reason, timeout))
+    }
+
+    fn transfer_owner(&self, serial_number: &str, from_owner: &str, to_owner: &str, timeout: Option<Duration>) -> Result<OwnerTransfer, StoreError> {
+        self.call(|store| store.transfer_owner(serial_number, from_owner, to_owner, timeout))
+    }
+
+    fn import_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        self.call(|store| store.import_many(serial_numbers, timeout))
+    }
+
+    fn probe(&self, timeout: Option<Duration>) -> Result<(), StoreError> {
+        self.call(|store| store.probe(timeout))
+    }
+
+    fn similar_candidates(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Vec<String>, StoreError> {
+        self.call(|store| store.similar_candidates(serial_number, timeout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{MemoryStore, FailingStore};
+
+    fn router(failure_threshold: u32, probe_interval: Duration) -> FailoverRouter {
+        FailoverRouter::new(FailoverSettings { failure_threshold, probe_interval, ..FailoverSettings::default() })
+    }
+
+    #[test]
+    fn fails_over_after_consecutive_failures() {
+        let router = router(2, Duration::from_secs(30));
+        let now = Instant::now();
+        router.record::<()>(&Err(StoreError::Timeout), now);
+        router.record(&Ok(()), now);
+        router.record::<()>(&Err(StoreError::Timeout), now);
+        router.record::<()>(&Err(StoreError::Misconfigured(String::from("no such table"))), now);
+        assert_eq!(true, router.on_primary());
+        router.record::<()>(&Err(StoreError::Unavailable(String::from("connection refused"))), now);
+        assert_eq!(false, router.on_primary());
+    }
+
+    #[test]
+    fn probes_the_primary_region_before_failing_back() {
+        let router = router(1, Duration::from_secs(30));
+        let now = Instant::now();
+        router.record::<()>(&Err(StoreError::Timeout), now);
+        assert_eq!(false, router.probe_due(now + Duration::from_secs(29)));
+        assert_eq!(true, router.probe_due(now + Duration::from_secs(30)));
+        router.record_probe(false, now + Duration::from_secs(30));
+        assert_eq!((false, false), (router.on_primary(), router.probe_due(now + Duration::from_secs(59))));
+        router.record_probe(true, now + Duration::from_secs(60));
+        assert_eq!(true, router.on_primary());
+    }
+
+    #[test]
+    fn answers_from_the_secondary_region_while_failed_over() {
+        let router = router(1, Duration::from_secs(30));
+        let failing = FailoverStore::new(Box::new(FailingStore) as Box<dyn SerialStore>, Box::new(MemoryStore::new(vec![String::from("serial1")])), &router);
+        assert_eq!(true, failing.contains("serial1", None).is_err());
+        assert_eq!(true, failing.contains("serial1", None).ok().unwrap());
+        assert_eq!(Registration::Registered, failing.register("serial2", None, None).ok().unwrap());
+        assert_eq!(true, failing.contains("serial2", None).ok().unwrap());
+    }
+}
@@ -0,0 +1,200 @@
+//! Failures injected into the store on purpose, to exercise retries, the circuit breaker and the
+//! degraded mode in integration tests and game days. Only built with `--features fault-injection`.
+
+use std::thread;
+use std::time::Duration;
+
+use super::{ConflictingItem, IdempotencyKey, MetadataUpdate, MetadataUpdated, OwnedRegistration, OwnerTransfer, Registration, SerialStore, StoreError};
+
+/// Delay added to slowed down calls unless `latency_ms` sets another.
+const DEFAULT_LATENCY: Duration = Duration::from_millis(200);
+
+/// Probability of each fault per store call, as set by `FAULT_INJECTION`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FaultInjectionSettings {
+    /// Calls delayed by `latency` before reaching the store.
+    pub latency_rate: f64,
+    pub latency: Duration,
+    /// Calls failing with `StoreError::Throttled`.
+    pub throttle_rate: f64,
+    /// Calls failing with `StoreError::Timeout` once their timeout has passed.
+    pub timeout_rate: f64,
+    /// Calls failing with `StoreError::Unavailable`.
+    pub unavailable_rate: f64
+}
+
+impl FaultInjectionSettings {
+    /// `<latency|throttle|timeout|unavailable>=<probability>` entries, and `latency_ms=<n>` for
+    /// the delay of slowed down calls, e.g. `latency=0.2,latency_ms=300,throttle=0.05`.
+    pub fn parse<S: AsRef<str>>(entries: &[S]) -> Result<FaultInjectionSettings, String> {
+        let mut settings = FaultInjectionSettings { latency: DEFAULT_LATENCY, ..FaultInjectionSettings::default() };
+        for entry in entries {
+            let entry = entry.as_ref();
+            let malformed = || format!("expected <latency|throttle|timeout|unavailable>=<probability> or latency_ms=<n>, got `{}`", entry);
+            let (fault, value) = entry.split_once('=').ok_or_else(malformed)?;
+            if fault.trim() == "latency_ms" {
+                settings.latency = Duration::from_millis(value.trim().parse().map_err(|_| malformed())?);
+                continue;
+            }
+            let rate = value.trim().parse::<f64>().ok().filter(|rate| (0.0..=1.0).contains(rate)).ok_or_else(malformed)?;
+            match fault.trim() {
+                "latency" => settings.latency_rate = rate,
+                "throttle" => settings.throttle_rate = rate,
+                "timeout" => settings.timeout_rate = rate,
+                "unavailable" => settings.unavailable_rate = rate,
+                _ => return Err(malformed()),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+/// Wraps a store so that its calls are slowed down or fail at the rates of the settings.
+pub struct FaultInjectingStore<S: SerialStore> {
+    inner: S,
+    settings: FaultInjectionSettings
+}
+
+impl<S: SerialStore> FaultInjectingStore<S> {
+    pub fn new(inner: S, settings: FaultInjectionSettings) -> FaultInjectingStore<S> {
+        FaultInjectingStore { inner, settings }
+    }
+
+    /// Sleeps or fails as the dice say, before a call with `timeout` reaches the store.
+    fn inject(&self, timeout: Option<Duration>) -> Result<(), StoreError> {
+        let faults = &self.settings;
+        if rand::random::<f64>() < faults.timeout_rate {
+            // like a real timeout, the caller gets to wait for it
+            thread::sleep(timeout.unwrap_or(faults.latency));
+            return Err(StoreError::Timeout);
+        }
+        if rand::random::<f64>() < faults.throttle_rate {
+            return Err(StoreError::Throttled(String::from("injected throttling")));
+        }
+        if rand::random::<f64>() < faults.unavailable_rate {
+            return Err(StoreError::Unavailable(String::from("injected outage")));
+        }
+        if rand::random::<f64>() < faults.latency_rate {
+            match timeout {
+                Some(timeout) if timeout <= faults.latency => {
+                    thread::sleep(timeout);
+                    return Err(StoreError::Timeout);
+                },
+                _ => thread::sleep(faults.latency),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: SerialStore> SerialStore for FaultInjectingStore<S> {
+    fn contains(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.inject(timeout)?;
+        self.inner.contains(serial_number, timeout)
+    }
+
+    fn conflicting_item(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Option<ConflictingItem>, StoreError> {
+        self.inject(timeout)?;
+        self.inner.conflicting_item(serial_number, timeout)
+    }
+
+    fn contains_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        self.inject(timeout)?;
+        self.inner.contains_many(serial_numbers, timeout)
+    }
+
+    fn register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        self.inject(timeout)?;
+        self.inner.register(serial_number, idempotency_key, timeout)
+    }
+
+    fn would_register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        self.inject(timeout)?;
+        self.inner.would_register(serial_number, idempotency_key, timeout)
+    }
+
+    fn register_owned(&self, serial_number: &str, owner_id: &str, timeout: Option<Duration>) -> Result<OwnedRegistration, StoreError> {
+        self.inject(timeout)?;
+        self.inner.register_owned(serial_number, owner_id, timeout)
+    }
+
+    fn update_metadata(&self, serial_number: &str, update: &MetadataUpdate, expected_version: u64, timeout: Option<Duration>) -> Result<MetadataUpdated, StoreError> {
+        self.inject(timeout)?;
+        self.inner.update_metadata(serial_number, update, expected_version, timeout)
+    }
+
+    fn reserve(&self, serial_number: &str, expires_at: u64, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.inject(timeout)?;
+        self.inner.reserve(serial_number, expires_at, timeout)
+    }
+
+    fn confirm(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.inject(timeout)?;
+        self.inner.confirm(serial_number, timeout)
+    }
+
+    fn release(&self, serial_number: &str, reason: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.inject(timeout)?;
+        self.inner.release(serial_number, reason, timeout)
+    }
+
+    fn transfer_owner(&self, serial_number: &str, from_owner: &str, to_owner: &str, timeout: Option<Duration>) -> Result<OwnerTransfer, StoreError> {
+        self.inject(timeout)?;
+        self.inner.transfer_owner(serial_number, from_owner, to_owner, timeout)
+    }
+
+    fn import_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        self.inject(timeout)?;
+        self.inner.import_many(serial_numbers, timeout)
+    }
+
+    fn probe(&self, timeout: Option<Duration>) -> Result<(), StoreError> {
+        self.inject(timeout)?;
+        self.inner.probe(timeout)
+    }
+
+    fn similar_candidates(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Vec<String>, StoreError> {
+        self.inject(timeout)?;
+        self.inner.similar_candidates(serial_number, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{CircuitBreaker, CircuitBreakerSettings, CircuitBreakerStore, MemoryStore};
+
+    fn faults(entries: &[&str]) -> FaultInjectionSettings {
+        FaultInjectionSettings::parse(entries).ok().unwrap()
+    }
+
+    #[test]
+    fn parses_fault_rates() {
+        let settings = faults(&["throttle=0.5", " latency = 1", "latency_ms=20"]);
+        assert_eq!((0.5, 1.0, Duration::from_millis(20)), (settings.throttle_rate, settings.latency_rate, settings.latency));
+        assert_eq!(DEFAULT_LATENCY, faults(&[]).latency);
+        assert_eq!(true, FaultInjectionSettings::parse(&["throttle=2"]).is_err());
+        assert_eq!(true, FaultInjectionSettings::parse(&["crash=0.1"]).is_err());
+        assert_eq!(true, FaultInjectionSettings::parse(&["timeout"]).is_err());
+    }
+
+    #[test]
+    fn injects_certain_faults_and_passes_through_otherwise() {
+        let store = FaultInjectingStore::new(MemoryStore::new(vec![String::from("serial1")]), faults(&[]));
+        assert_eq!(true, store.contains("serial1", None).ok().unwrap());
+        let throttled = FaultInjectingStore::new(MemoryStore::new(Vec::new()), faults(&["throttle=1"]));
+        assert_eq!(true, matches!(throttled.contains("serial1", None), Err(StoreError::Throttled(_))));
+        let slow = FaultInjectingStore::new(MemoryStore::new(Vec::new()), faults(&["latency=1", "latency_ms=50"]));
+        assert_eq!(true, matches!(slow.contains("serial1", Some(Duration::from_millis(10))), Err(StoreError::Timeout)));
+        assert_eq!(true, matches!(slow.contains("serial1", Some(Duration::from_secs(1))), Ok(false)));
+    }
+
+    #[test]
+    fn injected_outages_open_the_circuit_breaker() {
+        let breaker = CircuitBreaker::new(CircuitBreakerSettings { failure_threshold: 2, ..CircuitBreakerSettings::default() });
+        let store = CircuitBreakerStore::new(FaultInjectingStore::new(MemoryStore::new(Vec::new()), faults(&["unavailable=1"])), &breaker);
+        assert_eq!(true, matches!(store.contains("serial1", None), Err(StoreError::Unavailable(_))));
+        assert_eq!(true, matches!(store.contains("serial1", None), Err(StoreError::Unavailable(_))));
+        assert_eq!(true, matches!(store.contains("serial1", None), Err(StoreError::CircuitOpen)));
+    }
+}
@@ -0,0 +1,231 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{ConflictingItem, IdempotencyKey, MetadataUpdate, MetadataUpdated, OwnedRegistration, OwnerTransfer, Registration, SerialStore, StoreError};
+
+#[derive(Clone, Debug)]
+pub struct ReplicaRoutingSettings {
+    /// How often replica latencies are measured again.
+    pub probe_interval: Duration,
+    /// Probes slower than this mark the replica unhealthy.
+    pub probe_timeout: Duration
+}
+
+impl Default for ReplicaRoutingSettings {
+    fn default() -> ReplicaRoutingSettings {
+        ReplicaRoutingSettings {
+            probe_interval: Duration::from_secs(60),
+            probe_timeout: Duration::from_secs(1)
+        }
+    }
+}
+
+struct Measurements {
+    /// Probe latency per replica, `None` for replicas that failed their last probe or read.
+    latencies: Vec<Option<Duration>>,
+    measured_at: Option<Instant>
+}
+
+/// Latency ranking of the replicas, shared by every invocation handled by this container.
+pub struct ReplicaRouter {
+    settings: ReplicaRoutingSettings,
+    measurements: Mutex<Measurements>
+}
+
+impl ReplicaRouter {
+    pub fn new(settings: ReplicaRoutingSettings) -> ReplicaRouter {
+        ReplicaRouter {
+            settings,
+            measurements: Mutex::new(Measurements { latencies: Vec::new(), measured_at: None })
+        }
+    }
+
+    fn is_stale(&self, replica_count: usize, now: Instant) -> bool {
+        let measurements = self.measurements.lock().unwrap();
+        match measurements.measured_at {
+            Some(measured_at) => {
+                measurements.latencies.len() != replica_count
+                    || now.duration_since(measured_at) >= self.settings.probe_interval
+            },
+            None => true
+        }
+    }
+
+    /// Probes every replica in parallel and records how long each took.
+    fn measure<S: SerialStore + Sync>(&self, replicas: &[S], now: Instant) {
+        let timeout = self.settings.probe_timeout;
+        let latencies = thread::scope(|scope| {
+            let probes: Vec<_> = replicas.iter()
+                .map(|replica| scope.spawn(move || {
+                    let started = Instant::now();
+                    replica.probe(Some(timeout)).ok().map(|_| started.elapsed())
+                }))
+                .collect();
+            probes.into_iter().map(|probe| probe.join().unwrap_or(None)).collect()
+        });
+        self.record(latencies, now);
+    }
+
+    fn record(&self, latencies: Vec<Option<Duration>>, now: Instant) {
+        let mut measurements = self.measurements.lock().unwrap();
+        measurements.latencies = latencies;
+        measurements.measured_at = Some(now);
+    }
+
+    /// Index of the fastest healthy replica, or of the first replica when none is healthy.
+    fn fastest(&self) -> usize {
+        let measurements = self.measurements.lock().unwrap();
+        measurements.latencies.iter()
+            .enumerate()
+            .filter_map(|(index, latency)| latency.map(|latency| (index, latency)))
+            .min_by_key(|&(_, latency)| latency)
+            .map(|(index, _)| index)
+            .unwrap_or(0)
+    }
+
+    fn mark_unhealthy(&self, index: usize) {
+        let mut measurements = self.measurements.lock().unwrap();
+        if let Some(latency) = measurements.latencies.get_mut(index) {
+            *latency = None;
+        }
+    }
+}
+
+/// Sends each read to the replica region that answered its last probe the fastest.
+pub struct LatencyRoutedStore<'a, S: SerialStore + Sync> {
+    replicas: Vec<S>,
+    router: &'a ReplicaRouter
+}
+
+impl<'a, S: SerialStore + Sync> LatencyRoutedStore<'a, S> {
+    pub fn new(replicas: Vec<S>, router: &'a ReplicaRouter) -> LatencyRoutedStore<'a, S> {
+        LatencyRoutedStore { replicas, router }
+    }
+
+    fn observe<T>(&self, index: usize, result: &Result<T, StoreError>) {
+        if let Err(StoreError::Unavailable(_)) | Err(StoreError::Throttled(_)) | Err(StoreError::Timeout) = *result {
+            // route the next read elsewhere until the next measurement
+            self.router.mark_unhealthy(index);
+        }
+    }
+
+    fn route(&self) -> usize {
+        let now = Instant::now();
+        if self.router.is_stale(self.replicas.len(), now) {
+            self.router.measure(&self.replicas, now);
+        }
+        self.router.fastest()
+    }
+}
+
+impl<'a, S: SerialStore + Sync> SerialStore for LatencyRoutedStore<'a, S> {
+    fn contains(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        let index = self.route();
+        let result = self.replicas[index].contains(serial_number, timeout);
+        self.observe(index, &result);
+        result
+    }
+
+    fn conflicting_item(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Option<ConflictingItem>, StoreError> {
+        let index = self.route();
+        let result = self.replicas[index].conflicting_item(serial_number, timeout);
+        self.observe(index, &result);
+        result
+    }
+
+    fn contains_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        let index = self.route();
+        let result = self.replicas[index].contains_many(serial_numbers, timeout);
+        self.observe(index, &result);
+        result
+    }
+
+    /// Writes, reservations included, always go to the first (home) replica: conditional writes are only checked within
+    /// the region they are sent to, so spreading them would let two regions register the same serial.
+    fn register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        self.replicas[0].register(serial_number, idempotency_key, timeout)
+    }
+
+    fn would_register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        self.replicas[0].would_register(serial_number, idempotency_key, timeout)
+    }
+
+    fn register_owned(&self, serial_number: &str, owner_id: &str, timeout: Option<Duration>) -> Result<OwnedRegistration, StoreError> {
+        self.replicas[0].register_owned(serial_number, owner_id, timeout)
+    }
+
+    fn update_metadata(&self, serial_number: &str, update: &MetadataUpdate, expected_version: u64, timeout: Option<Duration>) -> Result<MetadataUpdated, StoreError> {
+        self.replicas[0].update_metadata(serial_number, update, expected_version, timeout)
+    }
+
+    fn reserve(&self, serial_number: &str, expires_at: u64, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.replicas[0].reserve(serial_number, expires_at, timeout)
+    }
+
+    fn confirm(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.replicas[0].confirm(serial_number, timeout)
+    }
+
+    fn release(&self, serial_number: &str, reason: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        self.replicas[0].release(serial_number, reason, timeout)
+    }
+
+    fn transfer_owner(&self, serial_number: &str, from_owner: &str, to_owner: &str, timeout: Option<Duration>) -> Result<OwnerTransfer, StoreError> {
+        self.replicas[0].transfer_owner(serial_number, from_owner, to_owner, timeout)
+    }
+
+    fn import_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        self.replicas[0].import_many(serial_numbers, timeout)
+    }
+
+    fn similar_candidates(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Vec<String>, StoreError> {
+        let index = self.route();
+        let result = self.replicas[index].similar_candidates(serial_number, timeout);
+        self.observe(index, &result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{MemoryStore, FailingStore};
+
+    #[test]
+    fn routes_to_the_fastest_healthy_replica() {
+        let router = ReplicaRouter::new(ReplicaRoutingSettings::default());
+        router.record(vec![Some(Duration::from_millis(180)), None, Some(Duration::from_millis(20))], Instant::now());
+        assert_eq!(2, router.fastest());
+    }
+
+    #[test]
+    fn falls_back_to_the_first_replica_when_none_is_healthy() {
+        let router = ReplicaRouter::new(ReplicaRoutingSettings::default());
+        router.record(vec![None, None], Instant::now());
+        assert_eq!(0, router.fastest());
+    }
+
+    #[test]
+    fn measurements_expire_after_the_probe_interval() {
+        let router = ReplicaRouter::new(ReplicaRoutingSettings { probe_interval: Duration::from_secs(60), ..Default::default() });
+        let now = Instant::now();
+        assert_eq!(true, router.is_stale(2, now));
+        router.record(vec![None, None], now);
+        assert_eq!(false, router.is_stale(2, now + Duration::from_secs(59)));
+        assert_eq!(true, router.is_stale(2, now + Duration::from_secs(60)));
+        assert_eq!(true, router.is_stale(3, now));
+    }
+
+    #[test]
+    fn probes_mark_failing_replicas_unhealthy() {
+        let router = ReplicaRouter::new(ReplicaRoutingSettings::default());
+        let store = LatencyRoutedStore::new(vec![MemoryStore::new(Vec::new())], &router);
+        store.route();
+        assert_eq!(true, router.measurements.lock().unwrap().latencies[0].is_some());
+
+        let store = LatencyRoutedStore::new(vec![FailingStore], &router);
+        router.measure(&store.replicas, Instant::now());
+        assert_eq!(true, router.measurements.lock().unwrap().latencies[0].is_none());
+    }
+}
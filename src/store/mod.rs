@@ -0,0 +1,542 @@
+mod asset;
+mod assumed_role;
+mod bloom_filtered;
+mod cached;
+mod capacity;
+mod circuit_breaker;
+mod counter;
+mod dynamodb;
+mod failover;
+#[cfg(feature = "fault-injection")]
+mod fault_injection;
+mod latency_routing;
+mod ranges;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_derive::{Serialize, Deserialize};
+use schemars::JsonSchema;
+
+pub use self::assumed_role::AssumeRoleSettings;
+pub use self::bloom_filtered::BloomFilteredStore;
+pub use self::cached::CachedStore;
+pub use self::capacity::CapacityBudget;
+pub use self::circuit_breaker::{CircuitBreaker, CircuitBreakerSettings, CircuitBreakerStore};
+pub use self::counter::{DynamoDbCounter, SequenceBlocks, SequenceCounter, SequenceSettings};
+#[cfg(test)]
+pub use self::counter::MemoryCounter;
+pub use self::dynamodb::{DynamoDbStore, DynamoDbSettings, DeletedPolicy, ListQuery, ListedSerial, DEFAULT_REGION, client_in, send, start_lookup_runtime, SECONDS_PER_DAY, normalize_serial, string_value, number_value};
+#[cfg(feature = "fault-injection")]
+pub use self::fault_injection::{FaultInjectingStore, FaultInjectionSettings};
+pub use self::failover::{FailoverRouter, FailoverSettings, FailoverStore};
+pub use self::latency_routing::{LatencyRoutedStore, ReplicaRouter, ReplicaRoutingSettings};
+pub use self::ranges::{load_ranges, RangeCache, RangeSettings};
+
+#[derive(Debug)]
+pub enum StoreError {
+    Timeout,
+    Throttled(String),
+    Unavailable(String),
+    Misconfigured(String),
+    /// The function's role may not call the store, e.g. lacking `dynamodb:GetItem` on the table.
+    AccessDenied(String),
+    /// The circuit breaker rejected the call without reaching the store.
+    CircuitOpen
+}
+
+/// Client token of a possibly retried request, kept on the serial it registered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdempotencyKey {
+    pub key: String,
+    /// Unix time after which a repeated key no longer replays the registration.
+    pub expires_at: u64
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Registration {
+    Registered,
+    /// The serial was registered earlier under the same, still valid, idempotency key.
+    Replayed,
+    AlreadyRegistered
+}
+
+/// Condition of a transactional registration that cancelled it, see `SerialStore::register_owned`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FailedCondition {
+    /// The serial is registered or held by a live reservation.
+    SerialTaken,
+    /// The owners table already holds an ownership record for the serial.
+    OwnerRecordExists
+}
+
+impl FailedCondition {
+    /// Error code reported to callers.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            FailedCondition::SerialTaken => "already_exists",
+            FailedCondition::OwnerRecordExists => "owner_record_exists",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum OwnedRegistration {
+    Registered,
+    /// Nothing was written because of the given condition.
+    Rejected(FailedCondition)
+}
+
+/// Metadata attributes changed by `SerialStore::update_metadata`; unset fields are left alone.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MetadataUpdate {
+    pub status: Option<String>,
+    pub owner_id: Option<String>
+}
+
+/// Attributes of the item holding a serial that is already taken, shown to callers allowed to
+/// know who holds it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct ConflictingItem {
+    #[serde(rename = "ownerId", skip_serializing_if = "Option::is_none", default)]
+    pub owner_id: Option<String>,
+    #[serde(rename = "registeredAt", skip_serializing_if = "Option::is_none", default)]
+    pub registered_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub status: Option<String>
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MetadataUpdated {
+    /// The item now carries `version`.
+    Updated { version: u64 },
+    /// Nothing was written because the item carries `version` instead of the expected one.
+    Conflict { version: u64 },
+    /// No live item holds the serial.
+    NotFound
+}
+
+#[derive(Debug, PartialEq)]
+pub enum OwnerTransfer {
+    /// The item is now owned by the new owner and carries `version`.
+    Transferred { version: u64 },
+    /// Nothing was written because the item is owned by someone else.
+    Conflict,
+    /// No live item holds the serial.
+    NotFound
+}
+
+/// Key looked up by `SerialStore::probe`; it is never a registered serial.
+pub const PROBE_SERIAL_NUMBER: &str = "__probe__";
+
+pub trait SerialStore {
+    fn contains(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError>;
+
+    /// Looks up several serials at once, answering in the order they were given. Stores that can
+    /// batch reads override this; each lookup shares `timeout`.
+    fn contains_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        serial_numbers.iter().map(|serial_number| self.contains(serial_number, timeout)).collect()
+    }
+
+    /// Like `contains`, but reads the owner, registration time and status of the item holding the
+    /// serial along the way. Stores that cannot read them answer with an empty item.
+    fn conflicting_item(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Option<ConflictingItem>, StoreError> {
+        self.contains(serial_number, timeout).map(|found| if found { Some(ConflictingItem::default()) } else { None })
+    }
+
+    /// Registers the serial unless it already exists. An existing serial registered under
+    /// `idempotency_key` before it expired is reported as `Replayed` rather than `AlreadyRegistered`.
+    fn register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError>;
+
+    /// What `register` would answer, without writing anything. Stores that cannot check the write
+    /// itself answer from a lookup, which does not tell replays apart.
+    fn would_register(&self, serial_number: &str, _idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        self.contains(serial_number, timeout).map(|found| if found { Registration::AlreadyRegistered } else { Registration::Registered })
+    }
+
+    /// Registers the serial and records `owner_id` as its owner in one transaction, writing
+    /// neither unless the serial is available and has no ownership record yet.
+    fn register_owned(&self, _serial_number: &str, _owner_id: &str, _timeout: Option<Duration>) -> Result<OwnedRegistration, StoreError> {
+        Err(StoreError::Misconfigured(String::from("this store does not record owners")))
+    }
+
+    /// Applies `update` to the item of a registered serial if it still carries `expected_version`,
+    /// bumping the version so that concurrent updates cannot overwrite each other. Items that
+    /// were never updated are at version `0`.
+    fn update_metadata(&self, _serial_number: &str, _update: &MetadataUpdate, _expected_version: u64, _timeout: Option<Duration>) -> Result<MetadataUpdated, StoreError> {
+        Err(StoreError::Misconfigured(String::from("this store does not update metadata")))
+    }
+
+    /// Holds the serial until `expires_at` unless it is registered or held by a live reservation.
+    /// Returns `false` when it is taken.
+    fn reserve(&self, serial_number: &str, expires_at: u64, timeout: Option<Duration>) -> Result<bool, StoreError>;
+
+    /// Turns a live reservation into a permanent registration. Returns `false` when the serial
+    /// is not reserved, e.g. because the reservation expired or was confirmed already.
+    fn confirm(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError>;
+
+    /// Tombstones the live item of the serial, recording `reason`, so that it no longer counts
+    /// as registered beyond what the `deleted_policy` keeps. Returns `false` when no live item
+    /// holds the serial.
+    fn release(&self, _serial_number: &str, _reason: &str, _timeout: Option<Duration>) -> Result<bool, StoreError> {
+        Err(StoreError::Misconfigured(String::from("this store does not release serials")))
+    }
+
+    /// Sets the owner of the live item of the serial to `to_owner` if it is still owned by
+    /// `from_owner`, bumping its version like `update_metadata` does.
+    fn transfer_owner(&self, _serial_number: &str, _from_owner: &str, _to_owner: &str, _timeout: Option<Duration>) -> Result<OwnerTransfer, StoreError> {
+        Err(StoreError::Misconfigured(String::from("this store does not transfer serials")))
+    }
+
+    /// Writes a registration for each serial without any condition, overwriting items that hold
+    /// them, so callers look the serials up first. Answers in the order given whether each serial
+    /// was written; writes the table kept refusing are reported rather than failing the call.
+    fn import_many(&self, _serial_numbers: &[&str], _timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        Err(StoreError::Misconfigured(String::from("this store does not import serials")))
+    }
+
+    /// Checks that the store answers, without caring about the result.
+    fn probe(&self, timeout: Option<Duration>) -> Result<(), StoreError> {
+        self.contains(PROBE_SERIAL_NUMBER, timeout).map(|_| ())
+    }
+
+    /// Registered serials that `serial_number` may be a typo of, left for the caller to rank.
+    /// Stores with no cheap way to narrow them down return none.
+    fn similar_candidates(&self, _serial_number: &str, _timeout: Option<Duration>) -> Result<Vec<String>, StoreError> {
+        Ok(Vec::new())
+    }
+}
+
+impl<S: SerialStore + ?Sized> SerialStore for Box<S> {
+    fn contains(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        (**self).contains(serial_number, timeout)
+    }
+
+    fn contains_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        (**self).contains_many(serial_numbers, timeout)
+    }
+
+    fn conflicting_item(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Option<ConflictingItem>, StoreError> {
+        (**self).conflicting_item(serial_number, timeout)
+    }
+
+    fn register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        (**self).register(serial_number, idempotency_key, timeout)
+    }
+
+    fn would_register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        (**self).would_register(serial_number, idempotency_key, timeout)
+    }
+
+    fn register_owned(&self, serial_number: &str, owner_id: &str, timeout: Option<Duration>) -> Result<OwnedRegistration, StoreError> {
+        (**self).register_owned(serial_number, owner_id, timeout)
+    }
+
+    fn update_metadata(&self, serial_number: &str, update: &MetadataUpdate, expected_version: u64, timeout: Option<Duration>) -> Result<MetadataUpdated, StoreError> {
+        (**self).update_metadata(serial_number, update, expected_version, timeout)
+    }
+
+    fn reserve(&self, serial_number: &str, expires_at: u64, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        (**self).reserve(serial_number, expires_at, timeout)
+    }
+
+    fn confirm(&self, serial_number: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        (**self).confirm(serial_number, timeout)
+    }
+
+    fn release(&self, serial_number: &str, reason: &str, timeout: Option<Duration>) -> Result<bool, StoreError> {
+        (**self).release(serial_number, reason, timeout)
+    }
+
+    fn transfer_owner(&self, serial_number: &str, from_owner: &str, to_owner: &str, timeout: Option<Duration>) -> Result<OwnerTransfer, StoreError> {
+        (**self).transfer_owner(serial_number, from_owner, to_owner, timeout)
+    }
+
+    fn import_many(&self, serial_numbers: &[&str], timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        (**self).import_many(serial_numbers, timeout)
+    }
+
+    fn probe(&self, timeout: Option<Duration>) -> Result<(), StoreError> {
+        (**self).probe(timeout)
+    }
+
+    fn similar_candidates(&self, serial_number: &str, timeout: Option<Duration>) -> Result<Vec<String>, StoreError> {
+        (**self).similar_candidates(serial_number, timeout)
+    }
+}
+
+/// Seconds since the unix epoch, the unit of reservation and idempotency expiries.
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
+
+#[derive(Default)]
+struct MemoryItem {
+    idempotency_key: Option<IdempotencyKey>,
+    reserved_until: Option<u64>,
+    version: u64,
+    metadata: MetadataUpdate
+}
+
+impl MemoryItem {
+    /// Expired reservations linger until DynamoDB deletes them, but no longer hold the serial.
+    fn is_live(&self, now: u64) -> bool {
+        self.reserved_until.is_none_or(|reserved_until| reserved_until > now)
+    }
+}
+
+/// In-memory store used by the tests and the self test in place of the `assets` and `asset_owners` tables.
+pub struct MemoryStore {
+    items: Mutex<HashMap<String, MemoryItem>>,
+    /// Owner id by serial.
+    owners: Mutex<HashMap<String, String>>
+}
+
+impl MemoryStore {
+    pub fn new(serial_numbers: Vec<String>) -> MemoryStore {
+        MemoryStore {
+            items: Mutex::new(serial_numbers.into_iter().map(|s| (s, MemoryItem::default())).collect()),
+            owners: Mutex::new(HashMap::new())
+        }
+    }
+}
+
+impl SerialStore for MemoryStore {
+    fn contains(&self, serial_number: &str, _timeout: Option<Duration>) -> Result<bool, StoreError> {
+        Ok(self.items.lock().unwrap().get(serial_number).is_some_and(|item| item.is_live(unix_now())))
+    }
+
+    fn conflicting_item(&self, serial_number: &str, _timeout: Option<Duration>) -> Result<Option<ConflictingItem>, StoreError> {
+        let items = self.items.lock().unwrap();
+        Ok(items.get(serial_number).filter(|item| item.is_live(unix_now())).map(|item| ConflictingItem {
+            owner_id: item.metadata.owner_id.clone(),
+            registered_at: None,
+            status: item.metadata.status.clone()
+        }))
+    }
+
+    fn register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, _timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        let now = unix_now();
+        let mut items = self.items.lock().unwrap();
+        match items.get(serial_number) {
+            Some(item) if item.is_live(now) => {
+                let existing = item.idempotency_key.as_ref().map(|existing| (existing.key.as_str(), existing.expires_at));
+                Ok(registration_of_existing(existing, idempotency_key, now))
+            },
+            _ => {
+                items.insert(serial_number.to_string(), MemoryItem { idempotency_key: idempotency_key.cloned(), ..Default::default() });
+                Ok(Registration::Registered)
+            }
+        }
+    }
+
+    fn would_register(&self, serial_number: &str, idempotency_key: Option<&IdempotencyKey>, _timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        let now = unix_now();
+        match self.items.lock().unwrap().get(serial_number) {
+            Some(item) if item.is_live(now) => {
+                let existing = item.idempotency_key.as_ref().map(|existing| (existing.key.as_str(), existing.expires_at));
+                Ok(registration_of_existing(existing, idempotency_key, now))
+            },
+            _ => Ok(Registration::Registered),
+        }
+    }
+
+    fn register_owned(&self, serial_number: &str, owner_id: &str, _timeout: Option<Duration>) -> Result<OwnedRegistration, StoreError> {
+        let mut items = self.items.lock().unwrap();
+        let mut owners = self.owners.lock().unwrap();
+        if items.get(serial_number).is_some_and(|item| item.is_live(unix_now())) {
+            return Ok(OwnedRegistration::Rejected(FailedCondition::SerialTaken));
+        }
+        if owners.contains_key(serial_number) {
+            return Ok(OwnedRegistration::Rejected(FailedCondition::OwnerRecordExists));
+        }
+        items.insert(serial_number.to_string(), MemoryItem::default());
+        owners.insert(serial_number.to_string(), owner_id.to_string());
+        Ok(OwnedRegistration::Registered)
+    }
+
+    fn update_metadata(&self, serial_number: &str, update: &MetadataUpdate, expected_version: u64, _timeout: Option<Duration>) -> Result<MetadataUpdated, StoreError> {
+        match self.items.lock().unwrap().get_mut(serial_number) {
+            Some(item) if item.is_live(unix_now()) => {
+                if item.version != expected_version {
+                    return Ok(MetadataUpdated::Conflict { version: item.version });
+                }
+                if update.status.is_some() {
+                    item.metadata.status = update.status.clone();
+                }
+                if update.owner_id.is_some() {
+                    item.metadata.owner_id = update.owner_id.clone();
+                }
+                item.version += 1;
+                Ok(MetadataUpdated::Updated { version: item.version })
+            },
+            _ => Ok(MetadataUpdated::NotFound),
+        }
+    }
+
+    fn reserve(&self, serial_number: &str, expires_at: u64, _timeout: Option<Duration>) -> Result<bool, StoreError> {
+        let mut items = self.items.lock().unwrap();
+        if items.get(serial_number).is_some_and(|item| item.is_live(unix_now())) {
+            return Ok(false);
+        }
+        items.insert(serial_number.to_string(), MemoryItem { reserved_until: Some(expires_at), ..Default::default() });
+        Ok(true)
+    }
+
+    fn confirm(&self, serial_number: &str, _timeout: Option<Duration>) -> Result<bool, StoreError> {
+        let now = unix_now();
+        match self.items.lock().unwrap().get_mut(serial_number) {
+            Some(item) if item.reserved_until.is_some() && item.is_live(now) => {
+                item.reserved_until = None;
+                Ok(true)
+            },
+            _ => Ok(false),
+        }
+    }
+
+    /// Removes the item rather than tombstoning it, as if released serials were available.
+    fn release(&self, serial_number: &str, _reason: &str, _timeout: Option<Duration>) -> Result<bool, StoreError> {
+        let mut items = self.items.lock().unwrap();
+        if !items.get(serial_number).is_some_and(|item| item.is_live(unix_now())) {
+            return Ok(false);
+        }
+        items.remove(serial_number);
+        Ok(true)
+    }
+
+    fn transfer_owner(&self, serial_number: &str, from_owner: &str, to_owner: &str, _timeout: Option<Duration>) -> Result<OwnerTransfer, StoreError> {
+        match self.items.lock().unwrap().get_mut(serial_number) {
+            Some(item) if item.is_live(unix_now()) => {
+                if item.metadata.owner_id.as_deref() != Some(from_owner) {
+                    return Ok(OwnerTransfer::Conflict);
+                }
+                item.metadata.owner_id = Some(to_owner.to_string());
+                item.version += 1;
+                Ok(OwnerTransfer::Transferred { version: item.version })
+            },
+            _ => Ok(OwnerTransfer::NotFound),
+        }
+    }
+
+    fn import_many(&self, serial_numbers: &[&str], _timeout: Option<Duration>) -> Result<Vec<bool>, StoreError> {
+        let mut items = self.items.lock().unwrap();
+        for serial_number in serial_numbers {
+            items.insert(serial_number.to_string(), MemoryItem::default());
+        }
+        Ok(vec![true; serial_numbers.len()])
+    }
+
+    /// Every live serial; there are few enough in tests.
+    fn similar_candidates(&self, _serial_number: &str, _timeout: Option<Duration>) -> Result<Vec<String>, StoreError> {
+        let now = unix_now();
+        Ok(self.items.lock().unwrap().iter().filter(|(_, item)| item.is_live(now)).map(|(serial_number, _)| serial_number.clone()).collect())
+    }
+}
+
+/// Outcome of registering a serial that already exists, given the idempotency key it was registered under.
+fn registration_of_existing(existing: Option<(&str, u64)>, idempotency_key: Option<&IdempotencyKey>, now: u64) -> Registration {
+    match (existing, idempotency_key) {
+        (Some((existing_key, expires_at)), Some(idempotency_key)) if existing_key == idempotency_key.key && expires_at > now => Registration::Replayed,
+        _ => Registration::AlreadyRegistered,
+    }
+}
+
+/// Store that fails every call, standing in for an unreachable table.
+pub struct FailingStore;
+
+impl SerialStore for FailingStore {
+    fn contains(&self, _serial_number: &str, _timeout: Option<Duration>) -> Result<bool, StoreError> {
+        Err(StoreError::Unavailable(String::from("connection refused")))
+    }
+
+    fn register(&self, _serial_number: &str, _idempotency_key: Option<&IdempotencyKey>, _timeout: Option<Duration>) -> Result<Registration, StoreError> {
+        Err(StoreError::Unavailable(String::from("connection refused")))
+    }
+
+    fn reserve(&self, _serial_number: &str, _expires_at: u64, _timeout: Option<Duration>) -> Result<bool, StoreError> {
+        Err(StoreError::Unavailable(String::from("connection refused")))
+    }
+
+    fn confirm(&self, _serial_number: &str, _timeout: Option<Duration>) -> Result<bool, StoreError> {
+        Err(StoreError::Unavailable(String::from("connection refused")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: &str, expires_at: u64) -> IdempotencyKey {
+        IdempotencyKey { key: String::from(key), expires_at }
+    }
+
+    #[test]
+    fn repeated_keys_replay_the_registration() {
+        assert_eq!(Registration::Replayed, registration_of_existing(Some(("k1", 2_000)), Some(&key("k1", 3_000)), 1_000));
+    }
+
+    #[test]
+    fn other_or_expired_keys_find_the_serial_registered() {
+        assert_eq!(Registration::AlreadyRegistered, registration_of_existing(Some(("k1", 2_000)), Some(&key("k2", 3_000)), 1_000));
+        assert_eq!(Registration::AlreadyRegistered, registration_of_existing(Some(("k1", 2_000)), Some(&key("k1", 4_000)), 2_000));
+        assert_eq!(Registration::AlreadyRegistered, registration_of_existing(None, Some(&key("k1", 3_000)), 1_000));
+        assert_eq!(Registration::AlreadyRegistered, registration_of_existing(Some(("k1", 2_000)), None, 1_000));
+    }
+
+    #[test]
+    fn memory_store_keeps_the_idempotency_key() {
+        let store = MemoryStore::new(Vec::new());
+        let far_future = key("k1", u64::MAX);
+        assert_eq!(Registration::Registered, store.register("AB1234", Some(&far_future), None).ok().unwrap());
+        assert_eq!(Registration::Replayed, store.register("AB1234", Some(&far_future), None).ok().unwrap());
+        assert_eq!(Registration::AlreadyRegistered, store.register("AB1234", None, None).ok().unwrap());
+    }
+
+    #[test]
+    fn reservations_hold_the_serial_until_they_expire() {
+        let store = MemoryStore::new(Vec::new());
+        assert_eq!(true, store.reserve("AB1234", u64::MAX, None).ok().unwrap());
+        assert_eq!(true, store.contains("AB1234", None).ok().unwrap());
+        assert_eq!(false, store.reserve("AB1234", u64::MAX, None).ok().unwrap());
+
+        assert_eq!(true, store.reserve("CD5678", 1, None).ok().unwrap());
+        assert_eq!(false, store.contains("CD5678", None).ok().unwrap());
+        assert_eq!(false, store.confirm("CD5678", None).ok().unwrap());
+        assert_eq!(true, store.reserve("CD5678", u64::MAX, None).ok().unwrap());
+    }
+
+    #[test]
+    fn confirmed_reservations_never_expire() {
+        let store = MemoryStore::new(vec![String::from("EF9012")]);
+        assert_eq!(false, store.confirm("EF9012", None).ok().unwrap());
+        assert_eq!(true, store.reserve("AB1234", u64::MAX, None).ok().unwrap());
+        assert_eq!(true, store.confirm("AB1234", None).ok().unwrap());
+        assert_eq!(false, store.confirm("AB1234", None).ok().unwrap());
+        assert_eq!(Registration::AlreadyRegistered, store.register("AB1234", None, None).ok().unwrap());
+    }
+
+    #[test]
+    fn owned_registrations_write_both_records_or_neither() {
+        let store = MemoryStore::new(vec![String::from("EF9012")]);
+        assert_eq!(OwnedRegistration::Registered, store.register_owned("AB1234", "owner-1", None).ok().unwrap());
+        assert_eq!(true, store.contains("AB1234", None).ok().unwrap());
+        assert_eq!(OwnedRegistration::Rejected(FailedCondition::SerialTaken), store.register_owned("EF9012", "owner-1", None).ok().unwrap());
+        assert_eq!(false, store.owners.lock().unwrap().contains_key("EF9012"));
+
+        // an ownership record outliving its asset item
+        store.owners.lock().unwrap().insert(String::from("CD5678"), String::from("owner-2"));
+        assert_eq!(OwnedRegistration::Rejected(FailedCondition::OwnerRecordExists), store.register_owned("CD5678", "owner-1", None).ok().unwrap());
+        assert_eq!(false, store.contains("CD5678", None).ok().unwrap());
+    }
+
+    #[test]
+    fn metadata_updates_need_the_current_version() {
+        let store = MemoryStore::new(vec![String::from("AB1234")]);
+        let retire = MetadataUpdate { status: Some(String::from("retired")), owner_id: None };
+        assert_eq!(MetadataUpdated::Updated { version: 1 }, store.update_metadata("AB1234", &retire, 0, None).ok().unwrap());
+        assert_eq!(MetadataUpdated::Conflict { version: 1 }, store.update_metadata("AB1234", &retire, 0, None).ok().unwrap());
+        assert_eq!(MetadataUpdated::Updated { version: 2 }, store.update_metadata("AB1234", &retire, 1, None).ok().unwrap());
+        assert_eq!(MetadataUpdated::NotFound, store.update_metadata("CD5678", &retire, 0, None).ok().unwrap());
+    }
+}
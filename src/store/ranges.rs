@@ -0,0 +1,131 @@
+//! The reserved ranges, kept in a DynamoDB table with one item per range: `range_start` as the key,
+//! `range_end`, and the `tenant_id` and optional `product_line` owning it. The table is small and
+//! read whole, then kept by the container until it is `refresh_interval` old.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rusoto_dynamodb::{AttributeValue, DynamoDb, ScanInput};
+
+use serial_validation::core::ranges::{RangeOwner, ReservedRange};
+
+use crate::context::log_shared;
+
+use super::{client_in, send, StoreError, DEFAULT_REGION};
+
+#[derive(Clone, Debug)]
+pub struct RangeSettings {
+    pub table_name: String,
+    /// How long the ranges are used before they are read again, picking up new reservations.
+    pub refresh_interval: Duration
+}
+
+fn string_of<'a>(item: &'a HashMap<String, AttributeValue>, name: &str) -> Option<&'a str> {
+    item.get(name).and_then(|value| value.s.as_deref())
+}
+
+/// The range an item holds, `None` for items missing an end or spanning serials of different lengths.
+fn range_of(item: &HashMap<String, AttributeValue>) -> Option<ReservedRange> {
+    let owner = RangeOwner {
+        tenant_id: string_of(item, "tenant_id").map(String::from),
+        product_line: string_of(item, "product_line").map(String::from)
+    };
+    ReservedRange::new(string_of(item, "range_start")?, string_of(item, "range_end")?, owner)
+}
+
+/// Every range in the table, read page by page. Malformed items are logged and left out.
+pub fn load_ranges(settings: &RangeSettings, timeout: Option<Duration>) -> Result<Vec<ReservedRange>, StoreError> {
+    let client = client_in(&DEFAULT_REGION);
+    let mut ranges = Vec::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let scan = ScanInput {
+            table_name: settings.table_name.clone(),
+            exclusive_start_key,
+            ..Default::default()
+        };
+        let page = send(client.scan(scan), timeout)?;
+        for item in page.items.unwrap_or_default() {
+            match range_of(&item) {
+                Some(range) => ranges.push(range),
+                None => log_shared(&format!("ignoring malformed reserved range: {:?}", string_of(&item, "range_start"))),
+            }
+        }
+        match page.last_evaluated_key {
+            Some(last_evaluated_key) => exclusive_start_key = Some(last_evaluated_key),
+            None => return Ok(ranges),
+        }
+    }
+}
+
+/// The ranges loaded by this container, shared by every invocation it serves.
+pub struct RangeCache {
+    ranges: Mutex<Option<(Arc<Vec<ReservedRange>>, Instant)>>
+}
+
+impl RangeCache {
+    pub fn new() -> RangeCache {
+        RangeCache { ranges: Mutex::new(None) }
+    }
+
+    /// The ranges, loaded again once they are `refresh_interval` old. A failed reload keeps the
+    /// ranges loaded before until the next attempt; `None` while none could be loaded yet.
+    pub fn get<F>(&self, refresh_interval: Duration, now: Instant, load: F) -> Option<Arc<Vec<ReservedRange>>>
+        where F: FnOnce() -> Result<Vec<ReservedRange>, StoreError>
+    {
+        let mut ranges = self.ranges.lock().unwrap();
+        if let Some((ref cached, loaded_at)) = *ranges {
+            if now.duration_since(loaded_at) < refresh_interval {
+                return Some(cached.clone());
+            }
+        }
+        match load() {
+            Ok(loaded) => *ranges = Some((Arc::new(loaded), now)),
+            Err(error) => {
+                log_shared(&format!("reserved ranges could not be loaded: {:?}", error));
+                if let Some((_, ref mut loaded_at)) = *ranges {
+                    *loaded_at = now;
+                }
+            },
+        }
+        ranges.as_ref().map(|(cached, _)| cached.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::string_value;
+
+    #[test]
+    fn reads_ranges_from_items() {
+        let mut item = HashMap::new();
+        item.insert(String::from("range_start"), string_value("xk100000"));
+        item.insert(String::from("range_end"), string_value("XK199999"));
+        item.insert(String::from("tenant_id"), string_value("acme"));
+        let range = range_of(&item).unwrap();
+        assert_eq!("XK100000", range.first);
+        assert_eq!(RangeOwner { tenant_id: Some(String::from("acme")), product_line: None }, range.owner);
+
+        item.insert(String::from("range_end"), string_value("XK1999999"));
+        assert_eq!(None, range_of(&item));
+    }
+
+    #[test]
+    fn keeps_the_ranges_until_they_are_stale() {
+        let cache = RangeCache::new();
+        let start = Instant::now();
+        let refresh_interval = Duration::from_secs(60);
+        let range = ReservedRange::new("AB100", "AB199", RangeOwner::default()).unwrap();
+
+        assert_eq!(true, cache.get(refresh_interval, start, || Err(StoreError::Timeout)).is_none());
+        assert_eq!(1, cache.get(refresh_interval, start, || Ok(vec![range.clone()])).unwrap().len());
+        assert_eq!(1, cache.get(refresh_interval, start + Duration::from_secs(30), || panic!("still fresh")).unwrap().len());
+        // a failed reload keeps the ranges and waits another interval
+        let later = start + Duration::from_secs(90);
+        assert_eq!(1, cache.get(refresh_interval, later, || Err(StoreError::Timeout)).unwrap().len());
+        assert_eq!(1, cache.get(refresh_interval, later + Duration::from_secs(30), || panic!("still fresh")).unwrap().len());
+        assert_eq!(0, cache.get(refresh_interval, later + Duration::from_secs(60), || Ok(Vec::new())).unwrap().len());
+    }
+}
@@ -0,0 +1,211 @@
+//! Handler of the `assets` table's DynamoDB stream, keeping the bloom filter snapshots current and
+//! appending changed serials to the invalidation logs of the containers' cached results. Built
+//! instead of the validation handler with `--features stream-consumer`.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use lambda_runtime::{error::HandlerError, Context};
+use rusoto_core::Region;
+use rusoto_dynamodb::AttributeValue;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::aws;
+use crate::bloom::{self, BloomFilter, BloomSettings};
+use crate::error::ServiceError;
+use crate::result_cache::{self, ResultCacheSettings};
+use crate::store::{DynamoDbSettings, DynamoDbStore};
+
+/// Tries to replace a snapshot that other shards keep replacing first before the batch is retried.
+const SNAPSHOT_SAVE_ATTEMPTS: u32 = 5;
+
+/// Longest a single S3 or DynamoDB request may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize)]
+pub struct StreamEvent {
+    #[serde(rename = "Records")]
+    records: Vec<StreamRecord>
+}
+
+#[derive(Deserialize)]
+struct StreamRecord {
+    /// `INSERT`, `MODIFY` or `REMOVE`.
+    #[serde(rename = "eventName")]
+    event_name: String,
+    #[serde(rename = "eventSourceARN")]
+    event_source_arn: String,
+    dynamodb: StreamData
+}
+
+#[derive(Deserialize)]
+struct StreamData {
+    #[serde(rename = "Keys", default)]
+    keys: HashMap<String, AttributeValue>,
+    #[serde(rename = "NewImage", default)]
+    new_image: Option<HashMap<String, AttributeValue>>
+}
+
+#[derive(Serialize)]
+pub struct StreamReport {
+    /// Serials added to the snapshots.
+    #[serde(rename = "serialsAdded")]
+    serials_added: usize,
+    #[serde(rename = "snapshotsSaved")]
+    snapshots_saved: usize,
+    /// Keys appended to the invalidation logs.
+    #[serde(rename = "keysInvalidated")]
+    keys_invalidated: usize
+}
+
+pub fn stream_handler(event: StreamEvent, ctx: Context) -> Result<StreamReport, HandlerError> {
+    let config = &*crate::CONFIG;
+    let outcome = match (config.bloom.as_ref(), config.result_cache.as_ref()) {
+        (None, None) => Err(ServiceError::StoreMisconfigured(String::from("neither BLOOM_BUCKET nor RESULT_CACHE_BUCKET is set"))),
+        (bloom, result_cache) => apply(&event, bloom, result_cache, &config.dynamodb),
+    };
+    // a failed batch is retried by Lambda, so nothing it carried gets lost
+    outcome.map_err(|error| ctx.new_error(&error.to_json()))
+}
+
+/// Table named in a stream ARN such as `arn:aws:dynamodb:eu-central-1:123456789012:table/assets/stream/2019-01-01T00:00:00.000`.
+fn stream_table(arn: &str) -> Option<&str> {
+    arn.split(':').nth(5)?.split('/').nth(1)
+}
+
+/// Filter keys added by the batch, grouped by table. Removed items stay in the filter: a false
+/// positive only costs a lookup, while a false negative would let a duplicate through.
+fn new_keys(event: &StreamEvent, settings: &DynamoDbSettings) -> BTreeMap<String, (Region, Vec<String>)> {
+    keys_of(event.records.iter().filter(|record| record.event_name != "REMOVE"), settings)
+}
+
+/// Filter keys of every item the batch inserted, modified or removed, grouped by table.
+fn changed_keys(event: &StreamEvent, settings: &DynamoDbSettings) -> BTreeMap<String, (Region, Vec<String>)> {
+    keys_of(event.records.iter(), settings)
+}
+
+fn keys_of<'a, I: Iterator<Item = &'a StreamRecord>>(records: I, settings: &DynamoDbSettings) -> BTreeMap<String, (Region, Vec<String>)> {
+    let mut tables: BTreeMap<String, (Region, Vec<String>)> = BTreeMap::new();
+    for record in records {
+        let table_name = match stream_table(&record.event_source_arn) {
+            Some(table_name) => table_name,
+            None => continue,
+        };
+        let region = aws::arn_region(&record.event_source_arn).unwrap_or_default();
+        let item = record.dynamodb.new_image.as_ref().unwrap_or(&record.dynamodb.keys);
+        tables.entry(table_name.to_string())
+            .or_insert_with(|| (region, Vec::new()))
+            .1.extend(settings.filter_keys(item));
+    }
+    tables
+}
+
+fn apply(event: &StreamEvent, bloom: Option<&BloomSettings>, result_cache: Option<&ResultCacheSettings>, settings: &DynamoDbSettings) -> Result<StreamReport, ServiceError> {
+    let mut report = StreamReport { serials_added: 0, snapshots_saved: 0, keys_invalidated: 0 };
+    // invalidated first: a serial the snapshot already holds costs a lookup at most, while a
+    // result kept after the item changed is a wrong answer
+    if let Some(result_cache) = result_cache {
+        for (table_name, (_, keys)) in changed_keys(event, settings) {
+            append_invalidations(result_cache, &table_name, &keys)?;
+            report.keys_invalidated += keys.len();
+        }
+    }
+    if let Some(bloom) = bloom {
+        for (table_name, (region, keys)) in new_keys(event, settings) {
+            let table_settings = DynamoDbSettings { table_name: table_name.clone(), ..settings.clone() };
+            update_snapshot(bloom, &table_settings, region, &keys)?;
+            report.serials_added += keys.len();
+            report.snapshots_saved += 1;
+        }
+    }
+    Ok(report)
+}
+
+/// Appends `keys` to the table's invalidation log.
+fn append_invalidations(settings: &ResultCacheSettings, table_name: &str, keys: &[String]) -> Result<(), ServiceError> {
+    let log_error = |error: aws::AwsError| ServiceError::StoreUnavailable(format!("invalidation log: {}", error));
+    for _ in 0..SNAPSHOT_SAVE_ATTEMPTS {
+        let (mut log, etag) = match result_cache::load(settings, table_name, Some(REQUEST_TIMEOUT)).map_err(log_error)? {
+            Some(object) => (object.log, object.etag),
+            None => Default::default(),
+        };
+        log.append(keys, settings.log_length);
+        if result_cache::save(settings, table_name, &log, etag.as_deref(), Some(REQUEST_TIMEOUT)).map_err(log_error)? {
+            return Ok(());
+        }
+    }
+    Err(ServiceError::StoreUnavailable(format!("invalidation log of `{}` kept changing while saving", table_name)))
+}
+
+/// Adds `keys` to the table's snapshot, first building it from a full scan when there is none.
+fn update_snapshot(bloom: &BloomSettings, settings: &DynamoDbSettings, region: Region, keys: &[String]) -> Result<(), ServiceError> {
+    let snapshot_error = |error: aws::AwsError| ServiceError::StoreUnavailable(format!("bloom filter snapshot: {}", error));
+    for _ in 0..SNAPSHOT_SAVE_ATTEMPTS {
+        let (filter, etag) = match bloom::load(bloom, &settings.table_name, Some(REQUEST_TIMEOUT)).map_err(snapshot_error)? {
+            Some(snapshot) => (snapshot.filter, snapshot.etag),
+            None => (backfill(bloom, settings, region.clone())?, None),
+        };
+        keys.iter().for_each(|key| filter.insert(key));
+        if bloom::save(bloom, &settings.table_name, &filter, etag.as_deref(), Some(REQUEST_TIMEOUT)).map_err(snapshot_error)? {
+            return Ok(());
+        }
+    }
+    Err(ServiceError::StoreUnavailable(format!("bloom filter snapshot of `{}` kept changing while saving", settings.table_name)))
+}
+
+/// A filter holding every serial already in the table, for tables whose stream started after them.
+fn backfill(bloom: &BloomSettings, settings: &DynamoDbSettings, region: Region) -> Result<BloomFilter, ServiceError> {
+    let filter = BloomFilter::with_capacity(bloom.expected_items, bloom.false_positive_rate);
+    let store = DynamoDbStore::in_region(settings.clone(), region);
+    for key in store.scan_filter_keys(Some(REQUEST_TIMEOUT))? {
+        filter.insert(&key);
+    }
+    println!("built bloom filter snapshot of `{}` from a table scan", settings.table_name);
+    Ok(filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STREAM_ARN: &str = "arn:aws:dynamodb:eu-west-1:123456789012:table/assets/stream/2019-01-01T00:00:00.000";
+
+    fn test_event() -> StreamEvent {
+        serde_json::from_value(serde_json::json!({
+            "Records": [
+                {"eventName": "INSERT", "eventSourceARN": STREAM_ARN, "dynamodb": {
+                    "Keys": {"serial_number": {"S": "AB1234"}},
+                    "NewImage": {"serial_number": {"S": "AB1234"}, "registered_at": {"N": "1000"}}
+                }},
+                {"eventName": "MODIFY", "eventSourceARN": STREAM_ARN, "dynamodb": {
+                    "Keys": {"serial_number": {"S": "CD5678"}}
+                }},
+                {"eventName": "REMOVE", "eventSourceARN": STREAM_ARN, "dynamodb": {
+                    "Keys": {"serial_number": {"S": "EF9012"}}
+                }}
+            ]
+        })).unwrap()
+    }
+
+    #[test]
+    fn reads_the_table_of_a_stream_arn() {
+        assert_eq!(Some("assets"), stream_table(STREAM_ARN));
+        assert_eq!(None, stream_table("assets"));
+    }
+
+    #[test]
+    fn collects_the_keys_of_new_and_modified_items() {
+        let tables = new_keys(&test_event(), &DynamoDbSettings::default());
+        let (region, keys) = &tables["assets"];
+        assert_eq!(&Region::EuWest1, region);
+        assert_eq!(&vec![String::from("AB1234"), String::from("CD5678")], keys);
+    }
+
+    #[test]
+    fn invalidates_the_keys_of_every_change() {
+        let tables = changed_keys(&test_event(), &DynamoDbSettings::default());
+        let (_, keys) = &tables["assets"];
+        assert_eq!(&vec![String::from("AB1234"), String::from("CD5678"), String::from("EF9012")], keys);
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use serde_derive::Deserialize;
+
+use crate::error::ServiceError;
+use crate::store::DynamoDbSettings;
+
+/// Where a tenant's serial numbers live. Unset fields fall back to the deployment's table settings.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct TenantSettings {
+    /// Dedicated table for the tenant.
+    #[serde(rename = "table", default)]
+    pub table_name: Option<String>,
+    /// Prefix added to the tenant's serial numbers in a shared table.
+    #[serde(rename = "keyPrefix", default)]
+    pub key_prefix: Option<String>,
+    /// Partition key value of the tenant in a table with composite keys.
+    #[serde(rename = "partitionValue", default)]
+    pub partition_value: Option<String>
+}
+
+/// Parses the `TENANTS` allowlist, a JSON object keyed by tenant id:
+///
+/// ```json
+/// {"acme": {"table": "acme_assets"}, "globex": {"keyPrefix": "globex#"}}
+/// ```
+pub fn parse_tenants(value: &str) -> Result<HashMap<String, TenantSettings>, String> {
+    serde_json::from_str(value).map_err(|error| error.to_string())
+}
+
+/// Table settings for the tenant of an event. Without an allowlist the deployment is single
+/// tenant and events must not name one; with an allowlist every event must name a listed tenant.
+pub fn resolve(tenants: &HashMap<String, TenantSettings>, tenant_id: Option<&str>, base: &DynamoDbSettings) -> Result<DynamoDbSettings, ServiceError> {
+    let tenant_id = match (tenants.is_empty(), tenant_id) {
+        (true, None) => return Ok(base.clone()),
+        (true, Some(_)) => return Err(ServiceError::InvalidRequest(String::from("this deployment does not serve multiple tenants"))),
+        (false, None) => return Err(ServiceError::InvalidRequest(String::from("tenantId is required"))),
+        (false, Some(tenant_id)) => tenant_id,
+    };
+    let tenant = tenants.get(tenant_id)
+        .ok_or_else(|| ServiceError::Unauthorized(format!("tenant `{}` is not allowed", tenant_id)))?;
+
+    let mut settings = base.clone();
+    if let Some(ref table_name) = tenant.table_name {
+        settings.table_name = table_name.clone();
+    }
+    if let Some(ref key_prefix) = tenant.key_prefix {
+        settings.key_prefix = key_prefix.clone();
+    }
+    if let Some(ref partition_value) = tenant.partition_value {
+        settings.partition_value = Some(partition_value.clone());
+    }
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_tenants() -> HashMap<String, TenantSettings> {
+        parse_tenants(r#"{"acme": {"table": "acme_assets"}, "globex": {"keyPrefix": "globex#"}}"#).unwrap()
+    }
+
+    #[test]
+    fn resolves_a_dedicated_table() {
+        let settings = resolve(&test_tenants(), Some("acme"), &DynamoDbSettings::default()).ok().unwrap();
+        assert_eq!("acme_assets", settings.table_name);
+        assert_eq!("", settings.key_prefix);
+    }
+
+    #[test]
+    fn resolves_a_key_prefix_in_the_shared_table() {
+        let settings = resolve(&test_tenants(), Some("globex"), &DynamoDbSettings::default()).ok().unwrap();
+        assert_eq!("assets", settings.table_name);
+        assert_eq!("globex#", settings.key_prefix);
+    }
+
+    #[test]
+    fn rejects_tenants_missing_from_the_allowlist() {
+        let error = resolve(&test_tenants(), Some("initech"), &DynamoDbSettings::default()).err().unwrap();
+        assert_eq!("Unauthorized", error.error_type());
+    }
+
+    #[test]
+    fn requires_a_tenant_when_an_allowlist_is_configured() {
+        let error = resolve(&test_tenants(), None, &DynamoDbSettings::default()).err().unwrap();
+        assert_eq!("InvalidRequest", error.error_type());
+    }
+
+    #[test]
+    fn single_tenant_deployments_use_the_base_settings() {
+        let settings = resolve(&HashMap::new(), None, &DynamoDbSettings::default()).ok().unwrap();
+        assert_eq!("assets", settings.table_name);
+    }
+}